@@ -0,0 +1,77 @@
+//! Identify BIOS dumps using rustation's BIOS database, to figure out
+//! why the core rejects a file placed in the system directory. The
+//! files are loaded exactly like the core does.
+//!
+//! Usage: biosinfo <BIOS file>...
+//!
+//! Exits with status 0 if all the files are usable BIOS dumps, 1
+//! otherwise.
+
+extern crate rustation;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use rustation::bios::db::Metadata;
+
+#[path = "../biosfile.rs"]
+mod biosfile;
+
+use biosfile::Rejection;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+
+    if paths.is_empty() {
+        println!("Usage: biosinfo <BIOS file>...");
+        process::exit(1);
+    }
+
+    let mut all_ok = true;
+
+    for path in &paths {
+        println!("{}:", path);
+
+        match biosfile::load(Path::new(path)) {
+            Ok(bios) => {
+                print_metadata(bios.metadata());
+                println!("  usable: yes");
+            }
+            Err(Rejection::Io(e)) => {
+                println!("  can't load: {}", e);
+                all_ok = false;
+            }
+            Err(Rejection::Unknown) => {
+                println!("  not in the database: not a PlayStation BIOS, \
+                          or a modified or corrupt dump");
+                all_ok = false;
+            }
+            Err(Rejection::KnownBad(bios)) => {
+                print_metadata(bios.metadata());
+                println!("  usable: no, known bad dump");
+                all_ok = false;
+            }
+        }
+    }
+
+    if !all_ok {
+        process::exit(1);
+    }
+}
+
+fn print_metadata(md: &Metadata) {
+    let sha256: String =
+        md.sha256.iter()
+        .fold(String::new(), |s, b| s + &format!("{:02x}", b));
+
+    let yes_no = |b| if b { "yes" } else { "no" };
+
+    println!("  SHA-256: {}", sha256);
+    println!("  version: {}.{}", md.version_major, md.version_minor);
+    println!("  region: {:?}", md.region);
+    println!("  known bad: {}", yes_no(md.known_bad));
+    println!("  boot animation skip and EXE loading: {}",
+             yes_no(md.animation_jump_hook.is_some()));
+    println!("  debug UART: {}", yes_no(md.debug_uart_patch.is_some()));
+}
@@ -0,0 +1,130 @@
+//! Compare two GPU command dumps (generated with the "Dump GPU
+//! commands" core option) and report the first diverging command
+//! with some context.
+//!
+//! Usage: gpudiff <reference dump> <new dump>
+//!
+//! Exits with status 0 if the dumps are identical, 1 if they diverge
+//! and 2 on error.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+/// Number of commands displayed before and after the divergence
+const CONTEXT: usize = 5;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 {
+        println!("Usage: {} <reference dump> <new dump>", args[0]);
+        process::exit(2);
+    }
+
+    let reference = load(&args[1]);
+    let new = load(&args[2]);
+
+    let a: Vec<&str> = reference.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    match first_divergence(&a, &b) {
+        None => {
+            println!("Dumps are identical ({} commands)", a.len());
+        }
+        Some(line) => {
+            let frame = current_frame(&a, line);
+
+            println!("First divergence at line {} (frame {})",
+                     line + 1, frame);
+
+            println!("\n--- {}", args[1]);
+            print_context(&a, line);
+            println!("\n+++ {}", args[2]);
+            print_context(&b, line);
+
+            process::exit(1);
+        }
+    }
+}
+
+fn load(path: &str) -> String {
+    let mut s = String::new();
+
+    let res = File::open(path).and_then(|mut f| f.read_to_string(&mut s));
+
+    if let Err(e) = res {
+        println!("Couldn't read {}: {}", path, e);
+        process::exit(2);
+    }
+
+    s
+}
+
+/// Return the index of the first line that differs between `a` and
+/// `b` (or the end of the shortest one if one is a prefix of the
+/// other), None if they're identical
+fn first_divergence(a: &[&str], b: &[&str]) -> Option<usize> {
+    let common = a.iter().zip(b.iter()).position(|(x, y)| x != y);
+
+    match common {
+        Some(i) => Some(i),
+        None if a.len() != b.len() => Some(::std::cmp::min(a.len(), b.len())),
+        None => None,
+    }
+}
+
+/// Return the number of the frame containing `line`
+fn current_frame(dump: &[&str], line: usize) -> String {
+    let end = ::std::cmp::min(line + 1, dump.len());
+
+    dump[..end].iter()
+        .rev()
+        .filter_map(|l| {
+            if l.starts_with("frame ") {
+                Some(l[6..].to_string())
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or_else(|| "?".into())
+}
+
+fn print_context(dump: &[&str], line: usize) {
+    let start = line.saturating_sub(CONTEXT);
+    let end = ::std::cmp::min(line + CONTEXT + 1, dump.len());
+
+    if start >= end {
+        println!("  <end of dump>");
+        return;
+    }
+
+    for i in start..end {
+        let marker =
+            if i == line {
+                ">"
+            } else {
+                " "
+            };
+
+        println!("{} {:6} {}", marker, i + 1, dump[i]);
+    }
+
+    if line >= dump.len() {
+        println!(">        <end of dump>");
+    }
+}
+
+#[test]
+fn test_first_divergence() {
+    let a = ["frame 0", "draw_offset 0 0", "frame 1", "fill_rect 0,0,0"];
+    let b = ["frame 0", "draw_offset 0 0", "frame 1", "fill_rect 1,0,0"];
+
+    assert_eq!(first_divergence(&a, &a), None);
+    assert_eq!(first_divergence(&a, &b), Some(3));
+    assert_eq!(first_divergence(&a[..2], &a), Some(2));
+    assert_eq!(current_frame(&a, 3), "1");
+    assert_eq!(current_frame(&a, 1), "0");
+}
@@ -0,0 +1,59 @@
+//! BIOS file loading, shared by the core and the `biosinfo` tool so
+//! that the tool accepts and rejects exactly the same dumps.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use rustation::bios::{Bios, BIOS_SIZE};
+
+/// Reason why a file isn't usable as a BIOS
+pub enum Rejection {
+    /// The file couldn't be read or doesn't have the right size
+    Io(io::Error),
+    /// The file's hash isn't in rustation's database
+    Unknown,
+    /// The dump is in the database but known to be bad
+    KnownBad(Bios),
+}
+
+/// Read and identify the BIOS at `path`
+pub fn load(path: &Path) -> Result<Bios, Rejection> {
+    let data = try!(read(path).map_err(Rejection::Io));
+
+    match Bios::new(data) {
+        Some(bios) =>
+            if bios.metadata().known_bad {
+                Err(Rejection::KnownBad(bios))
+            } else {
+                Ok(bios)
+            },
+        None => Err(Rejection::Unknown),
+    }
+}
+
+fn read(path: &Path) -> io::Result<Box<[u8; BIOS_SIZE]>> {
+    let mut file = try!(File::open(path));
+
+    let len = try!(file.metadata()).len();
+
+    if len != BIOS_SIZE as u64 {
+        let e = format!("bad size ({} bytes, expected {})", len, BIOS_SIZE);
+
+        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    let mut data = Box::new([0; BIOS_SIZE]);
+    let mut nread = 0;
+
+    while nread < BIOS_SIZE {
+        nread +=
+            match try!(file.read(&mut data[nread..])) {
+                0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                               "short read")),
+                n => n,
+            };
+    }
+
+    Ok(data)
+}
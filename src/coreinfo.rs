@@ -0,0 +1,155 @@
+//! Core information manifest (the libretro-core-info `.info` file).
+//! It's generated from the constants used by the core itself so that
+//! the advertised capabilities can't drift from what's implemented:
+//! the checked-in `rustation_libretro.info` is compared against
+//! `manifest()` by the test suite and can be regenerated with:
+//!
+//! ```text
+//! RUSTATION_UPDATE_INFO=1 cargo test coreinfo
+//! ```
+//!
+//! The same manifest can be queried at runtime through the exported
+//! `rustation_core_info` function, for debugging.
+
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+
+use libc::c_char;
+
+/// BIOS images commonly found in the system directory: file name,
+/// region and MD5 sum. Any BIOS for the content's region works, the
+/// core identifies them by hash and ignores the file name, so none of
+/// them is mandatory.
+const FIRMWARE: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("scph5500.bin", "JP", "8dd7d5296a650fac7319bce665a6a53c"),
+    ("scph5501.bin", "US", "490f666e1afb15b7362b406ed1cea246"),
+    ("scph5502.bin", "EU", "32736f17079d0b2b7024407c39bd3050"),
+];
+
+/// Optional libretro features and whether we support them
+const FEATURES: &'static [(&'static str, &'static str)] = &[
+    ("supports_no_game", "false"),
+    ("savestate", "true"),
+    ("savestate_features", "deterministic"),
+    ("cheats", "false"),
+    ("hw_render", "true"),
+    ("required_hw_api", "OpenGL Core >= 3.3"),
+];
+
+/// Build the contents of the `.info` file
+pub fn manifest() -> String {
+    let info = ::SYSTEM_INFO;
+
+    // Those come straight from our `SystemInfo` which holds
+    // NUL-terminated static strings
+    let name = unsafe { CStr::from_ptr(info.library_name) };
+    let extensions = unsafe { CStr::from_ptr(info.valid_extensions) };
+
+    let name = name.to_string_lossy();
+    let extensions = extensions.to_string_lossy();
+
+    let mut s = String::new();
+
+    let display_name = format!("Sony - PlayStation ({})", name);
+
+    line(&mut s, "display_name", &display_name);
+    line(&mut s, "authors", "Lionel Flandrin");
+    line(&mut s, "supported_extensions", &extensions);
+    line(&mut s, "corename", &name);
+    line(&mut s, "license", "GPLv2+");
+    line(&mut s, "permissions", "");
+    line(&mut s, "display_version", "git");
+    line(&mut s, "categories", "Emulator");
+
+    line(&mut s, "manufacturer", "Sony");
+    line(&mut s, "systemname", "PlayStation");
+    line(&mut s, "systemid", "playstation");
+
+    for &(key, value) in FEATURES {
+        line(&mut s, key, value);
+    }
+
+    line(&mut s, "firmware_count", &FIRMWARE.len().to_string());
+
+    let mut notes = vec!["(!) A BIOS matching the content's region is \
+                          required, any file name works.".to_string()];
+
+    for (i, &(file, region, md5)) in FIRMWARE.iter().enumerate() {
+        line(&mut s, &format!("firmware{}_desc", i),
+             &format!("{} (PS1 {} BIOS)", file, region));
+        line(&mut s, &format!("firmware{}_path", i), file);
+        line(&mut s, &format!("firmware{}_opt", i), "true");
+
+        notes.push(format!("(!) {} (md5): {}", file, md5));
+    }
+
+    line(&mut s, "notes", &notes.join("|"));
+
+    line(&mut s, "description", "A PlayStation emulator written in \
+                                 Rust, with an OpenGL 3.3 renderer \
+                                 supporting internal resolution \
+                                 upscaling.");
+
+    let header = "# Core information file read by RetroArch and other \
+                  frontends following\n\
+                  # the libretro-core-info conventions, generated from \
+                  src/coreinfo.rs.\n\
+                  # It must be installed as rustation_libretro.info, \
+                  next to the core\n\
+                  # installed as rustation_libretro.so (or .dll/.dylib).\n\
+                  \n";
+
+    format!("{}{}", header, s)
+}
+
+fn line(s: &mut String, key: &str, value: &str) {
+    // Writing to a String can't fail
+    writeln!(s, "{} = \"{}\"", key, value).unwrap();
+}
+
+/// Manifest returned by `rustation_core_info`, built on first use
+static mut MANIFEST: Option<CString> = None;
+
+/// Return the core info manifest as a NUL-terminated string. Not part
+/// of the libretro API, meant to be called from a debugger or a
+/// test harness. The string remains valid until the core is
+/// unloaded.
+#[no_mangle]
+pub extern "C" fn rustation_core_info() -> *const c_char {
+    unsafe {
+        if MANIFEST.is_none() {
+            // The manifest is built from static strings, there can't
+            // be any NUL in there
+            MANIFEST = Some(CString::new(manifest()).unwrap());
+        }
+
+        match MANIFEST {
+            Some(ref m) => m.as_ptr(),
+            None => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_info_file_up_to_date() {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    let manifest = manifest();
+
+    if env::var_os("RUSTATION_UPDATE_INFO").is_some() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"),
+                           "/rustation_libretro.info");
+
+        let mut f = File::create(path).unwrap();
+
+        f.write_all(manifest.as_bytes()).unwrap();
+
+        return;
+    }
+
+    assert!(include_str!("../rustation_libretro.info") == manifest,
+            "rustation_libretro.info is out of date, regenerate it with \
+             RUSTATION_UPDATE_INFO=1 cargo test coreinfo");
+}
@@ -0,0 +1,630 @@
+//! Cue sheet preprocessing done before handing the image over to
+//! `cdimage`.
+//!
+//! Some rips store their audio tracks as WAV or FLAC files referenced
+//! from the cue sheet. `cdimage` only understands raw BINARY tracks so
+//! we decode those files into raw CD-DA tracks in a cache directory
+//! and feed `cdimage` a rewritten cue sheet pointing at them. Each cue
+//! sheet gets its own cache directory since many games use the same
+//! track names.
+//!
+//! Cue sheets made on Windows also often reference their tracks with
+//! the wrong case ("Track01.BIN" for "track01.bin") or with stray
+//! whitespace, which breaks on case-sensitive filesystems. We match
+//! those against the actual files and rewrite the cue sheet if
+//! needed.
+//!
+//! Finally cue sheets from Japanese rips are often encoded in
+//! Shift-JIS, or in UTF-16 when they come from some Windows tools.
+//! We detect the encoding and rewrite those in UTF-8, which is what
+//! `cdimage` expects.
+//!
+//! XXX Track files whose names are themselves stored as raw
+//! Shift-JIS bytes on the filesystem (archives extracted with an old
+//! unzip on Unix) still won't be found, the decoded name is looked up
+//! in UTF-8.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufWriter};
+use std::path::{Path, PathBuf};
+
+use claxon::FlacReader;
+use encoding::{Encoding, DecoderTrap};
+use encoding::all::WINDOWS_31J;
+
+use libretro;
+use error::Error;
+
+/// Inspect the cue sheet at `cue` and return the path of the cue
+/// sheet that should actually be loaded. If no conversion is needed
+/// this is `cue` itself.
+pub fn prepare(cue: &Path) -> Result<PathBuf, Error> {
+    let raw =
+        match read_file(cue) {
+            Ok(s) => s,
+            Err(e) => return Err(Error::Io(cue.to_path_buf(), e)),
+        };
+
+    let (sheet, encoding) = decode_sheet(&raw);
+
+    let cue_dir = cue.parent().unwrap_or(Path::new("."));
+
+    let mut rewritten = String::with_capacity(sheet.len());
+    // `cdimage` can only read UTF-8 cue sheets
+    let mut needs_rewrite = encoding != SheetEncoding::Utf8;
+
+    if needs_rewrite {
+        info!("Cue sheet {:?} is encoded in {:?}", cue, encoding);
+    }
+
+    for line in sheet.lines() {
+        let file =
+            match parse_file_line(line) {
+                Some(f) => f,
+                None => {
+                    rewritten.push_str(line);
+                    rewritten.push('\n');
+                    continue;
+                }
+            };
+
+        let track_path = resolve_track(cue_dir, &file.name);
+
+        if track_path != cue_dir.join(&file.name) {
+            needs_rewrite = true;
+        }
+
+        // The rewritten cue sheet lives in the cache directory so we
+        // use absolute paths for all the tracks
+        let entry =
+            match AudioFormat::from_file(&file) {
+                Some(format) => {
+                    let bin_path =
+                        try!(convert_track(cue, &track_path, format));
+
+                    needs_rewrite = true;
+
+                    format!("FILE \"{}\" BINARY\n",
+                            bin_path.to_string_lossy())
+                }
+                None => {
+                    let track_path =
+                        fs::canonicalize(&track_path).unwrap_or(track_path);
+
+                    format!("FILE \"{}\" {}\n",
+                            track_path.to_string_lossy(),
+                            file.file_type)
+                }
+            };
+
+        rewritten.push_str(&entry);
+    }
+
+    if !needs_rewrite {
+        return Ok(cue.to_path_buf());
+    }
+
+    let cache = try!(cache_directory(cue));
+
+    let name =
+        cue.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image.cue".into());
+
+    let new_cue = cache.join(name);
+
+    let res = File::create(&new_cue)
+        .and_then(|mut f| f.write_all(rewritten.as_bytes()));
+
+    match res {
+        Ok(_) => {
+            info!("Using rewritten cue sheet {:?}", new_cue);
+            Ok(new_cue)
+        }
+        Err(e) => Err(Error::Io(new_cue, e)),
+    }
+}
+
+/// A `FILE` entry in a cue sheet
+struct FileEntry {
+    name: String,
+    file_type: String,
+}
+
+/// Audio container formats we know how to decode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AudioFormat {
+    Wave,
+    Flac,
+}
+
+impl AudioFormat {
+    fn from_file(file: &FileEntry) -> Option<AudioFormat> {
+        let extension =
+            Path::new(&file.name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+
+        match extension.as_ref().map(|e| &e[..]) {
+            Some("wav") => Some(AudioFormat::Wave),
+            Some("flac") => Some(AudioFormat::Flac),
+            // Some cue sheets flag FLAC files as WAVE, so the
+            // extension takes precedence
+            _ if file.file_type == "WAVE" => Some(AudioFormat::Wave),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a `FILE "name" TYPE` line, returns None if `line` is not a
+/// FILE command
+fn parse_file_line(line: &str) -> Option<FileEntry> {
+    let line = line.trim();
+
+    let is_file =
+        line.len() >= 5 &&
+        line.as_bytes()[..5].eq_ignore_ascii_case(b"FILE ");
+
+    if !is_file {
+        return None;
+    }
+
+    let rest = line[5..].trim_left();
+
+    let (name, rest) =
+        if rest.starts_with('"') {
+            let rest = &rest[1..];
+
+            match rest.find('"') {
+                Some(end) => (&rest[..end], &rest[end + 1..]),
+                None => return None,
+            }
+        } else {
+            match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], &rest[end..]),
+                None => (rest, ""),
+            }
+        };
+
+    Some(FileEntry {
+        name: name.into(),
+        file_type: rest.trim().to_uppercase(),
+    })
+}
+
+/// Find the file referenced as `name` in a cue sheet located in
+/// `cue_dir`. If there's no exact match we look for a file whose name
+/// only differs by case or whitespace. If nothing matches the exact
+/// path is returned and the error will be reported when the image is
+/// loaded.
+fn resolve_track(cue_dir: &Path, name: &str) -> PathBuf {
+    // Windows path separators are common in cue sheets
+    let normalized = name.trim().replace('\\', "/");
+
+    let path = cue_dir.join(&normalized);
+
+    if path.exists() {
+        if normalized != name {
+            info!("Cue sheet track {:?} resolved to {:?}", name, path);
+        }
+
+        return path;
+    }
+
+    let dir = path.parent().unwrap_or(cue_dir);
+
+    let file_name =
+        match path.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => return path,
+        };
+
+    let entries =
+        match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Can't read {:?} to look for {:?}: {}", dir, name, e);
+                return path;
+            }
+        };
+
+    let candidates: Vec<PathBuf> =
+        entries.filter_map(|e| e.ok())
+        .filter(|e| names_match(&e.file_name().to_string_lossy(),
+                                &file_name))
+        .map(|e| e.path())
+        .collect();
+
+    match candidates.len() {
+        0 => {
+            warn!("Cue sheet track {:?} not found in {:?}", name, dir);
+            path
+        }
+        1 => {
+            info!("Cue sheet track {:?} matched to {:?}",
+                  name, candidates[0]);
+            candidates[0].clone()
+        }
+        _ => {
+            warn!("Cue sheet track {:?} is ambiguous, candidates: {:?}",
+                  name, candidates);
+            path
+        }
+    }
+}
+
+/// Compare two file names ignoring case and whitespace differences
+fn names_match(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+
+    normalize(a) == normalize(b)
+}
+
+/// Decode the audio track at `path` into a raw 16bit little endian
+/// stereo CD-DA track in the cache directory of `cue` and return its
+/// path.
+fn convert_track(cue: &Path,
+                 path: &Path,
+                 format: AudioFormat) -> Result<PathBuf, Error> {
+    let cache = try!(cache_directory(cue));
+
+    let name =
+        path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "track".into());
+
+    let bin_path = cache.join(format!("{}.bin", name));
+
+    if is_up_to_date(path, &bin_path) {
+        info!("Reusing converted audio track {:?}", bin_path);
+        return Ok(bin_path);
+    }
+
+    info!("Converting {:?} audio track {:?} into {:?}",
+          format, path, bin_path);
+
+    let out =
+        match File::create(&bin_path) {
+            Ok(f) => f,
+            Err(e) => return Err(Error::Io(bin_path, e)),
+        };
+
+    let mut out = BufWriter::new(out);
+
+    let written =
+        match format {
+            AudioFormat::Wave => decode_wave(path, &mut out),
+            AudioFormat::Flac => decode_flac(path, &mut out),
+        };
+
+    let written =
+        match written {
+            Ok(n) => n,
+            Err(e) => {
+                // Don't leave a truncated file behind, it would be
+                // picked up as up-to-date next time
+                let _ = fs::remove_file(&bin_path);
+                return Err(Error::Io(path.to_path_buf(), e));
+            }
+        };
+
+    // Pad the track to a whole number of sectors
+    let padding = (SECTOR_SIZE - written % SECTOR_SIZE) % SECTOR_SIZE;
+
+    let res = out.write_all(&vec![0; padding as usize])
+        .and_then(|_| out.flush());
+
+    if let Err(e) = res {
+        let _ = fs::remove_file(&bin_path);
+        return Err(Error::Io(bin_path, e));
+    }
+
+    Ok(bin_path)
+}
+
+/// Copy the PCM payload of a RIFF WAVE file to `out`. Returns the
+/// number of bytes written.
+fn decode_wave(path: &Path, out: &mut Write) -> io::Result<u64> {
+    let mut file = try!(File::open(path));
+
+    let mut header = [0; 12];
+    try!(file.read_exact(&mut header));
+
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(invalid_data("not a RIFF WAVE file"));
+    }
+
+    let mut format_ok = false;
+
+    loop {
+        let mut chunk = [0; 8];
+        try!(file.read_exact(&mut chunk));
+
+        let len = read_u32_le(&chunk[4..8]) as u64;
+
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut fmt = vec![0; len as usize];
+                try!(file.read_exact(&mut fmt));
+
+                if fmt.len() < 16 {
+                    return Err(invalid_data("truncated fmt chunk"));
+                }
+
+                let format = read_u16_le(&fmt[0..2]);
+                let channels = read_u16_le(&fmt[2..4]);
+                let rate = read_u32_le(&fmt[4..8]);
+                let bits = read_u16_le(&fmt[14..16]);
+
+                try!(check_cdda_format(format == 1, channels as u32,
+                                       rate, bits as u32));
+
+                format_ok = true;
+            }
+            b"data" => {
+                if !format_ok {
+                    return Err(invalid_data("data chunk before fmt chunk"));
+                }
+
+                let mut data = (&mut file).take(len);
+
+                return io::copy(&mut data, out);
+            }
+            _ => {
+                // Skip unknown chunk (chunks are padded to an even
+                // length)
+                let skip = len + (len & 1);
+                try!(io::copy(&mut (&mut file).take(skip), &mut io::sink()));
+            }
+        }
+    }
+}
+
+/// Decode a FLAC file to `out`. Returns the number of bytes written.
+fn decode_flac(path: &Path, out: &mut Write) -> io::Result<u64> {
+    let mut reader =
+        match FlacReader::open(path) {
+            Ok(r) => r,
+            Err(e) => return Err(invalid_data(&format!("{}", e))),
+        };
+
+    {
+        let info = reader.streaminfo();
+
+        try!(check_cdda_format(true, info.channels,
+                               info.sample_rate, info.bits_per_sample));
+    }
+
+    let mut written = 0;
+
+    for sample in reader.samples() {
+        let sample =
+            match sample {
+                Ok(s) => s as i16,
+                Err(e) => return Err(invalid_data(&format!("{}", e))),
+            };
+
+        try!(out.write_all(&[sample as u8, (sample >> 8) as u8]));
+
+        written += 2;
+    }
+
+    Ok(written)
+}
+
+/// CD-DA is always 44.1kHz stereo 16bit PCM. We don't attempt to
+/// resample anything else.
+fn check_cdda_format(pcm: bool,
+                     channels: u32,
+                     rate: u32,
+                     bits: u32) -> io::Result<()> {
+    if pcm && channels == 2 && rate == 44_100 && bits == 16 {
+        Ok(())
+    } else {
+        let msg = format!("unsupported audio format (PCM: {}, {} channels, \
+                           {}Hz, {}bits), only 44.1kHz 16bit stereo PCM \
+                           is supported",
+                          pcm, channels, rate, bits);
+        Err(invalid_data(&msg))
+    }
+}
+
+/// Return the directory where we store the converted tracks and the
+/// rewritten cue sheet for `cue`, creating it if necessary
+fn cache_directory(cue: &Path) -> Result<PathBuf, Error> {
+    let base =
+        match libretro::get_save_directory() {
+            Some(d) => d,
+            None => return Err(Error::NoSaveDirectory("convert audio tracks")),
+        };
+
+    let cue = fs::canonicalize(cue).unwrap_or(cue.to_path_buf());
+
+    let dir = base.join("rustation").join("audio-cache").join(cache_key(&cue));
+
+    match fs::create_dir_all(&dir) {
+        Ok(_) => Ok(dir),
+        Err(e) => Err(Error::Io(dir, e)),
+    }
+}
+
+/// Name of the cache directory of the cue sheet at `cue`, which
+/// should be canonical. The file name is only there to make the
+/// cache easier to browse, the hash of the full path is what keeps
+/// cue sheets with the same name apart.
+fn cache_key(cue: &Path) -> String {
+    let path = cue.to_string_lossy();
+
+    // 64bit FNV-1a, it only needs to be stable across runs
+    let hash =
+        path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash: u64, b| {
+            (hash ^ b as u64).wrapping_mul(0x100_0000_01b3)
+        });
+
+    let stem =
+        cue.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".into());
+
+    format!("{}-{:016x}", stem, hash)
+}
+
+/// Return true if `converted` exists and is newer than `source`
+fn is_up_to_date(source: &Path, converted: &Path) -> bool {
+    let source_time = fs::metadata(source).and_then(|m| m.modified());
+    let converted_time = fs::metadata(converted).and_then(|m| m.modified());
+
+    match (source_time, converted_time) {
+        (Ok(s), Ok(c)) => c >= s,
+        _ => false,
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut v = Vec::new();
+
+    try!(try!(File::open(path)).read_to_end(&mut v));
+
+    Ok(v)
+}
+
+/// Text encodings we recognize in cue sheets
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SheetEncoding {
+    /// UTF-8 without BOM (or plain ASCII)
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    ShiftJis,
+    /// None of the above, invalid sequences have been replaced
+    Unknown,
+}
+
+/// Decode the contents of a cue sheet. UTF-16 is only detected with
+/// a BOM, anything that's not valid UTF-8 is tried as Shift-JIS (in
+/// its Windows code page 932 flavour, what Japanese tools produce).
+fn decode_sheet(raw: &[u8]) -> (String, SheetEncoding) {
+    let utf16 = |bytes: &[u8], big_endian: bool| {
+        let units: Vec<u16> =
+            bytes.chunks(2)
+            .filter(|c| c.len() == 2)
+            .map(|c| {
+                if big_endian {
+                    ((c[0] as u16) << 8) | c[1] as u16
+                } else {
+                    read_u16_le(c)
+                }
+            })
+            .collect();
+
+        String::from_utf16_lossy(&units)
+    };
+
+    if raw.starts_with(b"\xef\xbb\xbf") {
+        let s = String::from_utf8_lossy(&raw[3..]).into_owned();
+
+        return (s, SheetEncoding::Utf8Bom);
+    }
+
+    if raw.starts_with(b"\xff\xfe") {
+        return (utf16(&raw[2..], false), SheetEncoding::Utf16Le);
+    }
+
+    if raw.starts_with(b"\xfe\xff") {
+        return (utf16(&raw[2..], true), SheetEncoding::Utf16Be);
+    }
+
+    if let Ok(s) = ::std::str::from_utf8(raw) {
+        return (s.into(), SheetEncoding::Utf8);
+    }
+
+    match WINDOWS_31J.decode(raw, DecoderTrap::Strict) {
+        Ok(s) => (s, SheetEncoding::ShiftJis),
+        Err(_) => (String::from_utf8_lossy(raw).into_owned(),
+                   SheetEncoding::Unknown),
+    }
+}
+
+fn read_u16_le(b: &[u8]) -> u16 {
+    b[0] as u16 | ((b[1] as u16) << 8)
+}
+
+fn read_u32_le(b: &[u8]) -> u32 {
+    b[0] as u32 |
+    ((b[1] as u32) << 8) |
+    ((b[2] as u32) << 16) |
+    ((b[3] as u32) << 24)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Size of a raw CD sector in bytes
+const SECTOR_SIZE: u64 = 2352;
+
+#[test]
+fn test_parse_file_line() {
+    let f = parse_file_line("FILE \"Track 02.wav\" WAVE").unwrap();
+    assert_eq!(f.name, "Track 02.wav");
+    assert_eq!(f.file_type, "WAVE");
+
+    let f = parse_file_line("  file track01.bin binary").unwrap();
+    assert_eq!(f.name, "track01.bin");
+    assert_eq!(f.file_type, "BINARY");
+
+    assert!(parse_file_line("  TRACK 01 MODE2/2352").is_none());
+    assert!(parse_file_line("FILE \"unterminated.bin BINARY").is_none());
+}
+
+#[test]
+fn test_names_match() {
+    assert!(names_match("track01.bin", "Track01.BIN"));
+    assert!(names_match("Track  02.bin ", "track 02.bin"));
+    assert!(!names_match("track01.bin", "track02.bin"));
+}
+
+#[test]
+fn test_decode_sheet() {
+    let ascii = b"FILE \"track01.bin\" BINARY\n";
+
+    assert_eq!(decode_sheet(ascii),
+               (String::from_utf8_lossy(ascii).into_owned(),
+                SheetEncoding::Utf8));
+
+    // "トラック.bin" in Shift-JIS
+    let sjis = b"FILE \"\x83\x67\x83\x89\x83\x62\x83\x4e.bin\" BINARY";
+
+    assert_eq!(decode_sheet(sjis),
+               ("FILE \"トラック.bin\" BINARY".into(),
+                SheetEncoding::ShiftJis));
+
+    let utf16le = b"\xff\xfeF\0I\0L\0E\0";
+
+    assert_eq!(decode_sheet(utf16le),
+               ("FILE".into(), SheetEncoding::Utf16Le));
+
+    let utf16be = b"\xfe\xff\0F\0I\0L\0E";
+
+    assert_eq!(decode_sheet(utf16be),
+               ("FILE".into(), SheetEncoding::Utf16Be));
+
+    assert_eq!(decode_sheet(b"\xef\xbb\xbfFILE"),
+               ("FILE".into(), SheetEncoding::Utf8Bom));
+}
+
+#[test]
+fn test_cache_key() {
+    let a = cache_key(Path::new("/games/Foo (Disc 1)/game.cue"));
+    let b = cache_key(Path::new("/games/Bar/game.cue"));
+
+    assert!(a.starts_with("game-"));
+    assert!(a != b);
+    assert_eq!(a, cache_key(Path::new("/games/Foo (Disc 1)/game.cue")));
+}
@@ -3,6 +3,16 @@ use rustation::memory::map::mask_region;
 
 /// Called every time the PC changes when BIOS call logging is
 /// enabled
+///
+/// XXX The same detection could be used to run the BIOS's memcpy,
+/// memset and bzero (A(2Ah), A(2Bh) and A(28h)) natively during load
+/// screens, but that needs more than rustation's `Cpu` gives us:
+/// returning to `$ra` is easy with `force_pc` but we can only read
+/// the registers and memory, not write them, so there's no way to do
+/// the copy or to set `$v0`. Native routines would also have to
+/// account for the cycles the emulated ones take to keep savestates
+/// and timings identical with the option off, so this would be
+/// better implemented as a hook in rustation's CPU itself.
 pub fn check_bios_call(cpu: &mut Cpu) {
     let pc = mask_region(cpu.pc());
 
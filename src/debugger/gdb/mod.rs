@@ -5,6 +5,9 @@ use rustation::cpu::Cpu;
 use rustation::memory::{Byte, HalfWord, Word};
 
 use debugger::Debugger;
+use debugger::playback::Playback;
+use debugger::watch::Watch;
+use debugger::marker::MarkerSpec;
 
 use self::reply::Reply;
 
@@ -177,6 +180,7 @@ impl GdbRemote {
                 b's' => self.step(debugger, cpu, args),
                 b'Z' => self.add_breakpoint(debugger, args),
                 b'z' => self.del_breakpoint(debugger, args),
+                b'q' => self.query(debugger, args),
                 // Send empty response for unsupported packets
                 _ => self.send_empty_reply(),
             };
@@ -416,6 +420,175 @@ impl GdbRemote {
         self.send_ok()
     }
 
+    /// General query packet. We only handle `Rcmd` (GDB's `monitor`
+    /// command) for now.
+    fn query(&mut self,
+             debugger: &mut Debugger,
+             args: &[u8]) -> GdbResult {
+
+        if !args.starts_with(b"Rcmd,") {
+            return self.send_empty_reply();
+        }
+
+        let command = try!(parse_hex_string(&args[5..]));
+
+        let command = String::from_utf8_lossy(&command);
+        let mut words = command.split_whitespace();
+
+        match words.next() {
+            // `monitor capture [N]`: dump the next N frames
+            Some("capture") => {
+                let count =
+                    match words.next().map(|n| n.parse::<u32>()) {
+                        None => 1,
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => return self.send_error(),
+                    };
+
+                info!("GDB requested a capture of {} frames", count);
+
+                debugger.request_capture(count);
+
+                self.send_ok()
+            }
+            // `monitor vram`: dump the VRAM to PNG files
+            Some("vram") => {
+                info!("GDB requested a VRAM dump");
+
+                debugger.request_vram_dump();
+
+                self.send_ok()
+            }
+            // `monitor memwatch [N]`: dump the memory watch ranges
+            // for the next N frames
+            Some("memwatch") => {
+                let count =
+                    match words.next().map(|n| n.parse::<u32>()) {
+                        None => 1,
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => return self.send_error(),
+                    };
+
+                info!("GDB requested a memory watch for {} frames", count);
+
+                match debugger.start_memwatch(count) {
+                    Ok(()) => self.send_ok(),
+                    Err(()) => self.send_error(),
+                }
+            }
+            // `monitor input SCRIPT`: play back a button sequence,
+            // see `debugger::playback` for the syntax
+            Some("input") => {
+                let script = words.collect::<Vec<_>>().join(" ");
+
+                match Playback::parse(&script) {
+                    Ok(p) => {
+                        info!("GDB started input playback: {}", script);
+
+                        debugger.start_playback(p);
+
+                        self.send_ok()
+                    }
+                    Err(e) => {
+                        warn!("Invalid input script {:?}: {}", script, e);
+                        self.send_error()
+                    }
+                }
+            }
+            // `monitor frame N`: break at the beginning of frame N
+            // (counted from reset), `monitor frame off` cancels it
+            Some("frame") => {
+                let frame =
+                    match words.next().map(|n| (n, n.parse::<u32>())) {
+                        Some(("off", _)) => None,
+                        Some((_, Ok(n))) => Some(n),
+                        _ => return self.send_error(),
+                    };
+
+                debugger.set_break_frame(frame);
+
+                self.send_ok()
+            }
+            // `monitor watch ADDR SIZE [FORMAT] [PERIOD]`: log a RAM
+            // value at the end of the frame, see `debugger::watch`
+            Some("watch") => {
+                let args: Vec<_> = words.collect();
+
+                match Watch::parse(&args) {
+                    Ok(w) => {
+                        info!("GDB added watch expression {:?}", w);
+
+                        debugger.add_watch(w);
+
+                        self.send_ok()
+                    }
+                    Err(e) => {
+                        warn!("Invalid watch expression {:?}: {}", args, e);
+                        self.send_error()
+                    }
+                }
+            }
+            // `monitor unwatch [ADDR]`: remove the watch expressions
+            // at ADDR, or all of them
+            Some("unwatch") => {
+                let addr =
+                    match words.next().map(|a| u32::from_str_radix(a, 16)) {
+                        None => None,
+                        Some(Ok(a)) => Some(a),
+                        Some(Err(_)) => return self.send_error(),
+                    };
+
+                let removed = debugger.remove_watches(addr);
+
+                info!("GDB removed {} watch expressions", removed);
+
+                self.send_ok()
+            }
+            // `monitor mark X Y W H [RRGGBB]`: outline a rectangle on
+            // top of the display, see `debugger::marker`. `monitor
+            // mark clear` removes them all.
+            Some("mark") => {
+                let args: Vec<_> = words.collect();
+
+                if args == ["clear"] {
+                    info!("GDB cleared the markers");
+                    debugger.clear_markers();
+                    return self.send_ok();
+                }
+
+                match MarkerSpec::parse(&args) {
+                    Ok(m) => {
+                        info!("GDB added marker {:?}", m);
+
+                        match debugger.add_marker(m) {
+                            Ok(()) => self.send_ok(),
+                            Err(()) => {
+                                warn!("Too many markers");
+                                self.send_error()
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Invalid marker {:?}: {}", args, e);
+                        self.send_error()
+                    }
+                }
+            }
+            // XXX A `monitor freeze ADDR VALUE` command (and the
+            // matching core option) forcing RAM locations to a value
+            // after each frame would be handy to experiment with
+            // cheats, but rustation's `Cpu` only lets us `examine`
+            // memory. That's also why we don't implement the `M`
+            // packet. Once there's a side-effect free store we can
+            // keep the frozen locations in the `Debugger` and
+            // rewrite them in `end_of_frame`.
+            _ => {
+                warn!("Unknown GDB monitor command {:?}", command);
+                self.send_error()
+            }
+        }
+    }
+
     // Delete a breakpoint or watchpoint
     fn del_breakpoint(&mut self,
                       debugger: &mut Debugger,
@@ -480,6 +653,16 @@ fn parse_hex(hex: &[u8]) -> Result<u32, ()> {
     Ok(v)
 }
 
+/// Decode a string where each byte is encoded as two hexadecimal
+/// digits
+fn parse_hex_string(hex: &[u8]) -> Result<Vec<u8>, ()> {
+    if hex.len() % 2 != 0 {
+        return Err(());
+    }
+
+    hex.chunks(2).map(|b| parse_hex(b).map(|v| v as u8)).collect()
+}
+
 /// Parse a string in the format `addr,len` (both as hexadecimal
 /// strings) and return the values as a tuple. Returns `None` if
 /// the format is bogus.
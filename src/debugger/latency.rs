@@ -0,0 +1,116 @@
+//! Input latency test. When a button is pressed on the first
+//! controller the frame being rendered is replaced by a white flash
+//! drawn on top of everything else, bypassing the emulation entirely:
+//! filmed alongside the controller it shows the latency of the
+//! frontend, video driver and display alone. The difference with the
+//! time the game takes to react is the latency added by the game and
+//! by the core.
+//!
+//! We also log the number of frames between the libretro input edge
+//! and the first time the game reads the controller data register
+//! after it, that's the part the frontend's late polling and runahead
+//! settings can hide.
+
+/// Address of the JOY_DATA register, with the region masked
+pub const JOY_DATA: u32 = 0x1f80_1040;
+
+pub struct LatencyProbe {
+    /// Number of frames emulated since the probe was created
+    frame: u32,
+    /// True if a button was held during the previous poll
+    held: bool,
+    /// Frame of the last press not yet seen by the game, if any
+    pressed_at: Option<u32>,
+    /// Number of measurements so far
+    count: u32,
+    /// Sum of the measured delays in frames
+    total: u32,
+}
+
+impl LatencyProbe {
+    pub fn new() -> LatencyProbe {
+        LatencyProbe {
+            frame: 0,
+            held: false,
+            pressed_at: None,
+            count: 0,
+            total: 0,
+        }
+    }
+
+    /// Called after the controllers have been polled with `pressed`
+    /// set if any button is pressed on the first controller. Returns
+    /// true if a button has just been pressed, in which case the
+    /// current frame should flash.
+    pub fn input(&mut self, pressed: bool) -> bool {
+        let edge = pressed && !self.held;
+
+        self.held = pressed;
+
+        if edge {
+            self.pressed_at = Some(self.frame);
+        }
+
+        edge
+    }
+
+    /// Called when the game reads the controller data register
+    pub fn pad_read(&mut self) {
+        let delay =
+            match self.measure() {
+                Some(d) => d,
+                None => return,
+            };
+
+        let average = self.total as f32 / self.count as f32;
+
+        info!("Input latency: controller read {} frame(s) after the press \
+               (average {:.2} over {} presses)",
+              delay, average, self.count);
+
+        libretro_message!(100, "Input latency: {} frame(s) (average {:.2})",
+                          delay, average);
+    }
+
+    /// Record the delay between the pending press and the current
+    /// frame, if any
+    fn measure(&mut self) -> Option<u32> {
+        self.pressed_at.take().map(|pressed_at| {
+            let delay = self.frame.wrapping_sub(pressed_at);
+
+            self.count += 1;
+            self.total += delay;
+
+            delay
+        })
+    }
+
+    /// Called once the emulator is done with a frame
+    pub fn end_of_frame(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+#[test]
+fn test_latency_probe() {
+    let mut probe = LatencyProbe::new();
+
+    // Reads without a press aren't measured
+    assert_eq!(probe.measure(), None);
+
+    assert!(probe.input(true));
+    // Held button isn't a new edge
+    probe.end_of_frame();
+    assert!(!probe.input(true));
+    probe.end_of_frame();
+    assert_eq!(probe.measure(), Some(2));
+    // Only the first read after the press counts
+    assert_eq!(probe.measure(), None);
+
+    assert_eq!(probe.count, 1);
+    assert_eq!(probe.total, 2);
+
+    assert!(!probe.input(false));
+    assert!(probe.input(true));
+    assert_eq!(probe.pressed_at, Some(2));
+}
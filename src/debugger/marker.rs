@@ -0,0 +1,176 @@
+//! Markers: rectangles outlined on top of the display to see where
+//! the game thinks things are (hitboxes, trigger zones, the camera
+//! target...). They're drawn by the renderer's overlay pass after
+//! the frame has been rendered, the emulated VRAM and GPU state are
+//! never touched so they can't affect the game.
+//!
+//! They're added from GDB with `monitor mark X Y W H [RRGGBB]`: the
+//! position and size in display pixels and the color in hexadecimal
+//! (red by default). The position can be read from RAM at the end
+//! of each frame by giving `*ADDR` instead of a number, ADDR being
+//! the hexadecimal address of a signed 16bit value, optionally
+//! followed by `+N` or `-N` to add a constant offset. `monitor mark
+//! clear` removes all the markers.
+
+use rustation::cpu::Cpu;
+use rustation::memory::Byte;
+
+use renderer::overlay::Marker;
+
+/// Maximum number of markers, they're meant to highlight a few
+/// objects on screen, not to draw arbitrary scenes
+pub const MAX_MARKERS: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Coordinate {
+    Constant(i32),
+    /// Signed 16bit value at `addr` plus `offset`
+    Ram { addr: u32, offset: i32 },
+}
+
+impl Coordinate {
+    fn parse(s: &str) -> Result<Coordinate, String> {
+        let err = || format!("bad coordinate {}", s);
+
+        if !s.starts_with('*') {
+            return s.parse().map(Coordinate::Constant).map_err(|_| err());
+        }
+
+        let s = &s[1..];
+
+        let (addr, offset) =
+            match s.find(|c: char| c == '+' || c == '-') {
+                Some(i) => {
+                    let offset = &s[i..];
+                    // `i32::from_str` doesn't accept a leading `+`
+                    let offset =
+                        if offset.starts_with('+') {
+                            &offset[1..]
+                        } else {
+                            offset
+                        };
+
+                    (&s[..i], try!(offset.parse().map_err(|_| err())))
+                }
+                None => (s, 0),
+            };
+
+        let addr = try!(u32::from_str_radix(addr, 16).map_err(|_| err()));
+
+        Ok(Coordinate::Ram {
+            addr: addr,
+            offset: offset,
+        })
+    }
+
+    fn resolve<F>(&self, read: &mut F) -> i32
+        where F: FnMut(u32) -> u8 {
+
+        match *self {
+            Coordinate::Constant(c) => c,
+            Coordinate::Ram { addr, offset } => {
+                let lo = read(addr) as u16;
+                let hi = read(addr.wrapping_add(1)) as u16;
+
+                ((hi << 8) | lo) as i16 as i32 + offset
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MarkerSpec {
+    x: Coordinate,
+    y: Coordinate,
+    size: (u32, u32),
+    color: [u8; 3],
+}
+
+impl MarkerSpec {
+    /// Parse the arguments of the `monitor mark` command
+    pub fn parse(args: &[&str]) -> Result<MarkerSpec, String> {
+        if args.len() < 4 || args.len() > 5 {
+            return Err("expected `X Y W H [RRGGBB]`".into());
+        }
+
+        let x = try!(Coordinate::parse(args[0]));
+        let y = try!(Coordinate::parse(args[1]));
+
+        let mut size = [0; 2];
+
+        for (s, &arg) in size.iter_mut().zip(&args[2..4]) {
+            *s =
+                match arg.parse::<u32>() {
+                    Ok(v) if v > 0 && v <= 1024 => v,
+                    _ => return Err(format!("bad size {}", arg)),
+                };
+        }
+
+        let color =
+            match args.get(4) {
+                Some(c) => {
+                    let rgb =
+                        match u32::from_str_radix(c, 16) {
+                            Ok(v) if c.len() == 6 => v,
+                            _ => return Err(format!("bad color {}", c)),
+                        };
+
+                    [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]
+                }
+                None => [0xff, 0, 0],
+            };
+
+        Ok(MarkerSpec {
+            x: x,
+            y: y,
+            size: (size[0], size[1]),
+            color: color,
+        })
+    }
+
+    /// Compute the marker's current position with `read` returning
+    /// the byte at the given address
+    pub fn resolve<F>(&self, mut read: F) -> Marker
+        where F: FnMut(u32) -> u8 {
+
+        Marker {
+            top_left: (self.x.resolve(&mut read), self.y.resolve(&mut read)),
+            size: self.size,
+            color: self.color,
+        }
+    }
+
+    /// Compute the marker's current position from the emulated RAM
+    pub fn resolve_cpu(&self, cpu: &mut Cpu) -> Marker {
+        self.resolve(|addr| cpu.examine::<Byte>(addr) as u8)
+    }
+}
+
+#[test]
+fn test_marker() {
+    // x = -3, y = 0x0120
+    let mem = [0xfd, 0xff, 0x20, 0x01];
+    let read = |a: u32| mem[(a - 0x100) as usize];
+
+    let m = MarkerSpec::parse(&["*100", "*102-16", "8", "4"]).unwrap();
+
+    assert_eq!(m.resolve(&read), Marker {
+        top_left: (-3, 0x120 - 16),
+        size: (8, 4),
+        color: [0xff, 0, 0],
+    });
+
+    let m = MarkerSpec::parse(&["10", "*100+5", "1", "1", "00ff80"])
+        .unwrap();
+
+    assert_eq!(m.resolve(&read), Marker {
+        top_left: (10, 2),
+        size: (1, 1),
+        color: [0, 0xff, 0x80],
+    });
+
+    assert!(MarkerSpec::parse(&["10", "10", "8"]).is_err());
+    assert!(MarkerSpec::parse(&["10", "*zz", "8", "8"]).is_err());
+    assert!(MarkerSpec::parse(&["10", "10", "0", "8"]).is_err());
+    assert!(MarkerSpec::parse(&["10", "10", "8", "8", "red"]).is_err());
+}
@@ -0,0 +1,207 @@
+//! Memory watch: dump labeled RAM ranges to a text file at the end
+//! of each frame for a given number of frames. Meant for cheat
+//! development, the resulting time series can be diffed without
+//! having to attach a full debugger.
+//!
+//! The ranges are read from `rustation/memwatch.txt` in the save
+//! directory, one per line: `label address length` with the address
+//! and length in hexadecimal. Empty lines and lines starting with `#`
+//! are ignored.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufWriter};
+use std::path::Path;
+
+use rustation::cpu::Cpu;
+use rustation::memory::Byte;
+
+use time;
+
+use libretro;
+
+/// Memory range to dump
+struct WatchRange {
+    label: String,
+    addr: u32,
+    len: u32,
+}
+
+/// Memory watch in progress
+pub struct MemWatch {
+    ranges: Vec<WatchRange>,
+    /// Output file
+    out: BufWriter<File>,
+    /// Number of frames left to dump
+    remaining: u32,
+    /// Number of frames dumped so far
+    frame: u32,
+}
+
+impl MemWatch {
+    /// Load the range list and create a new output file for a watch
+    /// lasting `frames` frames
+    pub fn new(frames: u32) -> Result<MemWatch, ()> {
+        let base =
+            match libretro::get_save_directory() {
+                Some(d) => d,
+                None => {
+                    error!("The frontend didn't give us a save directory, \
+                            can't start the memory watch");
+                    return Err(());
+                }
+            };
+
+        let base = base.join("rustation");
+
+        let config_path = base.join("memwatch.txt");
+
+        let ranges =
+            match read_ranges(&config_path) {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("Couldn't load memory watch ranges from {:?}: {}",
+                           config_path, e);
+                    return Err(());
+                }
+            };
+
+        if ranges.is_empty() {
+            error!("No memory watch range in {:?}", config_path);
+            return Err(());
+        }
+
+        let dir = base.join("memwatch");
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            error!("Couldn't create {:?}: {}", dir, e);
+            return Err(());
+        }
+
+        let path = dir.join(format!("memwatch-{}.txt", time::get_time().sec));
+
+        let out =
+            match File::create(&path) {
+                Ok(f) => BufWriter::new(f),
+                Err(e) => {
+                    error!("Couldn't create {:?}: {}", path, e);
+                    return Err(());
+                }
+            };
+
+        info!("Dumping {} memory ranges for {} frames to {:?}",
+              ranges.len(), frames, path);
+
+        Ok(MemWatch {
+            ranges: ranges,
+            out: out,
+            remaining: frames,
+            frame: 0,
+        })
+    }
+
+    /// Dump all the ranges for the current frame. Returns false once
+    /// the watch is over.
+    pub fn snapshot(&mut self, cpu: &mut Cpu) -> bool {
+        if let Err(e) = self.write_snapshot(cpu) {
+            error!("Couldn't write memory watch snapshot: {}", e);
+            return false;
+        }
+
+        self.frame += 1;
+        self.remaining = self.remaining.saturating_sub(1);
+
+        if self.remaining == 0 {
+            info!("Memory watch complete ({} frames)", self.frame);
+            libretro_message!(200, "Memory watch complete");
+
+            false
+        } else {
+            true
+        }
+    }
+
+    fn write_snapshot(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        for range in &self.ranges {
+            try!(write!(self.out,
+                        "{} {} 0x{:08x}:",
+                        self.frame, range.label, range.addr));
+
+            for i in 0..range.len {
+                let b = cpu.examine::<Byte>(range.addr.wrapping_add(i));
+
+                try!(write!(self.out, " {:02x}", b as u8));
+            }
+
+            try!(writeln!(self.out, ""));
+        }
+
+        self.out.flush()
+    }
+}
+
+fn read_ranges(path: &Path) -> io::Result<Vec<WatchRange>> {
+    let mut config = String::new();
+
+    try!(try!(File::open(path)).read_to_string(&mut config));
+
+    parse_ranges(&config)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn parse_ranges(config: &str) -> Result<Vec<WatchRange>, String> {
+    let mut ranges = Vec::new();
+
+    for (n, line) in config.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<_> = line.split_whitespace().collect();
+
+        if fields.len() != 3 {
+            return Err(format!("line {}: expected `label address length`",
+                               n + 1));
+        }
+
+        let addr = try!(parse_hex(fields[1])
+                        .map_err(|_| format!("line {}: bad address", n + 1)));
+        let len = try!(parse_hex(fields[2])
+                       .map_err(|_| format!("line {}: bad length", n + 1)));
+
+        ranges.push(WatchRange {
+            label: fields[0].into(),
+            addr: addr,
+            len: len,
+        });
+    }
+
+    Ok(ranges)
+}
+
+fn parse_hex(s: &str) -> Result<u32, ::std::num::ParseIntError> {
+    let s = s.trim_left_matches("0x");
+
+    u32::from_str_radix(s, 16)
+}
+
+#[test]
+fn test_parse_ranges() {
+    let config = "# Player state\n\
+                  \n\
+                  hp 0x800a1234 2\n\
+                  inventory 800b0000 40\n";
+
+    let ranges = parse_ranges(config).unwrap();
+
+    assert_eq!(ranges.len(), 2);
+    assert_eq!(ranges[0].label, "hp");
+    assert_eq!(ranges[0].addr, 0x800a_1234);
+    assert_eq!(ranges[0].len, 2);
+    assert_eq!(ranges[1].addr, 0x800b_0000);
+    assert_eq!(ranges[1].len, 0x40);
+
+    assert!(parse_ranges("hp 0x800a1234").is_err());
+    assert!(parse_ranges("hp zzz 2").is_err());
+}
@@ -3,11 +3,28 @@ use std::net::TcpListener;
 use rustation::debugger::Debugger as DebuggerInterface;
 use rustation::memory::map::mask_region;
 use rustation::cpu::Cpu;
+use rustation::padmemcard::gamepad::Button;
+
+use savestate::{self, Serialize, Deserialize, Encoder, Decoder};
 
 use self::gdb::GdbRemote;
+use self::memwatch::MemWatch;
+use self::latency::LatencyProbe;
+use self::trigger::Trigger;
+use self::playback::Playback;
+use self::watch::Watch;
+use self::marker::{MarkerSpec, MAX_MARKERS};
+
+use renderer::overlay::Marker;
 
 mod gdb;
 mod bios;
+mod memwatch;
+mod latency;
+mod trigger;
+mod playback;
+mod watch;
+mod marker;
 
 /// Rustation-libretro debugger, based on the GDB remote serial
 /// interface
@@ -29,6 +46,97 @@ pub struct Debugger {
     write_watchpoints: Vec<u32>,
     /// If true we additionally log BIOS calls
     log_bios_calls: bool,
+    /// Number of frames to capture requested by the remote, if any
+    capture_request: Option<u32>,
+    /// Set when the remote requests a VRAM dump
+    vram_dump_request: bool,
+    /// Memory watch in progress, if any
+    memwatch: Option<MemWatch>,
+    /// Input latency probe, if the latency test is enabled
+    latency: Option<LatencyProbe>,
+    /// Memory triggers checked at the end of every frame
+    triggers: Vec<Trigger>,
+    /// Labels of the triggers that fired since the last call to
+    /// `take_fired_triggers`
+    fired_triggers: Vec<String>,
+    /// Input script requested by the remote, if any
+    playback: Option<Playback>,
+    /// Number of CPU instructions executed in the current frame
+    instructions: u32,
+    /// Number of CPU instructions executed in the last complete
+    /// frame
+    last_instructions: u32,
+    /// Number of frames emulated since the console was last reset
+    frame: u32,
+    /// Break when `frame` reaches this value, if set
+    break_frame: Option<u32>,
+    /// Watch expressions logged at the end of the frame
+    watches: Vec<Watch>,
+    /// Rectangles drawn on top of the display
+    markers: Vec<MarkerSpec>,
+}
+
+/// Maximum number of breakpoints (and of each kind of watchpoint)
+/// stored in savestates, this way the savestate size has a known
+/// bound
+const MAX_SAVED_POINTS: usize = 64;
+
+/// Breakpoints and watchpoints, optionally stored in savestates so
+/// that they survive a load
+#[derive(Clone)]
+pub struct DebuggerConfig {
+    breakpoints: Vec<u32>,
+    read_watchpoints: Vec<u32>,
+    write_watchpoints: Vec<u32>,
+}
+
+impl DebuggerConfig {
+    /// Upper bound of the encoded size of an `Option<DebuggerConfig>`
+    /// in a savestate, that is the size of a config with the maximum
+    /// number of points.
+    pub fn max_encoded_len() -> usize {
+        let points = vec![0; MAX_SAVED_POINTS];
+
+        let config = Some(DebuggerConfig {
+            breakpoints: points.clone(),
+            read_watchpoints: points.clone(),
+            write_watchpoints: points,
+        });
+
+        let mut buf = Vec::new();
+
+        {
+            // Writing to a Vec can't fail
+            let mut encoder = Encoder::new(&mut buf).unwrap();
+
+            config.serialize(&mut encoder).unwrap();
+        }
+
+        buf.len() - savestate::MAGIC.len()
+    }
+}
+
+impl Serialize for DebuggerConfig {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), savestate::Error> {
+        try!(e.begin_struct("DebuggerConfig", 1));
+        try!(e.field("breakpoints", &self.breakpoints));
+        try!(e.field("read_watchpoints", &self.read_watchpoints));
+
+        e.field("write_watchpoints", &self.write_watchpoints)
+    }
+}
+
+impl Deserialize for DebuggerConfig {
+    fn deserialize(d: &mut Decoder)
+                   -> Result<DebuggerConfig, savestate::Error> {
+        try!(d.begin_struct("DebuggerConfig", 1));
+
+        Ok(DebuggerConfig {
+            breakpoints: try!(d.field("breakpoints")),
+            read_watchpoints: try!(d.field("read_watchpoints")),
+            write_watchpoints: try!(d.field("write_watchpoints")),
+        })
+    }
 }
 
 impl Debugger {
@@ -53,6 +161,19 @@ impl Debugger {
             read_watchpoints: Vec::new(),
             write_watchpoints: Vec::new(),
             log_bios_calls: false,
+            capture_request: None,
+            vram_dump_request: false,
+            memwatch: None,
+            latency: None,
+            triggers: Vec::new(),
+            fired_triggers: Vec::new(),
+            playback: None,
+            instructions: 0,
+            last_instructions: 0,
+            frame: 0,
+            break_frame: None,
+            watches: Vec::new(),
+            markers: Vec::new(),
         }
     }
 
@@ -60,6 +181,182 @@ impl Debugger {
         self.log_bios_calls = enable;
     }
 
+    /// Enable or disable the input latency test
+    pub fn set_latency_test(&mut self, enable: bool) {
+        if enable != self.latency.is_some() {
+            self.latency =
+                if enable {
+                    Some(LatencyProbe::new())
+                } else {
+                    None
+                };
+        }
+    }
+
+    /// Load the memory triggers from the save directory if `enable`
+    /// is true, remove them otherwise
+    pub fn set_memory_triggers(&mut self, enable: bool) {
+        if enable == !self.triggers.is_empty() {
+            return;
+        }
+
+        self.triggers.clear();
+
+        if enable {
+            if let Ok(triggers) = trigger::load() {
+                for t in triggers {
+                    self.add_trigger(t);
+                }
+            }
+        }
+    }
+
+    /// Add a memory trigger, checked at the end of every frame
+    pub fn add_trigger(&mut self, trigger: Trigger) {
+        self.triggers.push(trigger);
+    }
+
+    /// Return the labels of the triggers that fired since the last
+    /// call
+    pub fn take_fired_triggers(&mut self) -> Vec<String> {
+        ::std::mem::replace(&mut self.fired_triggers, Vec::new())
+    }
+
+    /// Feed the input latency probe with the state of the first
+    /// controller. Returns true if a button has just been pressed and
+    /// the current frame should flash, always false if the latency
+    /// test is disabled.
+    pub fn latency_input(&mut self, pressed: bool) -> bool {
+        match self.latency {
+            Some(ref mut probe) => probe.input(pressed),
+            None => false,
+        }
+    }
+
+    /// Return a copy of the current breakpoints and watchpoints. Only
+    /// the first `MAX_SAVED_POINTS` of each kind are kept.
+    pub fn config(&self) -> DebuggerConfig {
+        let saved = |points: &[u32]| {
+            if points.len() > MAX_SAVED_POINTS {
+                warn!("Only {} of {} debugger points are saved",
+                      MAX_SAVED_POINTS, points.len());
+            }
+
+            points.iter().take(MAX_SAVED_POINTS).cloned().collect()
+        };
+
+        DebuggerConfig {
+            breakpoints: saved(&self.breakpoints),
+            read_watchpoints: saved(&self.read_watchpoints),
+            write_watchpoints: saved(&self.write_watchpoints),
+        }
+    }
+
+    /// Replace the current breakpoints and watchpoints with the ones
+    /// in `config`
+    pub fn load_config(&mut self, config: DebuggerConfig) {
+        info!("Restored {} breakpoints, {} read and {} write watchpoints",
+              config.breakpoints.len(),
+              config.read_watchpoints.len(),
+              config.write_watchpoints.len());
+
+        self.breakpoints = config.breakpoints;
+        self.read_watchpoints = config.read_watchpoints;
+        self.write_watchpoints = config.write_watchpoints;
+    }
+
+    /// Return the number of frames the remote asked us to capture
+    /// (if any) and clear the request
+    pub fn take_capture_request(&mut self) -> Option<u32> {
+        self.capture_request.take()
+    }
+
+    /// Return true if the remote asked for a VRAM dump and clear the
+    /// request
+    pub fn take_vram_dump_request(&mut self) -> bool {
+        ::std::mem::replace(&mut self.vram_dump_request, false)
+    }
+
+    /// Return the buttons the input script holds during the next
+    /// frame, None if there's no script running
+    pub fn playback_frame(&mut self) -> Option<Vec<Button>> {
+        let buttons = self.playback.as_mut().and_then(|p| p.next_frame());
+
+        if buttons.is_none() && self.playback.take().is_some() {
+            info!("Input playback done");
+        }
+
+        buttons
+    }
+
+    /// Called once the emulator is done with a frame
+    pub fn end_of_frame(&mut self, cpu: &mut Cpu) {
+        self.last_instructions = self.instructions;
+        self.instructions = 0;
+
+        self.frame = self.frame.wrapping_add(1);
+
+        if self.break_frame == Some(self.frame) {
+            info!("Reached frame {}, breaking", self.frame);
+            self.set_step();
+        }
+
+        if let Some(ref mut probe) = self.latency {
+            probe.end_of_frame();
+        }
+
+        if let Some(mut memwatch) = self.memwatch.take() {
+            if memwatch.snapshot(cpu) {
+                self.memwatch = Some(memwatch);
+            }
+        }
+
+        for t in &mut self.triggers {
+            if t.check_cpu(cpu) {
+                info!("Memory trigger {} fired", t.label());
+                self.fired_triggers.push(t.label().into());
+            }
+        }
+
+        for w in &mut self.watches {
+            if let Some(v) = w.check_cpu(cpu) {
+                info!("Frame {}: {}", self.frame, v);
+            }
+        }
+    }
+
+    /// Return the number of CPU instructions executed during the
+    /// last complete frame. Unlike cycles it doesn't account for
+    /// memory access timings, but it tells busy frames apart from
+    /// frames spent waiting.
+    pub fn instruction_count(&self) -> u32 {
+        self.last_instructions
+    }
+
+    /// Break into the debugger at the first instruction of `frame`
+    /// (counted from 0 at reset), or never if `frame` is None. The
+    /// break happens again after every reset, that way the same
+    /// input movie can be replayed up to the glitch repeatedly.
+    ///
+    /// XXX The frame count isn't stored in savestates, after a load
+    /// it keeps counting from the current value.
+    pub fn set_break_frame(&mut self, frame: Option<u32>) {
+        match frame {
+            Some(f) => info!("Will break at frame {} (now at frame {})",
+                             f, self.frame),
+            None if self.break_frame.is_some() =>
+                info!("Frame break cancelled"),
+            None => (),
+        }
+
+        self.break_frame = frame;
+    }
+
+    /// Called when the console is reset, restarts the frame count
+    pub fn console_reset(&mut self) {
+        self.frame = 0;
+    }
+
     fn debug(&mut self, cpu: &mut Cpu) {
         // If stepping was requested we can reset the flag here, this
         // way we won't "double step" if we're entering debug mode for
@@ -81,6 +378,17 @@ impl Debugger {
 
         // We loop as long as the remote debugger doesn't tell us to
         // continue
+        //
+        // XXX This blocks the frontend (UI, audio, vsync) for as long
+        // as we're halted since we're called from within
+        // `run_until_next_frame`, deep inside `retro_run`. Servicing
+        // the socket without blocking doesn't help unless we can
+        // return to the frontend between two polls, which means
+        // leaving the CPU loop in the middle of a frame and resuming
+        // it at the same instruction during the next `retro_run`
+        // (presenting the last frame again in the meantime).
+        // rustation's CPU loop can't be interrupted this way and
+        // `Cpu` has no "halted" state we could set from here.
         self.resume = false;
 
         while !self.resume {
@@ -106,6 +414,66 @@ impl Debugger {
         self.step = true;
     }
 
+    /// Request a capture of the next `count` rendered frames
+    fn request_capture(&mut self, count: u32) {
+        self.capture_request = Some(count);
+    }
+
+    /// Request a dump of the VRAM at the end of the frame
+    fn request_vram_dump(&mut self) {
+        self.vram_dump_request = true;
+    }
+
+    /// Start playing back an input script, replacing the current one
+    fn start_playback(&mut self, playback: Playback) {
+        self.playback = Some(playback);
+    }
+
+    fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    /// Add a marker, fails if there are too many of them already
+    fn add_marker(&mut self, marker: MarkerSpec) -> Result<(), ()> {
+        if self.markers.len() >= MAX_MARKERS {
+            return Err(());
+        }
+
+        self.markers.push(marker);
+
+        Ok(())
+    }
+
+    fn clear_markers(&mut self) {
+        self.markers.clear();
+    }
+
+    /// Return the markers to draw on top of the frame that just
+    /// ended, with their positions read from the current RAM
+    pub fn markers(&self, cpu: &mut Cpu) -> Vec<Marker> {
+        self.markers.iter().map(|m| m.resolve_cpu(cpu)).collect()
+    }
+
+    /// Remove the watch expressions at `addr`, or all of them if
+    /// `addr` is None. Returns the number of watches removed.
+    fn remove_watches(&mut self, addr: Option<u32>) -> usize {
+        let before = self.watches.len();
+
+        self.watches.retain(|w| addr.map_or(false, |a| w.addr() != a));
+
+        before - self.watches.len()
+    }
+
+    /// Dump the memory watch ranges at the end of each of the next
+    /// `count` frames
+    fn start_memwatch(&mut self, count: u32) -> Result<(), ()> {
+        let memwatch = try!(MemWatch::new(count));
+
+        self.memwatch = Some(memwatch);
+
+        Ok(())
+    }
+
     /// Add a breakpoint that will trigger when the instruction at
     /// `addr` is about to be executed.
     fn add_breakpoint(&mut self, addr: u32) {
@@ -175,6 +543,8 @@ impl DebuggerInterface for Debugger {
     /// instruction. This function is called before *all* CPU
     /// instructions so it needs to be as fast as possible.
     fn pc_change(&mut self, cpu: &mut Cpu) {
+        self.instructions = self.instructions.wrapping_add(1);
+
         let pc = mask_region(cpu.pc());
 
         if self.log_bios_calls {
@@ -200,6 +570,12 @@ impl DebuggerInterface for Debugger {
             info!("Read watchpoint triggered at 0x{:08x}", addr);
             self.debug(cpu);
         }
+
+        if addr == latency::JOY_DATA {
+            if let Some(ref mut probe) = self.latency {
+                probe.pad_read();
+            }
+        }
     }
 
     /// Called by the CPU when it's about to write a value to memory.
@@ -0,0 +1,122 @@
+//! Scripted input playback, started with `monitor input SCRIPT` from
+//! GDB. Meant to reproduce menu navigation exactly without recording
+//! a full input movie.
+//!
+//! The script is a comma separated list of steps, each one a `+`
+//! separated list of buttons held for the step optionally followed
+//! by `*N` to hold them for N frames (1 by default). `wait` holds no
+//! button. For instance `cross*5, wait*30, start` presses Cross for 5
+//! frames, waits half a second and presses Start for a frame.
+//!
+//! The buttons are pressed on the pad in the console's port 1, on top
+//! of the frontend's input.
+
+use std::collections::VecDeque;
+
+use rustation::padmemcard::gamepad::Button;
+
+/// Button names accepted in scripts
+const BUTTON_NAMES: [(&'static str, Button); 16] =
+    [("up", Button::DUp),
+     ("down", Button::DDown),
+     ("left", Button::DLeft),
+     ("right", Button::DRight),
+     ("start", Button::Start),
+     ("select", Button::Select),
+     ("circle", Button::Circle),
+     ("cross", Button::Cross),
+     ("square", Button::Square),
+     ("triangle", Button::Triangle),
+     ("l1", Button::L1),
+     ("r1", Button::R1),
+     ("l2", Button::L2),
+     ("r2", Button::R2),
+     ("l3", Button::L3),
+     ("r3", Button::R3)];
+
+/// Input script in progress
+pub struct Playback {
+    /// Remaining steps: the buttons held and for how many frames
+    steps: VecDeque<(Vec<Button>, u32)>,
+}
+
+impl Playback {
+    pub fn parse(script: &str) -> Result<Playback, String> {
+        let mut steps = VecDeque::new();
+
+        for step in script.split(',') {
+            let step = step.trim();
+
+            let (buttons, frames) =
+                match step.find('*') {
+                    Some(pos) => {
+                        let frames = &step[pos + 1..];
+
+                        let frames =
+                            match frames.trim().parse::<u32>() {
+                                Ok(n) if n > 0 => n,
+                                _ => return Err(format!("bad frame count \
+                                                         in {:?}", step)),
+                            };
+
+                        (&step[..pos], frames)
+                    }
+                    None => (step, 1),
+                };
+
+            let buttons = try!(parse_buttons(buttons.trim()));
+
+            steps.push_back((buttons, frames));
+        }
+
+        Ok(Playback {
+            steps: steps,
+        })
+    }
+
+    /// Return the buttons held during the next frame, None once the
+    /// script is over
+    pub fn next_frame(&mut self) -> Option<Vec<Button>> {
+        let (buttons, frames) =
+            match self.steps.pop_front() {
+                Some(s) => s,
+                None => return None,
+            };
+
+        if frames > 1 {
+            self.steps.push_front((buttons.clone(), frames - 1));
+        }
+
+        Some(buttons)
+    }
+}
+
+fn parse_buttons(s: &str) -> Result<Vec<Button>, String> {
+    if s == "wait" {
+        return Ok(Vec::new());
+    }
+
+    s.split('+').map(|name| {
+        let name = name.trim().to_lowercase();
+
+        BUTTON_NAMES.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, b)| b)
+            .ok_or_else(|| format!("unknown button {:?}", name))
+    }).collect()
+}
+
+#[test]
+fn test_playback() {
+    let mut p = Playback::parse("cross*2, wait, up+start").unwrap();
+
+    assert!(p.next_frame() == Some(vec![Button::Cross]));
+    assert!(p.next_frame() == Some(vec![Button::Cross]));
+    assert!(p.next_frame() == Some(vec![]));
+    assert!(p.next_frame() == Some(vec![Button::DUp, Button::Start]));
+    assert!(p.next_frame().is_none());
+
+    assert!(Playback::parse("cross*0").is_err());
+    assert!(Playback::parse("cross, jump").is_err());
+    assert!(Playback::parse("").is_err());
+}
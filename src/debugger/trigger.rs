@@ -0,0 +1,236 @@
+//! Memory triggers: conditions on RAM values checked at the end of
+//! each frame. When a condition becomes true the core saves a state
+//! and captures the next frame, which makes it possible to catch rare
+//! bugs (or develop achievements) without watching the game.
+//!
+//! The triggers are read from `rustation/triggers.txt` in the save
+//! directory, one per line: `label address size condition value`
+//! with the address and value in hexadecimal, the size in bytes (1, 2
+//! or 4) and the condition one of `==`, `!=`, `<`, `<=`, `>` and
+//! `>=`. Empty lines and lines starting with `#` are ignored.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use rustation::cpu::Cpu;
+use rustation::memory::Byte;
+
+use libretro;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Condition {
+    fn parse(s: &str) -> Option<Condition> {
+        let c =
+            match s {
+                "==" => Condition::Equal,
+                "!=" => Condition::NotEqual,
+                "<" => Condition::Less,
+                "<=" => Condition::LessOrEqual,
+                ">" => Condition::Greater,
+                ">=" => Condition::GreaterOrEqual,
+                _ => return None,
+            };
+
+        Some(c)
+    }
+
+    fn eval(self, a: u32, b: u32) -> bool {
+        match self {
+            Condition::Equal => a == b,
+            Condition::NotEqual => a != b,
+            Condition::Less => a < b,
+            Condition::LessOrEqual => a <= b,
+            Condition::Greater => a > b,
+            Condition::GreaterOrEqual => a >= b,
+        }
+    }
+}
+
+pub struct Trigger {
+    label: String,
+    addr: u32,
+    /// Size of the value in bytes
+    size: u32,
+    condition: Condition,
+    value: u32,
+    /// Result of the last evaluation, None if the trigger hasn't
+    /// been evaluated yet
+    previous: Option<bool>,
+}
+
+impl Trigger {
+    /// Create a trigger firing when the little endian value of
+    /// `size` bytes at `addr` compared to `value` with `condition`
+    /// becomes true
+    pub fn new(label: String,
+               addr: u32,
+               size: u32,
+               condition: Condition,
+               value: u32) -> Trigger {
+        Trigger {
+            label: label,
+            addr: addr,
+            size: size,
+            condition: condition,
+            value: value,
+            previous: None,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Evaluate the condition with `read` returning the byte at the
+    /// given address. Returns true if the trigger fires: the
+    /// condition is true and it wasn't the last time we checked. The
+    /// first evaluation never fires, it's just the baseline.
+    pub fn check<F>(&mut self, mut read: F) -> bool
+        where F: FnMut(u32) -> u8 {
+
+        let mut v = 0;
+
+        for i in 0..self.size {
+            v |= (read(self.addr.wrapping_add(i)) as u32) << (i * 8);
+        }
+
+        let active = self.condition.eval(v, self.value);
+
+        let fire = active && self.previous == Some(false);
+
+        self.previous = Some(active);
+
+        fire
+    }
+
+    /// Check the trigger against the emulated RAM
+    pub fn check_cpu(&mut self, cpu: &mut Cpu) -> bool {
+        self.check(|addr| cpu.examine::<Byte>(addr) as u8)
+    }
+}
+
+/// Load the triggers from `rustation/triggers.txt` in the save
+/// directory
+pub fn load() -> Result<Vec<Trigger>, ()> {
+    let path =
+        match libretro::get_save_directory() {
+            Some(d) => d.join("rustation").join("triggers.txt"),
+            None => {
+                error!("The frontend didn't give us a save directory, \
+                        can't load memory triggers");
+                return Err(());
+            }
+        };
+
+    match read_triggers(&path) {
+        Ok(t) => {
+            info!("Loaded {} memory triggers from {:?}", t.len(), path);
+            Ok(t)
+        }
+        Err(e) => {
+            error!("Couldn't load memory triggers from {:?}: {}", path, e);
+            Err(())
+        }
+    }
+}
+
+fn read_triggers(path: &Path) -> io::Result<Vec<Trigger>> {
+    let mut config = String::new();
+
+    try!(try!(File::open(path)).read_to_string(&mut config));
+
+    parse_triggers(&config)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn parse_triggers(config: &str) -> Result<Vec<Trigger>, String> {
+    let mut triggers = Vec::new();
+
+    for (n, line) in config.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<_> = line.split_whitespace().collect();
+
+        if fields.len() != 5 {
+            return Err(format!("line {}: expected \
+                                `label address size condition value`",
+                               n + 1));
+        }
+
+        let bad = |what| format!("line {}: bad {}", n + 1, what);
+
+        let addr = try!(parse_hex(fields[1]).map_err(|_| bad("address")));
+
+        let size =
+            match fields[2] {
+                "1" => 1,
+                "2" => 2,
+                "4" => 4,
+                _ => return Err(bad("size")),
+            };
+
+        let condition =
+            match Condition::parse(fields[3]) {
+                Some(c) => c,
+                None => return Err(bad("condition")),
+            };
+
+        let value = try!(parse_hex(fields[4]).map_err(|_| bad("value")));
+
+        triggers.push(Trigger::new(fields[0].into(),
+                                   addr,
+                                   size,
+                                   condition,
+                                   value));
+    }
+
+    Ok(triggers)
+}
+
+fn parse_hex(s: &str) -> Result<u32, ::std::num::ParseIntError> {
+    let s = s.trim_left_matches("0x");
+
+    u32::from_str_radix(s, 16)
+}
+
+#[test]
+fn test_triggers() {
+    let config = "# Boss defeated\n\
+                  \n\
+                  boss 0x800a1234 2 == 0\n\
+                  level 800b0000 1 >= 3\n";
+
+    let mut triggers = parse_triggers(config).unwrap();
+
+    assert_eq!(triggers.len(), 2);
+    assert_eq!(triggers[0].label(), "boss");
+    assert_eq!(triggers[0].addr, 0x800a_1234);
+    assert_eq!(triggers[1].condition, Condition::GreaterOrEqual);
+
+    assert!(parse_triggers("boss 800a1234 3 == 0").is_err());
+    assert!(parse_triggers("boss 800a1234 2 =! 0").is_err());
+
+    let boss = &mut triggers[0];
+
+    // Baseline, the condition is true but doesn't fire
+    assert!(!boss.check(|_| 0));
+    assert!(!boss.check(|a| if a == 0x800a_1235 { 1 } else { 0 }));
+    // 0x0100 -> 0x0000
+    assert!(boss.check(|_| 0));
+    // Still true, only fires once
+    assert!(!boss.check(|_| 0));
+}
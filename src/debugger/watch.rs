@@ -0,0 +1,176 @@
+//! Watch expressions: RAM values logged at the end of the frame,
+//! either periodically or whenever they change. Unlike watchpoints
+//! they never halt the emulation, so they don't disturb the timings
+//! of the code being traced.
+//!
+//! They're added from GDB with `monitor watch ADDR SIZE [FORMAT]
+//! [PERIOD]`: the address in hexadecimal, the size in bytes (1, 2 or
+//! 4), the format one of `hex` (default), `dec` and `signed`, and the
+//! period either `change` (default) to log the value when it changes
+//! or a number of frames. `monitor unwatch [ADDR]` removes the watch
+//! expressions at `ADDR`, or all of them.
+
+use rustation::cpu::Cpu;
+use rustation::memory::Byte;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Hex,
+    Unsigned,
+    Signed,
+}
+
+#[derive(Debug)]
+pub struct Watch {
+    addr: u32,
+    /// Size of the value in bytes
+    size: u32,
+    format: Format,
+    /// Log the value every `period` frames, or only when it changes
+    /// if None
+    period: Option<u32>,
+    /// Value logged last, None if it hasn't been logged yet
+    previous: Option<u32>,
+    /// Number of frames until the next periodic log
+    countdown: u32,
+}
+
+impl Watch {
+    /// Parse the arguments of the `monitor watch` command
+    pub fn parse(args: &[&str]) -> Result<Watch, String> {
+        if args.len() < 2 || args.len() > 4 {
+            return Err("expected `ADDR SIZE [FORMAT] [PERIOD]`".into());
+        }
+
+        let addr =
+            match u32::from_str_radix(args[0], 16) {
+                Ok(a) => a,
+                Err(_) => return Err(format!("bad address {}", args[0])),
+            };
+
+        let size =
+            match args[1] {
+                "1" => 1,
+                "2" => 2,
+                "4" => 4,
+                s => return Err(format!("bad size {}", s)),
+            };
+
+        let mut format = Format::Hex;
+        let mut period = None;
+
+        for &arg in &args[2..] {
+            match arg {
+                "hex" => format = Format::Hex,
+                "dec" => format = Format::Unsigned,
+                "signed" => format = Format::Signed,
+                "change" => period = None,
+                n => match n.parse::<u32>() {
+                    Ok(n) if n > 0 => period = Some(n),
+                    _ => return Err(format!("bad format or period {}", n)),
+                },
+            }
+        }
+
+        Ok(Watch {
+            addr: addr,
+            size: size,
+            format: format,
+            period: period,
+            previous: None,
+            countdown: 0,
+        })
+    }
+
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+
+    /// Read the value with `read` returning the byte at the given
+    /// address. Returns the formatted value if it must be logged this
+    /// frame.
+    pub fn check<F>(&mut self, mut read: F) -> Option<String>
+        where F: FnMut(u32) -> u8 {
+
+        let mut v = 0;
+
+        for i in 0..self.size {
+            v |= (read(self.addr.wrapping_add(i)) as u32) << (i * 8);
+        }
+
+        let log =
+            match self.period {
+                Some(period) => {
+                    let log = self.countdown == 0;
+
+                    self.countdown =
+                        if log {
+                            period - 1
+                        } else {
+                            self.countdown - 1
+                        };
+
+                    log
+                }
+                None => self.previous != Some(v),
+            };
+
+        self.previous = Some(v);
+
+        if log {
+            Some(self.format(v))
+        } else {
+            None
+        }
+    }
+
+    /// Check the watch against the emulated RAM
+    pub fn check_cpu(&mut self, cpu: &mut Cpu) -> Option<String> {
+        self.check(|addr| cpu.examine::<Byte>(addr) as u8)
+    }
+
+    fn format(&self, v: u32) -> String {
+        let value =
+            match self.format {
+                Format::Hex =>
+                    format!("0x{:01$x}", v, self.size as usize * 2),
+                Format::Unsigned => format!("{}", v),
+                Format::Signed => {
+                    // Sign-extend to 32 bits
+                    let shift = 32 - self.size * 8;
+
+                    format!("{}", ((v << shift) as i32) >> shift)
+                }
+            };
+
+        format!("[0x{:08x}] = {}", self.addr, value)
+    }
+}
+
+#[test]
+fn test_watch() {
+    let mem = [0xfe, 0xff, 0x00, 0x00];
+
+    let mut w = Watch::parse(&["10", "2", "signed"]).unwrap();
+
+    assert_eq!(w.check(|a| mem[(a - 0x10) as usize]),
+               Some("[0x00000010] = -2".into()));
+    // Only logged on change
+    assert_eq!(w.check(|a| mem[(a - 0x10) as usize]), None);
+    assert_eq!(w.check(|_| 0), Some("[0x00000010] = 0".into()));
+
+    let mut w = Watch::parse(&["10", "4", "3"]).unwrap();
+
+    let logged: Vec<_> =
+        (0..6).map(|_| w.check(|a| mem[(a - 0x10) as usize])).collect();
+
+    assert_eq!(logged[0], Some("[0x00000010] = 0x0000fffe".into()));
+    assert_eq!(logged[1], None);
+    assert_eq!(logged[2], None);
+    assert!(logged[3].is_some());
+
+    assert!(Watch::parse(&["10"]).is_err());
+    assert!(Watch::parse(&["10", "3"]).is_err());
+    assert!(Watch::parse(&["10", "1", "0"]).is_err());
+    assert!(Watch::parse(&["zz", "1"]).is_err());
+}
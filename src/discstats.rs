@@ -0,0 +1,236 @@
+//! Disc read statistics, to investigate games that are sensitive to
+//! the disc layout or to the seek timings. The image is wrapped so
+//! that every sector read by the emulated CD-ROM drive is accounted
+//! to its track. Reads that don't follow the previous one are
+//! counted as seeks, along with the distance covered.
+//!
+//! A summary is logged and written to `rustation/disc-stats/` in the
+//! save directory when the game is unloaded.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use cdimage::{Image, Toc, CdResult};
+use cdimage::msf::Msf;
+use cdimage::sector::Sector;
+
+use time;
+
+use libretro;
+
+/// Statistics for a single track
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+struct TrackStats {
+    reads: u64,
+    errors: u64,
+    seeks: u64,
+    /// Total number of sectors covered by the seeks
+    seek_distance: u64,
+    longest_seek: u32,
+}
+
+pub struct ReadStats {
+    /// First sector and number of each track, in disc order
+    track_starts: Vec<(u32, u8)>,
+    /// Statistics indexed by track number. Index 0 holds the reads
+    /// outside of any track (lead-in).
+    tracks: Vec<TrackStats>,
+    /// Sector read last
+    last_sector: Option<u32>,
+}
+
+impl ReadStats {
+    fn new(track_starts: Vec<(u32, u8)>) -> ReadStats {
+        let ntracks =
+            track_starts.iter().map(|&(_, t)| t as usize).max().unwrap_or(0);
+
+        ReadStats {
+            track_starts: track_starts,
+            tracks: vec![TrackStats::default(); ntracks + 1],
+            last_sector: None,
+        }
+    }
+
+    /// Account for a read of `sector`, `ok` is false if it failed
+    fn record(&mut self, sector: u32, ok: bool) {
+        let track =
+            self.track_starts.iter()
+            .filter(|&&(start, _)| start <= sector)
+            .last()
+            .map(|&(_, t)| t as usize)
+            .unwrap_or(0);
+
+        let seek =
+            match self.last_sector {
+                Some(last) if last.wrapping_add(1) == sector => None,
+                Some(last) => Some((last as i64 - sector as i64).abs()),
+                None => None,
+            };
+
+        self.last_sector = Some(sector);
+
+        let stats = &mut self.tracks[track];
+
+        stats.reads += 1;
+
+        if !ok {
+            stats.errors += 1;
+        }
+
+        if let Some(distance) = seek {
+            debug!("Disc seek to sector {} (track {}, {} sectors)",
+                   sector, track, distance);
+
+            stats.seeks += 1;
+            stats.seek_distance += distance as u64;
+            stats.longest_seek =
+                ::std::cmp::max(stats.longest_seek, distance as u32);
+        }
+    }
+
+    /// Return a human-readable summary, one line per track read
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+
+        for (track, stats) in self.tracks.iter().enumerate() {
+            if stats.reads == 0 {
+                continue;
+            }
+
+            let name =
+                if track == 0 {
+                    "Lead-in".into()
+                } else {
+                    format!("Track {:02}", track)
+                };
+
+            let average_seek =
+                if stats.seeks > 0 {
+                    stats.seek_distance / stats.seeks
+                } else {
+                    0
+                };
+
+            summary.push_str(&format!("{}: {} reads, {} errors, {} seeks \
+                                       (longest {} sectors, average {})\n",
+                                      name,
+                                      stats.reads,
+                                      stats.errors,
+                                      stats.seeks,
+                                      stats.longest_seek,
+                                      average_seek));
+        }
+
+        if summary.is_empty() {
+            summary.push_str("No sector read\n");
+        }
+
+        summary
+    }
+
+    /// Log the summary and write it to the save directory, `id`
+    /// identifies the disc
+    pub fn dump(&self, id: &str) {
+        let summary = self.summary();
+
+        info!("Disc read statistics:\n{}", summary);
+
+        let dir =
+            match libretro::get_save_directory() {
+                Some(d) => d.join("rustation").join("disc-stats"),
+                None => return,
+            };
+
+        let path = dir.join(format!("{}-{}.txt", id, time::get_time().sec));
+
+        let res = fs::create_dir_all(&dir)
+            .and_then(|_| File::create(&path))
+            .and_then(|mut f| f.write_all(summary.as_bytes()));
+
+        match res {
+            Ok(_) => info!("Disc read statistics written to {:?}", path),
+            Err(e) => error!("Couldn't write {:?}: {}", path, e),
+        }
+    }
+}
+
+/// Image wrapper feeding the statistics
+pub struct StatsImage {
+    image: Box<Image>,
+    stats: Arc<Mutex<ReadStats>>,
+}
+
+impl StatsImage {
+    /// Wrap `image`, the returned handle gives access to the
+    /// statistics once the image has been handed over to the
+    /// emulator
+    pub fn new(image: Box<Image>) -> (StatsImage, Arc<Mutex<ReadStats>>) {
+        let starts =
+            image.toc().tracks().iter()
+            .map(|t| (t.start.sector_index(), t.track.binary()))
+            .collect();
+
+        let stats = Arc::new(Mutex::new(ReadStats::new(starts)));
+
+        let wrapper = StatsImage {
+            image: image,
+            stats: stats.clone(),
+        };
+
+        (wrapper, stats)
+    }
+}
+
+impl Image for StatsImage {
+    fn image_format(&self) -> String {
+        self.image.image_format()
+    }
+
+    fn read_sector(&mut self, sector: &mut Sector, msf: Msf) -> CdResult<()> {
+        let res = self.image.read_sector(sector, msf);
+
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.record(msf.sector_index(), res.is_ok());
+        }
+
+        res
+    }
+
+    fn toc(&self) -> &Toc {
+        self.image.toc()
+    }
+}
+
+#[test]
+fn test_read_stats() {
+    let mut stats = ReadStats::new(vec![(150, 1), (1000, 2)]);
+
+    // Sequential reads on track 1
+    for s in 150..160 {
+        stats.record(s, true);
+    }
+
+    // Seek to track 2 and back
+    stats.record(2000, false);
+    stats.record(2001, true);
+    stats.record(500, true);
+
+    assert_eq!(stats.tracks[1], TrackStats {
+        reads: 11,
+        errors: 0,
+        seeks: 1,
+        seek_distance: 1501,
+        longest_seek: 1501,
+    });
+
+    assert_eq!(stats.tracks[2], TrackStats {
+        reads: 2,
+        errors: 1,
+        seeks: 1,
+        seek_distance: 1841,
+        longest_seek: 1841,
+    });
+
+    assert_eq!(stats.summary().lines().count(), 2);
+}
@@ -0,0 +1,88 @@
+//! Errors encountered while loading content or savestates. They're
+//! propagated up to the libretro entry points where they're logged
+//! and displayed to the user, so the messages should tell them what
+//! to do about it.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use l10n;
+
+pub enum Error {
+    /// The frontend didn't give us a system directory, we have
+    /// nowhere to look for a BIOS
+    NoSystemDirectory,
+    /// The frontend didn't give us a save directory, needed for the
+    /// given operation
+    NoSaveDirectory(&'static str),
+    /// No BIOS matching the description was found in the directory
+    BiosNotFound(PathBuf, String),
+    /// The BIOS couldn't be patched for the given feature
+    BiosPatch(&'static str),
+    /// I/O error while accessing a file or directory
+    Io(PathBuf, io::Error),
+    /// Invalid or corrupt executable
+    BadExe(PathBuf, String),
+    /// Invalid or corrupt homebrew package
+    BadPackage(PathBuf, String),
+    /// Invalid or corrupt disc image
+    BadDisc(PathBuf, String),
+    /// Couldn't setup the OpenGL renderer
+    GlInit(&'static str),
+    /// Couldn't encode or decode a savestate
+    Savestate(String),
+}
+
+impl Error {
+    /// Log the error and display it on screen
+    pub fn report(&self) {
+        error!("{}", self);
+
+        match *self {
+            Error::BiosNotFound(ref dir, ref wanted) =>
+                l10n::message(300,
+                              l10n::Text::BiosNotFound,
+                              &[wanted, &dir.display()]),
+            _ => libretro_message!(300, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoSystemDirectory =>
+                write!(f, "The frontend didn't provide a system directory, \
+                           set RUSTATION_BIOS_PATH to the BIOS location"),
+            Error::NoSaveDirectory(what) =>
+                write!(f, "The frontend didn't provide a save directory, \
+                           can't {}", what),
+            Error::BiosNotFound(ref dir, ref wanted) =>
+                write!(f, "No BIOS {} found, copy a PlayStation BIOS dump \
+                           to {}", wanted, dir.display()),
+            Error::BiosPatch(what) =>
+                write!(f, "This BIOS can't be patched for {}, try an \
+                           other one", what),
+            Error::Io(ref path, ref e) =>
+                write!(f, "Can't access {}: {}", path.display(), e),
+            Error::BadExe(ref path, ref e) =>
+                write!(f, "Invalid executable {}: {}", path.display(), e),
+            Error::BadPackage(ref path, ref e) =>
+                write!(f, "Invalid homebrew package {}: {}",
+                       path.display(), e),
+            Error::BadDisc(ref path, ref e) =>
+                write!(f, "Invalid disc image {}: {}", path.display(), e),
+            Error::GlInit(e) =>
+                write!(f, "Couldn't setup OpenGL rendering: {}", e),
+            Error::Savestate(ref e) =>
+                write!(f, "Savestate error: {}", e),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
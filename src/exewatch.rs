@@ -0,0 +1,107 @@
+//! Developer mode for homebrew: the loaded executable is watched and
+//! the console is reset with the new version whenever it's rebuilt,
+//! for a quick edit-compile-run loop without going through the
+//! frontend's menus.
+//!
+//! The file is polled a couple of times per second, there's no
+//! portable way to get notified of file changes with the standard
+//! library. A change is only acted upon once the modification time
+//! has been stable for a whole poll period, that way we don't load
+//! an executable the linker is still writing.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Number of frames between two polls
+const POLL_PERIOD: u32 = 30;
+
+pub struct ExeWatch {
+    path: PathBuf,
+    /// Modification time of the loaded executable
+    loaded: Option<SystemTime>,
+    /// New modification time seen at the previous poll, if any
+    changed: Option<SystemTime>,
+    /// Number of frames until the next poll
+    countdown: u32,
+}
+
+impl ExeWatch {
+    /// Watch the executable at `path`, which has just been loaded
+    pub fn new(path: &Path) -> ExeWatch {
+        info!("Watching {:?} for changes", path);
+
+        ExeWatch {
+            path: path.to_path_buf(),
+            loaded: modified(path),
+            changed: None,
+            countdown: POLL_PERIOD,
+        }
+    }
+
+    /// Called once per frame, returns true when the executable has
+    /// been rebuilt and must be reloaded
+    pub fn frame(&mut self) -> bool {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+
+        self.countdown = POLL_PERIOD;
+
+        let mtime = modified(&self.path);
+
+        self.update(mtime)
+    }
+
+    /// Handle the modification time `mtime` read during a poll, None
+    /// if the file couldn't be accessed (the build might have
+    /// deleted it)
+    fn update(&mut self, mtime: Option<SystemTime>) -> bool {
+        if mtime.is_none() || mtime == self.loaded {
+            self.changed = None;
+            return false;
+        }
+
+        if mtime == self.changed {
+            // Stable since the previous poll
+            self.loaded = mtime;
+            self.changed = None;
+            true
+        } else {
+            self.changed = mtime;
+            false
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|md| md.modified()).ok()
+}
+
+#[test]
+fn test_exe_watch() {
+    use std::time::Duration;
+
+    let t0 = SystemTime::now();
+    let t1 = t0 + Duration::from_secs(1);
+    let t2 = t0 + Duration::from_secs(2);
+
+    let mut watch = ExeWatch {
+        path: PathBuf::new(),
+        loaded: Some(t0),
+        changed: None,
+        countdown: 0,
+    };
+
+    assert!(!watch.update(Some(t0)));
+    // The file must be stable for a poll period
+    assert!(!watch.update(Some(t1)));
+    assert!(!watch.update(Some(t2)));
+    assert!(watch.update(Some(t2)));
+    assert!(!watch.update(Some(t2)));
+    // Deleted file, wait for the new one
+    assert!(!watch.update(None));
+    assert!(!watch.update(Some(t1)));
+    assert!(watch.update(Some(t1)));
+}
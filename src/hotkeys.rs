@@ -0,0 +1,203 @@
+//! Keyboard hotkeys. Each action can be bound to a key combo such as
+//! "ctrl+f5" through the core options, the keyboard is polled once
+//! per frame and the actions whose combo has just been pressed are
+//! returned to the caller.
+
+use libretro::{self, Key};
+
+/// Actions that can be bound to a hotkey
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    DebugBreak,
+    Reset,
+    ToggleTray,
+    QuickSave,
+    QuickLoad,
+    DumpVram,
+    TimeSummary,
+    NativeScreenshot,
+}
+
+/// Modifier bitflags
+const CTRL: u8 = 1 << 0;
+const SHIFT: u8 = 1 << 1;
+const ALT: u8 = 1 << 2;
+
+/// A key pressed while holding a (possibly empty) set of modifiers
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Combo {
+    modifiers: u8,
+    key: Key,
+}
+
+impl Combo {
+    fn is_held(&self) -> bool {
+        // The modifiers must match exactly, otherwise "f5" would
+        // trigger when "ctrl+f5" is pressed
+        libretro::key_pressed(0, self.key) &&
+            held_modifiers() == self.modifiers
+    }
+}
+
+fn held_modifiers() -> u8 {
+    let pressed = |a, b| {
+        libretro::key_pressed(0, a) || libretro::key_pressed(0, b)
+    };
+
+    let mut modifiers = 0;
+
+    if pressed(Key::LCtrl, Key::RCtrl) {
+        modifiers |= CTRL;
+    }
+
+    if pressed(Key::LShift, Key::RShift) {
+        modifiers |= SHIFT;
+    }
+
+    if pressed(Key::LAlt, Key::RAlt) {
+        modifiers |= ALT;
+    }
+
+    modifiers
+}
+
+struct Binding {
+    combo: Combo,
+    action: Action,
+    /// True if the combo was held during the previous poll, used to
+    /// trigger the action only once per press
+    held: bool,
+}
+
+pub struct Hotkeys {
+    bindings: Vec<Binding>,
+}
+
+impl Hotkeys {
+    pub fn new() -> Hotkeys {
+        Hotkeys {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Bind `action` to `combo`, replacing any previous binding for
+    /// this action. If `combo` is None the action is unbound.
+    pub fn bind(&mut self, action: Action, combo: Option<Combo>) {
+        let current =
+            self.bindings.iter().position(|b| b.action == action);
+
+        match (current, combo) {
+            (Some(i), Some(combo)) => {
+                if self.bindings[i].combo != combo {
+                    self.bindings[i].combo = combo;
+                    self.bindings[i].held = true;
+                }
+            }
+            (Some(i), None) => {
+                self.bindings.remove(i);
+            }
+            (None, Some(combo)) => {
+                self.bindings.push(Binding {
+                    combo: combo,
+                    action: action,
+                    // Wait for the combo to be released before
+                    // triggering the action, otherwise changing the
+                    // binding while holding the keys would fire it
+                    held: true,
+                });
+            }
+            (None, None) => (),
+        }
+    }
+
+    /// Poll the keyboard and return the actions whose combo has
+    /// just been pressed
+    pub fn poll(&mut self) -> Vec<Action> {
+        let mut triggered = Vec::new();
+
+        for binding in &mut self.bindings {
+            let held = binding.combo.is_held();
+
+            if held && !binding.held {
+                triggered.push(binding.action);
+            }
+
+            binding.held = held;
+        }
+
+        triggered
+    }
+}
+
+/// Key names used in the core options
+const KEY_NAMES: &'static [(&'static str, Key)] = &[
+    ("a", Key::A), ("b", Key::B), ("c", Key::C), ("d", Key::D),
+    ("e", Key::E), ("f", Key::F), ("g", Key::G), ("h", Key::H),
+    ("i", Key::I), ("j", Key::J), ("k", Key::K), ("l", Key::L),
+    ("m", Key::M), ("n", Key::N), ("o", Key::O), ("p", Key::P),
+    ("q", Key::Q), ("r", Key::R), ("s", Key::S), ("t", Key::T),
+    ("u", Key::U), ("v", Key::V), ("w", Key::W), ("x", Key::X),
+    ("y", Key::Y), ("z", Key::Z),
+    ("0", Key::Num0), ("1", Key::Num1), ("2", Key::Num2),
+    ("3", Key::Num3), ("4", Key::Num4), ("5", Key::Num5),
+    ("6", Key::Num6), ("7", Key::Num7), ("8", Key::Num8),
+    ("9", Key::Num9),
+    ("f1", Key::F1), ("f2", Key::F2), ("f3", Key::F3), ("f4", Key::F4),
+    ("f5", Key::F5), ("f6", Key::F6), ("f7", Key::F7), ("f8", Key::F8),
+    ("f9", Key::F9), ("f10", Key::F10), ("f11", Key::F11),
+    ("f12", Key::F12),
+    ("pause", Key::Pause), ("print", Key::Print),
+    ("scrolllock", Key::ScrolLock), ("escape", Key::Escape),
+    ("tab", Key::Tab), ("space", Key::Space),
+    ("backspace", Key::Backspace), ("return", Key::Return),
+    ("insert", Key::Insert), ("delete", Key::Delete),
+    ("home", Key::Home), ("end", Key::End),
+    ("pageup", Key::PageUp), ("pagedown", Key::PageDown),
+];
+
+/// Parse a hotkey core option such as "ctrl+shift+f5" or "disabled"
+pub fn parse_combo(opt: &str) -> Result<Option<Combo>, ()> {
+    if opt == "disabled" {
+        return Ok(None);
+    }
+
+    let mut parts: Vec<&str> = opt.split('+').collect();
+
+    // `split` always returns at least one element
+    let key_name = parts.pop().unwrap();
+
+    let key =
+        match KEY_NAMES.iter().find(|&&(name, _)| name == key_name) {
+            Some(&(_, key)) => key,
+            None => return Err(()),
+        };
+
+    let mut modifiers = 0;
+
+    for part in parts {
+        modifiers |=
+            match part {
+                "ctrl" => CTRL,
+                "shift" => SHIFT,
+                "alt" => ALT,
+                _ => return Err(()),
+            };
+    }
+
+    Ok(Some(Combo {
+        modifiers: modifiers,
+        key: key,
+    }))
+}
+
+#[test]
+fn test_parse_combo() {
+    assert_eq!(parse_combo("disabled"), Ok(None));
+    assert_eq!(parse_combo("pause"),
+               Ok(Some(Combo { modifiers: 0, key: Key::Pause })));
+    assert_eq!(parse_combo("ctrl+shift+f5"),
+               Ok(Some(Combo { modifiers: CTRL | SHIFT, key: Key::F5 })));
+    assert!(parse_combo("ctrl+").is_err());
+    assert!(parse_combo("meta+f5").is_err());
+    assert!(parse_combo("f13").is_err());
+}
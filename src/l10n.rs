@@ -0,0 +1,212 @@
+//! Translations of the most common on-screen messages in the
+//! frontend's language. The logs stay in English.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use libretro;
+
+/// Languages we have translations for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Language {
+    English = 0,
+    French = 1,
+    Spanish = 2,
+    German = 3,
+    Italian = 4,
+    Portuguese = 5,
+}
+
+impl Language {
+    /// Convert a libretro `retro_language` value, None if we don't
+    /// have a translation for it
+    fn from_retro(lang: u32) -> Option<Language> {
+        let l =
+            match lang {
+                0 => Language::English,
+                2 => Language::French,
+                3 => Language::Spanish,
+                4 => Language::German,
+                5 => Language::Italian,
+                // Brazilian and European Portuguese
+                7 | 8 => Language::Portuguese,
+                _ => return None,
+            };
+
+        Some(l)
+    }
+
+    fn from_usize(v: usize) -> Language {
+        match v {
+            1 => Language::French,
+            2 => Language::Spanish,
+            3 => Language::German,
+            4 => Language::Italian,
+            5 => Language::Portuguese,
+            _ => Language::English,
+        }
+    }
+}
+
+/// Current language, stored as a `Language` converted to usize
+static LANGUAGE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Query the frontend's language
+pub fn init() {
+    let language =
+        match libretro::get_language() {
+            Some(l) => l,
+            None => return,
+        };
+
+    match Language::from_retro(language) {
+        Some(l) => {
+            info!("Using {:?} messages", l);
+            LANGUAGE.store(l as usize, Ordering::Relaxed);
+        }
+        None => info!("No translation for language {}, using English",
+                      language),
+    }
+}
+
+fn language() -> Language {
+    Language::from_usize(LANGUAGE.load(Ordering::Relaxed))
+}
+
+/// Translated messages
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Text {
+    /// Parameters: BIOS description, system directory
+    BiosNotFound,
+    /// Parameter: slot number
+    StateSaved,
+    /// Parameter: slot number
+    StateLoaded,
+    /// Parameter: error description (in English)
+    StateLoadFailed,
+    TrayOpened,
+    TrayClosed,
+    /// Parameters: BIOS region, content region
+    RegionMismatch,
+}
+
+impl Text {
+    /// Return the message template in `lang`, each `{}` is replaced
+    /// by a parameter
+    fn template(self, lang: Language) -> &'static str {
+        let translations =
+            match self {
+                Text::BiosNotFound => [
+                    "No BIOS {} found, copy a PlayStation BIOS dump to {}",
+                    "Aucun BIOS {} trouvé, copiez un dump de BIOS \
+                     PlayStation dans {}",
+                    "No se encontró ninguna BIOS {}, copie un volcado de \
+                     BIOS de PlayStation en {}",
+                    "Kein BIOS {} gefunden, kopieren Sie ein \
+                     PlayStation-BIOS-Abbild nach {}",
+                    "Nessun BIOS {} trovato, copia un dump del BIOS \
+                     PlayStation in {}",
+                    "Nenhuma BIOS {} encontrada, copie um dump da BIOS do \
+                     PlayStation para {}",
+                ],
+                Text::StateSaved => [
+                    "State saved in slot {}",
+                    "État sauvegardé dans l'emplacement {}",
+                    "Estado guardado en la ranura {}",
+                    "Zustand in Slot {} gespeichert",
+                    "Stato salvato nello slot {}",
+                    "Estado salvo no slot {}",
+                ],
+                Text::StateLoaded => [
+                    "State loaded from slot {}",
+                    "État chargé depuis l'emplacement {}",
+                    "Estado cargado desde la ranura {}",
+                    "Zustand aus Slot {} geladen",
+                    "Stato caricato dallo slot {}",
+                    "Estado carregado do slot {}",
+                ],
+                Text::StateLoadFailed => [
+                    "Couldn't load the state: {}",
+                    "Impossible de charger l'état : {}",
+                    "No se pudo cargar el estado: {}",
+                    "Zustand konnte nicht geladen werden: {}",
+                    "Impossibile caricare lo stato: {}",
+                    "Não foi possível carregar o estado: {}",
+                ],
+                Text::TrayOpened => [
+                    "Disc tray opened",
+                    "Tiroir du disque ouvert",
+                    "Bandeja del disco abierta",
+                    "Disc-Lade geöffnet",
+                    "Vassoio del disco aperto",
+                    "Bandeja do disco aberta",
+                ],
+                Text::TrayClosed => [
+                    "Disc tray closed",
+                    "Tiroir du disque fermé",
+                    "Bandeja del disco cerrada",
+                    "Disc-Lade geschlossen",
+                    "Vassoio del disco chiuso",
+                    "Bandeja do disco fechada",
+                ],
+                Text::RegionMismatch => [
+                    "Warning: using a {} BIOS for a {} disc, the game \
+                     may not boot",
+                    "Attention : BIOS {} utilisé pour un disque {}, le \
+                     jeu risque de ne pas démarrer",
+                    "Aviso: se usa una BIOS {} para un disco {}, es \
+                     posible que el juego no arranque",
+                    "Warnung: {}-BIOS für eine {}-Disc, das Spiel \
+                     startet möglicherweise nicht",
+                    "Attenzione: BIOS {} usato per un disco {}, il \
+                     gioco potrebbe non avviarsi",
+                    "Aviso: usando uma BIOS {} para um disco {}, o jogo \
+                     pode não iniciar",
+                ],
+            };
+
+        translations[lang as usize]
+    }
+
+    /// Format the message in `lang` with `params`
+    fn format(self, lang: Language, params: &[&Display]) -> String {
+        let mut pieces = self.template(lang).split("{}");
+
+        let mut s = pieces.next().unwrap_or("").to_string();
+
+        for (piece, param) in pieces.zip(params.iter()) {
+            s.push_str(&param.to_string());
+            s.push_str(piece);
+        }
+
+        s
+    }
+}
+
+/// Display `text` with `params` on screen for `nframes` frames in the
+/// frontend's language
+pub fn message(nframes: u32, text: Text, params: &[&Display]) {
+    libretro::set_message(nframes, &text.format(language(), params));
+}
+
+/// Return `text` with `params` in the frontend's language
+pub fn localize(text: Text, params: &[&Display]) -> String {
+    text.format(language(), params)
+}
+
+#[test]
+fn test_l10n() {
+    assert_eq!(Text::StateSaved.format(Language::English, &[&3]),
+               "State saved in slot 3");
+    assert_eq!(Text::StateSaved.format(Language::German, &[&3]),
+               "Zustand in Slot 3 gespeichert");
+    assert_eq!(Text::BiosNotFound.format(Language::French,
+                                         &[&"SCPH-1001", &"/bios"]),
+               "Aucun BIOS SCPH-1001 trouvé, copiez un dump de BIOS \
+                PlayStation dans /bios");
+    assert_eq!(Text::TrayOpened.format(Language::Italian, &[]),
+               "Vassoio del disco aperto");
+
+    assert_eq!(Language::from_retro(8), Some(Language::Portuguese));
+    assert_eq!(Language::from_retro(1), None);
+}
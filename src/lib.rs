@@ -13,30 +13,70 @@ mod renderer;
 mod savestate;
 mod debugger;
 mod vcd;
+mod cuesheet;
+mod testpattern;
+mod psexe;
+mod profiler;
+mod error;
+mod quicksave;
+mod stress;
+mod hotkeys;
+mod coreinfo;
+mod playtime;
+mod selftest;
+mod pointer;
+mod l10n;
+mod watchdog;
+mod worker;
+mod biosfile;
+mod package;
+mod exewatch;
+mod discstats;
+mod pacing;
 
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, BufWriter};
 use std::str::FromStr;
+use std::env;
+use std::sync::{Arc, Mutex};
 
 use libc::{c_char, c_uint};
 
-use rustc_serialize::{Encodable, Encoder, Decodable, Decoder};
-
 use rustation::cdrom::disc::{Disc, Region};
 use rustation::bios::{Bios, BIOS_SIZE};
 use rustation::bios::db::Metadata;
-use rustation::gpu::{Gpu, VideoClock};
+use rustation::gpu::{Gpu, VideoClock, VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+use rustation::gpu::renderer::Renderer;
 use rustation::memory::Interconnect;
 use rustation::cpu::Cpu;
 use rustation::padmemcard::gamepad::{Button, ButtonState, DigitalProfile};
+use rustation::padmemcard::gamepad::DisconnectedProfile;
 use rustation::shared::SharedState;
 use rustation::parallel_io::exe_loader;
 use rustation::tracer;
 
 use cdimage::cue::Cue;
 
-use debugger::Debugger;
+use debugger::{Debugger, DebuggerConfig};
+use savestate::{Serialize, Encoder};
+use renderer::validator::Validator;
+use renderer::dump::CommandDump;
+use renderer::framehash::FrameHashLog;
+use renderer::recording::{Recording, RecordMode};
+use testpattern::TestPattern;
+use renderer::overlay::PressedButtons;
+use profiler::{Profiler, TimeSummary, Category};
+use exewatch::ExeWatch;
+use discstats::{ReadStats, StatsImage};
+use pacing::Pacing;
+use error::Error;
+use stress::Stress;
+use hotkeys::Hotkeys;
+use playtime::PlayTime;
+use selftest::SelfTest;
+use watchdog::Watchdog;
+use biosfile::Rejection;
 
 #[macro_use]
 extern crate log;
@@ -47,12 +87,15 @@ extern crate arrayvec;
 extern crate cdimage;
 extern crate rustc_serialize;
 extern crate time;
+extern crate claxon;
+extern crate zip;
+extern crate encoding;
 
 /// Static system information sent to the frontend on request
 const SYSTEM_INFO: libretro::SystemInfo = libretro::SystemInfo {
     library_name: cstring!("Rustation"),
     library_version: rustation::VERSION_CSTR as *const _ as *const c_char,
-    valid_extensions: cstring!("cue|exe|psexe|psx"),
+    valid_extensions: cstring!("cue|exe|psexe|psx|cpe|psxpkg"),
     need_fullpath: false,
     block_extract: false,
 };
@@ -63,38 +106,140 @@ struct Context {
     cpu: Cpu,
     shared_state: SharedState,
     debugger: Debugger,
+    /// If true the debugger's breakpoints and watchpoints are stored
+    /// in savestates
+    savestate_debugger: bool,
     disc_path: PathBuf,
+    /// Description of the loaded content
+    content_info: ContentInfo,
+    /// Play time of the current session
+    playtime: PlayTime,
+    /// Build self-test in progress, if any
+    selftest: Option<SelfTest>,
     video_clock: VideoClock,
     /// When true the internal FPS monitoring in enabled
     monitor_internal_fps: bool,
+    /// Last internal FPS measurement, displayed in the text overlay
+    internal_fps: Option<f32>,
+    /// Frame pacing monitor, only present when enabled
+    pacing: Option<Pacing>,
     /// Cached value for the maximum savestate size in bytes
     savestate_max_len: usize,
     /// If true we log the counters at the end of each frame
     log_frame_counters: bool,
-    /// If true we trigger the debugger when Pause/Break is pressed
-    debug_on_key: bool,
+    /// Keyboard hotkeys
+    hotkeys: Hotkeys,
+    /// GPU command validator, only present when validation is
+    /// enabled
+    gpu_validator: Option<Validator>,
+    /// GPU command dump, only present when dumping is enabled
+    gpu_dump: Option<CommandDump>,
+    /// Frame hash log, only present when hashing is enabled
+    frame_hashes: Option<FrameHashLog>,
+    /// Video and audio recording, only present when recording
+    recording: Option<Recording>,
+    /// Action triggered by the Select+Start+L1+R1 combo
+    combo_action: ComboAction,
+    /// True if the combo was held during the previous frame, used to
+    /// trigger the action only once per press
+    combo_held: bool,
+    /// Disc removed from the drive while the tray is open
+    ejected_disc: Option<Disc>,
+    /// True if the emulated disc tray is open
+    tray_open: bool,
+    /// When not disabled we display a calibration test pattern
+    /// instead of running the emulator
+    test_pattern: TestPattern,
+    /// If true we display the pressed buttons on screen
+    input_echo: bool,
+    /// Pads connected to the console's ports 1 and 2
+    pad_ports: [bool; 2],
+    /// If true libretro port 0 drives the console's port 2 and
+    /// vice-versa
+    swap_ports: bool,
+    /// If set the left analog stick also drives the D-pad when it's
+    /// pushed further than this threshold
+    analog_dpad: Option<i16>,
+    /// Chrome tracing profiler, only present when profiling is
+    /// enabled
+    profiler: Option<Profiler>,
+    /// Time spent in each part of the last frames, logged on demand
+    times: TimeSummary,
+    /// Currently selected quick-save slot, 0 until the options are
+    /// loaded
+    quicksave_slot: u32,
+    /// Option stress test, if enabled
+    stress: Option<Stress>,
+    /// Emulation hang detection
+    watchdog: Watchdog,
+    /// Action taken when the watchdog detects a hang
+    watchdog_action: WatchdogAction,
+    /// Core options, read from the frontend in `refresh_variables`
+    settings: Settings,
+    /// Executable watched for changes in developer mode
+    exe_watch: Option<ExeWatch>,
 }
 
 impl Context {
-    fn new(disc: &Path) -> Result<Context, ()> {
+    fn new(disc: &Path) -> Result<Context, Error> {
         info!("Using Rustation {}", rustation::VERSION);
 
-        let (mut cpu, video_clock) =
-            match exe_loader::ExeLoader::load_file(disc) {
-                Ok(l) => try!(Context::load_exe(l)),
-                // Not an EXE, load as a disc
-                Err(exe_loader::Error::UnknownFormat) =>
-                    try!(Context::load_disc(disc)),
-                Err(e) => {
-                    error!("Couldn't load EXE file: {:?}", e);
-                    return Err(())
-                }
+        let settings = CoreVariables::snapshot();
+
+        // Homebrew packages are unpacked and their executable loaded
+        // in their stead
+        let package =
+            if package::is_package(disc) {
+                Some(try!(package::open(disc)))
+            } else {
+                None
+            };
+
+        let disc =
+            match package {
+                Some(ref p) => p.exe.as_path(),
+                None => disc,
+            };
+
+        // Self-test builds replace the placeholder content with the
+        // embedded test program
+        let selftest_exe = try!(selftest::prepare(disc));
+
+        let disc =
+            match selftest_exe {
+                Some(ref p) => p.as_path(),
+                None => disc,
+            };
+
+        let (mut cpu, video_clock, mut content_info) =
+            try!(Context::load_content(disc, &settings));
+
+        // The frontend knows better than us what the content is
+        // called, in particular for compressed files
+        if let Some(name) = libretro::get_game_info_name() {
+            content_info.label = name;
+        }
+
+        // Unless the package tells us the real title
+        if let Some(ref p) = package {
+            content_info.label = p.title.clone();
+        }
+
+        content_info.report();
+
+        let selftest =
+            match selftest_exe {
+                Some(_) => Some(SelfTest::new()),
+                None => None,
             };
 
+        let playtime = PlayTime::new(content_info.id(),
+                                     video_output_framerate(video_clock));
+
         let shared_state = SharedState::new();
-        let retrogl = try!(retrogl::RetroGl::new(video_clock));
+        let retrogl = try!(retrogl::RetroGl::new(video_clock, &settings));
 
-        if CoreVariables::enable_debug_uart() {
+        if settings.enable_debug_uart {
             let result =
                 cpu.interconnect_mut().bios_mut().enable_debug_uart();
 
@@ -110,12 +255,39 @@ impl Context {
                 cpu: cpu,
                 shared_state: shared_state,
                 debugger: Debugger::new(),
+                savestate_debugger: false,
                 disc_path: disc.to_path_buf(),
+                content_info: content_info,
+                playtime: playtime,
+                selftest: selftest,
                 video_clock: video_clock,
                 monitor_internal_fps: false,
+                internal_fps: None,
+                pacing: None,
                 savestate_max_len: 0,
                 log_frame_counters: false,
-                debug_on_key: false,
+                hotkeys: Hotkeys::new(),
+                gpu_validator: None,
+                gpu_dump: None,
+                frame_hashes: None,
+                recording: None,
+                combo_action: ComboAction::Disabled,
+                combo_held: false,
+                ejected_disc: None,
+                tray_open: false,
+                test_pattern: TestPattern::Disabled,
+                input_echo: false,
+                pad_ports: [true, false],
+                swap_ports: false,
+                analog_dpad: None,
+                profiler: None,
+                times: TimeSummary::new(TIME_SUMMARY_FRAMES),
+                quicksave_slot: 0,
+                stress: None,
+                watchdog: Watchdog::new(WATCHDOG_TIMEOUT_FRAMES),
+                watchdog_action: WatchdogAction::Disabled,
+                settings: settings,
+                exe_watch: None,
             };
 
         libretro::Context::refresh_variables(&mut context);
@@ -124,26 +296,70 @@ impl Context {
 
         context.savestate_max_len = max_len;
 
+        Context::declare_serialization_quirks();
+
         context.setup_controllers();
 
-        if CoreVariables::debug_on_reset() {
+        let break_frame = context.settings.break_frame;
+        context.debugger.set_break_frame(break_frame);
+
+        if context.settings.debug_on_reset {
             context.trigger_break();
         }
 
+        worker::start();
+
         Ok(context)
     }
 
-    /// Initialize the controllers connected to the emulated console
+    /// Describe our savestates to the frontend so that it knows what
+    /// it can use them for
+    fn declare_serialization_quirks() {
+        // The rendered VRAM only lives on the GPU side and is not
+        // saved, we only store the CPU-side copy. Loading a state
+        // every frame (runahead, netplay) would therefore lose
+        // offscreen rendering. The encoding itself is explicitly
+        // little endian so the format doesn't depend on the host.
+        //
+        // The GPU load buffer can grow the state but
+        // `compute_savestate_max_length` accounts for its worst case
+        // so we report a fixed size.
+        let quirks = libretro::serialization_quirk::INCOMPLETE;
+
+        match libretro::set_serialization_quirks(quirks) {
+            Some(q) => debug!("Serialization quirks: 0x{:x}", q),
+            None => warn!("The frontend doesn't support serialization \
+                           quirks"),
+        }
+    }
+
+    /// Initialize the controllers connected to the emulated
+    /// console. `gamepads_mut()[0]` is the console's port 1.
     fn setup_controllers(&mut self) {
-        // XXX for now I only hardcode a digital pad in slot 1
-        // (leaving slot 0 disconnected).
-        self.cpu.interconnect_mut()
+        let pad_ports = self.pad_ports;
+
+        let gamepads = self.cpu.interconnect_mut()
             .pad_memcard_mut()
-            .gamepads_mut()[0]
-            .set_profile(Box::new(DigitalProfile::new()));
+            .gamepads_mut();
+
+        // XXX rustation only implements the digital pad so there's no
+        // mouse to connect here yet. Once there's a mouse profile,
+        // the frontend's relative motion (RETRO_DEVICE_MOUSE) should
+        // be scaled by a sensitivity core option (raw 1:1 deltas are
+        // unusable with high DPI mice) and optionally inverted. The
+        // fractional remainder should be accumulated across frames
+        // and the result clamped to the 8bit signed deltas of the
+        // PlayStation mouse protocol.
+        for (gamepad, &connected) in gamepads.iter_mut().zip(&pad_ports) {
+            if connected {
+                gamepad.set_profile(Box::new(DigitalProfile::new()));
+            } else {
+                gamepad.set_profile(Box::new(DisconnectedProfile::new()));
+            }
+        }
     }
 
-    fn compute_savestate_max_length(&mut self) -> Result<usize, ()> {
+    fn compute_savestate_max_length(&mut self) -> Result<usize, Error> {
         // In order to get the full size we're just going to use a
         // dummy Write struct which will just count how many bytes are
         // being written
@@ -169,72 +385,86 @@ impl Context {
 
         let len = counter.0;
 
-        // Our savestate format has variable length, in particular we
-        // have the GPU's load_buffer which can grow to 1MB in the
-        // worst case scenario (the entire VRAM). I'm going to be
-        // optimistic here and give us 512KB of "headroom", that
-        // should be enough 99% of the time, hopefully.
-        let len = len + 512 * 1024;
+        // Our savestate format has variable length, we add the worst
+        // case size of the variable parts to get a bound that won't
+        // change for the whole session. Frontends allocate the
+        // reported size for every rewind step so we don't want to
+        // pad it more than necessary.
+        //
+        // The GPU's load_buffer holds the image being uploaded, it
+        // can grow up to the size of the entire VRAM.
+        let load_buffer_max =
+            VRAM_WIDTH_PIXELS as usize * VRAM_HEIGHT as usize * 2;
+
+        // The debugger configuration is only stored with
+        // "savestate_debugger" enabled, which can change at any time
+        let len = len + load_buffer_max + DebuggerConfig::max_encoded_len();
 
         Ok(len)
     }
 
-    fn save_state(&self, writer: &mut ::std::io::Write) -> Result<(), ()> {
+    fn save_state(&self,
+                  writer: &mut ::std::io::Write) -> Result<(), Error> {
 
         let mut encoder =
             match savestate::Encoder::new(writer) {
                 Ok(encoder) => encoder,
                 Err(e) => {
-                    warn!("Couldn't create savestate encoder: {:?}", e);
-                    return Err(())
+                    let e = format!("couldn't create encoder: {:?}", e);
+
+                    return Err(Error::Savestate(e));
                 }
             };
 
-        match self.encode(&mut encoder) {
+        // `libretro::Context` has a `serialize` method too
+        match Serialize::serialize(self, &mut encoder) {
             Ok(_) => Ok(()),
             Err(e) => {
-                warn!("Couldn't serialize emulator state: {:?}", e);
-                Err(())
+                let e = format!("couldn't serialize emulator state: {:?}", e);
+
+                Err(Error::Savestate(e))
             }
         }
     }
 
-    fn load_state(&mut self, reader: &mut ::std::io::Read) -> Result<(), ()> {
+    fn load_state(&mut self,
+                  reader: &mut ::std::io::Read) -> Result<(), Error> {
         let mut decoder =
             match savestate::Decoder::new(reader) {
                 Ok(decoder) => decoder,
                 Err(e) => {
-                    warn!("Couldn't create savestate decoder: {:?}", e);
-                    return Err(())
+                    let e = format!("couldn't create decoder: {:?}", e);
+
+                    return Err(Error::Savestate(e));
                 }
             };
 
-        // I don't implement Decodable for Context itself because I
+        // I don't implement Deserialize for Context itself because I
         // don't want to create a brand new instance. Things like the
         // debugger or disc path don't need to be reset
         let decoded =
-            decoder.read_struct("Context", 4, |d| {
-                let cpu = try!(d.read_struct_field("cpu", 0,
-                                                   Decodable::decode));
-
-                let retrogl = try!(d.read_struct_field("retrogl", 1,
-                                                       Decodable::decode));
-
-                let video_clock = try!(d.read_struct_field("video_clock", 2,
-                                                           Decodable::decode));
-
-                let shared_state = try!(d.read_struct_field("shared_state", 3,
-                                                            Decodable::decode));
-
-                Ok((cpu, retrogl, video_clock, shared_state))
-            });
-
-        let (cpu, retrogl, video_clock, shared_state) =
+            (|d: &mut savestate::Decoder| -> Result<_, savestate::Error> {
+                try!(d.begin_struct("Context", CONTEXT_VERSION));
+
+                let cpu: Cpu = try!(d.legacy_field("cpu"));
+                let retrogl: retrogl::RetroGl = try!(d.field("retrogl"));
+                let video_clock: VideoClock =
+                    try!(d.legacy_field("video_clock"));
+                let shared_state: SharedState =
+                    try!(d.legacy_field("shared_state"));
+                let debugger: Option<DebuggerConfig> =
+                    try!(d.field("debugger"));
+
+                Ok((cpu, retrogl, video_clock, shared_state, debugger))
+            })(&mut decoder);
+
+        let (cpu, retrogl, video_clock, shared_state, debugger) =
             match decoded {
                 Ok(d) => d,
                 Err(e) => {
-                    warn!("Couldn't decode savestate: {:?}", e);
-                    return Err(())
+                    let e = format!("couldn't decode savestate: {:?}", e);
+
+                    return Err(Error::Savestate(e));
                 }
             };
 
@@ -252,44 +482,75 @@ impl Context {
         info!("Loading savestate BIOS: {:?} (SHA256: {})",
               bios_md, sha256_hex);
 
-        let bios =
-            match Context::find_bios(|md| { md.sha256 == bios_md.sha256 }) {
-                Some(b) => b,
-                None => {
-                    error!("Couldn't find the savestate BIOS, bailing out");
-                    return Err(());
-                }
-            };
+        let wanted = format!("matching the savestate (SHA256: {})",
+                             sha256_hex);
 
-        let gl_is_valid = self.retrogl.is_valid();
+        let bios = try!(Context::find_bios(|md| md.sha256 == bios_md.sha256,
+                                           &wanted));
+
+        // Save the disc before we replace everything. The savestate
+        // has no notion of our tray so if it's open we put the
+        // ejected disc back in and close it, otherwise the disc would
+        // be lost and the tray state wouldn't match the new CPU.
+        let disc =
+            match self.ejected_disc.take() {
+                Some(disc) => Some(disc),
+                None => self.cpu.interconnect_mut().cdrom_mut().remove_disc(),
+            };
 
-        // Save the disc before we replace everything
-        let disc = self.cpu.interconnect_mut().cdrom_mut().remove_disc();
+        self.tray_open = false;
 
         self.cpu = cpu;
-        self.retrogl = retrogl;
+        self.retrogl.load_savestate(retrogl);
         self.video_clock = video_clock;
         self.shared_state = shared_state;
 
+        // Only present if the savestate was made with
+        // "savestate_debugger" enabled
+        if let Some(config) = debugger {
+            self.debugger.load_config(config);
+        }
+
         self.cpu.interconnect_mut().set_bios(bios);
         self.cpu.interconnect_mut().cdrom_mut().set_disc(disc);
 
         self.setup_controllers();
 
-        // If we had a valid GL context before the load we can
-        // directly reload everything. Otherwise it'll be done when
-        // the frontend calls context_reset
-        if gl_is_valid {
-            self.retrogl.context_reset();
-        }
+        self.watchdog.reset();
 
         info!("Savestate load successful");
 
         Ok(())
     }
 
-    fn load_exe(loader: exe_loader::ExeLoader)
-                -> Result<(Cpu, VideoClock), ()> {
+    /// Load the executable or disc image at `path`
+    fn load_content(path: &Path, settings: &Settings)
+                    -> Result<(Cpu, VideoClock, ContentInfo), Error> {
+        // Validate (and convert if needed) executables before giving
+        // them to the loader
+        let exe = try!(psexe::prepare(path));
+
+        let exe_path =
+            match exe {
+                Some(ref p) => p,
+                None => path,
+            };
+
+        match exe_loader::ExeLoader::load_file(exe_path) {
+            Ok(l) => Context::load_exe(l, path),
+            // Not an EXE, load as a disc
+            Err(exe_loader::Error::UnknownFormat) if exe.is_none() =>
+                Context::load_disc(path, settings),
+            Err(e) => {
+                let e = format!("{:?}", e);
+
+                Err(Error::BadExe(exe_path.to_path_buf(), e))
+            }
+        }
+    }
+
+    fn load_exe(loader: exe_loader::ExeLoader, path: &Path)
+                -> Result<(Cpu, VideoClock, ContentInfo), Error> {
         let region =
             match loader.region() {
                 Some(r) => {
@@ -305,23 +566,27 @@ impl Context {
 
         // In order for the EXE loader to word correctly without any
         // disc we need to patch the BIOS, so let's make sure that the
-        // animation_jump_hook is available
+        // animation_jump_hook is available.
+        //
+        // XXX It would be nice to have an option to display the
+        // normal BIOS boot before side-loading the EXE. Unfortunately
+        // the animation *is* the point where the shell starts: the
+        // loader hooks the jump to the animation and without a disc
+        // the shell would never return control to the kernel, it
+        // would just show the memory card/CD player menu. Supporting
+        // this would require a new hook in rustation's EXE loader
+        // (after the shell's intro but before the menu).
         let bios_predicate = |md: &Metadata| {
             md.region == region && md.animation_jump_hook.is_some()
         };
 
-        let mut bios =
-            match Context::find_bios(bios_predicate) {
-                Some(b) => b,
-                None => {
-                    error!("Couldn't find a BIOS, bailing out");
-                    return Err(());
-                }
-            };
+        let wanted = format!("for region {:?} supporting EXE loading",
+                             region);
+
+        let mut bios = try!(Context::find_bios(bios_predicate, &wanted));
 
         if let Err(_) = loader.patch_bios(&mut bios) {
-             error!("EXE loader couldn't patch the BIOS, giving up");
-             return Err(());
+            return Err(Error::BiosPatch("EXE loading"));
         }
 
         let video_clock =
@@ -337,27 +602,42 @@ impl Context {
         // Plug the EXE loader in the Parallel I/O port
         inter.parallel_io_mut().set_module(Box::new(loader));
 
-        Ok((Cpu::new(inter), video_clock))
+        let info = ContentInfo::new(path, None, region);
+
+        Ok((Cpu::new(inter), video_clock, info))
     }
 
-    fn load_disc(disc: &Path) -> Result<(Cpu, VideoClock), ()> {
+    fn load_disc(disc: &Path, settings: &Settings)
+                 -> Result<(Cpu, VideoClock, ContentInfo), Error> {
+        let path = disc;
+
+        // Convert any WAV/FLAC audio track before loading the image
+        let cue = try!(cuesheet::prepare(disc));
 
         let image =
-            match Cue::new(disc) {
+            match Cue::new(&cue) {
                 Ok(c) => c,
-                Err(e) => {
-                    error!("Couldn't load {}: {}", disc.to_string_lossy(), e);
-                    return Err(());
-                }
+                Err(e) =>
+                    return Err(Error::BadDisc(disc.to_path_buf(),
+                                              e.to_string())),
             };
 
+        let mut image: Box<cdimage::Image> = Box::new(image);
+        let mut disc_stats = None;
+
+        if settings.disc_read_stats {
+            let (wrapper, stats) = StatsImage::new(image);
+
+            image = Box::new(wrapper);
+            disc_stats = Some(stats);
+        }
+
         let disc =
-            match Disc::new(Box::new(image)) {
+            match Disc::new(image) {
                 Ok(d) => d,
-                Err(e) => {
-                    error!("Couldn't load {}: {}", disc.to_string_lossy(), e);
-                    return Err(());
-                }
+                Err(e) =>
+                    return Err(Error::BadDisc(disc.to_path_buf(),
+                                              e.to_string())),
             };
 
         let serial = disc.serial_number();
@@ -366,20 +646,77 @@ impl Context {
         info!("Disc serial number: {}", serial);
         info!("Detected disc region: {:?}", region);
 
-        let mut bios =
-            match Context::find_bios(|md| { md.region == region }) {
-                Some(b) => b,
-                None => {
-                    error!("Couldn't find a BIOS, bailing out");
-                    return Err(());
+        let mut info = ContentInfo::new(path,
+                                        Some(serial.to_string()),
+                                        region);
+
+        info.disc_stats = disc_stats;
+
+        let bios_menu = settings.bios_menu;
+
+        // When booting to the BIOS menu the disc is ignored so the
+        // user can pick the shell of any region
+        let mut console_region =
+            match settings.bios_menu_region {
+                Some(r) if bios_menu => {
+                    info!("Using the {:?} BIOS menu", r);
+                    r
+                }
+                _ => region,
+            };
+
+        let wanted = format!("for region {:?}", console_region);
+
+        let bios =
+            match Context::find_bios(|md| md.region == console_region,
+                                     &wanted) {
+                Ok(b) => b,
+                // Fall back on whatever BIOS we have, the user has
+                // been warned that the game might not boot
+                Err(Error::BiosNotFound(..))
+                    if settings.region_fallback && !bios_menu => {
+                    let wanted = format!("for region {:?} or any other \
+                                          region", console_region);
+
+                    try!(Context::find_bios(|_| true, &wanted))
                 }
+                Err(e) => return Err(e),
             };
 
-        let bios_menu = CoreVariables::bios_menu();
+        let mut bios = bios;
+
+        let bios_region = bios.metadata().region;
+
+        if bios_region != console_region {
+            warn!("Booting {:?} disc with {:?} BIOS",
+                  console_region, bios_region);
+
+            info.bios_region = Some(bios_region);
+            console_region = bios_region;
+        }
+
+        // XXX There's no way to preset the BIOS menu's settings (the
+        // clock and language of later consoles don't exist on the
+        // PlayStation and the sound mode and screen position aren't
+        // stored anywhere, the shell resets them at every boot) so
+        // there's nothing to pass through from the frontend here.
+
+        // XXX It would be nice to patch the BIOS to boot imports and
+        // unlicensed discs the way a modded console would (in
+        // particular when the region fallback above kicked in), but
+        // it can't be done from here: the BIOS is validated against
+        // rustation's DB by its hash so we can't patch the raw image
+        // before loading it, and `Bios` only exposes fixed patches
+        // (boot animation, debug UART) whose offsets live in the DB's
+        // `Metadata`. Most of the region/license check also happens
+        // in the CD-ROM controller's SCEx handshake rather than in
+        // the BIOS itself. This needs per-BIOS-version patch offsets
+        // in rustation's DB (like `animation_jump_hook`) along with
+        // a way to bypass the SCEx check in its CD-ROM emulation.
 
         // Skipping BIOS animations seems to break the BIOS menu, so
         // we ignore this setting when the menu is requested.
-        if CoreVariables::skip_bios_animation() && !bios_menu {
+        if settings.skip_bios_animation && !bios_menu {
             match bios.patch_boot_animation() {
                 Ok(_) => info!("Patched BIOS to skip boot animation"),
                 Err(_) => warn!("Failed to patch BIOS to skip boot animations"),
@@ -387,7 +724,7 @@ impl Context {
         }
 
         let video_clock =
-            match region {
+            match console_region {
                 Region::Europe => VideoClock::Pal,
                 Region::NorthAmerica => VideoClock::Ntsc,
                 Region::Japan => VideoClock::Ntsc,
@@ -402,124 +739,166 @@ impl Context {
                 Some(disc)
             };
 
+        // XXX A "fast seek" option shortening load times without
+        // breaking the music would have to be configured here but
+        // the seek and read timings are hardcoded in rustation's
+        // CD-ROM controller. It would also have to keep feeding
+        // XA/CD-DA sectors at the nominal rate while the shortened
+        // seek completes (by prebuffering the audio stream before the
+        // seek), which means the audio path goes through the
+        // controller as well. Both need a timing override in
+        // rustation's `CdRom` and the core doesn't output any audio
+        // yet anyway.
+
         let gpu = Gpu::new(video_clock);
         let inter = Interconnect::new(bios, gpu, disc);
 
-        Ok((Cpu::new(inter), video_clock))
+        Ok((Cpu::new(inter), video_clock, info))
     }
 
-    /// Attempt to find a BIOS for `region` in the system directory
-    fn find_bios<F>(predicate: F) -> Option<Bios>
+    /// Attempt to find a BIOS matching `predicate` in the system
+    /// directory, then at the path in the `RUSTATION_BIOS_PATH`
+    /// environment variable if it's set. `wanted` describes what
+    /// we're looking for in the error message.
+    ///
+    /// XXX The BIOS path would be better as a core option but
+    /// libretro options only take values from a fixed list, there's
+    /// no way to enter a path.
+    fn find_bios<F>(predicate: F, wanted: &str) -> Result<Bios, Error>
         where F: Fn(&Metadata) -> bool {
-        let system_directory =
-            match libretro::get_system_directory() {
-                Some(dir) => dir,
-                // libretro.h says that when the system directory is not
-                // provided "it's up to the implementation to find a
-                // suitable directory" but I'm not sure what to put
-                // here. Maybe "."? I'd rather give an explicit error
-                // message instead.
-                None => {
-                    error!("The frontend didn't give us a system directory, \
-                            no BIOS can be loaded");
-                    return None;
-                }
+        let system_directory = libretro::get_system_directory();
+        let bios_path = env::var_os(BIOS_PATH_VAR).map(PathBuf::from);
+
+        // libretro.h says that when the system directory is not
+        // provided "it's up to the implementation to find a suitable
+        // directory" but I'm not sure what to put here. Maybe "."?
+        // I'd rather give an explicit error message instead.
+        let searched =
+            match (&system_directory, &bios_path) {
+                (&Some(ref dir), _) => dir.clone(),
+                (&None, &Some(ref path)) => path.clone(),
+                (&None, &None) => return Err(Error::NoSystemDirectory),
             };
 
-        info!("Looking for a suitable BIOS in {:?}", system_directory);
+        if let Some(ref dir) = system_directory {
+            info!("Looking for a suitable BIOS in {:?}", dir);
+
+            let bios = try!(Context::search_bios_dir(&predicate, dir));
+
+            if let Some(bios) = bios {
+                return Ok(bios);
+            }
+        }
+
+        if let Some(ref path) = bios_path {
+            info!("Looking for a suitable BIOS in {:?} ({})",
+                  path, BIOS_PATH_VAR);
+
+            let bios =
+                if path.is_dir() {
+                    try!(Context::search_bios_dir(&predicate, path))
+                } else {
+                    Context::try_bios_file(&predicate, path)
+                };
+
+            if let Some(bios) = bios {
+                return Ok(bios);
+            }
+        }
+
+        Err(Error::BiosNotFound(searched, wanted.into()))
+    }
 
-        let dir =
-            match ::std::fs::read_dir(&system_directory) {
+    /// Look for a BIOS matching `predicate` in `dir`
+    fn search_bios_dir<F>(predicate: F, dir: &Path)
+                          -> Result<Option<Bios>, Error>
+        where F: Fn(&Metadata) -> bool {
+        let entries =
+            match ::std::fs::read_dir(dir) {
                 Ok(d) => d,
-                Err(e) => {
-                    error!("Can't read directory {:?}: {}",
-                           system_directory, e);
-                    return None;
-                }
+                Err(e) => return Err(Error::Io(dir.to_path_buf(), e)),
             };
 
-        for entry in dir {
+        // `read_dir` returns the entries in an arbitrary order, sort
+        // them so that the same BIOS is picked every time when
+        // several of them match
+        let mut paths = Vec::new();
+
+        for entry in entries {
             match entry {
-                Ok(entry) => {
-                    let path = entry.path();
-
-                    match entry.metadata() {
-                        Ok(md) => {
-                            if !md.is_file() {
-                                debug!("Ignoring {:?}: not a file", path);
-                            } else if md.len() != BIOS_SIZE as u64 {
-                                debug!("Ignoring {:?}: bad size", path);
-                            } else {
-                                let bios = Context::try_bios(&predicate, &path);
-
-                                if bios.is_some() {
-                                    // Found a valid BIOS!
-                                    return bios;
-                                }
-                            }
-                        }
-                        Err(e) =>
-                            warn!("Ignoring {:?}: can't get file metadata: {}",
-                                  path, e)
-                    }
-                }
+                Ok(entry) => paths.push(entry.path()),
                 Err(e) => warn!("Error while reading directory: {}", e),
             }
         }
 
-        None
+        paths.sort();
+
+        for path in paths {
+            if let Some(bios) = Context::try_bios_file(&predicate, &path) {
+                // Found a valid BIOS!
+                return Ok(Some(bios));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check that `path` looks like a BIOS dump before attempting
+    /// to load it
+    fn try_bios_file<F>(predicate: F, path: &Path) -> Option<Bios>
+        where F: Fn(&Metadata) -> bool {
+        match ::std::fs::metadata(path) {
+            Ok(md) => {
+                if !md.is_file() {
+                    debug!("Ignoring {:?}: not a file", path);
+                    None
+                } else if md.len() != BIOS_SIZE as u64 {
+                    debug!("Ignoring {:?}: bad size", path);
+                    None
+                } else {
+                    Context::try_bios(&predicate, path)
+                }
+            }
+            Err(e) => {
+                warn!("Ignoring {:?}: can't get file metadata: {}",
+                      path, e);
+                None
+            }
+        }
     }
 
     /// Attempt to read and load the BIOS at `path`
     fn try_bios<F>(predicate: F, path: &Path) -> Option<Bios>
         where F: Fn(&Metadata) -> bool {
 
-        let mut file =
-            match File::open(&path) {
-                Ok(f) => f,
-                Err(e) => {
-                    warn!("Can't open {:?}: {}", path, e);
-                    return None;
-                }
-            };
+        match biosfile::load(path) {
+            Ok(bios) => {
+                let accepted = {
+                    let md = bios.metadata();
 
-        // Load the BIOS
-        let mut data = Box::new([0; BIOS_SIZE]);
-        let mut nread = 0;
+                    info!("Found BIOS DB entry for {:?}: {:?}", path, md);
 
-        while nread < BIOS_SIZE {
-            nread +=
-                match file.read(&mut data[nread..]) {
-                    Ok(0) => {
-                        warn!("Short read while loading {:?}", path);
-                        return None;
-                    }
-                    Ok(n) => n,
-                    Err(e) => {
-                        warn!("Error while reading {:?}: {}", path, e);
-                        return None;
-                    }
+                    predicate(md)
                 };
-        }
-
-        match Bios::new(data) {
-            Some(bios) => {
-                let md = bios.metadata();
 
-                info!("Found BIOS DB entry for {:?}: {:?}", path, md);
-
-                if md.known_bad {
-                    warn!("Ignoring {:?}: known bad dump", path);
-                    None
-                } else if !predicate(md) {
+                if accepted {
+                    info!("Using BIOS {:?} ({:?})", path, bios.metadata());
+                    Some(bios)
+                } else {
                     info!("Ignoring {:?}: rejected by predicate", path);
                     None
-                } else {
-                    info!("Using BIOS {:?} ({:?})", path, md);
-                    Some(bios)
                 }
             }
-            None => {
+            Err(Rejection::Io(e)) => {
+                warn!("Can't load {:?}: {}", path, e);
+                None
+            }
+            Err(Rejection::KnownBad(bios)) => {
+                warn!("Ignoring {:?}: known bad dump ({:?})",
+                      path, bios.metadata());
+                None
+            }
+            Err(Rejection::Unknown) => {
                 debug!("Ignoring {:?}: not a known PlayStation BIOS", path);
                 None
             }
@@ -527,199 +906,1101 @@ impl Context {
     }
 
     fn poll_controllers(&mut self) {
-        // XXX we only support pad 0 for now
-        let pad = self.cpu.interconnect_mut()
-            .pad_memcard_mut()
-            .gamepads_mut()[0]
-            .profile_mut();
+        let mut pressed = PressedButtons::new();
+        let mut first_pressed = false;
+        let analog_dpad = self.analog_dpad;
+        let swap_cross_circle = self.settings.swap_cross_circle;
+        // Buttons held by the debugger's input script on port 1
+        let scripted = self.debugger.playback_frame().unwrap_or_default();
+
+        for port in 0..2 {
+            if !self.pad_ports[port] {
+                continue;
+            }
 
-        for &(retrobutton, psxbutton) in &BUTTON_MAP {
-            let state =
-                if libretro::button_pressed(0, retrobutton) {
-                    ButtonState::Pressed
-                } else {
-                    ButtonState::Released
-                };
+            let retro_port = self.retro_port(port);
+            let buttons = libretro::JoyPadState::poll(retro_port);
+
+            let pad = self.cpu.interconnect_mut()
+                .pad_memcard_mut()
+                .gamepads_mut()[port]
+                .profile_mut();
+
+            for &(retrobutton, psxbutton) in &BUTTON_MAP {
+                let psxbutton =
+                    if swap_cross_circle {
+                        japanese_layout(psxbutton)
+                    } else {
+                        psxbutton
+                    };
+
+                let is_pressed =
+                    buttons.pressed(retrobutton) ||
+                    analog_dpad_pressed(retro_port, retrobutton, analog_dpad) ||
+                    (port == 0 && scripted.contains(&psxbutton));
+
+                let state =
+                    if is_pressed {
+                        // The input echo displays the first libretro
+                        // controller
+                        if retro_port == 0 {
+                            pressed.push(psxbutton);
+                            first_pressed = true;
+                        }
+                        ButtonState::Pressed
+                    } else {
+                        ButtonState::Released
+                    };
+
+                pad.set_button_state(psxbutton, state);
+            }
+        }
+
+        if self.input_echo {
+            self.retrogl.set_input_echo(Some(pressed));
+        }
 
-            pad.set_button_state(psxbutton, state);
+        if self.debugger.latency_input(first_pressed) {
+            self.retrogl.flash();
         }
     }
 
-    /// Trigger a breakpoint in the debugger
-    fn trigger_break(&mut self) {
-        rustation::debugger::Debugger::trigger_break(&mut self.debugger);
+    /// Return the libretro input port used for the PlayStation
+    /// controller port `port` (0 or 1)
+    fn retro_port(&self, port: usize) -> u8 {
+        if self.swap_ports {
+            (1 - port) as u8
+        } else {
+            port as u8
+        }
     }
-}
 
-impl Drop for Context {
-    fn drop(&mut self) {
-        if cfg!(feature = "trace") {
-            // Dump the trace before destroying everything
-            let path = VCD_TRACE_PATH;
+    /// Check for the Select+Start+L1+R1 combo and run the configured
+    /// action when it's pressed
+    fn check_combo(&mut self) {
+        if self.combo_action == ComboAction::Disabled {
+            return;
+        }
 
-            let trace = tracer::remove_trace();
+        let buttons = libretro::JoyPadState::poll(0);
 
-            if trace.is_empty() {
-                warn!("Empty trace, ignoring");
-            } else {
-                info!("Dumping VCD trace file to {}", path);
+        let held = COMBO_BUTTONS.iter().all(|&b| buttons.pressed(b));
 
-                let mut vcd_file = File::create(path).unwrap();
+        // Only trigger on the rising edge, otherwise we'd run the
+        // action every frame while the buttons are held
+        let triggered = held && !self.combo_held;
 
-                let content = &*self.disc_path.to_string_lossy();
+        self.combo_held = held;
 
-                let bios_md = self.cpu.interconnect().bios().metadata();
-                let bios_desc = format!("{:?}", bios_md);
+        if !triggered {
+            return;
+        }
 
-                vcd::dump_trace(&mut vcd_file, content, &bios_desc, trace);
+        match self.combo_action {
+            ComboAction::Disabled => (),
+            ComboAction::Reset => {
+                libretro_message!(100, "Combo: reset");
+                libretro::Context::reset(self);
             }
+            ComboAction::ToggleTray => self.toggle_tray(),
+            ComboAction::QuickSave => self.quick_save(),
+            ComboAction::QuickLoad => self.quick_load(),
         }
     }
-}
 
-impl libretro::Context for Context {
+    /// Update the hotkey bindings from the core options
+    fn bind_hotkeys(&mut self) {
+        use hotkeys::Action;
+
+        let bindings =
+            [(Action::DebugBreak, self.settings.hotkey_debug_break),
+             (Action::Reset, self.settings.hotkey_reset),
+             (Action::ToggleTray, self.settings.hotkey_toggle_tray),
+             (Action::QuickSave, self.settings.hotkey_quick_save),
+             (Action::QuickLoad, self.settings.hotkey_quick_load),
+             (Action::DumpVram, self.settings.hotkey_dump_vram),
+             (Action::TimeSummary, self.settings.hotkey_time_summary),
+             (Action::NativeScreenshot,
+              self.settings.hotkey_native_screenshot)];
+
+        for &(action, combo) in &bindings {
+            self.hotkeys.bind(action, combo);
+        }
+    }
 
-    fn render_frame(&mut self) {
-        self.poll_controllers();
+    /// Poll the keyboard hotkeys and run the corresponding actions
+    fn check_hotkeys(&mut self) {
+        use hotkeys::Action;
 
-        let debug_request =
-            self.debug_on_key &&
-            libretro::key_pressed(0, libretro::Key::Pause);
+        for action in self.hotkeys.poll() {
+            debug!("Hotkey: {:?}", action);
 
-        if debug_request {
-            self.trigger_break();
+            match action {
+                Action::DebugBreak => self.trigger_break(),
+                Action::Reset => {
+                    libretro_message!(100, "Hotkey: reset");
+                    libretro::Context::reset(self);
+                }
+                Action::ToggleTray => self.toggle_tray(),
+                Action::QuickSave => self.quick_save(),
+                Action::QuickLoad => self.quick_load(),
+                Action::DumpVram => self.retrogl.dump_vram(),
+                Action::TimeSummary => self.report_time_summary(),
+                Action::NativeScreenshot => self.retrogl.native_screenshot(),
+            }
         }
+    }
 
-        let cpu = &mut self.cpu;
-        let shared_state = &mut self.shared_state;
-        let debugger = &mut self.debugger;
-
-        self.retrogl.render_frame(|renderer| {
-            cpu.run_until_next_frame(debugger, shared_state, renderer);
-        });
+    /// Log where the time went over the last few seconds, for
+    /// performance issue reports
+    fn report_time_summary(&self) {
+        let (frames, totals) = self.times.summary();
 
-        let counters = shared_state.counters_mut();
+        let wall: u64 = totals.iter().map(|&(_, t)| t).sum();
 
-        if self.log_frame_counters {
-            debug!("Frame counters:");
-            debug!("    CPU interrupt count: {}", counters.cpu_interrupt.get());
+        if wall == 0 {
+            return;
         }
 
-        if self.monitor_internal_fps {
-            let frame_count = counters.frame.get();
-
-            if frame_count >= INTERNAL_FPS_SAMPLE_PERIOD {
-                // We compute the internal FPS relative to the
-                // full-speed video output FPS.
-                let video_fps = video_output_framerate(self.video_clock);
+        info!("Time spent over the last {} frames:", frames);
 
-                let internal_frame_count = counters.framebuffer_swap.get();
+        let mut osd = Vec::new();
 
-                let internal_fps =
-                    (internal_frame_count as f32 * video_fps)
-                    / INTERNAL_FPS_SAMPLE_PERIOD as f32;
+        for &(name, t) in &totals {
+            let percent = t as f64 * 100. / wall as f64;
 
-                libretro_message!(100, "Internal FPS: {:.2}", internal_fps);
+            info!("    {}: {:.1}% ({:.2}ms per frame)",
+                  name, percent, t as f64 / 1e6 / frames as f64);
 
-                counters.frame.reset();
-                counters.framebuffer_swap.reset();
-            }
-        } else {
-            // Keep those counters to 0 so that we don't get wild
-            // values if logging is enabled.
-            counters.frame.reset();
-            counters.framebuffer_swap.reset();
+            osd.push(format!("{} {:.0}%", name, percent));
         }
+
+        libretro_message!(300, "Time: {}", osd.join(", "));
     }
 
-    fn get_system_av_info(&self) -> libretro::SystemAvInfo {
-        let upscaling = CoreVariables::internal_upscale_factor();
+    /// Save the state in the current quick-save slot
+    fn quick_save(&mut self) {
+        let slot = self.quicksave_slot;
 
-        get_av_info(self.video_clock, upscaling)
-    }
+        let res =
+            quicksave::slot_path(&self.content_info.id(), slot)
+            .and_then(|path| {
+                let file =
+                    match File::create(&path) {
+                        Ok(f) => f,
+                        Err(e) => return Err(Error::Io(path, e)),
+                    };
 
-    fn refresh_variables(&mut self) {
-        self.monitor_internal_fps = CoreVariables::display_internal_fps();
-        self.log_frame_counters = CoreVariables::log_frame_counters();
-        self.debug_on_key = CoreVariables::debug_on_key();
-        self.cpu.set_debug_on_break(CoreVariables::debug_on_break());
-        self.debugger.set_log_bios_calls(CoreVariables::log_bios_calls());
+                let mut writer = BufWriter::new(file);
+
+                self.save_state(&mut writer)
+            });
 
-        self.retrogl.refresh_variables();
+        match res {
+            Ok(_) => {
+                info!("State saved in slot {}", slot);
+                l10n::message(100, l10n::Text::StateSaved, &[&slot]);
+            }
+            Err(e) => e.report(),
+        }
     }
 
-    fn reset(&mut self) {
-        match Context::load_disc(&self.disc_path) {
-            Ok((cpu, video_clock)) => {
-                info!("Game reset");
-                self.cpu = cpu;
-                self.video_clock = video_clock;
-                self.shared_state = SharedState::new();
+    /// Save the state and capture the next frame when memory trigger
+    /// `label` fires
+    fn trigger_snapshot(&mut self, label: &str) {
+        let path =
+            match libretro::get_save_directory() {
+                Some(d) => d.join("rustation").join("triggers"),
+                None => {
+                    let e = Error::NoSaveDirectory("save trigger states");
 
-                if CoreVariables::debug_on_reset() {
-                    self.trigger_break();
+                    return e.report();
                 }
-            },
-            Err(_) => warn!("Couldn't reset game"),
+            };
+
+        if let Err(e) = ::std::fs::create_dir_all(&path) {
+            return Error::Io(path, e).report();
         }
-    }
 
-    fn gl_context_reset(&mut self) {
-        self.retrogl.context_reset();
-    }
+        let path = path.join(format!("{}-{}.state",
+                                     label, time::get_time().sec));
 
-    fn gl_context_destroy(&mut self) {
-        self.retrogl.context_destroy();
-    }
+        let res =
+            match File::create(&path) {
+                Ok(f) => self.save_state(&mut BufWriter::new(f)),
+                Err(e) => Err(Error::Io(path.clone(), e)),
+            };
 
-    fn serialize_size(&self) -> usize {
-        self.savestate_max_len
-    }
+        match res {
+            Ok(_) => {
+                info!("Trigger {}: state saved to {:?}", label, path);
+                libretro_message!(100, "Trigger {} fired", label);
+            }
+            Err(e) => e.report(),
+        }
 
-    fn serialize(&self, mut buf: &mut [u8]) -> Result<(), ()> {
-        self.save_state(&mut buf)
+        self.retrogl.start_capture(1);
     }
 
-    fn unserialize(&mut self, mut buf: &[u8]) -> Result<(), ()> {
-        self.load_state(&mut buf)
-    }
-}
+    /// Load the state from the current quick-save slot
+    fn quick_load(&mut self) {
+        let slot = self.quicksave_slot;
 
-impl Encodable for Context {
-    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-        s.emit_struct("Context", 4, |s| {
-            try!(s.emit_struct_field("cpu", 0,
-                                     |s| self.cpu.encode(s)));
-            try!(s.emit_struct_field("retrogl", 1,
-                                     |s| self.retrogl.encode(s)));
-            try!(s.emit_struct_field("video_clock", 2,
-                                     |s| self.video_clock.encode(s)));
-            try!(s.emit_struct_field("shared_state", 3,
-                                     |s| self.shared_state.encode(s)));
+        let path =
+            match quicksave::slot_path(&self.content_info.id(), slot) {
+                Ok(p) => p,
+                Err(e) => return e.report(),
+            };
 
-            Ok(())
-        })
-    }
-}
+        let mut state = Vec::new();
 
-/// Init function, guaranteed called only once (unlike `retro_init`)
-fn init() {
-    retrolog::init();
-}
+        let res = File::open(&path).and_then(|mut f| f.read_to_end(&mut state));
+
+        if let Err(e) = res {
+            return Error::Io(path, e).report();
+        }
+
+        match self.load_state(&mut &state[..]) {
+            Ok(_) => {
+                info!("State loaded from slot {}", slot);
+                l10n::message(100, l10n::Text::StateLoaded, &[&slot]);
+            }
+            Err(e) => {
+                error!("{}", e);
+                l10n::message(300, l10n::Text::StateLoadFailed, &[&e]);
+            }
+        }
+    }
+
+    /// Open or close the emulated disc tray
+    fn toggle_tray(&mut self) {
+        let cdrom = self.cpu.interconnect_mut().cdrom_mut();
+
+        if self.tray_open {
+            cdrom.set_disc(self.ejected_disc.take());
+            self.tray_open = false;
+
+            info!("Disc tray closed");
+            l10n::message(100, l10n::Text::TrayClosed, &[]);
+        } else {
+            self.ejected_disc = cdrom.remove_disc();
+            self.tray_open = true;
+
+            info!("Disc tray opened");
+            l10n::message(100, l10n::Text::TrayOpened, &[]);
+        }
+    }
+
+    /// Emulate the console's reset button: the CPU restarts from the
+    /// BIOS entry point while the RAM, VRAM, disc tray and memory
+    /// cards are left untouched
+    fn soft_reset(&mut self) {
+        // XXX rustation doesn't expose a way to reset COP0 or the
+        // peripherals individually so we only reset the PC. The BIOS
+        // reinitializes the status register and the hardware it
+        // needs (memory control, DMA, GPU, SPU...) in its boot code
+        // so that's generally good enough, a game relying on the
+        // exact state of an uninitialized peripheral after a reset
+        // might misbehave.
+        self.cpu.force_pc(BIOS_RESET_VECTOR);
+        self.watchdog.reset();
+        self.debugger.console_reset();
+
+        info!("Game soft reset");
+
+        if self.settings.debug_on_reset {
+            self.trigger_break();
+        }
+    }
+
+    /// Reload the content and restart the console from scratch
+    fn hard_reset(&mut self) {
+        match Context::load_content(&self.disc_path, &self.settings) {
+            Ok((cpu, video_clock, info)) => {
+                info!("Game reset");
+                // The reloaded image comes with its own statistics
+                self.dump_disc_stats();
+                self.content_info.disc_stats = info.disc_stats;
+                self.cpu = cpu;
+                self.video_clock = video_clock;
+                self.shared_state = SharedState::new();
+                self.ejected_disc = None;
+                self.tray_open = false;
+                self.watchdog.reset();
+                self.debugger.console_reset();
+
+                // Don't keep displaying the previous run's VRAM until
+                // the game overwrites it
+                self.retrogl.reset();
+
+                if self.settings.debug_on_reset {
+                    self.trigger_break();
+                }
+            },
+            Err(e) => {
+                warn!("Couldn't reset game");
+                e.report();
+            }
+        }
+    }
+
+    /// Log and save the disc read statistics, if enabled
+    fn dump_disc_stats(&self) {
+        if let Some(ref stats) = self.content_info.disc_stats {
+            if let Ok(stats) = stats.lock() {
+                stats.dump(&self.content_info.id());
+            }
+        }
+    }
+
+    /// Check for emulation hangs and run the configured recovery
+    /// action. The CPU state hasn't changed since the end of the
+    /// previous frame.
+    fn check_watchdog(&mut self) {
+        if self.watchdog_action == WatchdogAction::Disabled {
+            return;
+        }
+
+        let pc = self.cpu.pc();
+        let sr = self.cpu.sr();
+
+        if !self.watchdog.frame(pc, sr) {
+            return;
+        }
+
+        let (sp, ra) = {
+            let regs = self.cpu.regs();
+
+            (regs[29], regs[31])
+        };
+
+        error!("Emulation hang: the CPU has been stuck around 0x{:08x} \
+                with interrupts disabled for {} frames",
+               pc, self.watchdog.stuck_frames());
+        error!("    SR: 0x{:08x} SP: 0x{:08x} RA: 0x{:08x}", sr, sp, ra);
+
+        match self.watchdog_action {
+            WatchdogAction::Disabled => (),
+            WatchdogAction::Report =>
+                libretro_message!(300, "Emulation hang detected, \
+                                        try resetting the game"),
+            WatchdogAction::DebugBreak => {
+                libretro_message!(300, "Emulation hang detected, \
+                                        breaking into the debugger");
+                self.trigger_break();
+            }
+            WatchdogAction::SoftReset => {
+                libretro_message!(300, "Emulation hang detected, \
+                                        resetting the game");
+                self.soft_reset();
+            }
+            WatchdogAction::Shutdown => {
+                libretro_message!(300, "Emulation hang detected, \
+                                        shutting down");
+                libretro::shutdown();
+            }
+        }
+    }
+
+    /// Trigger a breakpoint in the debugger
+    fn trigger_break(&mut self) {
+        rustation::debugger::Debugger::trigger_break(&mut self.debugger);
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        if cfg!(feature = "trace") {
+            // Dump the trace before destroying everything
+            let trace = tracer::remove_trace();
+
+            if trace.is_empty() {
+                warn!("Empty trace, ignoring");
+            } else {
+                let content = &*self.disc_path.to_string_lossy();
+
+                let bios_md = self.cpu.interconnect().bios().metadata();
+                let bios_desc = format!("{:?}", bios_md);
+
+                let rotation = vcd::Rotation {
+                    max_size: self.settings.trace_max_size,
+                    max_files: self.settings.trace_max_files,
+                };
+
+                // The trace is dumped synchronously: we're shutting
+                // down anyway and handing it to a worker would just
+                // mean waiting for it below
+                let res = vcd::dump_trace(Path::new(VCD_TRACE_BASE),
+                                          &rotation,
+                                          content,
+                                          &bios_desc,
+                                          trace);
+
+                if let Err(e) = res {
+                    error!("Couldn't dump VCD trace: {}", e);
+                }
+            }
+        }
+
+        self.dump_disc_stats();
+
+        // Wait for the pending captures
+        worker::shutdown();
+    }
+}
+
+impl libretro::Context for Context {
+
+    fn render_frame(&mut self) {
+        if let Some(ref mut p) = self.pacing {
+            p.frame_start(profiler::now());
+        }
+
+        self.times.new_frame();
+
+        let reload =
+            match self.exe_watch {
+                Some(ref mut w) => w.frame(),
+                None => false,
+            };
+
+        if reload {
+            info!("{:?} changed, reloading", self.disc_path);
+            libretro_message!(100, "Executable changed, reloading");
+            self.hard_reset();
+        }
+
+        let stress_change =
+            match self.stress {
+                Some(ref mut s) => s.tick(),
+                None => false,
+            };
+
+        if stress_change {
+            libretro::Context::refresh_variables(self);
+        }
+
+        if self.test_pattern != TestPattern::Disabled {
+            let pattern = self.test_pattern;
+
+            self.retrogl.render_detached_frame(|renderer| {
+                testpattern::draw(pattern, renderer);
+            });
+
+            return;
+        }
+
+        self.poll_controllers();
+        self.check_combo();
+        self.check_hotkeys();
+
+        if let Some(fps) = self.internal_fps {
+            self.retrogl.print(1, 1, &format!("Internal FPS: {:.2}", fps));
+
+            if let Some((current, requested)) = self.retrogl.upscaling() {
+                let upscaling =
+                    if current == requested {
+                        format!("Upscaling: {}x", current)
+                    } else {
+                        format!("Upscaling: {}x ({}x requested)",
+                                current, requested)
+                    };
+
+                self.retrogl.print(1, 2, &upscaling);
+            }
+
+            if let Some(stats) = self.retrogl.draw_stats() {
+                self.retrogl.print(1, 3,
+                                   &format!("Draws: {} ({} buffer full)",
+                                            stats.draws, stats.buffer_full));
+                self.retrogl.print(1, 4,
+                                   &format!("Primitives: {}, \
+                                             uploaded pixels: {}",
+                                            stats.primitives,
+                                            stats.uploaded_pixels));
+            }
+
+            self.retrogl.print(1, 5,
+                               &format!("CPU instructions: {}",
+                                        self.debugger.instruction_count()));
+
+            if let Some(r) = self.pacing.as_ref().and_then(|p| p.report()) {
+                self.retrogl.print(1, 6,
+                                   &format!("Pacing: {} late, {} early \
+                                             (core {:.1}ms, max {:.1}ms)",
+                                            r.late, r.early,
+                                            r.core_ms, r.core_max_ms));
+            }
+        }
+
+        self.check_watchdog();
+
+        // Triggers fired at the end of the previous frame, the state
+        // hasn't changed since
+        for label in self.debugger.take_fired_triggers() {
+            self.trigger_snapshot(&label);
+        }
+
+        let frame_start = profiler::now();
+
+        let cpu = &mut self.cpu;
+        let shared_state = &mut self.shared_state;
+        let debugger = &mut self.debugger;
+        let gpu_validator = &mut self.gpu_validator;
+        let gpu_dump = &mut self.gpu_dump;
+        let trace = &self.profiler;
+        let times = &self.times;
+        let mut emulation_end = 0;
+
+        self.retrogl.render_frame(|renderer| {
+            let emulation_start = profiler::now();
+
+            // The command dump wraps the real renderer, that way it
+            // also records commands flagged by the validator
+            let mut dumping;
+
+            let renderer: &mut Renderer =
+                match *gpu_dump {
+                    Some(ref mut dump) => {
+                        dumping = dump.wrap(renderer);
+                        &mut dumping
+                    }
+                    None => renderer,
+                };
+
+            // XXX Games spend a good chunk of each frame spinning on
+            // VBLANK or GPUSTAT, skipping those idle loops would save
+            // a lot of host CPU time on weak devices. It has to be
+            // done in rustation's CPU loop though: detecting the
+            // loop needs the decoded instructions (a short backward
+            // branch whose body only loads from an I/O register and
+            // doesn't store anything) and skipping it means
+            // advancing the cycle counter straight to the next
+            // scheduled peripheral event, neither of which is
+            // exposed to us. The debugger's `pc_change` hook sees
+            // every instruction but can't move the clock, and would
+            // cost more than it saves.
+            match *gpu_validator {
+                Some(ref mut validator) => {
+                    let mut renderer = validator.wrap(renderer);
+
+                    cpu.run_until_next_frame(debugger,
+                                             shared_state,
+                                             &mut renderer);
+                }
+                None => cpu.run_until_next_frame(debugger,
+                                                 shared_state,
+                                                 renderer),
+            }
+
+            if let Some(ref p) = *trace {
+                p.span("emulation", emulation_start);
+            }
+
+            times.add(Category::Emulation, emulation_start);
+
+            emulation_end = profiler::now();
+
+            debugger.markers(cpu)
+        });
+
+        if let Some(ref p) = *trace {
+            // Whatever happens after the emulation is done is the
+            // renderer finalizing and presenting the frame
+            p.span("render", emulation_end);
+            p.span("frame", frame_start);
+        }
+
+        times.add(Category::Render, emulation_end);
+
+        debugger.end_of_frame(cpu);
+
+        if let Some(count) = debugger.take_capture_request() {
+            self.retrogl.start_capture(count);
+        }
+
+        if debugger.take_vram_dump_request() {
+            self.retrogl.dump_vram();
+        }
+
+        if let Some(ref mut log) = self.frame_hashes {
+            log.push(self.retrogl.display_hash());
+        }
+
+        if let Some(ref mut rec) = self.recording {
+            let native = rec.mode() == RecordMode::Native;
+
+            if let Some((pixels, res)) = self.retrogl.display_pixels(native) {
+                rec.push_frame(&pixels, res);
+            }
+
+            rec.end_frame();
+        }
+
+        self.playtime.frame();
+
+        if let Some(ref mut test) = self.selftest {
+            test.frame(cpu);
+        }
+
+        let counters = shared_state.counters_mut();
+
+        // XXX CPU cycles, GPU command words and DMA transfers would be
+        // more precise workload metrics than instructions and
+        // primitives but rustation doesn't count them, they'd have to
+        // be added to its `SharedState` counters.
+        if self.log_frame_counters {
+            debug!("Frame counters:");
+            debug!("    CPU interrupt count: {}", counters.cpu_interrupt.get());
+            debug!("    CPU instructions: {}", debugger.instruction_count());
+
+            if let Some(stats) = self.retrogl.draw_stats() {
+                debug!("    GPU primitives: {}", stats.primitives);
+                debug!("    VRAM uploaded pixels: {}",
+                       stats.uploaded_pixels);
+            }
+        }
+
+        if self.monitor_internal_fps {
+            let frame_count = counters.frame.get();
+
+            if frame_count >= INTERNAL_FPS_SAMPLE_PERIOD {
+                // We compute the internal FPS relative to the
+                // full-speed video output FPS.
+                let video_fps = video_output_framerate(self.video_clock);
+
+                let internal_frame_count = counters.framebuffer_swap.get();
+
+                let internal_fps =
+                    (internal_frame_count as f32 * video_fps)
+                    / INTERNAL_FPS_SAMPLE_PERIOD as f32;
+
+                self.internal_fps = Some(internal_fps);
+
+                counters.frame.reset();
+                counters.framebuffer_swap.reset();
+            }
+        } else {
+            // Keep those counters to 0 so that we don't get wild
+            // values if logging is enabled.
+            counters.frame.reset();
+            counters.framebuffer_swap.reset();
+
+            self.internal_fps = None;
+        }
+
+        if let Some(ref mut p) = self.pacing {
+            p.frame_end(profiler::now());
+        }
+    }
+
+    fn get_system_av_info(&self) -> libretro::SystemAvInfo {
+        get_av_info(self.video_clock, &self.settings)
+    }
+
+    fn refresh_variables(&mut self) {
+        // The options are read back below so they're applied right
+        // away
+        if CoreVariables::reset_options() {
+            reset_options();
+        }
+
+        // This must be done first since the stress test overrides
+        // the other options
+        match CoreVariables::stress_test_period() {
+            Some(period) => {
+                let restart =
+                    match self.stress {
+                        Some(ref s) => s.period() != period,
+                        None => true,
+                    };
+
+                if restart {
+                    // Drop the previous instance first, it clears
+                    // the overrides
+                    self.stress = None;
+                    self.stress = Some(Stress::new(period));
+                }
+            }
+            None => self.stress = None,
+        }
+
+        let break_frame = self.settings.break_frame;
+
+        // Read everything else at once, after the stress test had a
+        // chance to override the options
+        self.settings = CoreVariables::snapshot();
+
+        // Don't override the target set by the debugger unless the
+        // option actually changed
+        if self.settings.break_frame != break_frame {
+            self.debugger.set_break_frame(self.settings.break_frame);
+        }
+
+        retrolog::set_level(self.settings.log_level);
+        retrolog::set_subsystem_level(retrolog::Subsystem::Cpu,
+                                      self.settings.log_level_cpu);
+        retrolog::set_subsystem_level(retrolog::Subsystem::Gpu,
+                                      self.settings.log_level_gpu);
+        retrolog::set_subsystem_level(retrolog::Subsystem::Cdrom,
+                                      self.settings.log_level_cdrom);
+        retrolog::set_subsystem_level(retrolog::Subsystem::Pad,
+                                      self.settings.log_level_pad);
+        retrolog::set_subsystem_level(retrolog::Subsystem::Debugger,
+                                      self.settings.log_level_debugger);
+        retrolog::set_file_level(self.settings.log_file_level);
+
+        self.monitor_internal_fps = self.settings.display_internal_fps;
+
+        if self.settings.monitor_pacing {
+            // The reported rate depends on the refresh rate option
+            let fps = get_av_info(self.video_clock, &self.settings)
+                .timing.fps as f32;
+
+            if let Some(ref mut p) = self.pacing {
+                p.set_fps(fps);
+            }
+
+            if self.pacing.is_none() {
+                self.pacing = Some(Pacing::new(fps));
+            }
+        } else {
+            self.pacing = None;
+        }
+
+        self.log_frame_counters = self.settings.log_frame_counters;
+        self.bind_hotkeys();
+        self.cpu.set_debug_on_break(self.settings.debug_on_break);
+        self.debugger.set_log_bios_calls(self.settings.log_bios_calls);
+        self.debugger.set_latency_test(self.settings.latency_test);
+        self.debugger.set_memory_triggers(self.settings.memory_triggers);
+        self.savestate_debugger = self.settings.savestate_debugger;
+        self.combo_action = self.settings.combo_action;
+        self.watchdog_action = self.settings.watchdog_action;
+        self.input_echo = self.settings.input_echo;
+        self.swap_ports = self.settings.swap_ports;
+        self.analog_dpad = self.settings.analog_dpad;
+        self.playtime.set_save(self.settings.save_playtime);
+
+        // Discs don't change under our feet, only watch executables
+        let is_exe = self.content_info.serial.is_none();
+
+        if self.settings.exe_hot_reload && is_exe {
+            if self.exe_watch.is_none() {
+                self.exe_watch = Some(ExeWatch::new(&self.disc_path));
+            }
+        } else {
+            self.exe_watch = None;
+        }
+
+        let pad_ports = [self.settings.pad_port1,
+                         self.settings.pad_port2];
+
+        if pad_ports != self.pad_ports {
+            self.pad_ports = pad_ports;
+            self.setup_controllers();
+        }
+
+        let quicksave_slot = self.settings.quicksave_slot;
+
+        if quicksave_slot != self.quicksave_slot {
+            // Don't display anything when the game is being loaded,
+            // the content info is shown at that point
+            let show = self.quicksave_slot != 0;
+
+            self.quicksave_slot = quicksave_slot;
+
+            match quicksave::list(&self.content_info.id(), quicksave_slot) {
+                Ok(ref desc) if show => libretro_message!(100, "{}", desc),
+                Ok(_) => (),
+                Err(e) => e.report(),
+            }
+        }
+
+        let test_pattern = self.settings.test_pattern;
+
+        // The patterns are drawn in the VRAM, save it first and put
+        // it back once we're done
+        if test_pattern != TestPattern::Disabled &&
+            self.test_pattern == TestPattern::Disabled {
+            self.retrogl.save_vram();
+        }
+
+        if test_pattern == TestPattern::Disabled &&
+            self.test_pattern != TestPattern::Disabled {
+            self.retrogl.restore_vram();
+        }
+
+        self.test_pattern = test_pattern;
+
+        if self.settings.chrome_trace {
+            if self.profiler.is_none() {
+                self.profiler = Profiler::new().ok();
+            }
+        } else {
+            // Dropping the profiler finalizes the trace file
+            self.profiler = None;
+        }
+
+        if self.settings.validate_gpu_commands {
+            if self.gpu_validator.is_none() {
+                info!("GPU command validation enabled");
+
+                let label = &self.content_info.label;
+
+                self.gpu_validator = Some(Validator::new(label));
+            }
+        } else {
+            self.gpu_validator = None;
+        }
+
+        if self.settings.dump_gpu_commands {
+            if self.gpu_dump.is_none() {
+                let label = &self.content_info.label;
+
+                self.gpu_dump = CommandDump::new(label).ok();
+            }
+        } else {
+            self.gpu_dump = None;
+        }
+
+        if self.settings.log_frame_hashes {
+            if self.frame_hashes.is_none() {
+                let label = &self.content_info.label;
+
+                self.frame_hashes = FrameHashLog::new(label).ok();
+            }
+        } else {
+            self.frame_hashes = None;
+        }
+
+        let recording_mode = self.recording.as_ref().map(|r| r.mode());
+
+        if self.settings.recording == RecordMode::Disabled {
+            self.recording = None;
+        } else if recording_mode != Some(self.settings.recording) {
+            // Finish the current recording before starting the new one
+            self.recording = None;
+
+            let fps = video_output_framerate(self.video_clock);
+
+            self.recording = Recording::new(&self.content_info.label,
+                                            self.settings.recording,
+                                            fps).ok();
+        }
+
+        self.retrogl.refresh_variables(&self.settings);
+    }
+
+    fn reset(&mut self) {
+        if self.settings.reset_mode == ResetMode::Soft {
+            self.soft_reset();
+        } else {
+            self.hard_reset();
+        }
+    }
+
+    fn gl_context_reset(&mut self) {
+        self.retrogl.context_reset();
+    }
+
+    fn gl_context_destroy(&mut self) {
+        self.retrogl.context_destroy();
+    }
+
+    fn serialize_size(&self) -> usize {
+        self.savestate_max_len
+    }
+
+    fn serialize(&self, mut buf: &mut [u8]) -> Result<(), ()> {
+        let start = profiler::now();
+
+        let res = self.save_state(&mut buf);
+
+        if let Some(ref p) = self.profiler {
+            p.span("savestate save", start);
+        }
+
+        self.times.add(Category::Savestate, start);
+
+        res.map_err(|e| e.report())
+    }
+
+    fn unserialize(&mut self, mut buf: &[u8]) -> Result<(), ()> {
+        let start = profiler::now();
+
+        let res = self.load_state(&mut buf);
+
+        if let Some(ref p) = self.profiler {
+            p.span("savestate load", start);
+        }
+
+        self.times.add(Category::Savestate, start);
+
+        res.map_err(|e| e.report())
+    }
+}
+
+impl Serialize for Context {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), savestate::Error> {
+        try!(e.begin_struct("Context", CONTEXT_VERSION));
+        try!(e.legacy_field("cpu", &self.cpu));
+        try!(e.field("retrogl", &self.retrogl));
+        try!(e.legacy_field("video_clock", &self.video_clock));
+        try!(e.legacy_field("shared_state", &self.shared_state));
+
+        let debugger =
+            if self.savestate_debugger {
+                Some(self.debugger.config())
+            } else {
+                None
+            };
+
+        e.field("debugger", &debugger)
+    }
+}
+
+/// Version of the `Context` savestate layout
+const CONTEXT_VERSION: u32 = 1;
+
+/// Description of the loaded content
+struct ContentInfo {
+    /// Human-readable name of the content
+    label: String,
+    /// Disc serial number (SCUS-94163 etc...), None for executables
+    serial: Option<String>,
+    region: Region,
+    /// Region of the BIOS if it doesn't match the content's
+    bios_region: Option<Region>,
+    /// Disc read statistics, if enabled
+    disc_stats: Option<Arc<Mutex<ReadStats>>>,
+}
+
+impl ContentInfo {
+    /// Build a description for the content at `path`. The label
+    /// defaults to the file name without extension.
+    fn new(path: &Path,
+           serial: Option<String>,
+           region: Region) -> ContentInfo {
+        let label =
+            path.file_stem()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "<unknown>".into());
+
+        ContentInfo {
+            label: label,
+            serial: serial,
+            region: region,
+            bios_region: None,
+            disc_stats: None,
+        }
+    }
+
+    /// Identifier used to organize per-game files: the serial
+    /// number if we have one, the label otherwise
+    fn id(&self) -> String {
+        match self.serial {
+            Some(ref s) => s.clone(),
+            None => self.label.clone(),
+        }
+    }
+
+    /// Log the content description and display it on screen.
+    ///
+    /// XXX libretro doesn't have any way for the core to hand this
+    /// metadata over to the frontend (for thumbnails or playlist
+    /// titles), frontends scan the disc themselves. The best we can
+    /// do for now is to make it visible to the user.
+    fn report(&self) {
+        let serial =
+            match self.serial {
+                Some(ref s) => &**s,
+                None => "none",
+            };
+
+        info!("Content: \"{}\" serial: {} region: {:?}",
+              self.label, serial, self.region);
+
+        match self.bios_region {
+            // The mismatch is more important than the label
+            Some(bios_region) => {
+                let bios_region = format!("{:?}", bios_region);
+                let region = format!("{:?}", self.region);
+
+                l10n::message(600,
+                              l10n::Text::RegionMismatch,
+                              &[&bios_region, &region]);
+            }
+            None => libretro_message!(180, "{} [{}] ({:?})",
+                                      self.label, serial, self.region),
+        }
+    }
+}
+
+/// Init function, guaranteed called only once (unlike `retro_init`)
+fn init() {
+    retrolog::init();
+    l10n::init();
+    worker::init();
+
+    if !libretro::init_bitmasks() {
+        info!("No input bitmask support, polling buttons individually");
+    }
+
+    if !libretro::perf::init() {
+        info!("No frontend performance interface, using the system clock");
+    }
+}
 
 /// Called when a game is loaded and a new context must be built
 fn load_game(disc: PathBuf) -> Option<Box<libretro::Context>> {
     info!("Loading {:?}", disc);
 
-    Context::new(&disc).ok()
-        .map(|c| Box::new(c) as Box<libretro::Context>)
+    match Context::new(&disc) {
+        Ok(c) => Some(Box::new(c) as Box<libretro::Context>),
+        Err(e) => {
+            e.report();
+            None
+        }
+    }
 }
 
+// XXX There's no "speed hacks" group in here: instant DMA, reduced
+// SPU precision, skipping the GPU busy emulation and assuming a
+// coherent texture cache would all have to be implemented in
+// rustation's DMA, SPU and GPU emulation, which don't expose any
+// such knobs. libretro's v0 variables have no notion of option
+// groups either, so once they exist they should share a common
+// "Speed hack: " description prefix and default to "disabled".
 libretro_variables!(
-    struct CoreVariables (prefix = "rustation") {
+    struct CoreVariables (prefix = "rustation", settings = Settings) {
         internal_upscale_factor: u32, parse_upscale
             => "Internal upscaling factor; \
                 1x (native)|2x|3x|4x|5x|6x|7x|8x|9x|10x",
+        dynamic_upscaling: bool, parse_bool
+            => "Dynamic internal resolution (up to the upscaling factor); \
+                disabled|enabled",
+        dynamic_upscaling_min: u32, parse_upscale
+            => "Dynamic internal resolution minimum; \
+                1x (native)|2x|3x|4x|5x|6x|7x|8x|9x|10x",
+        flip_output: bool, parse_bool
+            => "Flip video output vertically (for buggy drivers); \
+                disabled|enabled",
+        aspect_ratio: AspectRatio, parse_aspect_ratio
+            => "Aspect ratio; 4:3|accurate PAR",
+        stable_geometry: bool, parse_bool
+            => "Keep output resolution constant across mode switches; \
+                disabled|enabled",
+        dupe_idle_frames: bool, parse_bool
+            => "Don't redraw frames without GPU activity; enabled|disabled",
+        color_profile: ColorProfile, parse_color_profile
+            => "Output color profile; raw|PAL CRT",
+        output_gamma: f32, parse_gamma
+            => "Output gamma; 1.0|1.1|1.2|1.3|0.8|0.9",
+        output_brightness: f32, parse_percent
+            => "Output brightness; 100%|110%|120%|80%|90%",
+        output_saturation: f32, parse_percent
+            => "Output saturation; 100%|110%|120%|130%|0%|50%|80%|90%",
         internal_color_depth: u8, parse_color_depth
             => "Internal color depth; dithered 16bpp (native)|32bpp",
         scale_dither: bool, parse_bool
@@ -727,27 +2008,243 @@ libretro_variables!(
                 enabled|disabled",
         wireframe: bool, parse_bool
             => "Wireframe mode; disabled|enabled",
+        command_buffer_size: usize, parse_command_buffer_size
+            => "Draw command buffer size in vertices (restart); \
+                2048|4096|8192|16384|1024",
+        split_quads: bool, parse_bool
+            => "Split quads around their center (reduces seams); \
+                disabled|enabled",
+        sprite_uv_clamp: bool, parse_bool
+            => "Clamp sprite texture coordinates (fixes edge bleeding); \
+                disabled|enabled",
+        notify_display_mode: bool, parse_bool
+            => "Show display resolution changes on screen; \
+                disabled|enabled",
         bios_menu: bool, parse_bool
             => "Boot to BIOS menu; disabled|enabled",
+        bios_menu_region: Option<Region>, parse_bios_menu_region
+            => "BIOS menu region; content|NTSC-U|NTSC-J|PAL",
+        region_fallback: bool, parse_bool
+            => "Use a BIOS from another region if none matches the \
+                disc; disabled|enabled",
         skip_bios_animation: bool, parse_bool
             => "Skip BIOS boot animations; disabled|enabled",
         display_internal_fps: bool, parse_bool
             => "Display internal FPS; disabled|enabled",
+        monitor_pacing: bool, parse_bool
+            => "Monitor frame pacing (log and FPS display); \
+                disabled|enabled",
         log_frame_counters: bool, parse_bool
             => "Log frame counters; disabled|enabled",
         enable_debug_uart: bool, parse_bool
             => "Enable debug UART in the BIOS; disabled|enabled",
         debug_on_break: bool, parse_bool
             => "Trigger debugger on BREAK instructions; disabled|enabled",
-        debug_on_key: bool, parse_bool
-            => "Trigger debugger when Pause/Break is pressed; disabled|enabled",
+        hotkey_debug_break: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Trigger debugger hotkey; disabled|pause|f12|ctrl+b",
+        exe_hot_reload: bool, parse_bool
+            => "Reload the executable when it's rebuilt (homebrew \
+                development); disabled|enabled",
         debug_on_reset: bool, parse_bool
             => "Trigger debugger when starting or resetting the emulator; \
                 disabled|enabled",
+        break_frame: Option<u32>, parse_break_frame
+            => "Trigger debugger at frame (counted from reset); \
+                disabled|60|300|600|1800|3600|18000",
         log_bios_calls: bool, parse_bool
             => "Log BIOS calls; disabled|enabled",
+        savestate_debugger: bool, parse_bool
+            => "Store breakpoints and watchpoints in savestates; \
+                disabled|enabled",
+        validate_gpu_commands: bool, parse_bool
+            => "Validate and log suspicious GPU commands; disabled|enabled",
+        dump_gpu_commands: bool, parse_bool
+            => "Dump GPU commands to a file; disabled|enabled",
+        log_frame_hashes: bool, parse_bool
+            => "Log frame hashes to a file; disabled|enabled",
+        disc_read_stats: bool, parse_bool
+            => "Disc read statistics (restart); disabled|enabled",
+        recording: RecordMode, RecordMode::parse
+            => "Record video (Y4M) and audio (WAV); \
+                disabled|native resolution|internal resolution",
+        reset_mode: ResetMode, parse_reset_mode
+            => "Reset behavior; \
+                hard (reload content)|soft (console reset button)",
+        combo_action: ComboAction, parse_combo_action
+            => "Select+Start+L1+R1 combo; \
+                disabled|reset|toggle disc tray|quick save|quick load",
+        watchdog_action: WatchdogAction, parse_watchdog_action
+            => "Emulation hang watchdog; \
+                report|debug break|soft reset|shutdown|disabled",
+        quicksave_slot: u32, quicksave::parse_slot
+            => "Quick-save slot; 1|2|3|4|5|6|7|8",
+        save_playtime: bool, parse_bool
+            => "Store play time in the save directory; disabled|enabled",
+        hotkey_reset: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Reset hotkey; disabled|ctrl+r|shift+f10",
+        hotkey_toggle_tray: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Toggle disc tray hotkey; disabled|ctrl+e|shift+f11",
+        hotkey_quick_save: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Quick save hotkey; disabled|shift+f2|ctrl+s|f5",
+        hotkey_quick_load: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Quick load hotkey; disabled|shift+f4|ctrl+l|f7",
+        hotkey_dump_vram: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Dump VRAM to PNG hotkey; disabled|shift+f12|ctrl+v",
+        hotkey_native_screenshot: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Native resolution screenshot hotkey; \
+                disabled|shift+f8|ctrl+n",
+        hotkey_time_summary: Option<hotkeys::Combo>, hotkeys::parse_combo
+            => "Log performance summary hotkey; disabled|shift+f9|ctrl+p",
+        test_pattern: TestPattern, TestPattern::parse
+            => "Display test pattern (pauses emulation); \
+                disabled|color bars|dither gradient|sharpness",
+        input_echo: bool, parse_bool
+            => "Display pressed buttons on screen; disabled|enabled",
+        latency_test: bool, parse_bool
+            => "Input latency test (flash on button press); \
+                disabled|enabled",
+        memory_triggers: bool, parse_bool
+            => "Memory triggers (savestate and capture); disabled|enabled",
+        vram_init_pattern: retrogl::VramInit, retrogl::VramInit::parse
+            => "VRAM initialization pattern (restart); \
+                0xdead|zeros|pseudo-random",
+        vram_random_seed: u32, retrogl::parse_random_seed
+            => "Pseudo-random VRAM seed (restart); default|1|2|3|4|5|6|7|8",
+        stress_test_period: Option<u32>, stress::parse_period
+            => "Randomize video options every N frames (stress test); \
+                disabled|60|300|1800",
+        gl_debug_context: bool, parse_bool
+            => "OpenGL debug context and driver messages (restart); \
+                disabled|enabled",
+        startup_benchmark: bool, parse_bool
+            => "Renderer benchmark on startup; disabled|enabled",
+        log_level: log::LogLevelFilter, retrolog::parse_level
+            => "Log level; trace|debug|info|warn|error|off",
+        log_level_cpu: Option<log::LogLevelFilter>,
+        retrolog::parse_subsystem_level
+            => "CPU log level; default|trace|debug|info|warn|error|off",
+        log_level_gpu: Option<log::LogLevelFilter>,
+        retrolog::parse_subsystem_level
+            => "GPU log level; default|trace|debug|info|warn|error|off",
+        log_level_cdrom: Option<log::LogLevelFilter>,
+        retrolog::parse_subsystem_level
+            => "CD-ROM log level; default|trace|debug|info|warn|error|off",
+        log_level_pad: Option<log::LogLevelFilter>,
+        retrolog::parse_subsystem_level
+            => "Pad/memory card log level; \
+                default|trace|debug|info|warn|error|off",
+        log_level_debugger: Option<log::LogLevelFilter>,
+        retrolog::parse_subsystem_level
+            => "Debugger log level; default|trace|debug|info|warn|error|off",
+        log_file_level: log::LogLevelFilter, retrolog::parse_level
+            => "Log file level (rustation.log in the save directory); \
+                off|error|warn|info|debug|trace",
+        pad_port1: bool, parse_bool
+            => "Pad connected to port 1; enabled|disabled",
+        pad_port2: bool, parse_bool
+            => "Pad connected to port 2; disabled|enabled",
+        swap_ports: bool, parse_bool
+            => "Swap controller ports; disabled|enabled",
+        swap_cross_circle: bool, parse_bool
+            => "Swap Cross/Circle (Japanese style); disabled|enabled",
+        analog_dpad: Option<i16>, parse_analog_dpad
+            => "Map left analog stick to D-pad (threshold); \
+                disabled|25%|50%|75%",
+        chrome_trace: bool, parse_bool
+            => "Write Chrome tracing profile; disabled|enabled",
+        refresh_rate: RefreshRate, parse_refresh_rate
+            => "Reported refresh rate; \
+                exact|59.94/50Hz|60/50Hz",
+        trace_max_size: Option<u64>, parse_trace_size
+            => "Maximum VCD trace file size; 100MB|10MB|1GB|unlimited",
+        trace_max_files: usize, parse_trace_files
+            => "Number of VCD trace files kept; 10|1|3|30|100",
+        reset_options: bool, parse_bool
+            => "Reset all options to their defaults; disabled|enabled",
     });
 
+/// Set every core option back to its default value, including
+/// "reset_options" itself
+fn reset_options() {
+    if CoreVariables::reset_to_defaults() {
+        info!("Core options reset to their defaults");
+        libretro_message!(200, "Options reset to their defaults");
+    } else {
+        warn!("The frontend doesn't let us reset the core options");
+        libretro_message!(200, "The frontend can't reset core options");
+    }
+}
+
+/// Return true if the left analog stick of `port` is pushed in the
+/// direction of the D-pad `button`, beyond `threshold`. Always false
+/// for other buttons or if `threshold` is None.
+fn analog_dpad_pressed(port: u8,
+                       button: libretro::JoyPadButton,
+                       threshold: Option<i16>) -> bool {
+    use libretro::{JoyPadButton, AnalogStick, AnalogAxis};
+
+    let threshold =
+        match threshold {
+            Some(t) => t as i32,
+            None => return false,
+        };
+
+    let (axis, direction) =
+        match button {
+            JoyPadButton::Left => (AnalogAxis::X, -1),
+            JoyPadButton::Right => (AnalogAxis::X, 1),
+            JoyPadButton::Up => (AnalogAxis::Y, -1),
+            JoyPadButton::Down => (AnalogAxis::Y, 1),
+            _ => return false,
+        };
+
+    let pos = libretro::analog_axis(port, AnalogStick::Left, axis) as i32;
+
+    pos * direction > threshold
+}
+
+/// Parse the analog to D-pad threshold, given as a percentage of the
+/// full stick range
+fn parse_analog_dpad(opt: &str) -> Result<Option<i16>, ()> {
+    if opt == "disabled" {
+        return Ok(None);
+    }
+
+    let percent: i32 =
+        match opt.trim_right_matches('%').parse() {
+            Ok(p) if p > 0 && p < 100 => p,
+            _ => return Err(()),
+        };
+
+    Ok(Some((percent * 0x7fff / 100) as i16))
+}
+
+fn parse_command_buffer_size(opt: &str)
+                             -> Result<usize, <usize as FromStr>::Err> {
+    opt.parse()
+}
+
+fn parse_trace_size(opt: &str)
+                    -> Result<Option<u64>, <u64 as FromStr>::Err> {
+    if opt == "unlimited" {
+        return Ok(None);
+    }
+
+    let (num, unit) =
+        if opt.ends_with("GB") {
+            (&opt[..opt.len() - 2], 1 << 30)
+        } else {
+            (opt.trim_right_matches("MB"), 1 << 20)
+        };
+
+    num.parse::<u64>().map(|n| Some(n * unit))
+}
+
+fn parse_trace_files(opt: &str)
+                     -> Result<usize, <usize as FromStr>::Err> {
+    opt.parse()
+}
+
 fn parse_upscale(opt: &str) -> Result<u32, <u32 as FromStr>::Err> {
     let num = opt.trim_matches(|c: char| !c.is_numeric());
 
@@ -760,6 +2257,17 @@ fn parse_color_depth(opt: &str) -> Result<u8, <u8 as FromStr>::Err> {
     num.parse()
 }
 
+fn parse_gamma(opt: &str) -> Result<f32, <f32 as FromStr>::Err> {
+    opt.parse()
+}
+
+/// Parse a percentage and return it as a factor
+fn parse_percent(opt: &str) -> Result<f32, <u32 as FromStr>::Err> {
+    let num = opt.trim_matches(|c: char| !c.is_numeric());
+
+    num.parse::<u32>().map(|p| p as f32 / 100.)
+}
+
 fn parse_bool(opt: &str) -> Result<bool, ()> {
     match opt {
         "true" | "enabled" | "on" => Ok(true),
@@ -768,6 +2276,147 @@ fn parse_bool(opt: &str) -> Result<bool, ()> {
     }
 }
 
+fn parse_combo_action(opt: &str) -> Result<ComboAction, ()> {
+    match opt {
+        "disabled" => Ok(ComboAction::Disabled),
+        "reset" => Ok(ComboAction::Reset),
+        "toggle disc tray" => Ok(ComboAction::ToggleTray),
+        "quick save" => Ok(ComboAction::QuickSave),
+        "quick load" => Ok(ComboAction::QuickLoad),
+        _ => Err(()),
+    }
+}
+
+/// Action triggered by the Select+Start+L1+R1 button combo
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ComboAction {
+    Disabled,
+    Reset,
+    ToggleTray,
+    QuickSave,
+    QuickLoad,
+}
+
+fn parse_watchdog_action(opt: &str) -> Result<WatchdogAction, ()> {
+    match opt {
+        "disabled" => Ok(WatchdogAction::Disabled),
+        "report" => Ok(WatchdogAction::Report),
+        "debug break" => Ok(WatchdogAction::DebugBreak),
+        "soft reset" => Ok(WatchdogAction::SoftReset),
+        "shutdown" => Ok(WatchdogAction::Shutdown),
+        _ => Err(()),
+    }
+}
+
+/// Action taken when the CPU hangs with interrupts disabled
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum WatchdogAction {
+    Disabled,
+    /// Only log the CPU state and tell the user
+    Report,
+    DebugBreak,
+    SoftReset,
+    /// Ask the frontend to close the content
+    Shutdown,
+}
+
+fn parse_reset_mode(opt: &str) -> Result<ResetMode, ()> {
+    match opt {
+        "hard (reload content)" => Ok(ResetMode::Hard),
+        "soft (console reset button)" => Ok(ResetMode::Soft),
+        _ => Err(()),
+    }
+}
+
+/// Behavior of the frontend's reset
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ResetMode {
+    /// Rebuild the whole emulator state and reload the content
+    Hard,
+    /// Restart the CPU from the BIOS entry point, keeping the RAM
+    /// and memory cards like the console's reset button
+    Soft,
+}
+
+fn parse_refresh_rate(opt: &str) -> Result<RefreshRate, ()> {
+    match opt {
+        "exact" => Ok(RefreshRate::Exact),
+        // The "(VRR)" variants are the labels used by older versions
+        // of the core, they may still be in the frontend's config
+        "59.94/50Hz" | "59.94/50Hz (VRR)" => Ok(RefreshRate::Broadcast),
+        "60/50Hz" | "60/50Hz (VRR)" => Ok(RefreshRate::Round),
+        _ => Err(()),
+    }
+}
+
+/// Frame rate reported to the frontend. Besides VRR displays the
+/// rounded rates help frontends that pace poorly with the exact ones,
+/// the audio is kept in sync by scaling the reported sample rate (see
+/// `get_av_info`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RefreshRate {
+    /// The exact rate of the emulated video output
+    Exact,
+    /// Standard broadcast rates (59.94Hz for NTSC, 50Hz for PAL)
+    Broadcast,
+    /// Round rates (60Hz for NTSC, 50Hz for PAL)
+    Round,
+}
+
+fn parse_break_frame(opt: &str) -> Result<Option<u32>, ()> {
+    match opt {
+        "disabled" => Ok(None),
+        n => n.parse().map(Some).map_err(|_| ()),
+    }
+}
+
+/// Parse the region of the BIOS booted to display the BIOS menu,
+/// None to use the content's region
+fn parse_bios_menu_region(opt: &str) -> Result<Option<Region>, ()> {
+    match opt {
+        "content" => Ok(None),
+        "NTSC-U" => Ok(Some(Region::NorthAmerica)),
+        "NTSC-J" => Ok(Some(Region::Japan)),
+        "PAL" => Ok(Some(Region::Europe)),
+        _ => Err(()),
+    }
+}
+
+fn parse_aspect_ratio(opt: &str) -> Result<AspectRatio, ()> {
+    match opt {
+        "4:3" => Ok(AspectRatio::Fixed),
+        "accurate PAR" => Ok(AspectRatio::Accurate),
+        _ => Err(()),
+    }
+}
+
+fn parse_color_profile(opt: &str) -> Result<ColorProfile, ()> {
+    match opt {
+        "raw" => Ok(ColorProfile::Raw),
+        "PAL CRT" => Ok(ColorProfile::PalCrt),
+        _ => Err(()),
+    }
+}
+
+/// Display aspect ratio reported to the frontend
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AspectRatio {
+    /// Always 4:3, regardless of the display mode
+    Fixed,
+    /// Ratio of the picture on a real TV, which depends on the
+    /// display mode's pixel aspect ratio and number of lines
+    Accurate,
+}
+
+/// Color transformation applied to the output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ColorProfile {
+    /// The digital colors, untouched
+    Raw,
+    /// Approximation of the colors of a PAL CRT TV
+    PalCrt,
+}
+
 fn init_variables() {
     CoreVariables::register();
 }
@@ -788,13 +2437,31 @@ fn video_output_framerate(std: VideoClock) -> f32 {
     }
 }
 
-fn get_av_info(std: VideoClock, upscaling: u32) -> libretro::SystemAvInfo {
+fn get_av_info(std: VideoClock, settings: &Settings)
+               -> libretro::SystemAvInfo {
+    let upscaling = settings.internal_upscale_factor;
 
     // Maximum resolution supported by the PlayStation video
     // output is 640x480
     let max_width = (640 * upscaling) as c_uint;
     let max_height = (480 * upscaling) as c_uint;
 
+    let real_fps = video_output_framerate(std);
+
+    let fps =
+        match (settings.refresh_rate, std) {
+            (RefreshRate::Exact, _) => real_fps,
+            (RefreshRate::Broadcast, VideoClock::Ntsc) => 59.94,
+            (RefreshRate::Round, VideoClock::Ntsc) => 60.,
+            (_, VideoClock::Pal) => 50.,
+        };
+
+    // If we don't report the exact rate the frontend will run us
+    // slightly faster (or slower) than the real console, in order to
+    // keep the audio in sync we have to scale the sample rate by the
+    // same ratio.
+    let sample_rate = 44_100. * fps / real_fps;
+
     libretro::SystemAvInfo {
         geometry: libretro::GameGeometry {
             // The base resolution will be overriden using
@@ -807,8 +2474,8 @@ fn get_av_info(std: VideoClock, upscaling: u32) -> libretro::SystemAvInfo {
             aspect_ratio: 4./3.,
         },
         timing: libretro::SystemTiming {
-            fps: video_output_framerate(std) as f64,
-            sample_rate: 44_100.
+            fps: fps as f64,
+            sample_rate: sample_rate as f64,
         }
     }
 }
@@ -816,7 +2483,10 @@ fn get_av_info(std: VideoClock, upscaling: u32) -> libretro::SystemAvInfo {
 /// Libretro to PlayStation button mapping. Libretro's mapping is
 /// based on the SNES controller so libretro's A button matches the
 /// PlayStation's Circle button.
-const BUTTON_MAP: [(libretro::JoyPadButton, Button); 14] =
+///
+/// L3 and R3 are always forwarded to the pad profile, the digital
+/// profile simply ignores them.
+const BUTTON_MAP: [(libretro::JoyPadButton, Button); 16] =
     [(libretro::JoyPadButton::Up, Button::DUp),
      (libretro::JoyPadButton::Down, Button::DDown),
      (libretro::JoyPadButton::Left, Button::DLeft),
@@ -830,12 +2500,50 @@ const BUTTON_MAP: [(libretro::JoyPadButton, Button); 14] =
      (libretro::JoyPadButton::L, Button::L1),
      (libretro::JoyPadButton::R, Button::R1),
      (libretro::JoyPadButton::L2, Button::L2),
-     (libretro::JoyPadButton::R2, Button::R2)];
+     (libretro::JoyPadButton::R2, Button::R2),
+     (libretro::JoyPadButton::L3, Button::L3),
+     (libretro::JoyPadButton::R3, Button::R3)];
+
+/// Swap Cross and Circle: on japanese consoles Circle confirms and
+/// Cross cancels, the other way around from the western convention
+/// `BUTTON_MAP` follows
+fn japanese_layout(button: Button) -> Button {
+    match button {
+        Button::Cross => Button::Circle,
+        Button::Circle => Button::Cross,
+        b => b,
+    }
+}
+
+/// Buttons that must be held simultaneously to trigger the
+/// configured `ComboAction`
+const COMBO_BUTTONS: [libretro::JoyPadButton; 4] =
+    [libretro::JoyPadButton::Select,
+     libretro::JoyPadButton::Start,
+     libretro::JoyPadButton::L,
+     libretro::JoyPadButton::R];
+
+/// Address of the first instruction executed by the CPU after a
+/// reset, in the BIOS
+const BIOS_RESET_VECTOR: u32 = 0xbfc00000;
+
+/// Environment variable pointing at a BIOS file or a directory
+/// containing BIOS files, searched after the system directory
+const BIOS_PATH_VAR: &'static str = "RUSTATION_BIOS_PATH";
+
+/// Number of frames covered by the performance summary hotkey, about
+/// five seconds
+const TIME_SUMMARY_FRAMES: usize = 300;
 
 /// Number of output frames over which the internal FPS is averaged
 const INTERNAL_FPS_SAMPLE_PERIOD: u32 = 32;
 
-/// Hardcoded path for the generated VCD file when tracing is
-/// enabled. XXX Should probably be changed for Windows, maybe made
-/// configurable somehow?
-const VCD_TRACE_PATH: &'static str = "/tmp/rustation-trace.vcd";
+/// Number of frames the CPU must spend stuck with interrupts
+/// disabled before the watchdog reports a hang: five seconds in
+/// NTSC, six in PAL
+const WATCHDOG_TIMEOUT_FRAMES: u32 = 300;
+
+/// Hardcoded base path for the generated VCD files when tracing is
+/// enabled, the files are named `rustation-trace-NNN.vcd`. XXX Should
+/// probably be changed for Windows, maybe made configurable somehow?
+const VCD_TRACE_BASE: &'static str = "/tmp/rustation-trace";
@@ -0,0 +1,33 @@
+//! Audio output
+
+use libc::{c_uint, size_t};
+
+use super::{call_environment, Environment};
+use super::instance::callbacks;
+
+pub fn send_audio_samples(samples: &[i16]) {
+    if samples.len() & 1 != 0 {
+        panic!("Received an odd number of audio samples!");
+    }
+
+    let frames = (samples.len() / 2) as size_t;
+
+    let r = unsafe {
+        (callbacks().audio_sample_batch)(samples.as_ptr(), frames)
+    };
+
+    if r != frames {
+        panic!("Frontend didn't use all our samples! ({} != {})", r, frames);
+    }
+}
+
+/// Ask the frontend not to use an audio latency lower than
+/// `latency_ms` milliseconds. Returns false if the frontend doesn't
+/// support it.
+pub fn set_minimum_audio_latency(latency_ms: u32) -> bool {
+    let latency = latency_ms as c_uint;
+
+    unsafe {
+        call_environment(Environment::SetMinimumAudioLatency, &latency)
+    }
+}
@@ -0,0 +1,266 @@
+//! Environment callback helpers: queries and notifications that go
+//! through the frontend's `environment` callback and don't belong to
+//! a more specific module.
+
+use std::ptr;
+use std::ffi::CStr;
+use std::path::PathBuf;
+
+use libc::{c_char, c_uint, c_int, c_void, size_t};
+
+use super::{call_environment, call_environment_mut};
+use super::instance;
+use super::{ptr_as_ref, build_path, to_cstring, Environment, Message};
+
+/// Extended content information, see `get_game_info_name`
+#[repr(C)]
+struct GameInfoExt {
+    full_path: *const c_char,
+    archive_path: *const c_char,
+    archive_file: *const c_char,
+    dir: *const c_char,
+    name: *const c_char,
+    ext: *const c_char,
+    meta: *const c_char,
+    data: *const c_void,
+    size: size_t,
+    file_in_archive: bool,
+    persistent_data: bool,
+}
+
+pub fn get_system_directory() -> Option<PathBuf> {
+    let mut path: *const c_char = ptr::null();
+
+    let success =
+        unsafe {
+            call_environment_mut(Environment::GetSystemDirectory,
+                                 &mut path)
+        };
+
+    if success && !path.is_null() {
+        let path = unsafe { CStr::from_ptr(path) };
+
+        build_path(path)
+    } else {
+        None
+    }
+}
+
+pub fn get_save_directory() -> Option<PathBuf> {
+    let mut path: *const c_char = ptr::null();
+
+    let success =
+        unsafe {
+            call_environment_mut(Environment::GetSaveDirectory,
+                                 &mut path)
+        };
+
+    if success && !path.is_null() {
+        let path = unsafe { CStr::from_ptr(path) };
+
+        build_path(path)
+    } else {
+        None
+    }
+}
+
+/// Return the name of the content being loaded as reported by the
+/// frontend (file name without extension, or the file name inside
+/// the archive for compressed content). Only valid while the game is
+/// being loaded.
+pub fn get_game_info_name() -> Option<String> {
+    let mut info: *const GameInfoExt = ptr::null();
+
+    let success =
+        unsafe {
+            call_environment_mut(Environment::GetGameInfoExt, &mut info)
+        };
+
+    if !success {
+        return None;
+    }
+
+    let info =
+        match ptr_as_ref(info) {
+            Some(i) => i,
+            None => return None,
+        };
+
+    if info.name.is_null() {
+        return None;
+    }
+
+    let name = unsafe { CStr::from_ptr(info.name) };
+
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Flags describing the limitations of our savestates, used with
+/// `set_serialization_quirks`
+pub mod serialization_quirk {
+    /// Savestates don't hold the full emulator state and can't be
+    /// relied upon for runahead or netplay
+    pub const INCOMPLETE: u64 = 1 << 0;
+    /// The core must run at least one frame before savestates work
+    pub const MUST_INITIALIZE: u64 = 1 << 1;
+    /// The savestate size can change while the core is running
+    pub const CORE_VARIABLE_SIZE: u64 = 1 << 2;
+    /// Set by the frontend if it supports variable size savestates
+    pub const FRONT_VARIABLE_SIZE: u64 = 1 << 3;
+    /// Savestates can only be loaded in the session they were made
+    pub const SINGLE_SESSION: u64 = 1 << 4;
+    /// Savestates depend on the host's endianness
+    pub const ENDIAN_DEPENDENT: u64 = 1 << 5;
+    /// Savestates depend on the host platform for other reasons
+    pub const PLATFORM_DEPENDENT: u64 = 1 << 6;
+}
+
+/// Tell the frontend about the limitations of our savestates (see
+/// the `serialization_quirk` module). On success returns the flags
+/// as updated by the frontend.
+pub fn set_serialization_quirks(quirks: u64) -> Option<u64> {
+    let mut quirks = quirks;
+
+    let ok =
+        unsafe {
+            call_environment_mut(Environment::SetSerializationQuirks,
+                                 &mut quirks)
+        };
+
+    if ok {
+        Some(quirks)
+    } else {
+        None
+    }
+}
+
+/// Reason why the frontend is asking for a savestate
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SavestateContext {
+    /// Regular user-initiated savestate (or unknown)
+    Normal = 0,
+    /// Runahead, the state will be loaded back in this instance
+    RunaheadSameInstance = 1,
+    /// Runahead with a second instance of the same core binary
+    RunaheadSameBinary = 2,
+    /// Rollback netplay, the state might be sent to other machines
+    RollbackNetplay = 3,
+}
+
+impl SavestateContext {
+    /// Return true if the state is only used internally by the
+    /// frontend and should be made as fast as possible
+    pub fn is_fast(self) -> bool {
+        self != SavestateContext::Normal
+    }
+}
+
+/// Return the context of the savestate currently being made or
+/// loaded. Returns `Normal` if the frontend doesn't support the
+/// query.
+pub fn get_savestate_context() -> SavestateContext {
+    let mut ctx: c_int = 0;
+
+    let ok =
+        unsafe {
+            call_environment_mut(Environment::GetSavestateContext, &mut ctx)
+        };
+
+    if !ok {
+        return SavestateContext::Normal;
+    }
+
+    match ctx {
+        1 => SavestateContext::RunaheadSameInstance,
+        2 => SavestateContext::RunaheadSameBinary,
+        3 => SavestateContext::RollbackNetplay,
+        _ => SavestateContext::Normal,
+    }
+}
+
+/// Return true if the frontend can present the previous frame again
+/// when we send it a dupe
+pub fn can_dupe() -> bool {
+    let mut can_dupe = false;
+
+    let ok =
+        unsafe {
+            call_environment_mut(Environment::GetCanDupe, &mut can_dupe)
+        };
+
+    ok && can_dupe
+}
+
+/// Return true if the frontend is currently fast-forwarding. Returns
+/// false if the frontend doesn't support the query.
+pub fn is_fast_forwarding() -> bool {
+    let mut fast_forwarding = false;
+
+    let ok =
+        unsafe {
+            call_environment_mut(Environment::GetFastForwarding,
+                                 &mut fast_forwarding)
+        };
+
+    ok && fast_forwarding
+}
+
+/// Return the frontend's language as a raw `retro_language` value,
+/// None if the frontend doesn't support the query
+pub fn get_language() -> Option<u32> {
+    let mut language: c_uint = 0;
+
+    let ok =
+        unsafe {
+            call_environment_mut(Environment::GetLanguage, &mut language)
+        };
+
+    if ok {
+        Some(language as u32)
+    } else {
+        None
+    }
+}
+
+/// Display `msg` on the screen for `nframes` frames
+pub fn set_message(nframes: u32, msg: &str) {
+    let msg = to_cstring(msg);
+
+    let message = Message { msg: msg.as_ptr(), frames: nframes as c_uint };
+
+    unsafe {
+        call_environment(Environment::SetMessage, &message);
+    }
+}
+
+/// Report an unrecoverable error to the user and ask the frontend to
+/// stop the core. The frontend might still call us (`retro_run` in
+/// particular) before it actually shuts down so the caller must
+/// leave things in a sane state.
+pub fn shutdown_with_error(msg: &str) {
+    error!("{}, shutting down", msg);
+
+    set_message(300, msg);
+
+    shutdown();
+}
+
+/// Ask the frontend to stop the core. Like `shutdown_with_error` the
+/// frontend might still call us before it actually shuts down.
+pub fn shutdown() {
+    let ok =
+        unsafe {
+            instance::environment(Environment::Shutdown as c_uint,
+                                  ptr::null_mut())
+        };
+
+    if !ok {
+        error!("The frontend refused to shut down");
+    }
+}
+
+#[macro_export]
+macro_rules! libretro_message {
+    ($nframes:expr, $($arg:tt)+) =>
+        ($crate::libretro::set_message($nframes, &format!($($arg)+)))
+}
@@ -0,0 +1,345 @@
+//! This file contains the libretro definitions ported from `libretro.h`
+//!
+//! For more details see the original well-commented C header file:
+//! https://github.com/libretro/RetroArch/blob/master/libretro.h
+//!
+//! I took the liberty to "rustify" the calling convention: I dropped
+//! the `retro_` prefix (useless when you have namespaces) and
+//! CamelCased the struct names.
+//!
+//! Callback typedefs are altered in the same way and suffixed with
+//! `Fn` for clarity.
+
+use libc::{c_void, c_char, c_uint, c_float, c_double, size_t, int16_t};
+
+#[repr(C)]
+pub struct SystemInfo {
+   pub library_name: *const c_char,
+   pub library_version: *const c_char,
+   pub valid_extensions: *const c_char,
+   pub need_fullpath: bool,
+   pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: c_float,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: c_double,
+    pub sample_rate: c_double,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+pub type EnvironmentFn =
+    unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+
+pub type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void,
+                         width: c_uint,
+                         height: c_uint,
+                         pitch: size_t);
+pub type AudioSampleFn =
+    extern "C" fn(left: int16_t, right: int16_t);
+
+pub type AudioSampleBatchFn =
+    unsafe extern "C" fn(data: *const int16_t,
+                         frames: size_t) -> size_t;
+
+pub type InputPollFn = extern "C" fn();
+
+pub type InputStateFn =
+    extern "C" fn(port: c_uint,
+                  device: c_uint,
+                  index: c_uint,
+                  id:c_uint) -> int16_t;
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: size_t,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+#[repr(C)]
+pub struct Message {
+    pub msg: *const c_char,
+    pub frames: c_uint,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    SetMessage = 6,
+    GetCanDupe = 3,
+    Shutdown = 7,
+    GetSystemDirectory = 9,
+    SetPixelFormat = 10,
+    SetHwRender = 14,
+    GetVariable = 15,
+    SetVariables = 16,
+    GetVariableUpdate = 17,
+    GetLogInterface = 27,
+    GetPerfInterface = 28,
+    SetSerializationQuirks = 44,
+    GetFastForwarding = 49,
+    GetSaveDirectory = 31,
+    SetSystemAvInfo = 32,
+    SetGeometry = 37,
+    GetLanguage = 39,
+    SetMinimumAudioLatency = 63,
+    GetGameInfoExt = 66,
+    SetVariable = 70,
+    /// Experimental (flagged with 0x10000)
+    GetInputBitmasks = 0x10000 | 51,
+    /// Experimental (flagged with 0x10000)
+    GetSavestateContext = 0x10000 | 72,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputDevice {
+    None = 0,
+    JoyPad = 1,
+    Mouse = 2,
+    Keyboard = 3,
+    LightGun = 4,
+    Analog = 5,
+    Pointer = 6,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    Unknown = 0,
+    Backspace = 8,
+    Tab = 9,
+    Clear = 12,
+    Return = 13,
+    Pause = 19,
+    Escape = 27,
+    Space = 32,
+    Exclaim = 33,
+    QuoteDbl = 34,
+    Hash = 35,
+    Dollar = 36,
+    Ampersand = 38,
+    Quote = 39,
+    LeftParen = 40,
+    RightParen = 41,
+    Asterisk = 42,
+    Plus = 43,
+    Comma = 44,
+    Minus = 45,
+    Period = 46,
+    Slash = 47,
+    Num0 = 48,
+    Num1 = 49,
+    Num2 = 50,
+    Num3 = 51,
+    Num4 = 52,
+    Num5 = 53,
+    Num6 = 54,
+    Num7 = 55,
+    Num8 = 56,
+    Num9 = 57,
+    Colon = 58,
+    Semicolon = 59,
+    Less = 60,
+    Equals = 61,
+    Greater = 62,
+    Question = 63,
+    At = 64,
+    LeftBracket = 91,
+    Backslash = 92,
+    RightBracket = 93,
+    Caret = 94,
+    Underscore = 95,
+    Backquote = 96,
+    A = 97,
+    B = 98,
+    C = 99,
+    D = 100,
+    E = 101,
+    F = 102,
+    G = 103,
+    H = 104,
+    I = 105,
+    J = 106,
+    K = 107,
+    L = 108,
+    M = 109,
+    N = 110,
+    O = 111,
+    P = 112,
+    Q = 113,
+    R = 114,
+    S = 115,
+    T = 116,
+    U = 117,
+    V = 118,
+    W = 119,
+    X = 120,
+    Y = 121,
+    Z = 122,
+    Delete = 127,
+    Kp0 = 256,
+    Kp1 = 257,
+    Kp2 = 258,
+    Kp3 = 259,
+    Kp4 = 260,
+    Kp5 = 261,
+    Kp6 = 262,
+    Kp7 = 263,
+    Kp8 = 264,
+    Kp9 = 265,
+    KpPeriod = 266,
+    KpDivide = 267,
+    KpMultiply = 268,
+    KpMinus = 269,
+    KpPlus = 270,
+    KpEnter = 271,
+    KpEquals = 272,
+    Up = 273,
+    Down = 274,
+    Right = 275,
+    Left = 276,
+    Insert = 277,
+    Home = 278,
+    End = 279,
+    PageUp = 280,
+    PageDown = 281,
+    F1 = 282,
+    F2 = 283,
+    F3 = 284,
+    F4 = 285,
+    F5 = 286,
+    F6 = 287,
+    F7 = 288,
+    F8 = 289,
+    F9 = 290,
+    F10 = 291,
+    F11 = 292,
+    F12 = 293,
+    F13 = 294,
+    F14 = 295,
+    F15 = 296,
+    NumLock = 300,
+    CapsLock = 301,
+    ScrolLock = 302,
+    RShift = 303,
+    LShift = 304,
+    RCtrl = 305,
+    LCtrl = 306,
+    RAlt = 307,
+    LAlt = 308,
+    RMeta = 309,
+    LMeta = 310,
+    LSuper = 311,
+    RSuper = 312,
+    Mode = 313,
+    Compose = 314,
+
+    Help = 315,
+    Print = 316,
+    SysReq = 317,
+    Break = 318,
+    Menu = 319,
+    Power = 320,
+    Euro = 321,
+    Undo = 322,
+}
+
+/// RETRO_DEVICE_ID_JOYPAD_* constants
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JoyPadButton {
+    B = 0,
+    Y = 1,
+    Select = 2,
+    Start = 3,
+    Up = 4,
+    Down = 5,
+    Left = 6,
+    Right = 7,
+    A = 8,
+    X = 9,
+    L = 10,
+    R = 11,
+    L2 = 12,
+    R2 = 13,
+    L3 = 14,
+    R3 = 15,
+}
+
+/// RETRO_DEVICE_INDEX_ANALOG_* constants
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AnalogStick {
+    Left = 0,
+    Right = 1,
+}
+
+/// RETRO_DEVICE_ID_ANALOG_* constants
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AnalogAxis {
+    /// Positive values point to the right
+    X = 0,
+    /// Positive values point down
+    Y = 1,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Xrgb1555 = 0,
+    Xrgb8888 = 1,
+    Rgb565 = 2,
+}
+
+pub mod dummy {
+    //! Placeholder implementation for the libretro callback in order
+    //! to catch calls to those function in the function pointer has
+    //! not yet been loaded.
+
+    use libc::{c_void, c_uint, size_t, int16_t};
+
+    pub unsafe extern "C" fn video_refresh(_: *const c_void,
+                                       _: c_uint,
+                                       _: c_uint,
+                                       _: size_t) {
+        panic!("Called missing video_refresh callback");
+    }
+
+    pub extern "C" fn input_poll() {
+        panic!("Called missing input_poll callback");
+    }
+
+    pub unsafe extern "C" fn audio_sample_batch(_: *const int16_t,
+                                                _: size_t) -> size_t {
+        panic!("Called missing audio_sample_batch callback");
+    }
+
+    pub extern "C" fn input_state(_: c_uint,
+                                  _: c_uint,
+                                  _: c_uint,
+                                  _: c_uint) -> int16_t {
+        panic!("Called missing input_state callback");
+    }
+
+    pub unsafe extern "C" fn environment(_: c_uint, _: *mut c_void) -> bool {
+        panic!("Called missing environment callback");
+    }
+}
@@ -0,0 +1,161 @@
+//! OpenGL hardware rendering interface
+
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use libc::{uintptr_t, c_char, c_uint, c_void};
+use super::{call_environment_mut, Environment};
+use super::instance;
+
+pub type ResetFn = extern "C" fn();
+
+pub type GetCurrentFramebufferFn = extern "C" fn() -> uintptr_t;
+
+pub type GetProcAddressFn = extern "C" fn(sym: *const c_char) -> *const c_void;
+
+#[repr(C)]
+pub enum ContextType {
+    None = 0,
+    OpenGl = 1,
+    OpenGlEs2 = 2,
+    OpenGlCore = 3,
+    OpenGlEs3 = 4,
+    OpenGlEsVersion = 5,
+}
+
+#[repr(C)]
+pub struct RenderCallback {
+    context_type: ContextType,
+    context_reset: ResetFn,
+    get_current_framebuffer: GetCurrentFramebufferFn,
+    get_proc_address: GetProcAddressFn,
+    depth: bool,
+    stencil: bool,
+    bottom_left_origin: bool,
+    version_major: c_uint,
+    version_minor: c_uint,
+    cache_context: bool,
+    context_destroy: ResetFn,
+    debug_context: bool,
+}
+
+pub extern "C" fn reset() {
+    let _current = make_current();
+
+    if let Some(c) = super::context() {
+        c.gl_context_reset();
+    }
+}
+
+pub extern "C" fn context_destroy() {
+    let _current = make_current();
+
+    if let Some(c) = super::context() {
+        c.gl_context_destroy();
+    }
+}
+
+/// Set while we're in a callback where the frontend guarantees
+/// that our OpenGL context is current: `retro_run` and the
+/// context reset/destroy callbacks. The other entry points
+/// (`retro_serialize`, `retro_reset`...) can be called from a
+/// thread where it isn't, for instance when the frontend uses
+/// threaded video.
+static CONTEXT_CURRENT: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Return true if we're allowed to make OpenGL calls
+pub fn is_current() -> bool {
+    CONTEXT_CURRENT.load(Ordering::Relaxed)
+}
+
+/// Mark the context as current until the returned guard is
+/// dropped
+pub fn make_current() -> CurrentGuard {
+    let previous = CONTEXT_CURRENT.swap(true, Ordering::Relaxed);
+
+    CurrentGuard {
+        previous: previous,
+    }
+}
+
+pub struct CurrentGuard {
+    /// State to restore when the guard is dropped, the context
+    /// callbacks can be called from within `retro_run`
+    previous: bool,
+}
+
+impl Drop for CurrentGuard {
+    fn drop(&mut self) {
+        CONTEXT_CURRENT.store(self.previous, Ordering::Relaxed);
+    }
+}
+
+pub extern "C" fn dummy_get_current_framebuffer() -> uintptr_t {
+    panic!("Called missing get_current_framebuffer callback");
+}
+
+pub extern "C" fn dummy_get_proc_address(_: *const c_char) -> *const c_void {
+    panic!("Called missing get_proc_address callback");
+}
+
+/// OpenGL version we request from the frontend: (major, minor)
+pub const VERSION: (u32, u32) = (3, 3);
+
+/// Parameters requested in `init`, the frontend fills the callbacks.
+/// The live copy is in the `instance`.
+pub const INITIAL_RENDER_CALLBACK: RenderCallback = RenderCallback {
+    context_type: ContextType::OpenGlCore,
+    context_reset: reset,
+    // Filled by frontend
+    get_current_framebuffer: dummy_get_current_framebuffer,
+    // Filled by frontend
+    get_proc_address: dummy_get_proc_address,
+    depth: false,
+    stencil: false,
+    bottom_left_origin: true,
+    version_major: VERSION.0,
+    version_minor: VERSION.1,
+    cache_context: false,
+    context_destroy: context_destroy,
+    debug_context: false,
+};
+
+/// Request an OpenGL context. If `debug_context` is true we ask
+/// for a debug context, which lets the driver report more
+/// diagnostics at the cost of performance.
+pub fn init(debug_context: bool) -> bool {
+    unsafe {
+        let hw_render = &mut instance::get().hw_render;
+
+        hw_render.debug_context = debug_context;
+
+        call_environment_mut(Environment::SetHwRender, hw_render)
+    }
+}
+
+pub fn get_proc_address(sym: &str) -> *const c_void {
+    // OpenGL symbols should never contain \0 or something's very
+    // wrong.
+    let sym = CString::new(sym).unwrap();
+
+    unsafe {
+        let get_proc_address = instance::get().hw_render.get_proc_address;
+
+        get_proc_address(sym.as_ptr() as *const c_char)
+    }
+}
+
+/// Return true if the frontend's framebuffer has its origin in
+/// the bottom left corner (the OpenGL convention). We request it
+/// in `init` but it's a negotiation: the frontend is allowed to
+/// clear the flag if it can't honour it.
+pub fn bottom_left_origin() -> bool {
+    unsafe {
+        instance::get().hw_render.bottom_left_origin
+    }
+}
+
+pub fn get_current_framebuffer() -> uintptr_t {
+    unsafe {
+        (instance::get().hw_render.get_current_framebuffer)()
+    }
+}
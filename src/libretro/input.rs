@@ -0,0 +1,104 @@
+//! Input polling
+
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use libc::c_uint;
+
+use super::Environment;
+use super::instance::{self, callbacks};
+use super::{InputDevice, JoyPadButton, AnalogStick, AnalogAxis, Key};
+
+/// RETRO_DEVICE_ID_JOYPAD_MASK: query all the joypad buttons at once
+const JOYPAD_MASK: c_uint = 256;
+
+/// True if the frontend supports `JOYPAD_MASK`
+static BITMASKS: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Ask the frontend whether it can return the state of all the
+/// joypad buttons in a single `input_state` call. Returns true if
+/// it's supported.
+pub fn init_bitmasks() -> bool {
+    let supported =
+        unsafe {
+            instance::environment(Environment::GetInputBitmasks as c_uint,
+                                  ptr::null_mut())
+        };
+
+    BITMASKS.store(supported, Ordering::Relaxed);
+
+    supported
+}
+
+pub fn button_pressed(port: u8, b: JoyPadButton) -> bool {
+    unsafe {
+        (callbacks().input_state)(port as c_uint,
+                                  InputDevice::JoyPad as c_uint,
+                                  0,
+                                  b as c_uint) != 0
+    }
+}
+
+/// State of all the buttons of a joypad, polled with a single call
+/// if the frontend supports it
+#[derive(Clone, Copy)]
+pub struct JoyPadState {
+    port: u8,
+    /// Bitmask of the pressed buttons, None if the frontend doesn't
+    /// support bitmasks in which case each button is queried
+    /// individually
+    mask: Option<u16>,
+}
+
+impl JoyPadState {
+    pub fn poll(port: u8) -> JoyPadState {
+        let mask =
+            if BITMASKS.load(Ordering::Relaxed) {
+                let mask =
+                    unsafe {
+                        let input_state = callbacks().input_state;
+
+                        input_state(port as c_uint,
+                                    InputDevice::JoyPad as c_uint,
+                                    0,
+                                    JOYPAD_MASK)
+                    };
+
+                Some(mask as u16)
+            } else {
+                None
+            };
+
+        JoyPadState {
+            port: port,
+            mask: mask,
+        }
+    }
+
+    pub fn pressed(&self, b: JoyPadButton) -> bool {
+        match self.mask {
+            Some(mask) => mask & (1 << b as u16) != 0,
+            None => button_pressed(self.port, b),
+        }
+    }
+}
+
+/// Return the position of an analog stick along `axis`, in the range
+/// [-0x8000, 0x7fff]
+pub fn analog_axis(port: u8, stick: AnalogStick, axis: AnalogAxis) -> i16 {
+    unsafe {
+        (callbacks().input_state)(port as c_uint,
+                                  InputDevice::Analog as c_uint,
+                                  stick as c_uint,
+                                  axis as c_uint)
+    }
+}
+
+pub fn key_pressed(port: u8, k: Key) -> bool {
+    unsafe {
+        (callbacks().input_state)(port as c_uint,
+                                  InputDevice::Keyboard as c_uint,
+                                  0,
+                                  k as c_uint) != 0
+    }
+}
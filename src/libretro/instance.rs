@@ -0,0 +1,120 @@
+//! State of the core instance: the callbacks loaded by the frontend,
+//! the hardware rendering negotiation and the `Context` of the loaded
+//! game. They used to be separate `static mut`s, keeping them in a
+//! single slot makes it obvious what would have to be duplicated to
+//! run several instances in one process.
+//!
+//! Libretro 1 has no instance handle so there's exactly one instance
+//! per process, and a frontend loading the core twice gets both
+//! instances fighting over the same slot. We can't prevent it but
+//! `retro_init` reports it instead of letting the second instance
+//! silently replace the first one's callbacks.
+//!
+//! The core options aren't stored here: they're read through the
+//! environment callback and the resulting `Settings` live in the
+//! `Context`.
+//!
+//! With the `multi-instance` feature the slot is thread-local, which
+//! is what a frontend running each instance in its own thread would
+//! need. This is an experiment, not a supported configuration:
+//!
+//! XXX With a thread-local slot, entry points called from an other
+//! thread (`retro_serialize` with RetroArch's threaded video for
+//! instance) see an empty instance. Besides the slot is only part of
+//! the story, the logger, the performance counter callback, the
+//! language, the worker pool, the video geometry, the input bitmask
+//! support and the OpenGL "current" flags are still process-wide.
+
+use libc::{c_uint, c_void};
+
+use super::{Context, VideoRefreshFn, InputPollFn, InputStateFn};
+use super::{AudioSampleBatchFn, EnvironmentFn};
+use super::dummy;
+use super::hw_context::{self, RenderCallback};
+
+/// Callbacks set by the frontend through the `retro_set_*` entry
+/// points
+pub struct Callbacks {
+    pub video_refresh: VideoRefreshFn,
+    pub input_poll: InputPollFn,
+    pub input_state: InputStateFn,
+    pub audio_sample_batch: AudioSampleBatchFn,
+    pub environment: EnvironmentFn,
+}
+
+pub struct Instance {
+    pub callbacks: Callbacks,
+    /// Hardware rendering parameters, the frontend fills the
+    /// callbacks when it accepts them
+    pub hw_render: RenderCallback,
+    /// Emulator state, None when no game is loaded
+    pub context: Option<Box<Context>>,
+    /// Set between `retro_init` and `retro_deinit`
+    pub initialized: bool,
+}
+
+impl Instance {
+    /// Mark the instance as initialized. Returns false if it already
+    /// was, which means that the frontend either forgot to call
+    /// `retro_deinit` or created a second instance of the core.
+    pub fn init(&mut self) -> bool {
+        let first = !self.initialized;
+
+        self.initialized = true;
+
+        first
+    }
+
+    pub fn deinit(&mut self) {
+        self.initialized = false;
+    }
+}
+
+/// State of a fresh instance, before the frontend sets anything
+const EMPTY: Instance = Instance {
+    callbacks: Callbacks {
+        video_refresh: dummy::video_refresh,
+        input_poll: dummy::input_poll,
+        input_state: dummy::input_state,
+        audio_sample_batch: dummy::audio_sample_batch,
+        environment: dummy::environment,
+    },
+    hw_render: hw_context::INITIAL_RENDER_CALLBACK,
+    context: None,
+    initialized: false,
+};
+
+#[cfg(not(feature = "multi-instance"))]
+static mut INSTANCE: Instance = EMPTY;
+
+/// Return the instance. The libretro API is not reentrant (apart
+/// from the few entry points which deal with `CONTEXT_BUSY`) so the
+/// caller must not hold the reference across a call to the frontend
+/// which could call back into the core.
+#[cfg(not(feature = "multi-instance"))]
+pub unsafe fn get() -> &'static mut Instance {
+    &mut INSTANCE
+}
+
+#[cfg(feature = "multi-instance")]
+thread_local!(static INSTANCE: ::std::cell::UnsafeCell<Instance> =
+              ::std::cell::UnsafeCell::new(EMPTY));
+
+/// Return the instance of the calling thread, see the
+/// single-instance version for the restrictions
+#[cfg(feature = "multi-instance")]
+pub unsafe fn get() -> &'static mut Instance {
+    // The thread-local lives as long as the thread, which outlives
+    // any libretro call made from it
+    &mut *INSTANCE.with(|i| i.get())
+}
+
+/// Shorthand for the frontend callbacks of the instance
+pub unsafe fn callbacks() -> &'static Callbacks {
+    &get().callbacks
+}
+
+/// Call the frontend's environment callback
+pub unsafe fn environment(which: c_uint, data: *mut c_void) -> bool {
+    (callbacks().environment)(which, data)
+}
@@ -0,0 +1,73 @@
+//! Frontend logging interface
+
+use super::{call_environment_mut, to_cstring, Environment};
+use libc::c_char;
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+/// I'm lying here for convenience: the function is really a
+/// variadic printf-like but Rust won't let me implement a
+/// variadic `dummy_log`. It doesn't matter anyway, we'll let Rust
+/// do all the formatting and simply pass a single ("%s",
+/// "formatted string").
+pub type PrintfFn = extern "C" fn(Level,
+                                  *const c_char,
+                                  *const c_char);
+
+#[repr(C)]
+pub struct Callback {
+    log: PrintfFn,
+}
+
+extern "C" fn dummy_log(_: Level,
+                        _: *const c_char,
+                        _: *const c_char) {
+    panic!("Called missing log callback");
+}
+
+static mut STATIC_LOG: PrintfFn = dummy_log as PrintfFn;
+
+pub fn init() -> bool {
+    let mut cb = Callback { log: dummy_log };
+
+    unsafe {
+        let ok = call_environment_mut(Environment::GetLogInterface,
+                                      &mut cb);
+
+        if ok {
+            STATIC_LOG = cb.log;
+        }
+
+        ok
+    }
+}
+
+/// Send `msg` to the frontend's logger.
+pub fn log(lvl: Level, msg: &str) {
+    // Make sure the message ends in a \n, mandated by the
+    // libretro API.
+
+    let trailing_newline =
+        msg.as_bytes().last().map_or(false, |&c| c == b'\n');
+
+    let format =
+        if trailing_newline {
+            // Message already contains a \n
+            "%s\0"
+        } else {
+            "%s\n\0"
+        };
+
+    let msg = to_cstring(msg);
+
+    unsafe {
+        STATIC_LOG(lvl, format.as_ptr() as *const _, msg.as_ptr());
+    }
+}
@@ -0,0 +1,524 @@
+//! libretro API layer. The FFI definitions live in `ffi`, the
+//! remaining modules wrap the frontend callbacks in a safe(r) Rust
+//! interface. This module holds the libretro entry points which
+//! forward the calls to the `Context` of the loaded game.
+
+use std::ptr;
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+use std::panic;
+use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use libc::{c_void, c_char, c_uint, size_t};
+
+#[macro_use]
+mod variables;
+#[macro_use]
+mod environment;
+mod ffi;
+mod input;
+mod video;
+mod audio;
+mod instance;
+
+pub mod hw_context;
+pub mod perf;
+pub mod log;
+
+pub use self::ffi::*;
+pub use self::variables::*;
+pub use self::environment::*;
+pub use self::input::*;
+pub use self::video::*;
+pub use self::audio::*;
+
+pub trait Context {
+    /// Get the system's audio and video parameters
+    fn get_system_av_info(&self) -> SystemAvInfo;
+    /// Advance the emulation state by one video frame and render it
+    /// to the frontend's framebuffer
+    fn render_frame(&mut self);
+    /// Called when some configuration variables have been
+    /// modified. The core should load the new values and change its
+    /// behavior accordingly.
+    fn refresh_variables(&mut self);
+    /// Reset the game being played
+    fn reset(&mut self);
+    /// The OpenGL context has been reset, it needs to be rebuilt
+    fn gl_context_reset(&mut self);
+    /// The OpenGL context is about to be destroyed
+    fn gl_context_destroy(&mut self);
+    /// Return the maximum size of a save state in bytes
+    fn serialize_size(&self) -> usize;
+    /// Serialize the savestate in the provided buffer
+    fn serialize(&self, &mut [u8]) -> Result<(), ()>;
+    /// Deserialize the savestate from the provided buffer
+    fn unserialize(&mut self, &[u8]) -> Result<(), ()>;
+}
+
+unsafe fn set_context(context: Box<Context>) {
+    let instance = instance::get();
+
+    // `retro_load_game` drops the previous context before creating
+    // the new one, there shouldn't be anything left here
+    if instance.context.is_some() {
+        warn!("Replacing existing context");
+    }
+
+    instance.context = Some(context);
+}
+
+/// Drop the current context, if any. Returns false if there wasn't
+/// one.
+unsafe fn drop_context() -> bool {
+    // Take the context out of the instance before dropping it so that
+    // nothing reached from its destructor can get a reference to a
+    // half-destroyed context
+    match instance::get().context.take() {
+        Some(c) => {
+            drop(c);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Return the current context, None if no game is loaded. The
+/// frontend isn't supposed to call anything requiring a context
+/// outside of `retro_load_game`/`retro_unload_game` but we'd rather
+/// log an error than crash if it does.
+fn context() -> Option<&'static mut Context> {
+    let context = unsafe {
+        instance::get().context.as_mut().map(|c| &mut **c)
+    };
+
+    if context.is_none() {
+        error!("Frontend called the core without a loaded game");
+    }
+
+    context
+}
+
+/// Set while `retro_run`, `retro_serialize` or `retro_unserialize`
+/// is using the context.
+///
+/// Some frontends call `retro_serialize` from an other thread
+/// (RetroArch's threaded video driver while paused, for instance),
+/// that's fine as long as the emulation isn't running at the same
+/// time: the savestate code itself doesn't touch the OpenGL context
+/// when it's not current (see `RetroGl`'s `Serialize` impl) and
+/// loading only stages the state until the next `retro_run`. If
+/// they do overlap the state would be a mix of two frames (or the
+/// emulator would run from a half loaded state) so we refuse to
+/// serialize, there's no serialization quirk to tell the frontend
+/// about it. `retro_run` waits for a serialization in progress
+/// instead, it's short and skipping a frame would desync netplay.
+static CONTEXT_BUSY: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Claim `CONTEXT_BUSY`, returns None if it's already taken
+fn try_claim_context() -> Option<ContextClaim> {
+    if CONTEXT_BUSY.compare_and_swap(false, true, Ordering::Acquire) {
+        None
+    } else {
+        Some(ContextClaim)
+    }
+}
+
+/// Claim `CONTEXT_BUSY`, waiting for the current owner if needed
+fn claim_context() -> ContextClaim {
+    loop {
+        if let Some(c) = try_claim_context() {
+            return c;
+        }
+
+        // A savestate is being made on an other thread
+        thread::yield_now();
+    }
+}
+
+/// Releases `CONTEXT_BUSY` when dropped
+struct ContextClaim;
+
+impl Drop for ContextClaim {
+    fn drop(&mut self) {
+        CONTEXT_BUSY.store(false, Ordering::Release);
+    }
+}
+
+//*******************************************
+// Libretro callbacks loaded by the frontend
+//*******************************************
+
+// The callbacks themselves are stored in the `instance`
+
+unsafe fn call_environment_mut<T>(which: Environment, var: &mut T) -> bool {
+    instance::environment(which as c_uint, var as *mut _ as *mut c_void)
+}
+
+unsafe fn call_environment<T>(which: Environment, var: &T) -> bool {
+    instance::environment(which as c_uint, var as *const _ as *mut c_void)
+}
+
+unsafe fn call_environment_slice<T>(which: Environment, var: &[T]) -> bool {
+    instance::environment(which as c_uint,
+                          var.as_ptr() as *const _ as *mut c_void)
+}
+
+/// Cast a mutable pointer into a mutable reference, return None if
+/// it's NULL.
+fn ptr_as_mut_ref<'a, T>(v: *mut T) -> Option<&'a mut T> {
+
+    if v.is_null() {
+        None
+    } else {
+        Some(unsafe { &mut *v })
+    }
+}
+
+/// Cast a const pointer into a reference, return None if it's NULL.
+fn ptr_as_ref<'a, T>(v: *const T) -> Option<&'a T> {
+
+    if v.is_null() {
+        None
+    } else {
+        Some(unsafe { &*v })
+    }
+}
+
+/// Convert `msg` into a C string for the frontend. Interior NULs
+/// would truncate the message so they're replaced with '?'.
+fn to_cstring(msg: &str) -> CString {
+    let msg = msg.replace('\0', "?");
+
+    // Can't fail, we've just removed all the NULs
+    CString::new(msg).unwrap()
+}
+
+/// Build a PathBuf from a C-string provided by the frontend. If the
+/// C-string doesn't contain a valid Path encoding return
+/// "None". `c_str` *must* be a valid pointer to a C-string.
+#[cfg(unix)]
+fn build_path(cstr: &CStr) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // On unix I assume that the path is an arbitrary null-terminated
+    // byte string
+    Some(PathBuf::from(OsStr::from_bytes(cstr.to_bytes())))
+}
+
+/// Build a PathBuf from a C-string provided by the frontend. If the
+/// C-string doesn't contain a valid Path encoding return
+/// "None". `c_str` *must* be a valid pointer to a C-string.
+#[cfg(not(unix))]
+fn build_path(cstr: &CStr) -> Option<PathBuf> {
+    // On Windows and other non-unices I assume that the path is utf-8
+    // encoded
+    match cstr.to_str() {
+        Ok(s) => Some(PathBuf::from(s)),
+        Err(_) => {
+            error!("The frontend gave us an invalid path: {}",
+                   cstr.to_string_lossy());
+            None
+        }
+    }
+}
+
+//**********************************************
+// Libretro entry points called by the frontend
+//**********************************************
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    // We implement the version 1 of the API
+    1
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    unsafe {
+        instance::get().callbacks.environment = callback
+    }
+
+    ::init_variables();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    unsafe {
+        instance::get().callbacks.video_refresh = callback
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    unsafe {
+        instance::get().callbacks.audio_sample_batch = callback
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    unsafe {
+        instance::get().callbacks.input_poll = callback
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    unsafe {
+        instance::get().callbacks.input_state = callback
+    }
+}
+
+static mut FIRST_INIT: bool = true;
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    // retro_init can potentially be called several times even if the
+    // library hasn't been unloaded (statics are not reset etc...)
+    // which makes it rather useless in my opinion. Let's change that.
+
+    unsafe {
+        if FIRST_INIT {
+            ::init();
+            FIRST_INIT = false;
+        }
+
+        if !instance::get().init() {
+            error!("retro_init called again without retro_deinit. \
+                    Libretro cores can't be instantiated more than \
+                    once per process, if the frontend loaded the core \
+                    twice both instances now share the same callbacks \
+                    and game.");
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    unsafe {
+        instance::get().deinit();
+    }
+
+    // XXX Should I reset the callbacks to the dummy implementations
+    // here?
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    let info = ptr_as_mut_ref(info).unwrap();
+
+    // Strings must be static and, of course, 0-terminated
+    *info = ::SYSTEM_INFO;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let info = ptr_as_mut_ref(info).unwrap();
+
+    if let Some(c) = context() {
+        *info = c.get_system_av_info();
+
+        remember_geometry(&info.geometry);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint,
+                                                   _device: c_uint) {
+    debug!("port device: {} {}", _port, _device);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(c) = context() {
+        c.reset();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let _current = hw_context::make_current();
+
+    let _claim = claim_context();
+
+    (instance::callbacks().input_poll)();
+
+    let context =
+        match context() {
+            Some(c) => c,
+            None => return,
+        };
+
+    let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        if variables_need_update() {
+            context.refresh_variables();
+        }
+
+        context.render_frame();
+    }));
+
+    // Catch panics to cleanly destroy everything. Since this method
+    // is called from a C FFI the panic won't be able to go up anyway.
+    if r.is_err() {
+        drop_context();
+        // This will probably cause an abort
+        panic!("retro_run panicked");
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> size_t {
+    context().map(|c| c.serialize_size()).unwrap_or(0)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize(data: *mut c_void,
+                                  size: size_t) -> bool {
+    let data = unsafe {
+        ::std::slice::from_raw_parts_mut(data as *mut u8, size)
+    };
+
+    // Set the buffer to 0 in case parts of it remain unused, it'll
+    // avoid putting garbage in the save file and might help with
+    // compression
+    for b in data.iter_mut() {
+        *b = 0;
+    }
+
+    let _claim =
+        match try_claim_context() {
+            Some(c) => c,
+            None => {
+                error!("retro_serialize called while the emulation is \
+                        running on an other thread, refusing");
+                return false;
+            }
+        };
+
+    match context() {
+        Some(c) => c.serialize(data).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unserialize(data: *const c_void,
+                                    size: size_t) -> bool {
+    let data = unsafe {
+        ::std::slice::from_raw_parts(data as *const u8, size)
+    };
+
+    let _claim =
+        match try_claim_context() {
+            Some(c) => c,
+            None => {
+                error!("retro_unserialize called while the emulation is \
+                        running on an other thread, refusing");
+                return false;
+            }
+        };
+
+    match context() {
+        Some(c) => c.unserialize(data).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint,
+                                  _enabled: bool,
+                                  _code: *const c_char) {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    let info = ptr_as_ref(info).unwrap();
+
+    if info.path.is_null() {
+        warn!("No path in GameInfo!");
+        return false;
+    }
+
+    let path = unsafe { CStr::from_ptr(info.path) };
+
+    let path =
+        match build_path(path) {
+            Some(p) => p,
+            None => return false,
+        };
+
+    // Some frontends load a new game without unloading the previous
+    // one. Get rid of it first, we can't have two contexts alive at
+    // the same time (the debugger's socket for instance can only be
+    // bound once).
+    if unsafe { drop_context() } {
+        warn!("Loading a game without unloading the previous one");
+    }
+
+    match ::load_game(path) {
+        Some(c) => {
+            unsafe {
+                set_context(c);
+            }
+            true
+        }
+        None => {
+            error!("Couldn't load game!");
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_type: c_uint,
+                                          _info: *const GameInfo,
+                                          _num_info: size_t) -> bool {
+    false
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unload_game()  {
+    if !drop_context() {
+        warn!("retro_unload_game called without a loaded game");
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> size_t {
+    0
+}
+
+#[test]
+fn test_build_path() {
+    let path = CString::new("/tmp/some game.cue").unwrap();
+
+    assert_eq!(build_path(&path),
+               Some(PathBuf::from("/tmp/some game.cue")));
+}
+
+#[test]
+fn test_to_cstring() {
+    assert_eq!(to_cstring("Saved state").as_bytes(), b"Saved state");
+    assert_eq!(to_cstring("a\0b\0").as_bytes(), b"a?b?");
+}
@@ -0,0 +1,55 @@
+//! Frontend performance interface
+
+use super::{call_environment_mut, Environment};
+use libc::c_void;
+
+pub type GetTimeUsecFn = extern "C" fn() -> i64;
+
+/// retro_perf_callback. We only use `get_time_usec` for now, the
+/// other callbacks are there to get the layout right. The
+/// frontend might leave some of them NULL, hence the `Option`s.
+#[repr(C)]
+pub struct Callback {
+    get_time_usec: Option<GetTimeUsecFn>,
+    get_cpu_features: Option<extern "C" fn() -> u64>,
+    get_perf_counter: Option<extern "C" fn() -> u64>,
+    perf_register: Option<extern "C" fn(*mut c_void)>,
+    perf_start: Option<extern "C" fn(*mut c_void)>,
+    perf_stop: Option<extern "C" fn(*mut c_void)>,
+    perf_log: Option<extern "C" fn()>,
+}
+
+static mut STATIC_GET_TIME_USEC: Option<GetTimeUsecFn> = None;
+
+/// Fetch the frontend's performance interface. Returns false if
+/// it's not supported.
+pub fn init() -> bool {
+    let mut cb = Callback {
+        get_time_usec: None,
+        get_cpu_features: None,
+        get_perf_counter: None,
+        perf_register: None,
+        perf_start: None,
+        perf_stop: None,
+        perf_log: None,
+    };
+
+    unsafe {
+        let ok = call_environment_mut(Environment::GetPerfInterface,
+                                      &mut cb);
+
+        if ok {
+            STATIC_GET_TIME_USEC = cb.get_time_usec;
+        }
+
+        ok && STATIC_GET_TIME_USEC.is_some()
+    }
+}
+
+/// Return the frontend's monotonic time in microseconds, or None
+/// if the frontend didn't give us a performance interface
+pub fn time_usec() -> Option<i64> {
+    unsafe {
+        STATIC_GET_TIME_USEC.map(|f| f())
+    }
+}
@@ -0,0 +1,320 @@
+//! Core options ("variables" in libretro parlance)
+
+use std::ptr;
+use std::ffi::{CStr, CString};
+
+use libc::c_char;
+
+use super::{call_environment, call_environment_mut, call_environment_slice};
+use super::{Environment, Variable};
+
+pub fn variables_need_update() -> bool {
+    let mut needs_update = false;
+
+    let ok =
+        unsafe {
+            call_environment_mut(Environment::GetVariableUpdate,
+                                 &mut needs_update)
+        };
+
+    if !ok {
+        panic!("Environment::GetVariableUpdate failed");
+    }
+
+    needs_update
+}
+
+/// `variables` *must* end with a `{ NULL, NULL }` marker
+pub unsafe fn register_variables(variables: &[Variable]) -> bool {
+    call_environment_slice(Environment::SetVariables, variables)
+}
+
+/// Values overriding the frontend's variables, by variable name
+/// (without the prefix)
+static mut VARIABLE_OVERRIDES: Option<Vec<(&'static str, String)>> = None;
+
+/// Override the value of variable `var` (without the prefix), the
+/// frontend's value is ignored until the override is cleared with
+/// `clear_variable_overrides`. Used for stress testing.
+pub fn override_variable(var: &'static str, value: &str) {
+    let overrides = unsafe {
+        VARIABLE_OVERRIDES.get_or_insert_with(Vec::new)
+    };
+
+    overrides.retain(|&(v, _)| v != var);
+    overrides.push((var, value.into()));
+}
+
+pub fn clear_variable_overrides() {
+    unsafe {
+        VARIABLE_OVERRIDES = None;
+    }
+}
+
+pub unsafe fn get_variable<T, E>(var: &str,
+                                 var_cstr: *const c_char,
+                                 parser: fn (&str) -> Result<T, E>) -> T
+{
+    if let Some(ref overrides) = VARIABLE_OVERRIDES {
+        if let Some(&(_, ref value)) = overrides.iter()
+            .find(|&&(v, _)| v == var) {
+            match parser(value) {
+                Ok(v) => return v,
+                Err(_) => panic!("Couldn't parse override {} for {}",
+                                 value, var),
+            }
+        }
+    }
+
+    let mut v = Variable {
+        key: var_cstr as *const _,
+        value: ptr::null(),
+    };
+
+    let ok =
+        call_environment_mut(Environment::GetVariable, &mut v);
+
+    if !ok || v.value.is_null() {
+        panic!("Couldn't get variable {}", var);
+    }
+
+    let value = CStr::from_ptr(v.value).to_str().unwrap();
+
+    match parser(value) {
+        Ok(v) => v,
+        Err(_) => panic!("Couldn't parse variable {}", var),
+    }
+}
+
+/// Return the default value in the variable description `desc`, that
+/// is the first of the possible values
+pub fn default_value(desc: &str) -> Option<&str> {
+    desc.find("; ")
+        .and_then(|pos| desc[pos + 2..].split('|').next())
+}
+
+/// Ask the frontend to set the variable `var_cstr` (with the prefix)
+/// back to the default value in its description `desc`. Returns false
+/// if the frontend doesn't let cores change variables.
+pub unsafe fn reset_variable(var_cstr: *const c_char, desc: &str) -> bool {
+    let value =
+        match default_value(desc).and_then(|v| CString::new(v).ok()) {
+            Some(v) => v,
+            None => panic!("Bad variable description {:?}", desc),
+        };
+
+    let v = Variable {
+        key: var_cstr,
+        value: value.as_ptr(),
+    };
+
+    call_environment(Environment::SetVariable, &v)
+}
+
+macro_rules! cstring {
+    ($x:expr) => {
+        concat!($x, '\0') as *const _ as *const c_char
+    };
+}
+
+/// Create a structure `$st` which will be used to register and access
+/// libretro variables and a structure `$settings` holding a snapshot
+/// of their values:
+///
+/// ```rust
+/// libretro_variables!(
+///     struct MyVariables (prefix = "mycore", settings = MySettings) {
+///         some_option: i32, FromStr::from_str => "Do something; 1|2|3",
+///         enable_stuff: bool, parse_bool => "Enable stuff; enabled|disabled",
+///     });
+///
+/// fn parse_bool(opt: &str) -> Result<bool, ()> {
+///    match opt {
+///        "true" | "enabled" | "on" => Ok(true),
+///        "false" | "disabled" | "off" => Ok(false),
+///        _ => Err(()),
+///    }
+/// }
+///
+/// ```
+///
+/// The variable names given to the frontend will be prefixed with
+/// `$prefix` as mandated by libretro.
+///
+/// $parser must be a function that takes an &str and returns a
+/// Result<T, _> where T is the option type.
+///
+/// The variables can then be registered with the frontend (prefrably
+/// in the `init_variables` callback with:
+///
+/// ```rust
+/// MyVariables::register();
+/// ```
+///
+/// Individual variables can be accessed using getter functions:
+///
+/// ```rust
+/// let value = MyVariables::some_option();
+/// ```
+///
+/// Each getter queries the frontend, code reading many options at
+/// once should take a snapshot instead and pass it around:
+///
+/// ```rust
+/// let settings = MyVariables::snapshot();
+///
+/// let value = settings.some_option;
+/// ```
+///
+/// `MyVariables::defaults()` returns the settings with every variable
+/// set to its default value, without querying the frontend.
+///
+/// `MyVariables::reset_to_defaults()` sets all the variables back to
+/// their default value, if the frontend supports it.
+#[macro_export]
+macro_rules! libretro_variables {
+    (struct $st:ident (prefix = $prefix:expr, settings = $settings:ident) {
+        $($name:ident : $ty:ty , $parser:expr => $str:expr),+$(,)*
+    }) => (
+        struct $st;
+
+        // Not every option is necessarily read through both the
+        // snapshot and the getters
+        #[allow(dead_code)]
+        #[derive(Clone, Debug)]
+        struct $settings {
+            $($name: $ty),+
+        }
+
+        #[allow(dead_code)]
+        impl $st {
+            fn register() {
+
+                let variables = [
+                    $($crate::libretro::Variable {
+                        key: cstring!(concat!($prefix, '_', stringify!($name))),
+                        value: cstring!($str),
+                    }),+,
+                    // End of table marker
+                    $crate::libretro::Variable {
+                        key: ::std::ptr::null() as *const c_char,
+                        value: ::std::ptr::null() as *const c_char,
+                    }
+                    ];
+
+                let ok = unsafe {
+                    $crate::libretro::register_variables(&variables)
+                };
+
+                if !ok {
+                    warn!("Failed to register variables");
+                }
+            }
+
+            fn reset_to_defaults() -> bool {
+                $({
+                    let cstr =
+                        cstring!(concat!($prefix, '_', stringify!($name)));
+
+                    let ok = unsafe {
+                        $crate::libretro::reset_variable(cstr, $str)
+                    };
+
+                    if !ok {
+                        return false;
+                    }
+                })+
+
+                true
+            }
+
+            fn snapshot() -> $settings {
+                $settings {
+                    $($name: $st::$name()),+
+                }
+            }
+
+            fn defaults() -> $settings {
+                $settings {
+                    $($name: {
+                        let value =
+                            match $crate::libretro::default_value($str) {
+                                Some(v) => v,
+                                None => panic!("Bad variable description \
+                                                {:?}", $str),
+                            };
+
+                        match $parser(value) {
+                            Ok(v) => v,
+                            Err(_) => panic!("Couldn't parse default {} \
+                                              for {}",
+                                             value, stringify!($name)),
+                        }
+                    }),+
+                }
+            }
+
+            $(fn $name() -> $ty {
+                let cstr = cstring!(concat!($prefix, '_', stringify!($name)));
+
+                unsafe {
+                    $crate::libretro::get_variable(stringify!($name),
+                                                   cstr,
+                                                   $parser)
+                }
+            })+
+        });
+}
+
+#[test]
+fn test_variable_overrides() {
+    fn parse(opt: &str) -> Result<u32, ()> {
+        opt.parse().map_err(|_| ())
+    }
+
+    override_variable("test_option", "3");
+    // Overriding again replaces the previous value
+    override_variable("test_option", "4");
+
+    // The overridden value is used without querying the frontend
+    let v = unsafe { get_variable("test_option", ptr::null(), parse) };
+
+    assert_eq!(v, 4);
+
+    clear_variable_overrides();
+}
+
+#[test]
+fn test_settings_defaults() {
+    fn parse_bool(opt: &str) -> Result<bool, ()> {
+        match opt {
+            "enabled" => Ok(true),
+            "disabled" => Ok(false),
+            _ => Err(()),
+        }
+    }
+
+    fn parse_u32(opt: &str) -> Result<u32, ()> {
+        opt.parse().map_err(|_| ())
+    }
+
+    libretro_variables!(
+        struct TestVariables (prefix = "test", settings = TestSettings) {
+            some_option: u32, parse_u32 => "Do something; 3|1|2",
+            enable_stuff: bool, parse_bool => "Enable stuff; enabled|disabled",
+        });
+
+    let settings = TestVariables::defaults();
+
+    assert_eq!(settings.some_option, 3);
+    assert!(settings.enable_stuff);
+}
+
+#[test]
+fn test_default_value() {
+    assert_eq!(default_value("Do something; 1|2|3"), Some("1"));
+    assert_eq!(default_value("Enable stuff; enabled"), Some("enabled"));
+    assert_eq!(default_value("Reported refresh rate (restart); \
+                              exact|59.94/50Hz (VRR)"), Some("exact"));
+    assert_eq!(default_value("No values"), None);
+}
@@ -0,0 +1,99 @@
+//! Video output
+
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use libc::c_uint;
+
+use super::{call_environment, Environment};
+use super::instance::callbacks;
+use super::{PixelFormat, GameGeometry, SystemAvInfo};
+
+pub fn gl_frame_done(width: u32, height: u32) {
+    unsafe {
+        // When using a hardware renderer we set the data pointer to
+        // -1 to notify the frontend that the frame has been rendered
+        // in the framebuffer.
+        (callbacks().video_refresh)(-1isize as *const _,
+                                    width as c_uint,
+                                    height as c_uint,
+                                    0);
+    }
+}
+
+/// Tell the frontend to present the previous frame again, only valid
+/// if `can_dupe` returned true
+pub fn frame_dupe(width: u32, height: u32) {
+    unsafe {
+        (callbacks().video_refresh)(ptr::null(),
+                                    width as c_uint,
+                                    height as c_uint,
+                                    0);
+    }
+}
+
+pub fn set_pixel_format(format: PixelFormat) -> bool {
+    let f = format as c_uint;
+
+    unsafe {
+        call_environment(Environment::SetPixelFormat, &f)
+    }
+}
+
+/// Last geometry reported to the frontend: base width, base height
+/// and the bits of the aspect ratio. All 0 if unknown.
+static GEOMETRY_WIDTH: AtomicUsize = ATOMIC_USIZE_INIT;
+static GEOMETRY_HEIGHT: AtomicUsize = ATOMIC_USIZE_INIT;
+static GEOMETRY_ASPECT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Remember `geom` as the frontend's current geometry. Must be called
+/// whenever the frontend is told about a new geometry, including
+/// through `retro_get_system_av_info`.
+pub fn remember_geometry(geom: &GameGeometry) {
+    GEOMETRY_WIDTH.store(geom.base_width as usize, Ordering::Relaxed);
+    GEOMETRY_HEIGHT.store(geom.base_height as usize, Ordering::Relaxed);
+    GEOMETRY_ASPECT.store(geom.aspect_ratio.to_bits() as usize,
+                          Ordering::Relaxed);
+}
+
+fn is_current_geometry(geom: &GameGeometry) -> bool {
+    GEOMETRY_WIDTH.load(Ordering::Relaxed) == geom.base_width as usize &&
+        GEOMETRY_HEIGHT.load(Ordering::Relaxed) ==
+        geom.base_height as usize &&
+        GEOMETRY_ASPECT.load(Ordering::Relaxed) ==
+        geom.aspect_ratio.to_bits() as usize
+}
+
+/// Change the output size and aspect ratio. The call is skipped if
+/// they're the same as the last ones reported: the renderer is
+/// rebuilt every time the frontend resets the GL context and it
+/// doesn't know what the frontend is already using, some frontends
+/// reconfigure their video output on every call even if nothing
+/// changed.
+pub fn set_geometry(geom: &GameGeometry) -> bool {
+    if is_current_geometry(geom) {
+        return true;
+    }
+
+    let ok =
+        unsafe {
+            call_environment(Environment::SetGeometry, geom)
+        };
+
+    if ok {
+        remember_geometry(geom);
+    }
+
+    ok
+}
+
+/// Can destroy the OpenGL context!
+pub unsafe fn set_system_av_info(av_info: &SystemAvInfo) -> bool {
+    let ok = call_environment(Environment::SetSystemAvInfo, av_info);
+
+    if ok {
+        remember_geometry(&av_info.geometry);
+    }
+
+    ok
+}
@@ -0,0 +1,242 @@
+//! Frame pacing monitor. We measure the interval between two
+//! consecutive `retro_run` calls with the frontend's clock and compare
+//! it to the refresh rate we report. Frames coming much later than
+//! expected have been dropped somewhere, frames coming much earlier
+//! mean the frontend is catching up (or presenting duplicates).
+//!
+//! The time spent in the core is measured alongside: if the core is
+//! well within its budget while the intervals are irregular, the
+//! stutter comes from the frontend or the video driver and not from
+//! the emulation.
+
+use libretro;
+
+/// Number of frames in a measurement window
+const WINDOW_FRAMES: u32 = 300;
+
+/// An interval longer than this means the emulation was paused (or
+/// the frontend was in its menu), it's not a pacing problem
+const PAUSE_THRESHOLD_NS: u64 = 500_000_000;
+
+/// Fraction of irregular frames over a window above which the
+/// pacing is reported
+const IRREGULAR_THRESHOLD: f32 = 0.02;
+
+/// Results of a measurement window
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PacingReport {
+    /// Number of frames measured
+    pub frames: u32,
+    /// Frames coming more than half a period late
+    pub late: u32,
+    /// Frames coming more than half a period early
+    pub early: u32,
+    /// Longest interval between two frames in milliseconds
+    pub longest_ms: f32,
+    /// Average time spent in the core per frame in milliseconds
+    pub core_ms: f32,
+    /// Longest time spent in the core for a frame in milliseconds
+    pub core_max_ms: f32,
+}
+
+impl PacingReport {
+    /// Return true if the window had too many irregular frames
+    pub fn irregular(&self) -> bool {
+        let irregular = (self.late + self.early) as f32;
+
+        self.frames > 0 && irregular / self.frames as f32 > IRREGULAR_THRESHOLD
+    }
+}
+
+pub struct Pacing {
+    /// Expected interval between two frames in nanoseconds
+    period: u64,
+    /// Start of the current frame
+    frame_start: Option<u64>,
+    /// Statistics for the current window
+    frames: u32,
+    late: u32,
+    early: u32,
+    longest: u64,
+    core_total: u64,
+    core_max: u64,
+    /// Result of the last complete window
+    report: Option<PacingReport>,
+}
+
+impl Pacing {
+    /// Monitor the pacing for a frontend running us at `fps`
+    pub fn new(fps: f32) -> Pacing {
+        let mut pacing = Pacing {
+            period: 0,
+            frame_start: None,
+            frames: 0,
+            late: 0,
+            early: 0,
+            longest: 0,
+            core_total: 0,
+            core_max: 0,
+            report: None,
+        };
+
+        pacing.set_fps(fps);
+
+        pacing
+    }
+
+    /// Change the expected frame rate, the current window is
+    /// discarded
+    pub fn set_fps(&mut self, fps: f32) {
+        let period = (1e9 / fps) as u64;
+
+        if period != self.period {
+            self.period = period;
+            self.restart();
+        }
+    }
+
+    /// Called at the beginning of a frame, `now` is a timestamp in
+    /// nanoseconds
+    pub fn frame_start(&mut self, now: u64) {
+        let previous = self.frame_start.take();
+
+        self.frame_start = Some(now);
+
+        if libretro::is_fast_forwarding() {
+            // Not expected to be paced at all
+            self.restart();
+            return;
+        }
+
+        let previous =
+            match previous {
+                Some(p) => p,
+                None => return,
+            };
+
+        let interval = now.saturating_sub(previous);
+
+        if interval > PAUSE_THRESHOLD_NS {
+            self.restart();
+            return;
+        }
+
+        self.add_interval(interval);
+    }
+
+    /// Called once the core is done with the frame
+    pub fn frame_end(&mut self, now: u64) {
+        if let Some(start) = self.frame_start {
+            let t = now.saturating_sub(start);
+
+            self.core_total += t;
+            self.core_max = ::std::cmp::max(self.core_max, t);
+        }
+    }
+
+    /// Return the result of the last complete window
+    pub fn report(&self) -> Option<PacingReport> {
+        self.report
+    }
+
+    fn add_interval(&mut self, interval: u64) {
+        let half = self.period / 2;
+
+        if interval > self.period + half {
+            self.late += 1;
+        } else if interval < half {
+            self.early += 1;
+        }
+
+        self.longest = ::std::cmp::max(self.longest, interval);
+        self.frames += 1;
+
+        if self.frames < WINDOW_FRAMES {
+            return;
+        }
+
+        let ms = |ns: u64| ns as f32 / 1e6;
+
+        let report = PacingReport {
+            frames: self.frames,
+            late: self.late,
+            early: self.early,
+            longest_ms: ms(self.longest),
+            core_ms: ms(self.core_total / self.frames as u64),
+            core_max_ms: ms(self.core_max),
+        };
+
+        let was_irregular = self.report.map_or(false, |r| r.irregular());
+
+        if report.irregular() {
+            let culprit =
+                if report.core_max_ms < ms(self.period) {
+                    "the core kept up, check the frontend's video \
+                     and sync settings"
+                } else {
+                    "the core took longer than a frame period"
+                };
+
+            let msg = format!("Irregular frame pacing: {} late and {} \
+                               early frames out of {} (longest interval \
+                               {:.1}ms, expected {:.1}ms). Core time \
+                               {:.1}ms average, {:.1}ms max: {}",
+                              report.late, report.early, report.frames,
+                              report.longest_ms, ms(self.period),
+                              report.core_ms, report.core_max_ms,
+                              culprit);
+
+            // Only warn when it starts, then keep it quiet
+            if was_irregular {
+                debug!("{}", msg);
+            } else {
+                warn!("{}", msg);
+            }
+        } else if was_irregular {
+            info!("Frame pacing back to normal");
+        }
+
+        self.report = Some(report);
+        self.restart();
+    }
+
+    /// Start a new measurement window
+    fn restart(&mut self) {
+        self.frames = 0;
+        self.late = 0;
+        self.early = 0;
+        self.longest = 0;
+        self.core_total = 0;
+        self.core_max = 0;
+    }
+}
+
+#[test]
+fn test_pacing() {
+    let mut pacing = Pacing::new(50.);
+
+    // 20ms period: regular, then one dropped and one early frame
+    for _ in 0..(WINDOW_FRAMES - 2) {
+        pacing.add_interval(20_000_000);
+    }
+
+    assert!(pacing.report().is_none());
+
+    pacing.add_interval(40_000_000);
+    pacing.add_interval(5_000_000);
+
+    let report = pacing.report().unwrap();
+
+    assert_eq!((report.frames, report.late, report.early),
+               (WINDOW_FRAMES, 1, 1));
+    assert_eq!(report.longest_ms, 40.);
+    assert!(!report.irregular());
+
+    for i in 0..WINDOW_FRAMES {
+        let interval = if i % 10 == 0 { 40_000_000 } else { 20_000_000 };
+
+        pacing.add_interval(interval);
+    }
+
+    assert!(pacing.report().unwrap().irregular());
+}
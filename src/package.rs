@@ -0,0 +1,239 @@
+//! Homebrew packages: ZIP archives containing an executable and an
+//! optional manifest describing it. They use the `.psxpkg` extension
+//! so that frontends hand us the archive itself instead of extracting
+//! it (and discarding the manifest).
+//!
+//! The manifest is a `manifest.txt` file at the root of the archive
+//! made of `key = value` lines:
+//!
+//! ```text
+//! # Comments start with a hash
+//! title = My Homebrew Game
+//! exe = bin/game.exe
+//! ```
+//!
+//! Without manifest (or without `exe` key) the archive must contain a
+//! single executable. The executable is extracted to the EXE cache
+//! and loaded like a standalone one, the title replaces the content
+//! label.
+//!
+//! XXX The `icon` key is accepted but unused, libretro has no way to
+//! show it. Encrypted archives aren't supported either, the ZIP
+//! encryption schemes are too weak to protect anything anyway.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+use psexe;
+use error::Error;
+
+/// A package ready to be loaded
+pub struct Package {
+    /// Title from the manifest, or the package file name
+    pub title: String,
+    /// Path of the extracted executable
+    pub exe: PathBuf,
+}
+
+/// Return true if the file at `path` should be loaded as a package
+pub fn is_package(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => ext.to_string_lossy().to_lowercase() == EXTENSION,
+        None => false,
+    }
+}
+
+/// Open the package at `path` and extract its executable
+pub fn open(path: &Path) -> Result<Package, Error> {
+    let bad = |e: String| Error::BadPackage(path.to_path_buf(), e);
+
+    let file =
+        match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(Error::Io(path.to_path_buf(), e)),
+        };
+
+    let mut archive = try!(ZipArchive::new(file)
+                           .map_err(|e| bad(format!("{}", e))));
+
+    let manifest =
+        match archive.by_name(MANIFEST) {
+            Ok(mut f) => {
+                let mut text = String::new();
+
+                try!(f.read_to_string(&mut text)
+                     .map_err(|e| bad(format!("can't read manifest: {}",
+                                              e))));
+
+                try!(Manifest::parse(&text).map_err(&bad))
+            }
+            Err(ZipError::FileNotFound) => Manifest::default(),
+            Err(e) => return Err(bad(format!("{}", e))),
+        };
+
+    let member =
+        match manifest.exe {
+            Some(ref exe) => exe.clone(),
+            None => {
+                let exes: Vec<String> =
+                    (0..archive.len())
+                    .filter_map(|i| archive.by_index(i).ok()
+                                .map(|f| f.name().to_owned()))
+                    .filter(|n| is_executable_name(n))
+                    .collect();
+
+                match exes.len() {
+                    1 => exes[0].clone(),
+                    0 => return Err(bad("no executable found".into())),
+                    _ => return Err(bad(format!("{} executables found, \
+                                                 the manifest must tell \
+                                                 which one to load",
+                                                exes.len()))),
+                }
+            }
+        };
+
+    let data = {
+        let mut f =
+            match archive.by_name(&member) {
+                Ok(f) => f,
+                Err(e) => return Err(bad(format!("{}: {}", member, e))),
+            };
+
+        // Don't let a bogus (or malicious) package fill up the disk
+        if f.size() > MAX_EXE_SIZE {
+            return Err(bad(format!("{} is too big ({} bytes)",
+                                   member, f.size())));
+        }
+
+        let mut data = Vec::new();
+
+        // The size in the header can't be trusted, limit the amount
+        // we actually decompress as well
+        try!(f.by_ref().take(MAX_EXE_SIZE + 1).read_to_end(&mut data)
+             .map_err(|e| bad(format!("{}: {}", member, e))));
+
+        if data.len() as u64 > MAX_EXE_SIZE {
+            return Err(bad(format!("{} is too big (more than {} bytes)",
+                                   member, MAX_EXE_SIZE)));
+        }
+
+        data
+    };
+
+    if !psexe::is_executable(&data) {
+        return Err(bad(format!("{} isn't a PlayStation executable",
+                               member)));
+    }
+
+    let name =
+        match Path::new(&member).file_name() {
+            Some(n) => n.to_owned(),
+            None => return Err(bad(format!("bad member name {}", member))),
+        };
+
+    let exe = try!(psexe::cache_directory()).join(name);
+
+    let res = File::create(&exe).and_then(|mut f| f.write_all(&data));
+
+    if let Err(e) = res {
+        return Err(Error::Io(exe, e));
+    }
+
+    let title =
+        match manifest.title {
+            Some(t) => t,
+            None =>
+                path.file_stem()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| member.clone()),
+        };
+
+    info!("Loading {} from homebrew package \"{}\"", member, title);
+
+    Ok(Package {
+        title: title,
+        exe: exe,
+    })
+}
+
+fn is_executable_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+
+    EXE_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct Manifest {
+    title: Option<String>,
+    exe: Option<String>,
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Manifest, String> {
+        let mut manifest = Manifest::default();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) =
+                match line.find('=') {
+                    Some(pos) => (line[..pos].trim(),
+                                  line[pos + 1..].trim().to_owned()),
+                    None => return Err(format!("manifest line {}: \
+                                                expected key = value",
+                                               n + 1)),
+                };
+
+            match key {
+                "title" => manifest.title = Some(value),
+                "exe" => manifest.exe = Some(value),
+                "icon" => (),
+                _ => warn!("Unknown package manifest key {:?}", key),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Extension of the package files, in lowercase
+const EXTENSION: &'static str = "psxpkg";
+
+/// Name of the manifest in the archive
+const MANIFEST: &'static str = "manifest.txt";
+
+/// Executables picked automatically when there's no manifest
+const EXE_EXTENSIONS: [&'static str; 4] = [".exe", ".psexe", ".psx", ".cpe"];
+
+/// Maximum size of the executable: a CPE file loading the whole RAM
+/// with plenty of room for chunk headers
+const MAX_EXE_SIZE: u64 = 4 * 1024 * 1024;
+
+#[test]
+fn test_manifest() {
+    let manifest = Manifest::parse("# Test package\n\
+                                    \n\
+                                    title = Some Game = Fun\n\
+                                    exe=bin/GAME.EXE\n\
+                                    icon = icon.png\n").unwrap();
+
+    assert_eq!(manifest, Manifest {
+        title: Some("Some Game = Fun".into()),
+        exe: Some("bin/GAME.EXE".into()),
+    });
+
+    assert_eq!(Manifest::parse("").unwrap(), Manifest::default());
+    assert!(Manifest::parse("title\n").is_err());
+
+    assert!(is_executable_name("bin/GAME.EXE"));
+    assert!(!is_executable_name("readme.txt"));
+}
@@ -0,0 +1,177 @@
+//! Play time tracking. Frontends keep track of the time spent on each
+//! content file which is useless for multi-disc games, so we keep our
+//! own count per disc serial. Only the frames emulated at normal
+//! speed are counted: the frontend doesn't call us while the game is
+//! paused and we skip the frames run while fast-forwarding.
+//!
+//! The session time is logged when the game is unloaded and, if
+//! enabled, added to the totals stored in `rustation/playtime.json`
+//! in the save directory.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use rustc_serialize::json;
+
+use time;
+
+use libretro;
+
+/// Play time totals for one disc
+#[derive(RustcEncodable, RustcDecodable, Clone, Default)]
+struct Record {
+    /// Total play time in seconds
+    seconds: u64,
+    /// Number of sessions played
+    sessions: u32,
+    /// Date of the end of the last session, in seconds since the
+    /// epoch
+    last_played: i64,
+}
+
+/// Play time totals, by disc serial
+type Records = BTreeMap<String, Record>;
+
+pub struct PlayTime {
+    /// Identifier of the content being played (disc serial)
+    id: String,
+    /// Duration of one frame in microseconds
+    frame_us: u64,
+    /// Time played during this session in microseconds
+    session_us: u64,
+    /// If true the session time is added to the totals in the save
+    /// directory when the game is unloaded
+    save: bool,
+}
+
+impl PlayTime {
+    /// Start a new session for `id`, running at `fps` frames per
+    /// second
+    pub fn new(id: String, fps: f32) -> PlayTime {
+        PlayTime {
+            id: id,
+            frame_us: (1_000_000. / fps) as u64,
+            session_us: 0,
+            save: false,
+        }
+    }
+
+    pub fn set_save(&mut self, save: bool) {
+        self.save = save;
+    }
+
+    /// Called after every emulated frame
+    pub fn frame(&mut self) {
+        if !libretro::is_fast_forwarding() {
+            self.session_us += self.frame_us;
+        }
+    }
+
+    fn session_seconds(&self) -> u64 {
+        self.session_us / 1_000_000
+    }
+
+    /// Add the session to the totals stored in the save directory and
+    /// return the new total for our content
+    fn store(&self) -> Result<u64, ()> {
+        let path = try!(records_path());
+
+        let mut records = try!(load_records(&path));
+
+        let total = {
+            let record =
+                records.entry(self.id.clone()).or_insert_with(Record::default);
+
+            record.seconds += self.session_seconds();
+            record.sessions += 1;
+            record.last_played = time::get_time().sec;
+
+            record.seconds
+        };
+
+        let encoded = format!("{}\n", json::as_pretty_json(&records));
+
+        if let Err(e) = File::create(&path)
+            .and_then(|mut f| f.write_all(encoded.as_bytes())) {
+            error!("Couldn't write {:?}: {}", path, e);
+            return Err(());
+        }
+
+        Ok(total)
+    }
+}
+
+impl Drop for PlayTime {
+    fn drop(&mut self) {
+        let session = format_duration(self.session_seconds());
+
+        if !self.save {
+            info!("Played {} for {}", self.id, session);
+            return;
+        }
+
+        match self.store() {
+            Ok(total) => info!("Played {} for {} (total {})",
+                               self.id, session, format_duration(total)),
+            Err(_) => warn!("Played {} for {}, couldn't update the total",
+                            self.id, session),
+        }
+    }
+}
+
+fn records_path() -> Result<PathBuf, ()> {
+    let base =
+        match libretro::get_save_directory() {
+            Some(d) => d,
+            None => {
+                error!("The frontend didn't give us a save directory, \
+                        can't store play time");
+                return Err(());
+            }
+        };
+
+    let dir = base.join("rustation");
+
+    if let Err(e) = fs::create_dir_all(&dir) {
+        error!("Couldn't create {:?}: {}", dir, e);
+        return Err(());
+    }
+
+    Ok(dir.join("playtime.json"))
+}
+
+/// Load the totals from `path`. A missing file is treated as empty
+/// but a corrupted one is an error, we don't want to overwrite it.
+fn load_records(path: &PathBuf) -> Result<Records, ()> {
+    let mut contents = String::new();
+
+    match File::open(path) {
+        Ok(mut f) => {
+            if let Err(e) = f.read_to_string(&mut contents) {
+                error!("Couldn't read {:?}: {}", path, e);
+                return Err(());
+            }
+        }
+        // First session
+        Err(_) => return Ok(Records::new()),
+    }
+
+    json::decode(&contents).map_err(|e| {
+        error!("Couldn't parse {:?}: {}", path, e);
+    })
+}
+
+/// Format `secs` as H:MM:SS
+fn format_duration(secs: u64) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(0), "0:00:00");
+    assert_eq!(format_duration(59), "0:00:59");
+    assert_eq!(format_duration(3600 + 2 * 60 + 3), "1:02:03");
+    assert_eq!(format_duration(100 * 3600), "100:00:00");
+}
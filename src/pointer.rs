@@ -0,0 +1,152 @@
+//! Conversion of the frontend's pointer and lightgun coordinates to
+//! PlayStation display and VRAM coordinates. Every pointer-based
+//! device (mouse cursor emulation, GunCon, Justifier...) needs the
+//! same math and it's easy to get subtly wrong, so it lives here.
+//!
+//! Libretro reports pointer positions in the range [-0x7fff, 0x7fff]
+//! over the picture we output, after the frontend applied the aspect
+//! ratio, so the aspect and the frontend's window size don't matter
+//! here. The output covers the display area of the VRAM (minus the
+//! overscan crop, if any) stretched to the full frame.
+//!
+//! XXX rustation doesn't emulate any pointer device yet, nothing uses
+//! this module for now.
+
+#![allow(dead_code)]
+
+use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+
+use retrogl::DrawState;
+
+/// Largest coordinate reported by libretro pointer devices
+const POINTER_MAX: i32 = 0x7fff;
+
+/// Mapping between the frontend's picture and the PlayStation
+/// display
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    /// Coordinates of the top-left corner of the display area in VRAM
+    pub top_left: (u16, u16),
+    /// Display resolution in pixels
+    pub resolution: (u16, u16),
+    /// True if the display is in 24bpp mode, in which case each
+    /// displayed pixel takes 1.5 VRAM pixels
+    pub depth_24bpp: bool,
+    /// Number of columns and lines cropped on each side of the
+    /// display before output
+    pub crop: (u16, u16),
+}
+
+impl Viewport {
+    /// Viewport for the current display configuration, without any
+    /// crop
+    pub fn from_state(state: &DrawState) -> Viewport {
+        Viewport {
+            top_left: state.display_top_left,
+            resolution: state.display_resolution,
+            depth_24bpp: state.display_24bpp,
+            crop: (0, 0),
+        }
+    }
+
+    /// Convert libretro pointer coordinates into display coordinates,
+    /// (0, 0) being the top-left pixel of the display area. Returns
+    /// None if the display area is empty.
+    pub fn pointer_to_display(&self,
+                              (x, y): (i16, i16)) -> Option<(u16, u16)> {
+        let (w, h) = self.resolution;
+        let (crop_x, crop_y) = self.crop;
+
+        let visible_w = w.saturating_sub(crop_x * 2);
+        let visible_h = h.saturating_sub(crop_y * 2);
+
+        if visible_w == 0 || visible_h == 0 {
+            return None;
+        }
+
+        Some((crop_x + scale_pointer(x, visible_w),
+              crop_y + scale_pointer(y, visible_h)))
+    }
+
+    /// Convert display coordinates to VRAM coordinates, wrapping
+    /// around the VRAM edges like the display does
+    pub fn display_to_vram(&self, (x, y): (u16, u16)) -> (u16, u16) {
+        let x =
+            if self.depth_24bpp {
+                (x as u32 * 3 / 2) as u16
+            } else {
+                x
+            };
+
+        ((self.top_left.0 + x) % VRAM_WIDTH_PIXELS,
+         (self.top_left.1 + y) % VRAM_HEIGHT)
+    }
+
+    /// Convert libretro pointer coordinates directly to the
+    /// coordinates of the pointed pixel in the upscaled framebuffer
+    pub fn pointer_to_internal(&self,
+                               pointer: (i16, i16),
+                               upscaling: u32) -> Option<(u32, u32)> {
+        self.pointer_to_display(pointer).map(|d| {
+            let (x, y) = self.display_to_vram(d);
+
+            (x as u32 * upscaling, y as u32 * upscaling)
+        })
+    }
+}
+
+/// Scale a libretro pointer coordinate to a pixel in [0, size)
+fn scale_pointer(coord: i16, size: u16) -> u16 {
+    // Clamp to the valid range, -0x8000 is used for offscreen
+    // positions by some frontends
+    let coord = coord as i32;
+    let coord =
+        if coord < -POINTER_MAX {
+            -POINTER_MAX
+        } else {
+            coord
+        };
+
+    let pos = (coord + POINTER_MAX) as u32 * size as u32;
+
+    let pixel = pos / (POINTER_MAX as u32 * 2);
+
+    // The rightmost (or bottommost) position would be just past the
+    // last pixel
+    ::std::cmp::min(pixel, size as u32 - 1) as u16
+}
+
+#[test]
+fn test_pointer_transform() {
+    let mut v = Viewport {
+        top_left: (0, 256),
+        resolution: (320, 240),
+        depth_24bpp: false,
+        crop: (0, 0),
+    };
+
+    assert_eq!(v.pointer_to_display((-0x7fff, -0x7fff)), Some((0, 0)));
+    assert_eq!(v.pointer_to_display((-0x8000, -0x8000)), Some((0, 0)));
+    assert_eq!(v.pointer_to_display((0x7fff, 0x7fff)), Some((319, 239)));
+    assert_eq!(v.pointer_to_display((0, 0)), Some((160, 120)));
+
+    assert_eq!(v.display_to_vram((160, 120)), (160, 376));
+    assert_eq!(v.pointer_to_internal((0, 0), 2), Some((320, 752)));
+
+    // Cropped borders aren't visible, the picture is stretched over
+    // the remaining pixels
+    v.crop = (8, 8);
+
+    assert_eq!(v.pointer_to_display((-0x7fff, -0x7fff)), Some((8, 8)));
+    assert_eq!(v.pointer_to_display((0x7fff, 0x7fff)), Some((311, 231)));
+
+    // 24bpp pixels take 1.5 VRAM pixels and the display wraps
+    v.depth_24bpp = true;
+    v.top_left = (1000, 500);
+
+    assert_eq!(v.display_to_vram((10, 20)), (1015, 8));
+
+    v.resolution = (0, 240);
+
+    assert_eq!(v.pointer_to_display((0, 0)), None);
+}
@@ -0,0 +1,245 @@
+//! Self-profiling output in the Chrome tracing JSON format. The
+//! generated file can be loaded in chrome://tracing (or any
+//! compatible viewer) to inspect the time spent in each part of a
+//! frame.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{Write, BufWriter};
+use std::path::PathBuf;
+
+use time;
+
+use libretro;
+
+pub struct Profiler {
+    /// Output file and number of events written so far
+    out: RefCell<(BufWriter<File>, u64)>,
+    /// Path of the output file, for logging
+    path: PathBuf,
+    /// Timestamp of the profiler creation, all events are relative to
+    /// it
+    origin: u64,
+}
+
+impl Profiler {
+    /// Create a new trace file in the frontend's save directory
+    pub fn new() -> Result<Profiler, ()> {
+        let base =
+            match libretro::get_save_directory() {
+                Some(d) => d,
+                None => {
+                    error!("The frontend didn't give us a save directory, \
+                            can't write the profiling trace");
+                    return Err(());
+                }
+            };
+
+        let dir = base.join("rustation").join("profiles");
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            error!("Couldn't create {:?}: {}", dir, e);
+            return Err(());
+        }
+
+        let path = dir.join(format!("trace-{}.json", time::get_time().sec));
+
+        let mut out =
+            match File::create(&path) {
+                Ok(f) => BufWriter::new(f),
+                Err(e) => {
+                    error!("Couldn't create {:?}: {}", path, e);
+                    return Err(());
+                }
+            };
+
+        if let Err(e) = out.write_all(b"[\n") {
+            error!("Couldn't write to {:?}: {}", path, e);
+            return Err(());
+        }
+
+        info!("Writing profiling trace to {:?}", path);
+
+        Ok(Profiler {
+            out: RefCell::new((out, 0)),
+            path: path,
+            origin: now(),
+        })
+    }
+
+    /// Record a span called `name` starting at `start` (as returned
+    /// by `now`) and ending now
+    pub fn span(&self, name: &str, start: u64) {
+        let end = now();
+
+        let mut out = self.out.borrow_mut();
+
+        let separator =
+            if out.1 == 0 {
+                ""
+            } else {
+                ",\n"
+            };
+
+        // Chrome wants timestamps in microseconds
+        let res =
+            write!(out.0,
+                   "{}{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\
+                    \"ts\":{},\"dur\":{}}}",
+                   separator,
+                   name,
+                   start.saturating_sub(self.origin) / 1000,
+                   end.saturating_sub(start) / 1000);
+
+        match res {
+            Ok(_) => out.1 += 1,
+            Err(e) => warn!("Couldn't write profiling event: {}", e),
+        }
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        let mut out = self.out.borrow_mut();
+
+        let res = out.0.write_all(b"\n]\n")
+            .and_then(|_| out.0.flush());
+
+        match res {
+            Ok(_) => info!("Profiling trace {:?} complete ({} events)",
+                           self.path, out.1),
+            Err(e) => warn!("Couldn't finalize profiling trace {:?}: {}",
+                            self.path, e),
+        }
+    }
+}
+
+/// Current timestamp in nanoseconds, used as the start of a span.
+/// We use the frontend's clock when available to be consistent with
+/// its own profiling.
+pub fn now() -> u64 {
+    match libretro::perf::time_usec() {
+        Some(us) => (us as u64) * 1000,
+        None => time::precise_time_ns(),
+    }
+}
+
+/// Parts of a frame tracked by `TimeSummary`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Category {
+    Emulation = 0,
+    Render = 1,
+    Savestate = 2,
+}
+
+/// Names of the categories, in the order of their values
+const CATEGORY_NAMES: [&'static str; 3] =
+    ["emulation", "render", "savestates"];
+
+/// Lightweight accounting of the time spent in each `Category`
+/// over the last few frames, always running so that the summary
+/// can be printed at any time (unlike the `Profiler` which must be
+/// enabled beforehand)
+pub struct TimeSummary {
+    /// Start timestamp and time spent in each category for the
+    /// last frames, oldest first
+    frames: VecDeque<(u64, [u64; 3])>,
+    /// Number of frames kept in `frames`
+    len: usize,
+    /// Start of the current frame
+    start: u64,
+    /// Time spent in each category during the current frame. Cells
+    /// since savestates are created through a shared reference.
+    current: [Cell<u64>; 3],
+}
+
+impl TimeSummary {
+    /// Create a summary covering the last `len` frames
+    pub fn new(len: usize) -> TimeSummary {
+        TimeSummary {
+            frames: VecDeque::with_capacity(len),
+            len: len,
+            start: now(),
+            current: [Cell::new(0), Cell::new(0), Cell::new(0)],
+        }
+    }
+
+    /// Account the time elapsed since `start` (as returned by `now`)
+    /// to `category`
+    pub fn add(&self, category: Category, start: u64) {
+        let cell = &self.current[category as usize];
+
+        cell.set(cell.get() + now().saturating_sub(start));
+    }
+
+    /// Start a new frame
+    pub fn new_frame(&mut self) {
+        if self.frames.len() == self.len {
+            self.frames.pop_front();
+        }
+
+        let mut times = [0; 3];
+
+        for (t, c) in times.iter_mut().zip(self.current.iter()) {
+            *t = c.replace(0);
+        }
+
+        self.frames.push_back((self.start, times));
+        self.start = now();
+    }
+
+    /// Return the number of complete frames covered and the time
+    /// spent in each category, sorted by decreasing duration. The
+    /// time spent outside of all categories (in the frontend or
+    /// idle) is reported as "other".
+    pub fn summary(&self) -> (usize, Vec<(&'static str, u64)>) {
+        let wall =
+            match self.frames.front() {
+                Some(&(start, _)) => self.start.saturating_sub(start),
+                None => 0,
+            };
+
+        let mut totals: Vec<(&'static str, u64)> =
+            CATEGORY_NAMES.iter()
+            .enumerate()
+            .map(|(i, &name)| {
+                (name, self.frames.iter().map(|&(_, t)| t[i]).sum::<u64>())
+            })
+            .collect();
+
+        let accounted: u64 = totals.iter().map(|&(_, t)| t).sum();
+
+        totals.push(("other", wall.saturating_sub(accounted)));
+
+        totals.sort_by(|a, b| b.1.cmp(&a.1));
+
+        (self.frames.len(), totals)
+    }
+}
+
+#[test]
+fn test_time_summary() {
+    let mut summary = TimeSummary::new(2);
+
+    // Fake three frames of 10ms, only the last two are kept
+    for &(start, emulation, render) in &[(0, 9, 1), (10, 6, 2), (20, 3, 5)] {
+        let ms = 1_000_000;
+
+        summary.frames.push_back((start * ms, [emulation * ms,
+                                               render * ms, 0]));
+
+        if summary.frames.len() > summary.len {
+            summary.frames.pop_front();
+        }
+    }
+
+    summary.start = 30_000_000;
+
+    let (frames, totals) = summary.summary();
+
+    assert_eq!(frames, 2);
+    assert_eq!(totals[0], ("emulation", 9_000_000));
+    assert_eq!(totals[1], ("render", 7_000_000));
+    assert_eq!(totals[2], ("other", 4_000_000));
+}
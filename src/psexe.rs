@@ -0,0 +1,354 @@
+//! Pre-validation of executables before handing them over to
+//! rustation's EXE loader.
+//!
+//! The loader only understands well-formed PS-X EXE files. Here we
+//! check the header to give meaningful error messages, fix up EXEs
+//! with unusual (but harmless) text section sizes and convert Psy-Q
+//! CPE files into regular PS-X EXEs.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use libretro;
+use error::Error;
+
+/// Inspect the file at `path`. Returns `Ok(None)` if it's neither a
+/// PS-X EXE nor a CPE file, `Ok(Some(p))` with the path of the EXE
+/// that should be given to the loader (which may be a converted copy)
+/// or an error if the file looks like an executable but is corrupt.
+pub fn prepare(path: &Path) -> Result<Option<PathBuf>, Error> {
+    let data =
+        match read_file(path) {
+            Ok(d) => d,
+            Err(e) => return Err(Error::Io(path.to_path_buf(), e)),
+        };
+
+    let converted =
+        if data.starts_with(EXE_MAGIC) {
+            match check_exe(&data) {
+                Ok(None) => return Ok(Some(path.to_path_buf())),
+                Ok(Some(fixed)) => fixed,
+                Err(e) => {
+                    let e = format!("corrupt PS-X EXE: {}", e);
+
+                    return Err(Error::BadExe(path.to_path_buf(), e));
+                }
+            }
+        } else if data.starts_with(CPE_MAGIC) {
+            match cpe_to_exe(&data) {
+                Ok(exe) => exe,
+                Err(e) => {
+                    let e = format!("corrupt CPE file: {}", e);
+
+                    return Err(Error::BadExe(path.to_path_buf(), e));
+                }
+            }
+        } else {
+            return Ok(None);
+        };
+
+    let cache = try!(cache_directory());
+
+    let name =
+        path.file_stem()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "program".into());
+
+    let exe_path = cache.join(format!("{}.exe", name));
+
+    let res = File::create(&exe_path)
+        .and_then(|mut f| f.write_all(&converted));
+
+    match res {
+        Ok(_) => {
+            info!("Loading converted executable {:?}", exe_path);
+            Ok(Some(exe_path))
+        }
+        Err(e) => Err(Error::Io(exe_path, e)),
+    }
+}
+
+/// Validate a PS-X EXE header. Returns `Ok(None)` if the file can be
+/// loaded as-is or a fixed up copy if the text section size doesn't
+/// match the file size.
+fn check_exe(data: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    if data.len() < HEADER_SIZE {
+        return Err(format!("file too small for the header ({} bytes)",
+                           data.len()));
+    }
+
+    let pc = read_u32_le(&data[0x10..]);
+    let text_addr = read_u32_le(&data[0x18..]);
+    let text_size = read_u32_le(&data[0x1c..]) as usize;
+
+    if !in_ram(text_addr) {
+        return Err(format!("text address 0x{:08x} is not in RAM",
+                           text_addr));
+    }
+
+    if !in_ram(pc) {
+        return Err(format!("entry point 0x{:08x} is not in RAM", pc));
+    }
+
+    let available = data.len() - HEADER_SIZE;
+
+    if text_size > RAM_SIZE || available > RAM_SIZE {
+        return Err(format!("text section too large ({} bytes)",
+                           ::std::cmp::max(text_size, available)));
+    }
+
+    let padded_size = round_up(available, TEXT_ALIGN);
+
+    if ram_offset(text_addr) + padded_size > RAM_SIZE {
+        return Err(format!("text section at 0x{:08x} overflows RAM",
+                           text_addr));
+    }
+
+    if text_size == available && text_size == padded_size {
+        // Nothing unusual
+        return Ok(None);
+    }
+
+    // Some tools write a text size that doesn't match the file (or
+    // isn't a multiple of 2KB). Trust the file contents and rebuild a
+    // consistent header.
+    warn!("PS-X EXE text size ({} bytes) doesn't match the file ({} bytes), \
+           fixing it up", text_size, available);
+
+    let mut fixed = data.to_vec();
+
+    fixed.resize(HEADER_SIZE + padded_size, 0);
+    write_u32_le(&mut fixed[0x1c..], padded_size as u32);
+
+    Ok(Some(fixed))
+}
+
+/// Convert a Psy-Q CPE file into a PS-X EXE
+fn cpe_to_exe(data: &[u8]) -> Result<Vec<u8>, String> {
+    // RAM image built from the load chunks
+    let mut ram = vec![0u8; RAM_SIZE];
+    // Range of the RAM that has been loaded
+    let mut start = RAM_SIZE;
+    let mut end = 0;
+    let mut pc = None;
+
+    let mut pos = CPE_MAGIC.len();
+
+    loop {
+        let chunk = try!(read_bytes(data, &mut pos, 1))[0];
+
+        match chunk {
+            // End of file
+            0x00 => break,
+            // Load data
+            0x01 => {
+                let addr = read_u32_le(try!(read_bytes(data, &mut pos, 4)));
+                let len = read_u32_le(try!(read_bytes(data, &mut pos, 4)));
+                let payload = try!(read_bytes(data, &mut pos, len as usize));
+
+                if !in_ram(addr) {
+                    return Err(format!("load address 0x{:08x} is not in RAM",
+                                       addr));
+                }
+
+                let offset = ram_offset(addr);
+
+                if offset + payload.len() > RAM_SIZE {
+                    return Err(format!("load at 0x{:08x} overflows RAM",
+                                       addr));
+                }
+
+                ram[offset..offset + payload.len()].copy_from_slice(payload);
+
+                start = ::std::cmp::min(start, offset);
+                end = ::std::cmp::max(end, offset + payload.len());
+            }
+            // Run address
+            0x02 => {
+                pc = Some(read_u32_le(try!(read_bytes(data, &mut pos, 4))));
+            }
+            // Set 32bit register
+            0x03 => {
+                let reg = read_u16_le(try!(read_bytes(data, &mut pos, 2)));
+                let val = read_u32_le(try!(read_bytes(data, &mut pos, 4)));
+
+                if reg == CPE_REG_PC {
+                    pc = Some(val);
+                }
+            }
+            // Set 16, 8 and 24bit registers, we only care about the PC
+            0x04 => { try!(read_bytes(data, &mut pos, 2 + 2)); }
+            0x05 => { try!(read_bytes(data, &mut pos, 2 + 1)); }
+            0x06 => { try!(read_bytes(data, &mut pos, 2 + 3)); }
+            // Select workspace
+            0x07 => { try!(read_bytes(data, &mut pos, 4)); }
+            // Select unit
+            0x08 => { try!(read_bytes(data, &mut pos, 1)); }
+            c => return Err(format!("unknown chunk type 0x{:02x} at offset {}",
+                                    c, pos - 1)),
+        }
+    }
+
+    if start >= end {
+        return Err("no data to load".into());
+    }
+
+    let pc =
+        match pc {
+            Some(pc) => pc,
+            None => return Err("no entry point".into()),
+        };
+
+    if !in_ram(pc) {
+        return Err(format!("entry point 0x{:08x} is not in RAM", pc));
+    }
+
+    // The text section must start on a word boundary
+    let start = start & !3;
+
+    Ok(build_exe(pc, 0x8000_0000 | start as u32, &ram[start..end]))
+}
+
+/// Build a PS-X EXE loading `text` at `text_addr` and starting at
+/// `pc`. The text section is padded to a multiple of 2KB.
+pub fn build_exe(pc: u32, text_addr: u32, text: &[u8]) -> Vec<u8> {
+    let text_size = round_up(text.len(), TEXT_ALIGN);
+
+    let mut exe = vec![0u8; HEADER_SIZE];
+
+    exe[0..8].copy_from_slice(EXE_MAGIC);
+    write_u32_le(&mut exe[0x10..], pc);
+    write_u32_le(&mut exe[0x18..], text_addr);
+    write_u32_le(&mut exe[0x1c..], text_size as u32);
+    // Default stack at the top of RAM
+    write_u32_le(&mut exe[0x30..], 0x801f_fff0);
+
+    exe.extend_from_slice(text);
+    exe.resize(HEADER_SIZE + text_size, 0);
+
+    exe
+}
+
+/// Return `len` bytes at `*pos` and advance `*pos`
+fn read_bytes<'a>(data: &'a [u8],
+                  pos: &mut usize,
+                  len: usize) -> Result<&'a [u8], String> {
+    let start = *pos;
+
+    if data.len() - start < len {
+        return Err(format!("truncated file (expected {} bytes at offset {})",
+                           len, start));
+    }
+
+    *pos += len;
+
+    Ok(&data[start..start + len])
+}
+
+/// Return true if `addr` points to main RAM in any of the KUSEG,
+/// KSEG0 or KSEG1 regions
+fn in_ram(addr: u32) -> bool {
+    match addr >> 29 {
+        0 | 4 | 5 => (addr & 0x1fff_ffff) < RAM_SIZE as u32,
+        _ => false,
+    }
+}
+
+fn ram_offset(addr: u32) -> usize {
+    (addr & 0x1fff_ffff) as usize
+}
+
+fn round_up(v: usize, align: usize) -> usize {
+    (v + align - 1) / align * align
+}
+
+/// Return true if `data` looks like a PS-X EXE or CPE file
+pub fn is_executable(data: &[u8]) -> bool {
+    data.starts_with(EXE_MAGIC) || data.starts_with(CPE_MAGIC)
+}
+
+/// Return the directory where we store converted executables,
+/// creating it if necessary
+pub fn cache_directory() -> Result<PathBuf, Error> {
+    let base =
+        match libretro::get_save_directory() {
+            Some(d) => d,
+            None =>
+                return Err(Error::NoSaveDirectory("convert the executable")),
+        };
+
+    let dir = base.join("rustation").join("exe-cache");
+
+    match fs::create_dir_all(&dir) {
+        Ok(_) => Ok(dir),
+        Err(e) => Err(Error::Io(dir, e)),
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut v = Vec::new();
+
+    try!(try!(File::open(path)).read_to_end(&mut v));
+
+    Ok(v)
+}
+
+fn read_u16_le(b: &[u8]) -> u16 {
+    b[0] as u16 | ((b[1] as u16) << 8)
+}
+
+fn read_u32_le(b: &[u8]) -> u32 {
+    b[0] as u32 |
+    ((b[1] as u32) << 8) |
+    ((b[2] as u32) << 16) |
+    ((b[3] as u32) << 24)
+}
+
+fn write_u32_le(b: &mut [u8], v: u32) {
+    b[0] = v as u8;
+    b[1] = (v >> 8) as u8;
+    b[2] = (v >> 16) as u8;
+    b[3] = (v >> 24) as u8;
+}
+
+const EXE_MAGIC: &'static [u8] = b"PS-X EXE";
+const CPE_MAGIC: &'static [u8] = b"CPE\x01";
+
+/// Size of the PS-X EXE header
+const HEADER_SIZE: usize = 2048;
+/// The text section size must be a multiple of this
+const TEXT_ALIGN: usize = 2048;
+/// Size of the main RAM
+const RAM_SIZE: usize = 2 * 1024 * 1024;
+/// Register number of the PC in CPE "set register" chunks
+const CPE_REG_PC: u16 = 0x90;
+
+#[test]
+fn test_cpe_to_exe() {
+    let mut cpe = CPE_MAGIC.to_vec();
+
+    // Select unit 0
+    cpe.extend_from_slice(&[0x08, 0x00]);
+    // Load 4 bytes at 0x80010000
+    cpe.extend_from_slice(&[0x01,
+                            0x00, 0x00, 0x01, 0x80,
+                            0x04, 0x00, 0x00, 0x00,
+                            0xde, 0xad, 0xbe, 0xef]);
+    // PC = 0x80010000
+    cpe.extend_from_slice(&[0x03, 0x90, 0x00, 0x00, 0x00, 0x01, 0x80]);
+    // End
+    cpe.push(0x00);
+
+    let exe = cpe_to_exe(&cpe).unwrap();
+
+    assert_eq!(&exe[0..8], EXE_MAGIC);
+    assert_eq!(exe.len(), HEADER_SIZE + TEXT_ALIGN);
+    assert_eq!(read_u32_le(&exe[0x10..]), 0x8001_0000);
+    assert_eq!(read_u32_le(&exe[0x18..]), 0x8001_0000);
+    assert_eq!(read_u32_le(&exe[0x1c..]), TEXT_ALIGN as u32);
+    assert_eq!(&exe[HEADER_SIZE..HEADER_SIZE + 4], &[0xde, 0xad, 0xbe, 0xef]);
+
+    // Truncated chunk
+    assert!(cpe_to_exe(&cpe[..10]).is_err());
+}
@@ -0,0 +1,110 @@
+//! Internal quick-save slots. The states are stored per game in the
+//! save directory as `rustation/<serial>/slot<N>.state`, which keeps
+//! them organized even with frontends that don't namespace their
+//! savestates per core.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use time;
+
+use error::Error;
+use libretro;
+
+/// Number of quick-save slots
+pub const SLOTS: u32 = 8;
+
+/// Return the directory holding the states for `content`, creating
+/// it if necessary
+fn directory(content: &str) -> Result<PathBuf, Error> {
+    let base =
+        match libretro::get_save_directory() {
+            Some(d) => d,
+            None => return Err(Error::NoSaveDirectory("quick-save")),
+        };
+
+    let dir = base.join("rustation").join(sanitize(content));
+
+    match fs::create_dir_all(&dir) {
+        Ok(_) => Ok(dir),
+        Err(e) => Err(Error::Io(dir, e)),
+    }
+}
+
+/// Return the path of the state file for `slot`
+pub fn slot_path(content: &str, slot: u32) -> Result<PathBuf, Error> {
+    directory(content).map(|d| d.join(format!("slot{}.state", slot)))
+}
+
+/// Log the state of every slot for `content` and return a short
+/// description of `selected`
+pub fn list(content: &str, selected: u32) -> Result<String, Error> {
+    let dir = try!(directory(content));
+
+    info!("Quick-save slots in {:?}:", dir);
+
+    let mut description = String::new();
+
+    for slot in 1..(SLOTS + 1) {
+        let path = dir.join(format!("slot{}.state", slot));
+
+        let desc =
+            match fs::metadata(&path).and_then(|m| m.modified()) {
+                Ok(t) => {
+                    let secs =
+                        t.duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+
+                    format!("slot {}: {}", slot, format_timestamp(secs))
+                }
+                Err(_) => format!("slot {}: empty", slot),
+            };
+
+        info!("  {}", desc);
+
+        if slot == selected {
+            description = desc;
+        }
+    }
+
+    Ok(description)
+}
+
+fn format_timestamp(secs: u64) -> String {
+    let tm = time::at(time::Timespec::new(secs as i64, 0));
+
+    time::strftime("%Y-%m-%d %H:%M:%S", &tm)
+        .unwrap_or_else(|_| secs.to_string())
+}
+
+/// Make `name` usable as a directory name
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            }
+        })
+        .collect()
+}
+
+/// Parse the slot number core option
+pub fn parse_slot(opt: &str) -> Result<u32, ()> {
+    match opt.parse() {
+        Ok(n) if n >= 1 && n <= SLOTS => Ok(n),
+        _ => Err(()),
+    }
+}
+
+#[test]
+fn test_sanitize() {
+    assert_eq!(sanitize("SCUS-94163"), "SCUS-94163");
+    assert_eq!(sanitize("a/b:c?"), "a_b_c_");
+    assert_eq!(parse_slot("3"), Ok(3));
+    assert!(parse_slot("0").is_err());
+    assert!(parse_slot("9").is_err());
+}
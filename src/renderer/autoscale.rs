@@ -0,0 +1,242 @@
+//! Dynamic internal resolution: measure the time taken by the GPU to
+//! render each frame using timer queries and step the upscaling
+//! factor down when we can't keep up with the frame rate (and back up
+//! when there's enough headroom).
+
+use gl;
+use gl::types::{GLuint, GLint, GLuint64};
+
+use retrogl::error::{Error, error_or};
+
+/// Number of timer queries in flight. We only read the result of a
+/// query a few frames after issuing it in order not to stall the
+/// pipeline waiting for the GPU.
+const QUERY_RING: usize = 3;
+
+/// Fraction of the frame period above which we lower the upscaling
+/// factor. The CPU-side emulation needs time too so we can't use the
+/// whole frame for rendering.
+const HIGH_LOAD: f32 = 0.75;
+/// Fraction of the frame period the estimated cost of the next
+/// factor must stay under before we attempt to raise it
+const LOW_LOAD: f32 = 0.60;
+/// Number of frames to wait after a change before considering a new
+/// one, it gives the average time to settle and avoids oscillations
+const SETTLE_FRAMES: u32 = 120;
+/// Weight of the newest sample in the frame time moving average
+const SMOOTHING: f32 = 0.05;
+
+/// Ring of GL_TIME_ELAPSED queries
+pub struct GpuTimer {
+    queries: [GLuint; QUERY_RING],
+    /// Set for queries whose result hasn't been read yet
+    pending: [bool; QUERY_RING],
+    /// Index of the next query to issue
+    next: usize,
+}
+
+impl GpuTimer {
+    pub fn new() -> Result<GpuTimer, Error> {
+        let mut queries = [0; QUERY_RING];
+
+        unsafe {
+            gl::GenQueries(QUERY_RING as GLint, queries.as_mut_ptr());
+        }
+
+        error_or(GpuTimer {
+            queries: queries,
+            pending: [false; QUERY_RING],
+            next: 0,
+        })
+    }
+
+    /// Start timing a new frame
+    pub fn begin(&mut self) {
+        // If the result of the query we're about to reuse still
+        // hasn't been read it's lost, that's not a big deal
+        self.pending[self.next] = false;
+
+        unsafe {
+            gl::BeginQuery(gl::TIME_ELAPSED, self.queries[self.next]);
+        }
+    }
+
+    /// Stop timing the current frame
+    pub fn end(&mut self) {
+        unsafe {
+            gl::EndQuery(gl::TIME_ELAPSED);
+        }
+
+        self.pending[self.next] = true;
+        self.next = (self.next + 1) % QUERY_RING;
+    }
+
+    /// Return the GPU time of the oldest pending frame in
+    /// nanoseconds if it's available
+    pub fn poll(&mut self) -> Option<u64> {
+        // The oldest query is the one we'll reuse next
+        let index = self.next;
+
+        if !self.pending[index] {
+            return None;
+        }
+
+        let query = self.queries[index];
+
+        let mut available = 0;
+
+        unsafe {
+            gl::GetQueryObjectiv(query,
+                                 gl::QUERY_RESULT_AVAILABLE,
+                                 &mut available);
+        }
+
+        if available == 0 {
+            return None;
+        }
+
+        let mut elapsed: GLuint64 = 0;
+
+        unsafe {
+            gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut elapsed);
+        }
+
+        self.pending[index] = false;
+
+        Some(elapsed)
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteQueries(QUERY_RING as GLint, self.queries.as_ptr());
+        }
+    }
+}
+
+/// Upscaling factor controller
+pub struct AutoScale {
+    min: u32,
+    max: u32,
+    /// Current upscaling factor
+    factor: u32,
+    /// Moving average of the GPU frame time, in nanoseconds. None
+    /// until we get the first sample after a change.
+    average: Option<f32>,
+    /// Number of samples received since the last change
+    samples: u32,
+}
+
+impl AutoScale {
+    /// Create a new controller stepping between `min` and `max`,
+    /// starting at `factor` (clamped to the range)
+    pub fn new(min: u32, max: u32, factor: u32) -> AutoScale {
+        // If the configuration doesn't make sense we can't go
+        // above the max
+        let min = ::std::cmp::min(min, max);
+
+        let factor = ::std::cmp::max(min, ::std::cmp::min(factor, max));
+
+        AutoScale {
+            min: min,
+            max: max,
+            factor: factor,
+            average: None,
+            samples: 0,
+        }
+    }
+
+    pub fn factor(&self) -> u32 {
+        self.factor
+    }
+
+    /// Lower the maximum factor to `max`, used when we can't
+    /// allocate the framebuffer for higher factors
+    pub fn cap(&mut self, max: u32) {
+        self.max = ::std::cmp::min(self.max, max);
+        self.min = ::std::cmp::min(self.min, self.max);
+        self.factor = ::std::cmp::min(self.factor, self.max);
+    }
+
+    /// Feed the GPU time of a frame (in nanoseconds) with the
+    /// duration of a frame period. Returns the new factor if it must
+    /// be changed.
+    pub fn update(&mut self, gpu_time: u64, frame_period: u64) -> Option<u32> {
+        let sample = gpu_time as f32;
+
+        let average =
+            match self.average {
+                Some(a) => a + (sample - a) * SMOOTHING,
+                None => sample,
+            };
+
+        self.average = Some(average);
+        self.samples += 1;
+
+        if self.samples < SETTLE_FRAMES {
+            return None;
+        }
+
+        let load = average / frame_period as f32;
+
+        let new_factor =
+            if load > HIGH_LOAD && self.factor > self.min {
+                self.factor - 1
+            } else if self.factor < self.max {
+                // The cost is roughly proportional to the number of
+                // pixels
+                let f = self.factor as f32;
+                let next = f + 1.;
+
+                if load * (next * next) / (f * f) < LOW_LOAD {
+                    self.factor + 1
+                } else {
+                    return None;
+                }
+            } else {
+                return None;
+            };
+
+        info!("Dynamic resolution: GPU load {:.0}%, switching to {}x",
+              load * 100., new_factor);
+
+        self.factor = new_factor;
+        self.average = None;
+        self.samples = 0;
+
+        Some(new_factor)
+    }
+}
+
+#[test]
+fn test_autoscale() {
+    // 60fps
+    let period = 16_666_667;
+
+    let mut scale = AutoScale::new(1, 4, 4);
+
+    // Heavy load: step down once the average has settled
+    for _ in 0..(SETTLE_FRAMES - 1) {
+        assert_eq!(scale.update(15_000_000, period), None);
+    }
+
+    assert_eq!(scale.update(15_000_000, period), Some(3));
+
+    // Moderate load: no change
+    for _ in 0..(SETTLE_FRAMES * 2) {
+        assert_eq!(scale.update(8_000_000, period), None);
+    }
+
+    // Light load: 1ms at 3x should be about 1.8ms at 4x
+    for _ in 0..(SETTLE_FRAMES - 1) {
+        assert_eq!(scale.update(1_000_000, period), None);
+    }
+
+    assert_eq!(scale.update(1_000_000, period), Some(4));
+    assert_eq!(scale.factor(), 4);
+
+    // Out of range start factor is clamped
+    assert_eq!(AutoScale::new(2, 3, 10).factor(), 3);
+    assert_eq!(AutoScale::new(5, 3, 1).factor(), 3);
+}
@@ -0,0 +1,150 @@
+//! Synthetic renderer benchmark, run when the game is loaded if the
+//! "Renderer benchmark on startup" core option is enabled. It draws a
+//! large number of textured, semi-transparent triangles through the
+//! regular command pipeline and uploads the whole VRAM a few times
+//! in order to give an idea of what the GPU can sustain at the
+//! current internal resolution.
+
+use gl;
+
+use time;
+
+use rustation::gpu::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use rustation::gpu::renderer::{TextureDepth, BlendMode, SemiTransparencyMode};
+use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+
+use retrogl::error::{Error, get_error};
+
+use super::GlRenderer;
+
+/// Number of triangles drawn for the throughput measurement
+const TRIANGLES: u32 = 100_000;
+/// Number of full VRAM uploads for the bandwidth measurement
+const UPLOADS: u32 = 32;
+/// Size of the area the triangles are drawn into, in native pixels
+const AREA: (u16, u16) = (320, 240);
+
+pub struct Results {
+    /// Triangles drawn per second
+    pub triangles: f64,
+    /// VRAM upload bandwidth in MB/s
+    pub upload: f64,
+}
+
+/// Run the benchmark using `renderer`. The contents of `fb_out` are
+/// overwritten with the raw VRAM contents, this should only be called
+/// right after the renderer's been created.
+pub fn run(renderer: &mut GlRenderer) -> Result<Results, Error> {
+    let saved = renderer.draw_config().state();
+
+    // Don't let the dynamic resolution controller see the benchmark,
+    // it would think the GPU can't keep up
+    let gpu_timer = renderer.gpu_timer.take();
+
+    let results = measure(renderer);
+
+    renderer.restore_draw_state(&saved);
+    renderer.gpu_timer = gpu_timer;
+
+    results
+}
+
+fn measure(renderer: &mut GlRenderer) -> Result<Results, Error> {
+    renderer.prepare_render();
+    renderer.set_draw_offset(0, 0);
+    renderer.set_draw_area((0, 0), AREA);
+
+    let attributes = PrimitiveAttributes {
+        semi_transparent: true,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::Blended,
+        texture_page: [0, 0],
+        clut: [0, 480],
+        texture_depth: TextureDepth::T4Bpp,
+        dither: true,
+    };
+
+    let mut rng = 0x1234_5678u32;
+
+    let start = finish();
+
+    for _ in 0..TRIANGLES {
+        let x = (xorshift(&mut rng) % (AREA.0 as u32 - 32)) as i16;
+        let y = (xorshift(&mut rng) % (AREA.1 as u32 - 32)) as i16;
+
+        let vertices = [vertex(x, y, [0, 0]),
+                        vertex(x + 32, y, [31, 0]),
+                        vertex(x, y + 32, [0, 31])];
+
+        renderer.push_triangle(&attributes, &vertices);
+    }
+
+    try!(renderer.draw());
+
+    let triangle_time = finish() - start;
+
+    let vram = renderer.config.vram.clone();
+
+    let start = finish();
+
+    // This also copies the VRAM to `fb_out`, erasing our triangles
+    for _ in 0..UPLOADS {
+        try!(renderer.upload_textures((0, 0),
+                                      (VRAM_WIDTH_PIXELS, VRAM_HEIGHT),
+                                      &vram));
+    }
+
+    let upload_time = finish() - start;
+
+    try!(get_error());
+
+    let upload_bytes = vram.len() as f64 * 2. * UPLOADS as f64;
+
+    Ok(Results {
+        triangles: per_second(TRIANGLES as f64, triangle_time),
+        upload: per_second(upload_bytes / (1024. * 1024.), upload_time),
+    })
+}
+
+/// Wait for the GPU to be done with all the pending commands and
+/// return the current time in nanoseconds
+fn finish() -> u64 {
+    unsafe {
+        gl::Finish();
+    }
+
+    time::precise_time_ns()
+}
+
+fn per_second(count: f64, duration_ns: u64) -> f64 {
+    // Avoid dividing by zero with coarse clocks
+    let duration_ns = ::std::cmp::max(duration_ns, 1);
+
+    count * 1_000_000_000. / duration_ns as f64
+}
+
+fn xorshift(state: &mut u32) -> u32 {
+    let mut s = *state;
+
+    s ^= s << 13;
+    s ^= s >> 17;
+    s ^= s << 5;
+
+    *state = s;
+
+    s
+}
+
+fn vertex(x: i16, y: i16, texture_coord: [u8; 2]) -> Vertex {
+    Vertex {
+        position: [x, y],
+        color: [0x80, 0x80, 0x80],
+        texture_coord: [texture_coord[0] as _, texture_coord[1] as _],
+    }
+}
+
+#[test]
+fn test_per_second() {
+    assert!((per_second(1000., 500_000_000) - 2000.).abs() < 0.001);
+    assert!(per_second(1., 0).is_finite());
+}
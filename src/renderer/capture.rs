@@ -0,0 +1,437 @@
+//! Capture of the rendered frames to disk, used to produce
+//! self-contained artifacts for rendering bug reports.
+//!
+//! Each captured frame is dumped as a PNG (the display area of
+//! `fb_out` at the internal resolution) and the GPU state for every
+//! frame is appended to a text file in the same directory.
+//!
+//! The whole VRAM can also be dumped on demand, which is the most
+//! useful artifact for texture corruption reports.
+//!
+//! Native screenshots contain the display area decoded straight from
+//! the VRAM at 1x, whatever the internal resolution, for users
+//! archiving the original graphics.
+
+use std::fs::{self, File};
+use std::io::{self, Write, BufWriter};
+use std::path::{Path, PathBuf};
+
+use time;
+
+use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+
+use libretro;
+use worker;
+
+/// Capture in progress
+pub struct FrameCapture {
+    /// Directory where the frames are stored
+    directory: PathBuf,
+    /// Text file holding the GPU state of each captured frame
+    state_log: BufWriter<File>,
+    /// Number of frames left to capture
+    remaining: u32,
+    /// Number of frames captured so far
+    captured: u32,
+}
+
+impl FrameCapture {
+    /// Prepare a capture of the next `count` frames in a new
+    /// directory in the frontend's save directory
+    pub fn new(count: u32) -> Result<FrameCapture, ()> {
+        let name = format!("capture-{}", time::get_time().sec);
+
+        let directory = try!(create_directory(&name));
+
+        let state_path = directory.join("gpu-state.txt");
+
+        let state_log =
+            match File::create(&state_path) {
+                Ok(f) => BufWriter::new(f),
+                Err(e) => {
+                    error!("Couldn't create {:?}: {}", state_path, e);
+                    return Err(());
+                }
+            };
+
+        info!("Capturing {} frames to {:?}", count, directory);
+
+        Ok(FrameCapture {
+            directory: directory,
+            state_log: state_log,
+            remaining: count,
+            captured: 0,
+        })
+    }
+
+    /// Store a frame. `pixels` contains `resolution` 8bit RGBA
+    /// pixels, top row first. `state` is a textual description of
+    /// the GPU state. Returns false once the capture is over.
+    ///
+    /// The PNG is encoded and written by a worker thread.
+    pub fn push_frame(&mut self,
+                      pixels: Vec<u8>,
+                      resolution: (u32, u32),
+                      state: &str) -> bool {
+        let png_path =
+            self.directory.join(format!("frame-{:04}.png", self.captured));
+
+        worker::spawn(move || {
+            if let Err(e) = write_png(&png_path, resolution, &pixels) {
+                error!("Couldn't write {:?}: {}", png_path, e);
+            }
+        });
+
+        let res = writeln!(self.state_log,
+                           "Frame {}: {}x{}\n{}",
+                           self.captured, resolution.0, resolution.1, state)
+            .and_then(|_| self.state_log.flush());
+
+        if let Err(e) = res {
+            error!("Couldn't write the GPU state log: {}", e);
+        }
+
+        self.captured += 1;
+        self.remaining = self.remaining.saturating_sub(1);
+
+        if self.remaining == 0 {
+            info!("Captured {} frames to {:?}", self.captured, self.directory);
+            libretro_message!(200, "Captured {} frames", self.captured);
+
+            false
+        } else {
+            true
+        }
+    }
+}
+
+/// Dump the CPU-side copy of the VRAM to a PNG. If `rendered` is
+/// provided it contains the RGBA contents of `fb_out` (at the
+/// internal resolution) along with its dimensions and it's dumped
+/// alongside.
+pub fn dump_vram(vram: &[u16],
+                 rendered: Option<(&[u8], (u32, u32))>) -> Result<(), ()> {
+    let name = format!("vram-{}", time::get_time().sec);
+
+    let directory = try!(create_directory(&name));
+
+    let path = directory.join("vram.png");
+
+    let mut rgba = Vec::with_capacity(vram.len() * 4);
+
+    push_rgba_1555(&mut rgba, vram);
+
+    let resolution = (VRAM_WIDTH_PIXELS as u32, VRAM_HEIGHT as u32);
+
+    if let Err(e) = write_png(&path, resolution, &rgba) {
+        error!("Couldn't write {:?}: {}", path, e);
+        return Err(());
+    }
+
+    if let Some((pixels, resolution)) = rendered {
+        let path = directory.join("vram-rendered.png");
+
+        if let Err(e) = write_png(&path, resolution, pixels) {
+            error!("Couldn't write {:?}: {}", path, e);
+            return Err(());
+        }
+    }
+
+    info!("VRAM dumped to {:?}", directory);
+    libretro_message!(200, "VRAM dumped");
+
+    Ok(())
+}
+
+/// Save the display area of `vram` as a PNG, without any scaling.
+/// The parameters are those of the GPU's display configuration.
+pub fn native_screenshot(vram: &[u16],
+                         top_left: (u16, u16),
+                         resolution: (u16, u16),
+                         depth_24bpp: bool) -> Result<(), ()> {
+    let (rgba, resolution) =
+        decode_display(vram, top_left, resolution, depth_24bpp);
+
+    if resolution.0 == 0 || resolution.1 == 0 {
+        warn!("Empty display area, no screenshot taken");
+        return Err(());
+    }
+
+    let directory = try!(create_directory("screenshots"));
+
+    let path =
+        directory.join(format!("screenshot-{}.png", time::get_time().sec));
+
+    worker::spawn(move || {
+        match write_png(&path, resolution, &rgba) {
+            Ok(_) => info!("Screenshot saved to {:?}", path),
+            Err(e) => error!("Couldn't write {:?}: {}", path, e),
+        }
+    });
+
+    libretro_message!(100, "Native resolution screenshot saved");
+
+    Ok(())
+}
+
+/// Convert the display area of `vram` to RGBA. Returns the pixels
+/// and the dimensions of the image, which are clipped to the VRAM
+/// boundaries.
+pub fn decode_display(vram: &[u16],
+                      (x, y): (u16, u16),
+                      (w, h): (u16, u16),
+                      depth_24bpp: bool) -> (Vec<u8>, (u32, u32)) {
+    let stride = VRAM_WIDTH_PIXELS as usize;
+
+    let x = x as usize;
+    let y = y as usize;
+
+    let h = ::std::cmp::min(h as usize, VRAM_HEIGHT as usize - y);
+
+    // In 24bpp mode each displayed pixel takes 1.5 VRAM pixels
+    let max_w =
+        if depth_24bpp {
+            (stride - x) * 2 / 3
+        } else {
+            stride - x
+        };
+
+    let w = ::std::cmp::min(w as usize, max_w);
+
+    let mut rgba = Vec::with_capacity(w * h * 4);
+
+    for line in y..y + h {
+        let start = line * stride + x;
+
+        if depth_24bpp {
+            let words = &vram[start..start + (w * 3 + 1) / 2];
+
+            let byte = |i: usize| {
+                let v = words[i / 2];
+
+                if i & 1 == 0 {
+                    v as u8
+                } else {
+                    (v >> 8) as u8
+                }
+            };
+
+            for p in 0..w {
+                let i = p * 3;
+
+                rgba.extend_from_slice(&[byte(i),
+                                         byte(i + 1),
+                                         byte(i + 2),
+                                         0xff]);
+            }
+        } else {
+            push_rgba_1555(&mut rgba, &vram[start..start + w]);
+        }
+    }
+
+    (rgba, (w as u32, h as u32))
+}
+
+/// Convert 1555 `pixels` to 8bit RGBA and append them to `rgba`
+fn push_rgba_1555(rgba: &mut Vec<u8>, pixels: &[u16]) {
+    for &p in pixels {
+        let r = (p & 0x1f) as u8;
+        let g = ((p >> 5) & 0x1f) as u8;
+        let b = ((p >> 10) & 0x1f) as u8;
+
+        rgba.extend_from_slice(&[r << 3, g << 3, b << 3, 0xff]);
+    }
+}
+
+/// Create directory `name` for a capture or dump in the frontend's
+/// save directory
+fn create_directory(name: &str) -> Result<PathBuf, ()> {
+    let base =
+        match libretro::get_save_directory() {
+            Some(d) => d,
+            None => {
+                error!("The frontend didn't give us a save directory, \
+                        can't store captures");
+                return Err(());
+            }
+        };
+
+    let directory = base.join("rustation").join("captures").join(name);
+
+    if let Err(e) = fs::create_dir_all(&directory) {
+        error!("Couldn't create {:?}: {}", directory, e);
+        return Err(());
+    }
+
+    Ok(directory)
+}
+
+/// Write `rgba` as an uncompressed 8bit RGB PNG. We don't bother
+/// with compression (or alpha, which is meaningless for the
+/// PlayStation output), the files are only meant to be attached to
+/// bug reports.
+fn write_png(path: &Path,
+             (width, height): (u32, u32),
+             rgba: &[u8]) -> io::Result<()> {
+    let mut out = BufWriter::new(try!(File::create(path)));
+
+    try!(out.write_all(b"\x89PNG\r\n\x1a\n"));
+
+    let mut header = Vec::with_capacity(13);
+
+    push_u32_be(&mut header, width);
+    push_u32_be(&mut header, height);
+    // 8bit depth, truecolor, deflate, no filtering, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    try!(write_chunk(&mut out, b"IHDR", &header));
+
+    // Each scanline starts with the filter type (0: None)
+    let mut raw = Vec::with_capacity((height * (width * 3 + 1)) as usize);
+
+    for line in rgba.chunks((width * 4) as usize) {
+        raw.push(0);
+
+        for pixel in line.chunks(4) {
+            raw.extend_from_slice(&pixel[0..3]);
+        }
+    }
+
+    try!(write_chunk(&mut out, b"IDAT", &zlib_stored(&raw)));
+    try!(write_chunk(&mut out, b"IEND", &[]));
+
+    out.flush()
+}
+
+fn write_chunk(out: &mut Write,
+               chunk_type: &[u8; 4],
+               data: &[u8]) -> io::Result<()> {
+    let mut len = Vec::with_capacity(4);
+    push_u32_be(&mut len, data.len() as u32);
+
+    try!(out.write_all(&len));
+    try!(out.write_all(chunk_type));
+    try!(out.write_all(data));
+
+    let crc = crc32(crc32(!0, chunk_type), data);
+
+    let mut crc_bytes = Vec::with_capacity(4);
+    push_u32_be(&mut crc_bytes, !crc);
+
+    out.write_all(&crc_bytes)
+}
+
+/// Wrap `data` in a zlib stream using uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Maximum length of a stored deflate block
+    const BLOCK_MAX: usize = 0xffff;
+
+    let nblocks = (data.len() + BLOCK_MAX - 1) / BLOCK_MAX;
+
+    let mut out = Vec::with_capacity(data.len() + nblocks * 5 + 6);
+
+    // CMF/FLG: deflate with 32K window, no dictionary, fastest
+    // compression level
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(BLOCK_MAX).peekable();
+
+    if blocks.peek().is_none() {
+        // Empty input, we still need a final block
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8,
+                                !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+
+    push_u32_be(&mut out, adler32(data));
+
+    out
+}
+
+/// Update a CRC-32 (as used by PNG) with `data`. The initial value
+/// should be `!0` and the final value must be inverted.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+
+        for _ in 0..8 {
+            crc =
+                if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+        }
+    }
+
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    for &d in data {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn push_u32_be(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&[(v >> 24) as u8,
+                            (v >> 16) as u8,
+                            (v >> 8) as u8,
+                            v as u8]);
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+}
+
+#[test]
+fn test_decode_display() {
+    let stride = VRAM_WIDTH_PIXELS as usize;
+
+    let mut vram = vec![0u16; stride * VRAM_HEIGHT as usize];
+
+    // 15bpp: pure red, green and blue at (16, 8)
+    vram[8 * stride + 16] = 0x001f;
+    vram[8 * stride + 17] = 0x03e0;
+    vram[8 * stride + 18] = 0x7c00;
+
+    let (rgba, res) = decode_display(&vram, (16, 8), (3, 1), false);
+
+    assert_eq!(res, (3, 1));
+    assert_eq!(rgba, [0xf8, 0, 0, 0xff,
+                      0, 0xf8, 0, 0xff,
+                      0, 0, 0xf8, 0xff]);
+
+    // 24bpp: two pixels packed in three VRAM words
+    vram[stride] = 0x2211;
+    vram[stride + 1] = 0x4433;
+    vram[stride + 2] = 0x6655;
+
+    let (rgba, res) = decode_display(&vram, (0, 1), (2, 1), true);
+
+    assert_eq!(res, (2, 1));
+    assert_eq!(rgba, [0x11, 0x22, 0x33, 0xff,
+                      0x44, 0x55, 0x66, 0xff]);
+
+    // Clipped to the VRAM boundaries
+    let (rgba, res) = decode_display(&vram, (1020, 510), (320, 240), false);
+
+    assert_eq!(res, (4, 2));
+    assert_eq!(rgba.len(), 4 * 2 * 4);
+}
@@ -0,0 +1,217 @@
+//! Renderer crash reports. Many OpenGL failures still end up in an
+//! `unwrap` and the panic message alone rarely says enough to
+//! reproduce the problem without the user's disc. The renderer keeps
+//! a small snapshot of its state up to date and a panic hook writes
+//! it, along with the pending OpenGL errors, to `rustation/crashes/`
+//! in the save directory.
+
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::panic::{self, PanicInfo};
+use std::sync::{Once, ONCE_INIT};
+
+use gl;
+use gl::types::GLenum;
+
+use time;
+
+use libretro;
+use retrogl::DrawState;
+
+/// Maximum number of pending OpenGL errors reported
+const MAX_GL_ERRORS: usize = 16;
+
+/// State of the renderer recorded for crash reports
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    pub draw_state: DrawState,
+    /// Number of opaque vertices waiting to be drawn
+    pub command_vertices: usize,
+    /// Number of semi-transparent vertices waiting to be drawn
+    pub semi_transparent_vertices: usize,
+    pub upscaling: u32,
+    pub color_depth: u8,
+}
+
+// The renderer only runs on the thread holding the OpenGL context
+// and that's the only thread whose panics we're interested in
+thread_local!(static SNAPSHOT: Cell<Option<Snapshot>> = Cell::new(None));
+
+static HOOK: Once = ONCE_INIT;
+
+/// Install the panic hook writing the crash reports. Only the first
+/// call has an effect.
+pub fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            report(info);
+            previous(info);
+        }));
+    });
+}
+
+/// Update the snapshot reported if we panic
+pub fn record(snapshot: Snapshot) {
+    SNAPSHOT.with(|s| s.set(Some(snapshot)));
+}
+
+/// Forget the snapshot, called when the renderer goes away
+pub fn clear() {
+    SNAPSHOT.with(|s| s.set(None));
+}
+
+fn report(info: &PanicInfo) {
+    let snapshot =
+        match SNAPSHOT.with(|s| s.get()) {
+            Some(s) => s,
+            // Not a renderer thread (or no renderer anymore)
+            None => return,
+        };
+
+    // Only one report per renderer, a panic during the cleanup would
+    // overwrite the interesting one
+    clear();
+
+    let errors =
+        if libretro::hw_context::is_current() {
+            pending_gl_errors()
+        } else {
+            Vec::new()
+        };
+
+    let contents = format_report(&panic_message(info), &snapshot, &errors);
+
+    if let Ok(path) = write_report(&contents) {
+        error!("Renderer state written to {:?}", path);
+    }
+}
+
+fn panic_message(info: &PanicInfo) -> String {
+    let payload = info.payload();
+
+    let message =
+        match (payload.downcast_ref::<&str>(),
+               payload.downcast_ref::<String>()) {
+            (Some(s), _) => s.to_string(),
+            (_, Some(s)) => s.clone(),
+            _ => "unknown panic".into(),
+        };
+
+    match info.location() {
+        Some(l) => format!("{} at {}:{}", message, l.file(), l.line()),
+        None => message,
+    }
+}
+
+fn pending_gl_errors() -> Vec<GLenum> {
+    let mut errors = Vec::new();
+
+    while errors.len() < MAX_GL_ERRORS {
+        let e = unsafe { gl::GetError() };
+
+        if e == gl::NO_ERROR {
+            break;
+        }
+
+        errors.push(e);
+    }
+
+    errors
+}
+
+fn format_report(message: &str,
+                 snapshot: &Snapshot,
+                 errors: &[GLenum]) -> String {
+    let state = &snapshot.draw_state;
+
+    let errors =
+        if errors.is_empty() {
+            "none".to_string()
+        } else {
+            errors.iter()
+                .map(|e| format!("0x{:04x}", e))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+    format!("panic: {}\n\
+             display: {:?} {:?} 24bpp: {}\n\
+             draw area: {:?} {:?} offset: {:?}\n\
+             internal upscaling: {}x color depth: {}bpp\n\
+             pending vertices: {} opaque, {} semi-transparent\n\
+             OpenGL errors: {}\n",
+            message,
+            state.display_top_left,
+            state.display_resolution,
+            state.display_24bpp,
+            state.draw_area_top_left,
+            state.draw_area_dimensions,
+            state.draw_offset,
+            snapshot.upscaling,
+            snapshot.color_depth,
+            snapshot.command_vertices,
+            snapshot.semi_transparent_vertices,
+            errors)
+}
+
+fn write_report(contents: &str) -> Result<PathBuf, ()> {
+    let base =
+        match libretro::get_save_directory() {
+            Some(d) => d,
+            None => {
+                error!("The frontend didn't give us a save directory, \
+                        can't write the crash report");
+                return Err(());
+            }
+        };
+
+    let directory = base.join("rustation").join("crashes");
+
+    if let Err(e) = fs::create_dir_all(&directory) {
+        error!("Couldn't create {:?}: {}", directory, e);
+        return Err(());
+    }
+
+    let path =
+        directory.join(format!("renderer-{}.txt", time::get_time().sec));
+
+    let res = File::create(&path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()));
+
+    match res {
+        Ok(_) => Ok(path),
+        Err(e) => {
+            error!("Couldn't write {:?}: {}", path, e);
+            Err(())
+        }
+    }
+}
+
+#[test]
+fn test_format_report() {
+    let snapshot = Snapshot {
+        draw_state: DrawState {
+            display_top_left: (0, 0),
+            display_resolution: (320, 240),
+            display_24bpp: false,
+            draw_offset: (0, 0),
+            draw_area_top_left: (0, 0),
+            draw_area_dimensions: (320, 240),
+        },
+        command_vertices: 12,
+        semi_transparent_vertices: 3,
+        upscaling: 2,
+        color_depth: 16,
+    };
+
+    let report = format_report("oops", &snapshot, &[gl::INVALID_OPERATION]);
+
+    assert!(report.starts_with("panic: oops\n"));
+    assert!(report.contains("pending vertices: 12 opaque, \
+                             3 semi-transparent"));
+    assert!(report.contains("OpenGL errors: 0x0502\n"));
+}
@@ -0,0 +1,254 @@
+//! GPU command dump: a renderer wrapper writing every command it
+//! receives to a text file (one command per line) before forwarding
+//! it to the real renderer. Two dumps of the same content can then be
+//! compared with the `gpudiff` tool to find the first diverging
+//! command when hunting regressions.
+
+use std::fs::{self, File};
+use std::io::{Write, BufWriter};
+
+use rustation::gpu::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use rustation::gpu::renderer::{TextureDepth, BlendMode, SemiTransparencyMode};
+
+use time;
+
+use libretro;
+
+/// Persistent dump state, kept across frames
+pub struct CommandDump {
+    out: BufWriter<File>,
+    /// Number of frames dumped so far
+    frame: u32,
+    /// Set if we encountered a write error, in which case we stop
+    /// dumping
+    failed: bool,
+}
+
+impl CommandDump {
+    /// Create a new dump file for `content` in the save directory
+    pub fn new(content: &str) -> Result<CommandDump, ()> {
+        let base =
+            match libretro::get_save_directory() {
+                Some(d) => d,
+                None => {
+                    error!("The frontend didn't give us a save directory, \
+                            can't dump GPU commands");
+                    return Err(());
+                }
+            };
+
+        let dir = base.join("rustation").join("gpu-dumps");
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            error!("Couldn't create {:?}: {}", dir, e);
+            return Err(());
+        }
+
+        let path = dir.join(format!("{}-{}.txt",
+                                    content, time::get_time().sec));
+
+        let out =
+            match File::create(&path) {
+                Ok(f) => BufWriter::new(f),
+                Err(e) => {
+                    error!("Couldn't create {:?}: {}", path, e);
+                    return Err(());
+                }
+            };
+
+        info!("Dumping GPU commands to {:?}", path);
+
+        Ok(CommandDump {
+            out: out,
+            frame: 0,
+            failed: false,
+        })
+    }
+
+    /// Wrap `renderer` for the duration of a frame
+    pub fn wrap<'a>(&'a mut self,
+                    renderer: &'a mut Renderer) -> DumpingRenderer<'a> {
+        let frame = self.frame;
+
+        self.frame = self.frame.wrapping_add(1);
+
+        self.write(format_args!("frame {}", frame));
+
+        DumpingRenderer {
+            dump: self,
+            renderer: renderer,
+        }
+    }
+
+    fn write(&mut self, line: ::std::fmt::Arguments) {
+        if self.failed {
+            return;
+        }
+
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            error!("Couldn't write GPU command dump, giving up: {}", e);
+            self.failed = true;
+        }
+    }
+
+    fn primitive(&mut self,
+                 primitive: &str,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex]) {
+        let mut line =
+            format!("{} {}", primitive, format_attributes(attributes));
+
+        for v in vertices {
+            line.push_str(&format!(" {},{}/{},{},{}/{},{}",
+                                   v.position[0], v.position[1],
+                                   v.color[0], v.color[1], v.color[2],
+                                   v.texture_coord[0], v.texture_coord[1]));
+        }
+
+        self.write(format_args!("{}", line));
+    }
+}
+
+impl Drop for CommandDump {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.flush() {
+            error!("Couldn't flush GPU command dump: {}", e);
+        }
+    }
+}
+
+fn format_attributes(attributes: &PrimitiveAttributes) -> String {
+    let semi_transparency_mode =
+        match attributes.semi_transparency_mode {
+            SemiTransparencyMode::Average => 0,
+            SemiTransparencyMode::Add => 1,
+            SemiTransparencyMode::SubstractSource => 2,
+            SemiTransparencyMode::AddQuarterSource => 3,
+        };
+
+    let blend_mode =
+        match attributes.blend_mode {
+            BlendMode::None => 0,
+            BlendMode::Raw => 1,
+            BlendMode::Blended => 2,
+        };
+
+    let texture_depth =
+        match attributes.texture_depth {
+            TextureDepth::T4Bpp => 4,
+            TextureDepth::T8Bpp => 8,
+            TextureDepth::T16Bpp => 16,
+        };
+
+    format!("st={}:{} blend={} page={},{} clut={},{} depth={} dither={}",
+            attributes.semi_transparent as u8,
+            semi_transparency_mode,
+            blend_mode,
+            attributes.texture_page[0], attributes.texture_page[1],
+            attributes.clut[0], attributes.clut[1],
+            texture_depth,
+            attributes.dither as u8)
+}
+
+/// FNV-1a hash of the pixels, we don't want to dump whole images
+fn hash_pixels(pixels: &[u16]) -> u32 {
+    let mut hash = 0x811c_9dc5u32;
+
+    for &p in pixels {
+        for &b in &[p as u8, (p >> 8) as u8] {
+            hash ^= b as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    hash
+}
+
+/// Renderer wrapper dumping every command before passing it on to
+/// the real renderer
+pub struct DumpingRenderer<'a> {
+    dump: &'a mut CommandDump,
+    renderer: &'a mut Renderer,
+}
+
+impl<'a> Renderer for DumpingRenderer<'a> {
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.dump.write(format_args!("draw_offset {} {}", x, y));
+
+        self.renderer.set_draw_offset(x, y)
+    }
+
+    fn set_draw_area(&mut self, top_left: (u16, u16), dimensions: (u16, u16)) {
+        self.dump.write(format_args!("draw_area {} {} {} {}",
+                                     top_left.0, top_left.1,
+                                     dimensions.0, dimensions.1));
+
+        self.renderer.set_draw_area(top_left, dimensions)
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.dump.write(format_args!("display_mode {} {} {} {} {}",
+                                     top_left.0, top_left.1,
+                                     resolution.0, resolution.1,
+                                     depth_24bpp as u8));
+
+        self.renderer.set_display_mode(top_left, resolution, depth_24bpp)
+    }
+
+    fn push_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 2]) {
+        self.dump.primitive("line", attributes, vertices);
+
+        self.renderer.push_line(attributes, vertices)
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: &[Vertex; 3]) {
+        self.dump.primitive("triangle", attributes, vertices);
+
+        self.renderer.push_triangle(attributes, vertices)
+    }
+
+    fn push_quad(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 4]) {
+        self.dump.primitive("quad", attributes, vertices);
+
+        self.renderer.push_quad(attributes, vertices)
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.dump.write(format_args!("fill_rect {},{},{} {} {} {} {}",
+                                     color[0], color[1], color[2],
+                                     top_left.0, top_left.1,
+                                     dimensions.0, dimensions.1));
+
+        self.renderer.fill_rect(color, top_left, dimensions)
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  resolution: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        self.dump.write(format_args!("load_image {} {} {} {} {:08x}",
+                                     top_left.0, top_left.1,
+                                     resolution.0, resolution.1,
+                                     hash_pixels(pixel_buffer)));
+
+        self.renderer.load_image(top_left, resolution, pixel_buffer)
+    }
+}
+
+#[test]
+fn test_hash_pixels() {
+    assert_eq!(hash_pixels(&[]), 0x811c_9dc5);
+    assert!(hash_pixels(&[0x1234]) != hash_pixels(&[0x3412]));
+}
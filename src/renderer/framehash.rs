@@ -0,0 +1,171 @@
+//! Frame hashing for automated compatibility sweeps: the display area
+//! of every frame is hashed at native resolution and the hashes are
+//! written to a text file. Two runs of the same content with the same
+//! inputs and video options can then be compared line by line to
+//! detect visual divergences between core versions without storing
+//! any screenshot.
+
+use std::fs::{self, File};
+use std::io::{Write, BufWriter};
+
+use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+
+use time;
+
+use libretro;
+
+/// Hash log file, kept across frames
+pub struct FrameHashLog {
+    out: BufWriter<File>,
+    /// Number of frames logged so far
+    frame: u32,
+    /// Set if we encountered a write error, in which case we stop
+    /// logging
+    failed: bool,
+}
+
+impl FrameHashLog {
+    /// Create a new hash log for `content` in the save directory
+    pub fn new(content: &str) -> Result<FrameHashLog, ()> {
+        let base =
+            match libretro::get_save_directory() {
+                Some(d) => d,
+                None => {
+                    error!("The frontend didn't give us a save directory, \
+                            can't log frame hashes");
+                    return Err(());
+                }
+            };
+
+        let dir = base.join("rustation").join("frame-hashes");
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            error!("Couldn't create {:?}: {}", dir, e);
+            return Err(());
+        }
+
+        let path = dir.join(format!("{}-{}.txt",
+                                    content, time::get_time().sec));
+
+        let out =
+            match File::create(&path) {
+                Ok(f) => BufWriter::new(f),
+                Err(e) => {
+                    error!("Couldn't create {:?}: {}", path, e);
+                    return Err(());
+                }
+            };
+
+        info!("Logging frame hashes to {:?}", path);
+
+        Ok(FrameHashLog {
+            out: out,
+            frame: 0,
+            failed: false,
+        })
+    }
+
+    /// Log the hash of the next frame, None if it couldn't be
+    /// computed
+    pub fn push(&mut self, hash: Option<u64>) {
+        let frame = self.frame;
+
+        self.frame = self.frame.wrapping_add(1);
+
+        if self.failed {
+            return;
+        }
+
+        let res =
+            match hash {
+                Some(h) => writeln!(self.out, "{} {:016x}", frame, h),
+                None => writeln!(self.out, "{} unavailable", frame),
+            };
+
+        if let Err(e) = res {
+            error!("Couldn't write frame hash log, giving up: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+impl Drop for FrameHashLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.flush() {
+            error!("Couldn't flush frame hash log: {}", e);
+        }
+    }
+}
+
+/// Hash the display area of `vram`. The display mode is hashed as
+/// well so that a mode change is detected even if the picture
+/// doesn't change.
+pub fn hash_display(vram: &[u16],
+                    top_left: (u16, u16),
+                    resolution: (u16, u16),
+                    depth_24bpp: bool) -> u64 {
+    let (x, y) = top_left;
+    let (w, h) = resolution;
+
+    // In 24bpp mode each displayed pixel takes 1.5 VRAM pixels
+    let w =
+        if depth_24bpp {
+            (w as u32 * 3 / 2) as u16
+        } else {
+            w
+        };
+
+    // Don't read past the end of the VRAM
+    let w = ::std::cmp::min(w, VRAM_WIDTH_PIXELS - x);
+    let h = ::std::cmp::min(h, VRAM_HEIGHT - y);
+
+    let mode = [x, y, w, h, depth_24bpp as u16];
+
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, &mode);
+
+    for line in 0..h {
+        let start = (y + line) as usize * VRAM_WIDTH_PIXELS as usize +
+            x as usize;
+
+        hash = fnv1a(hash, &vram[start..start + w as usize]);
+    }
+
+    hash
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+/// Update a 64bit FNV-1a hash with `data`, in little endian order
+fn fnv1a(mut hash: u64, data: &[u16]) -> u64 {
+    for &v in data {
+        for &b in &[v as u8, (v >> 8) as u8] {
+            hash = (hash ^ b as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    hash
+}
+
+#[test]
+fn test_hash_display() {
+    let mut vram = vec![0u16; VRAM_WIDTH_PIXELS as usize *
+                              VRAM_HEIGHT as usize];
+
+    let hash = hash_display(&vram, (0, 0), (320, 240), false);
+
+    // Outside of the display area
+    vram[320] = 0x7fff;
+    assert_eq!(hash_display(&vram, (0, 0), (320, 240), false), hash);
+
+    // Inside the display area
+    vram[319] = 0x7fff;
+    assert!(hash_display(&vram, (0, 0), (320, 240), false) != hash);
+
+    // The display mode is part of the hash
+    vram[319] = 0;
+    assert!(hash_display(&vram, (0, 0), (320, 240), true) != hash);
+
+    // Display area going past the end of the VRAM
+    hash_display(&vram, (1000, 500), (640, 480), true);
+}
@@ -1,24 +1,43 @@
 use gl;
-use gl::types::{GLuint, GLint, GLsizei, GLenum, GLfloat};
+use gl::types::{GLuint, GLint, GLenum, GLfloat};
 use arrayvec::ArrayVec;
 use libc::c_uint;
 use rustation::gpu::renderer::{Renderer, Vertex, PrimitiveAttributes};
 use rustation::gpu::renderer::{TextureDepth, BlendMode, SemiTransparencyMode};
-use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT, VideoClock};
 
-use retrogl::DrawConfig;
+use retrogl::{DrawConfig, DrawState};
 use retrogl::error::{Error, get_error};
 use retrogl::buffer::DrawBuffer;
-use retrogl::shader::{Shader, ShaderType};
-use retrogl::program::Program;
+use retrogl::program::{Program, Uniform};
 use retrogl::types::GlType;
 use retrogl::texture::Texture;
 use retrogl::framebuffer::Framebuffer;
+use retrogl::pass::{self, RenderPass, Target, Blend};
+use retrogl::text::TextRenderer;
 
-use CoreVariables;
+use {Settings, AspectRatio, ColorProfile};
 
 use libretro;
 
+pub mod validator;
+pub mod overlay;
+pub mod capture;
+pub mod dump;
+pub mod autoscale;
+pub mod framehash;
+pub mod benchmark;
+pub mod crash;
+pub mod recording;
+
+use self::overlay::{OverlayVertex, PressedButtons, Marker};
+use self::capture::FrameCapture;
+use self::autoscale::{AutoScale, GpuTimer};
+use self::crash::Snapshot;
+
+/// Capacity of the overlay buffer in vertices
+const OVERLAY_BUFFER_LEN: usize = 256;
+
 pub struct GlRenderer {
     /// Buffer used to handle PlayStation GPU draw commands
     command_buffer: DrawBuffer<CommandVertex>,
@@ -32,10 +51,32 @@ pub struct GlRenderer {
     semi_transparency_mode: SemiTransparencyMode,
     /// Polygon mode (for wireframe)
     command_polygon_mode: GLenum,
+    /// Uniform locations for `command_buffer`'s program
+    command_uniforms: CommandUniforms,
     /// Buffer used to draw to the frontend's framebuffer
     output_buffer: DrawBuffer<OutputVertex>,
+    /// Uniform locations for `output_buffer`'s program
+    output_uniforms: OutputUniforms,
     /// Buffer used to copy textures from `fb_texture` to `fb_out`
     image_load_buffer: DrawBuffer<ImageLoadVertex>,
+    /// Location of the `fb_texture` uniform in `image_load_buffer`'s
+    /// program
+    image_load_fb_texture: Uniform,
+    /// Buffer used to draw overlays on top of the frontend's
+    /// framebuffer
+    overlay_buffer: DrawBuffer<OverlayVertex>,
+    /// Buttons to display in the input echo overlay, None if the
+    /// overlay is disabled
+    input_echo: Option<PressedButtons>,
+    /// Debugger markers outlined on top of the display
+    markers: Vec<Marker>,
+    /// If true the frame is covered with a white flash for the input
+    /// latency test
+    flash: bool,
+    /// Debug text overlay
+    text: TextRenderer,
+    /// Frame capture in progress, if any
+    capture: Option<FrameCapture>,
     /// Texture used to store the VRAM for texture mapping
     config: DrawConfig,
     /// Framebuffer used as a shader input for texturing draw commands
@@ -46,31 +87,152 @@ pub struct GlRenderer {
     fb_out_depth: Texture,
     /// Current resolution of the frontend's framebuffer
     frontend_resolution: (u32, u32),
+    /// Aspect ratio last reported to the frontend
+    frontend_aspect_ratio: f32,
+    /// Aspect ratio setting from the core options
+    aspect_ratio: AspectRatio,
+    /// If true the frontend geometry doesn't follow the display mode
+    stable_geometry: bool,
+    /// Color adjustments applied by the output pass
+    color_correction: ColorCorrection,
+    /// If true frames that wouldn't change the output are sent to the
+    /// frontend as dupes instead of being drawn again
+    dupe_idle_frames: bool,
+    /// Set when a command touches the VRAM, cleared once the frame is
+    /// output
+    vram_dirty: bool,
+    /// Parameters of the last frame drawn to the frontend's
+    /// framebuffer, None if the next frame must be drawn regardless
+    last_output: Option<OutputParams>,
+    /// Video standard of the emulated console, needed to compute
+    /// the display aspect ratio
+    video_clock: VideoClock,
     /// Current internal resolution upscaling factor
     internal_upscaling: u32,
+    /// Upscaling factor set in the core options. If dynamic
+    /// resolution is enabled it's the maximum value of
+    /// `internal_upscaling`.
+    upscaling_max: u32,
+    /// True if the dithering pattern is scaled with the internal
+    /// resolution
+    scale_dither: bool,
+    /// True if quads are split in four triangles around their center
+    /// instead of two along a diagonal
+    split_quads: bool,
+    /// True if the texture coordinates of sprites are clamped to the
+    /// sprite's texture area
+    sprite_uv_clamp: bool,
     /// Current internal color depth
     internal_color_depth: u8,
+    /// If true display mode changes are shown on screen
+    notify_display_mode: bool,
+    /// Dynamic resolution controller, None if disabled
+    autoscale: Option<AutoScale>,
+    /// Timer queries used to measure the GPU load for the dynamic
+    /// resolution
+    gpu_timer: Option<GpuTimer>,
     /// Counter for preserving primitive draw order in the z-buffer
     /// since we draw semi-transparent primitives out-of-order.
     primitive_ordering: i16,
+    /// Draw statistics for the frame being rendered
+    draw_stats: DrawStats,
+    /// Draw statistics of the last complete frame
+    last_draw_stats: DrawStats,
+}
+
+/// Number of times the command buffer was flushed during a frame and
+/// the amount of work the GPU emulation sent our way
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DrawStats {
+    /// Total number of draw calls
+    pub draws: u32,
+    /// Number of draws forced by a full command buffer. If it's high
+    /// increasing the buffer size should help.
+    pub buffer_full: u32,
+    /// Number of primitives (lines, triangles and quads) pushed
+    pub primitives: u32,
+    /// Number of pixels uploaded to the VRAM by `load_image`
+    pub uploaded_pixels: u32,
+}
+
+/// Copy of the VRAM made by `GlRenderer::save_vram`
+pub struct SavedVram {
+    /// Copy of `fb_out`, None if it had to be released because we
+    /// lost the GL context
+    fb_out: Option<Texture>,
+    /// Copy of our CPU-side VRAM
+    vram: Vec<u16>,
+}
+
+impl SavedVram {
+    /// Build a copy made of the CPU-side VRAM only
+    pub fn from_vram(vram: Vec<u16>) -> SavedVram {
+        SavedVram {
+            fb_out: None,
+            vram: vram,
+        }
+    }
+
+    /// Return the CPU-side copy of the VRAM
+    pub fn vram(&self) -> &[u16] {
+        &self.vram
+    }
+
+    /// Release the copy of `fb_out`, must be called before the GL
+    /// context is destroyed
+    pub fn release_texture(&mut self) {
+        self.fb_out = None;
+    }
 }
 
 impl GlRenderer {
-    pub fn from_config(config: DrawConfig) -> Result<GlRenderer, Error> {
+    pub fn from_config(config: DrawConfig,
+                       video_clock: VideoClock,
+                       settings: &Settings) -> Result<GlRenderer, Error> {
 
-        let upscaling = CoreVariables::internal_upscale_factor();
-        let depth = CoreVariables::internal_color_depth();
-        let scale_dither = CoreVariables::scale_dither();
-        let wireframe = CoreVariables::wireframe();
+        crash::install_hook();
+
+        let upscaling_max = settings.internal_upscale_factor;
+        let depth = settings.internal_color_depth;
+        let scale_dither = settings.scale_dither;
+        let wireframe = settings.wireframe;
+
+        let mut autoscale =
+            dynamic_upscaling(settings, upscaling_max, upscaling_max);
+
+        let upscaling =
+            match autoscale {
+                Some(ref a) => a.factor(),
+                None => upscaling_max,
+            };
+
+        // We might not be able to allocate the framebuffer for the
+        // requested factor, in which case we get a lower one
+        let (upscaling, fb_out, fb_out_depth) =
+            try!(alloc_fb_out(upscaling, depth));
+
+        if let Some(ref mut a) = autoscale {
+            a.cap(upscaling);
+        }
+
+        let gpu_timer =
+            match autoscale {
+                Some(_) => Some(try!(GpuTimer::new())),
+                None => None,
+            };
 
         info!("Building OpenGL state ({}x internal res., {}bpp)",
               upscaling, depth);
 
+        Program::invalidate_binding();
+
+        let command_buffer_size = settings.command_buffer_size;
+
         let opaque_command_buffer =
             try!(GlRenderer::build_buffer(
                 include_str!("shaders/command_vertex.glsl"),
                 include_str!("shaders/command_fragment.glsl"),
-                2048,
+                command_buffer_size,
                 true));
 
         let output_buffer =
@@ -87,6 +249,20 @@ impl GlRenderer {
                 4,
                 false));
 
+        let overlay_buffer =
+            try!(GlRenderer::build_buffer(
+                include_str!("shaders/overlay_vertex.glsl"),
+                include_str!("shaders/overlay_fragment.glsl"),
+                OVERLAY_BUFFER_LEN,
+                false));
+
+        let command_uniforms =
+            try!(CommandUniforms::new(opaque_command_buffer.program()));
+        let output_uniforms =
+            try!(OutputUniforms::new(output_buffer.program()));
+        let image_load_fb_texture =
+            try!(image_load_buffer.program().find_uniform("fb_texture"));
+
         let native_width = VRAM_WIDTH_PIXELS as u32;
         let native_height = VRAM_HEIGHT as u32;
 
@@ -119,37 +295,58 @@ impl GlRenderer {
         try!(opaque_command_buffer.program()
              .uniform1ui("dither_scaling", dither_scaling));
 
-        let texture_storage =
-            match depth {
-                16 => gl::RGB5_A1,
-                32 => gl::RGBA8,
-                _ => panic!("Unsupported depth {}", depth),
-            };
+        let flip = flip_output(settings) as GLint;
 
-        let fb_out = try!(Texture::new(native_width * upscaling,
-                                       native_height * upscaling,
-                                       texture_storage));
+        try!(output_buffer.program().uniform1i("flip_y", flip));
+        try!(overlay_buffer.program().uniform1i("flip_y", flip));
 
-        let fb_out_depth = try!(Texture::new(fb_out.width(),
-                                             fb_out.height(),
-                                             gl::DEPTH_COMPONENT32F));
+        let text = try!(TextRenderer::new());
+
+        try!(text.set_flip_y(flip_output(settings)));
 
         let mut state = GlRenderer {
             command_buffer: opaque_command_buffer,
+            command_uniforms: command_uniforms,
             command_draw_mode: gl::TRIANGLES,
-            semi_transparent_vertices: Vec::with_capacity(2048),
+            semi_transparent_vertices:
+                Vec::with_capacity(command_buffer_size),
             semi_transparency_mode: SemiTransparencyMode::Average,
             command_polygon_mode: command_draw_mode,
             output_buffer: output_buffer,
+            output_uniforms: output_uniforms,
             image_load_buffer: image_load_buffer,
+            image_load_fb_texture: image_load_fb_texture,
+            overlay_buffer: overlay_buffer,
+            input_echo: None,
+            markers: Vec::new(),
+            flash: false,
+            text: text,
+            capture: None,
             config: config,
             fb_texture: fb_texture,
             fb_out: fb_out,
             fb_out_depth: fb_out_depth,
             frontend_resolution: (0, 0),
+            frontend_aspect_ratio: 0.,
+            aspect_ratio: settings.aspect_ratio,
+            stable_geometry: settings.stable_geometry,
+            color_correction: ColorCorrection::from_options(settings),
+            dupe_idle_frames: dupe_idle_frames(settings),
+            vram_dirty: true,
+            last_output: None,
+            video_clock: video_clock,
             internal_upscaling: upscaling,
+            upscaling_max: upscaling_max,
+            scale_dither: scale_dither,
+            split_quads: settings.split_quads,
+            sprite_uv_clamp: settings.sprite_uv_clamp,
             internal_color_depth: depth,
+            notify_display_mode: settings.notify_display_mode,
+            autoscale: autoscale,
+            gpu_timer: gpu_timer,
             primitive_ordering: 0,
+            draw_stats: DrawStats::default(),
+            last_draw_stats: DrawStats::default(),
         };
 
         // Yet an other copy of this 1MB array to make the borrow
@@ -164,62 +361,79 @@ impl GlRenderer {
         Ok(state)
     }
 
+    /// State reported if we panic while rendering
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            draw_state: self.config.state(),
+            command_vertices: self.command_buffer.len(),
+            semi_transparent_vertices: self.semi_transparent_vertices.len(),
+            upscaling: self.internal_upscaling,
+            color_depth: self.internal_color_depth,
+        }
+    }
+
     fn build_buffer<T>(vertex_shader: &str,
                        fragment_shader: &str,
                        capacity: usize,
                        lifo: bool) -> Result<DrawBuffer<T>, Error>
         where T: ::retrogl::vertex::Vertex {
 
-        let vs = try!(Shader::new(vertex_shader, ShaderType::Vertex));
-
-        let fs = try!(Shader::new(fragment_shader, ShaderType::Fragment));
-
-        let program = try!(Program::new(vs, fs));
+        let program = try!(Program::from_sources(vertex_shader,
+                                                 fragment_shader));
 
         DrawBuffer::new(capacity, program, lifo)
     }
 
     fn draw(&mut self) -> Result<(), Error> {
+        crash::record(self.snapshot());
 
         if self.command_buffer.empty() {
             // Nothing to be done
             return Ok(())
         }
 
-        unsafe {
-            // XXX No semi-transparency support for now
-            gl::BlendFuncSeparate(gl::ONE,
-                                  gl::ZERO,
-                                  gl::ONE,
-                                  gl::ZERO);
-            gl::Disable(gl::BLEND);
-        }
+        self.draw_stats.draws += 1;
 
         let (x, y) = self.config.draw_offset;
 
-        try!(self.command_buffer.program().uniform2i("offset",
-                                                     x as GLint,
-                                                     y as GLint));
+        {
+            let program = self.command_buffer.program();
+            let uniforms = &self.command_uniforms;
 
-        // XXX implement me
-        try!(self.command_buffer.program().uniform1ui("tex_x_mask", 0xff));
-        try!(self.command_buffer.program().uniform1ui("tex_x_or", 0));
-        try!(self.command_buffer.program().uniform1ui("tex_y_mask", 0xff));
-        try!(self.command_buffer.program().uniform1ui("tex_y_or", 0));
+            program.set2i(uniforms.offset, x as GLint, y as GLint);
 
-        // We use texture unit 0
-        try!(self.command_buffer.program().uniform1i("fb_texture", 0));
+            // XXX implement me
+            program.set1ui(uniforms.tex_x_mask, 0xff);
+            program.set1ui(uniforms.tex_x_or, 0);
+            program.set1ui(uniforms.tex_y_mask, 0xff);
+            program.set1ui(uniforms.tex_y_or, 0);
 
-        // Bind the out framebuffer
-        let _fb = Framebuffer::new_with_depth(&self.fb_out, &self.fb_out_depth);
+            // We use texture unit 0
+            program.set1i(uniforms.fb_texture, 0);
+        }
+
+        let (scissor_top_left, scissor_dimensions) = self.draw_area_scissor();
+
+        // In case we're upscaling we need to increase the line width
+        // proportionally
+        let pass =
+            RenderPass::new(Target::Texture(&self.fb_out,
+                                            Some(&self.fb_out_depth)))
+            .input(gl::TEXTURE0, &self.fb_texture)
+            .scissor(scissor_top_left, scissor_dimensions)
+            .depth_test()
+            .polygon_mode(self.command_polygon_mode)
+            .line_width(self.internal_upscaling as GLfloat);
+
+        let pass = try!(pass.begin());
 
         unsafe {
             gl::Clear(gl::DEPTH_BUFFER_BIT);
         }
 
         // First we draw the opaque vertices
-        try!(self.command_buffer.program()
-             .uniform1ui("draw_semi_transparent", 0));
+        self.command_buffer.program()
+            .set1ui(self.command_uniforms.draw_semi_transparent, 0);
 
         try!(self.command_buffer.draw(self.command_draw_mode));
 
@@ -235,7 +449,7 @@ impl GlRenderer {
                 match self.semi_transparency_mode {
                     SemiTransparencyMode::Average =>
                         (gl::FUNC_ADD,
-                         // Set to 0.5 with gl::BlendColor
+                         // Set to 0.5 by the blend color below
                          gl::CONSTANT_ALPHA,
                          gl::CONSTANT_ALPHA),
                     SemiTransparencyMode::Add =>
@@ -248,24 +462,20 @@ impl GlRenderer {
                          gl::ONE),
                     SemiTransparencyMode::AddQuarterSource =>
                         (gl::FUNC_ADD,
-                         // Set to 0.25 with gl::BlendColor
+                         // Set to 0.25 by the blend color below
                          gl::CONSTANT_COLOR,
                          gl::ONE),
                 };
 
-            unsafe {
-                gl::BlendFuncSeparate(blend_src,
-                                      blend_dst,
-                                      gl::ONE,
-                                      gl::ZERO);
-
-                gl::BlendEquationSeparate(blend_func, gl::FUNC_ADD);
-
-                gl::Enable(gl::BLEND);
-            }
+            pass.set_blend(Some(Blend {
+                equation: blend_func,
+                src: blend_src,
+                dst: blend_dst,
+                color: [0.25, 0.25, 0.25, 0.5],
+            }));
 
-            try!(self.command_buffer.program()
-                 .uniform1ui("draw_semi_transparent", 1));
+            self.command_buffer.program()
+                .set1ui(self.command_uniforms.draw_semi_transparent, 1);
 
             try!(self.command_buffer
                  .push_slice(&self.semi_transparent_vertices));
@@ -281,36 +491,44 @@ impl GlRenderer {
         Ok(())
     }
 
-    fn apply_scissor(&mut self) {
-        let (x, y) = self.config.draw_area_top_left;
-        let (w, h) = self.config.draw_area_dimensions;
+    /// Return the scissor box matching the current draw area in
+    /// `fb_out` pixels
+    fn draw_area_scissor(&self) -> ((u32, u32), (u32, u32)) {
+        let top_left = self.config.draw_area_top_left;
+        let dimensions = self.config.draw_area_dimensions;
 
-        let upscale = self.internal_upscaling as GLsizei;
+        (self.upscaled(top_left), self.upscaled(dimensions))
+    }
 
-        // We need to scale those to match the internal resolution if
-        // upscaling is enabled
-        let x = (x as GLsizei) * upscale;
-        let y = (y as GLsizei) * upscale;
-        let w = (w as GLsizei) * upscale;
-        let h = (h as GLsizei) * upscale;
+    /// Scale native VRAM coordinates to `fb_out` pixels
+    fn upscaled(&self, (x, y): (u16, u16)) -> (u32, u32) {
+        let upscale = self.internal_upscaling;
 
-        unsafe {
-            gl::Scissor(x, y, w, h);
-        }
+        (x as u32 * upscale, y as u32 * upscale)
     }
 
-    fn bind_libretro_framebuffer(&mut self) {
+    /// Return the frontend's framebuffer as a render target,
+    /// reconfiguring the frontend first if the output resolution or
+    /// aspect ratio changed
+    fn frontend_target(&mut self) -> Target<'static> {
         let (f_w, f_h) = self.frontend_resolution;
-        let (w, h) = self.config.display_resolution;
 
-        let upscale = self.internal_upscaling;
+        let (w, h) = output_resolution(self.config.display_resolution,
+                                       self.internal_upscaling,
+                                       self.stable_geometry);
 
-        // XXX scale w and h when implementing increased internal
-        // resolution
-        let w = (w as u32) * upscale;
-        let h = (h as u32) * upscale;
+        let aspect_ratio =
+            match self.aspect_ratio {
+                AspectRatio::Fixed => 4. / 3.,
+                AspectRatio::Accurate =>
+                    display_aspect_ratio(self.video_clock,
+                                         self.config.display_resolution),
+            };
+
+        let aspect_changed =
+            (aspect_ratio - self.frontend_aspect_ratio).abs() > 0.0001;
 
-        if w != f_w || h != f_h {
+        if w != f_w || h != f_h || aspect_changed {
             // We need to change the frontend's resolution
             let geometry = libretro::GameGeometry {
                 base_width: w as c_uint,
@@ -318,24 +536,22 @@ impl GlRenderer {
                 // Max parameters are ignored by this call
                 max_width: 0,
                 max_height: 0,
-                // Is this accurate?
-                aspect_ratio: 4./3.,
+                aspect_ratio: aspect_ratio,
             };
 
-            info!("Target framebuffer size: {}x{}", w, h);
+            info!("Target framebuffer size: {}x{} (aspect ratio {:.3})",
+                  w, h, aspect_ratio);
 
             libretro::set_geometry(&geometry);
 
             self.frontend_resolution = (w, h);
+            self.frontend_aspect_ratio = aspect_ratio;
         }
 
-        // Bind the output framebuffer provided by the frontend
+        // The output framebuffer provided by the frontend
         let fbo = libretro::hw_context::get_current_framebuffer() as GLuint;
 
-        unsafe {
-            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
-            gl::Viewport(0, 0, w as GLsizei, h as GLsizei);
-        }
+        Target::Frontend(fbo, (w, h))
     }
 
     fn upload_textures(&mut self,
@@ -363,22 +579,15 @@ impl GlRenderer {
               ImageLoadVertex { position: [x_end, y_end] },
               ]));
 
-        try!(self.image_load_buffer.program().uniform1i("fb_texture", 0));
-
-        unsafe {
-            gl::Disable(gl::SCISSOR_TEST);
-            gl::Disable(gl::BLEND);
-            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
-        }
-
-        // Bind the output framebuffer
-        let _fb = Framebuffer::new(&self.fb_out);
+        self.image_load_buffer.program().set1i(self.image_load_fb_texture, 0);
 
-        try!(self.image_load_buffer.draw(gl::TRIANGLE_STRIP));
+        {
+            let _pass =
+                try!(RenderPass::new(Target::Texture(&self.fb_out, None))
+                     .input(gl::TEXTURE0, &self.fb_texture)
+                     .begin());
 
-        unsafe {
-            gl::PolygonMode(gl::FRONT_AND_BACK, self.command_polygon_mode);
-            gl::Enable(gl::SCISSOR_TEST);
+            try!(self.image_load_buffer.draw(gl::TRIANGLE_STRIP));
         }
 
         get_error()
@@ -388,83 +597,196 @@ impl GlRenderer {
         &self.config
     }
 
-    pub fn prepare_render(&mut self) {
+    /// Return a copy of the VRAM as rendered on the GPU, downscaled
+    /// to the native resolution. Unlike `draw_config().vram` this
+    /// includes the results of the draw commands and not just the
+    /// images loaded by the CPU.
+    pub fn rendered_vram(&self) -> Result<Vec<u16>, Error> {
+        let w = VRAM_WIDTH_PIXELS as u32;
+        let h = VRAM_HEIGHT as u32;
 
-        self.apply_scissor();
+        let native = try!(Texture::new(w, h, gl::RGBA8));
 
-        // In case we're upscaling we need to increase the line width
-        // proportionally
-        unsafe {
-            gl::LineWidth(self.internal_upscaling as GLfloat);
-            gl::PolygonMode(gl::FRONT_AND_BACK, self.command_polygon_mode);
-            gl::Enable(gl::SCISSOR_TEST);
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LEQUAL);
-            // Used for PSX GPU command blending
-            gl::BlendColor(0.25, 0.25, 0.25, 0.5);
-        }
+        try!(blit_texture(&self.fb_out, &native));
 
-        // Bind `fb_texture` to texture unit 0
-        self.fb_texture.bind(gl::TEXTURE0);
+        let pixels = {
+            let fb_native = try!(Framebuffer::new(&native));
+
+            try!(fb_native.read_pixels((0, 0), (w, h)))
+        };
+
+        // Convert back to the native 1555 format
+        let vram =
+            pixels.chunks(4)
+            .map(|p| {
+                let r = (p[0] >> 3) as u16;
+                let g = (p[1] >> 3) as u16;
+                let b = (p[2] >> 3) as u16;
+                let mask = (p[3] >> 7) as u16;
+
+                r | (g << 5) | (b << 10) | (mask << 15)
+            })
+            .collect();
+
+        Ok(vram)
     }
 
-    pub fn refresh_variables(&mut self) -> bool {
-        let upscaling = CoreVariables::internal_upscale_factor();
-        let depth = CoreVariables::internal_color_depth();
-        let scale_dither = CoreVariables::scale_dither();
-        let wireframe = CoreVariables::wireframe();
+    /// Make a copy of the VRAM, including whatever has been rendered
+    /// in `fb_out`, so that it can be put back with `restore_vram`
+    /// after drawing something unrelated to the emulation (test
+    /// patterns)
+    pub fn save_vram(&mut self) -> Result<SavedVram, Error> {
+        // Draw pending commands
+        try!(self.draw());
 
-        let rebuild_fb_out =
-            upscaling != self.internal_upscaling ||
-            depth != self.internal_color_depth;
+        let storage = try!(fb_out_storage(self.internal_color_depth));
 
-        if rebuild_fb_out {
+        let fb_out = try!(Texture::new(self.fb_out.width(),
+                                       self.fb_out.height(),
+                                       storage));
 
-            if depth > 16 {
-                self.command_buffer.disable_attribute("dither").unwrap()
-            } else {
-                self.command_buffer.enable_attribute("dither").unwrap()
-            }
+        try!(blit_texture(&self.fb_out, &fb_out));
+
+        Ok(SavedVram {
+            fb_out: Some(fb_out),
+            vram: self.config.vram.clone(),
+        })
+    }
 
-            let native_width = VRAM_WIDTH_PIXELS as u32;
-            let native_height = VRAM_HEIGHT as u32;
+    /// Put back the VRAM saved by `save_vram`
+    pub fn restore_vram(&mut self, saved: SavedVram) -> Result<(), Error> {
+        // Drop anything drawn since the save
+        try!(self.command_buffer.clear());
+        self.semi_transparent_vertices.clear();
 
-            let w = native_width * upscaling;
-            let h = native_height * upscaling;
+        self.config.vram = saved.vram;
+        self.vram_dirty = true;
 
-            let texture_storage =
-                match depth {
-                    16 => gl::RGB5_A1,
-                    32 => gl::RGBA8,
-                    _ => panic!("Unsupported depth {}", depth),
-                };
+        let vram_contents = self.config.vram.clone();
 
-            let fb_out = Texture::new(w, h, texture_storage).unwrap();
+        try!(self.upload_textures((0, 0),
+                                  (VRAM_WIDTH_PIXELS, VRAM_HEIGHT),
+                                  &vram_contents));
 
-            self.fb_out = fb_out;
+        // The upload only contains what the CPU loaded, put the
+        // rendered contents back on top. If the internal resolution
+        // changed in the meantime the blit rescales them.
+        if let Some(fb_out) = saved.fb_out {
+            try!(blit_texture(&fb_out, &self.fb_out));
+        }
+
+        Ok(())
+    }
 
+    /// Replace the whole configuration with `config`, without
+    /// rebuilding the OpenGL objects. If `upload_vram` is false the
+    /// VRAM contents on the GPU are left untouched.
+    pub fn load_config(&mut self,
+                       config: DrawConfig,
+                       upload_vram: bool) -> Result<(), Error> {
+        Program::invalidate_binding();
+
+        // Drop anything still pending, it belongs to the old state
+        try!(self.command_buffer.clear());
+        self.semi_transparent_vertices.clear();
+        self.primitive_ordering = 0;
+
+        self.config = config;
+        self.vram_dirty = true;
+
+        if upload_vram {
             let vram_contents = self.config.vram.clone();
 
-            // This is a bit wasteful since it'll re-upload the data
-            // to `fb_texture` even though we haven't touched it but
-            // this code is not very performance-critical anyway.
-            self.upload_textures((0, 0),
-                                 (VRAM_WIDTH_PIXELS, VRAM_HEIGHT),
-                                 &*vram_contents).unwrap();
+            try!(self.upload_textures((0, 0),
+                                      (VRAM_WIDTH_PIXELS, VRAM_HEIGHT),
+                                      &vram_contents));
+        }
 
-            self.fb_out_depth =
-                Texture::new(w, h, gl::DEPTH_COMPONENT32F).unwrap();
+        Ok(())
+    }
+
+    /// Change the video standard used to compute the display aspect
+    /// ratio, when a savestate for the other one is loaded
+    pub fn set_video_clock(&mut self, video_clock: VideoClock) {
+        self.video_clock = video_clock;
+    }
+
+    /// Restore the display and drawing configuration, leaving the
+    /// VRAM untouched
+    pub fn restore_draw_state(&mut self, state: &DrawState) {
+        self.config.display_top_left = state.display_top_left;
+        self.config.display_resolution = state.display_resolution;
+        self.config.display_24bpp = state.display_24bpp;
+        self.config.draw_offset = state.draw_offset;
+        self.config.draw_area_top_left = state.draw_area_top_left;
+        self.config.draw_area_dimensions = state.draw_area_dimensions;
+    }
+
+    pub fn prepare_render(&mut self) {
+        // The frontend might have used its own programs since the
+        // last frame
+        Program::invalidate_binding();
+
+        // The rest of the OpenGL state is set up by each render pass
+
+        if let Some(ref mut timer) = self.gpu_timer {
+            timer.begin();
         }
+    }
 
-        let dither_scaling =
-            if scale_dither {
-                upscaling
-            } else {
-                1
+    /// Apply the core options in `settings`. Returns true if the frontend
+    /// must be reconfigured for the new internal resolution.
+    pub fn refresh_variables(&mut self,
+                             settings: &Settings) -> Result<bool, Error> {
+        Program::invalidate_binding();
+
+        let upscaling_max = settings.internal_upscale_factor;
+        let depth = settings.internal_color_depth;
+        let scale_dither = settings.scale_dither;
+        let wireframe = settings.wireframe;
+
+        // Keep the current dynamic factor if it's still in range to
+        // avoid resetting the resolution every time an unrelated
+        // option changes
+        self.autoscale =
+            dynamic_upscaling(settings, upscaling_max,
+                              self.internal_upscaling);
+
+        let upscaling =
+            match self.autoscale {
+                Some(ref a) => a.factor(),
+                None => upscaling_max,
             };
 
-        self.command_buffer.program()
-            .uniform1ui("dither_scaling", dither_scaling).unwrap();
+        self.gpu_timer =
+            match (self.autoscale.is_some(), self.gpu_timer.take()) {
+                (true, Some(t)) => Some(t),
+                (true, None) => Some(try!(GpuTimer::new())),
+                (false, _) => None,
+            };
+
+        self.scale_dither = scale_dither;
+        self.split_quads = settings.split_quads;
+        self.sprite_uv_clamp = settings.sprite_uv_clamp;
+        self.notify_display_mode = settings.notify_display_mode;
+        self.aspect_ratio = settings.aspect_ratio;
+        self.stable_geometry = settings.stable_geometry;
+        self.color_correction = ColorCorrection::from_options(settings);
+        self.dupe_idle_frames = dupe_idle_frames(settings);
+        // The output pass parameters may have changed
+        self.last_output = None;
+
+        let upscaling = try!(self.set_internal_resolution(upscaling, depth));
+
+        if let Some(ref mut a) = self.autoscale {
+            a.cap(upscaling);
+        }
+
+        let flip = flip_output(settings) as GLint;
+
+        try!(self.output_buffer.program().uniform1i("flip_y", flip));
+        try!(self.overlay_buffer.program().uniform1i("flip_y", flip));
+        try!(self.text.set_flip_y(flip_output(settings)));
 
         self.command_polygon_mode =
             if wireframe {
@@ -473,42 +795,177 @@ impl GlRenderer {
                 gl::FILL
             };
 
+        // If the maximum scaling factor has changed the frontend
+        // should be reconfigured. We can't do that here because it
+        // could destroy the OpenGL context which would destroy
+        // `self`
+        let reconfigure_frontend = self.upscaling_max != upscaling_max;
+
+        self.upscaling_max = upscaling_max;
+
+        Ok(reconfigure_frontend)
+    }
+
+    /// Rebuild `fb_out` if the upscaling factor or color depth
+    /// changed. Must be called between frames. Returns the upscaling
+    /// factor actually used, which can be lower than `upscaling` if
+    /// we ran out of video memory.
+    fn set_internal_resolution(&mut self,
+                               upscaling: u32,
+                               depth: u8) -> Result<u32, Error> {
+        let rebuild_fb_out =
+            upscaling != self.internal_upscaling ||
+            depth != self.internal_color_depth;
+
+        let mut upscaling = upscaling;
+
+        if rebuild_fb_out {
+            // Allocate the new textures before we start touching
+            // anything, if it fails we keep the current state
+            let (factor, fb_out, fb_out_depth) =
+                try!(alloc_fb_out(upscaling, depth));
+
+            upscaling = factor;
+
+            if depth > 16 {
+                try!(self.command_buffer.disable_attribute("dither"));
+            } else {
+                try!(self.command_buffer.enable_attribute("dither"));
+            }
+
+            // Copy the current contents of the VRAM (including
+            // whatever has been rendered so far, which isn't in our
+            // CPU-side copy) to the new texture, rescaling it if
+            // needed. Otherwise the screen would be corrupted until
+            // the game redraws it entirely.
+            try!(blit_texture(&self.fb_out, &fb_out));
+
+            self.fb_out = fb_out;
+            self.fb_out_depth = fb_out_depth;
+        }
+
+        let dither_scaling =
+            if self.scale_dither {
+                upscaling
+            } else {
+                1
+            };
+
+        try!(self.command_buffer.program()
+             .uniform1ui("dither_scaling", dither_scaling));
+
         unsafe {
             gl::LineWidth(upscaling as GLfloat);
         }
 
-        // If the scaling factor has changed the frontend should be
-        // reconfigured. We can't do that here because it could
-        // destroy the OpenGL context which would destroy `self`
-        let reconfigure_frontend = self.internal_upscaling != upscaling;
-
         self.internal_upscaling = upscaling;
         self.internal_color_depth = depth;
 
-        return reconfigure_frontend
+        Ok(upscaling)
+    }
+
+    /// Feed the GPU time of the last frames to the dynamic resolution
+    /// controller and change the upscaling factor if needed.
+    /// `frame_period` is the duration of a frame in nanoseconds. Must
+    /// be called between frames.
+    pub fn update_dynamic_upscaling(&mut self,
+                                    frame_period: u64) -> Result<(), Error> {
+        let gpu_time =
+            match self.gpu_timer {
+                Some(ref mut t) => t.poll(),
+                None => None,
+            };
+
+        let new_factor =
+            match (gpu_time, self.autoscale.as_mut()) {
+                (Some(t), Some(a)) => a.update(t, frame_period),
+                _ => None,
+            };
+
+        match new_factor {
+            Some(f) => {
+                // The frontend's geometry will be updated by
+                // `frontend_target` for the next frame,
+                // the maximum geometry already accounts for
+                // `upscaling_max`
+                let depth = self.internal_color_depth;
+
+                let actual = try!(self.set_internal_resolution(f, depth));
+
+                if actual != f {
+                    // Don't try to go that high again
+                    if let Some(ref mut a) = self.autoscale {
+                        a.cap(actual);
+                    }
+                }
+
+                Ok(())
+            }
+            None => Ok(()),
+        }
     }
 
     pub fn finalize_frame(&mut self) {
         // Draw pending commands
         self.draw().unwrap();
 
-        // We can now render to the frontend's buffer.
-        self.bind_libretro_framebuffer();
+        if self.capture.is_some() {
+            self.capture_frame().unwrap();
+        }
 
-        // Bind `fb_out` to texture unit 1
-        self.fb_out.bind(gl::TEXTURE1);
+        // We can now render to the frontend's buffer.
+        let target = self.frontend_target();
+
+        let output = OutputParams {
+            display_top_left: self.config.display_top_left,
+            display_resolution: self.config.display_resolution,
+            display_24bpp: self.config.display_24bpp,
+            frontend_resolution: self.frontend_resolution,
+            internal_upscaling: self.internal_upscaling,
+            overlays: self.flash ||
+                      self.input_echo.is_some() ||
+                      !self.markers.is_empty() ||
+                      !self.text.is_empty(),
+        };
 
-        // First we draw the visible part of fb_out
-        unsafe {
-            gl::Disable(gl::SCISSOR_TEST);
-            gl::Disable(gl::DEPTH_TEST);
-            gl::Disable(gl::BLEND);
-            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+        // Static screens (loading, menus waiting for input) don't
+        // need to go through the output pass again. Frames with
+        // overlays are always drawn, so is the frame following them
+        // since it has to erase them.
+        let idle =
+            self.dupe_idle_frames &&
+            !self.vram_dirty &&
+            !output.overlays &&
+            self.last_output == Some(output);
+
+        self.vram_dirty = false;
+        self.last_output = Some(output);
+
+        if idle {
+            self.end_frame();
+
+            libretro::frame_dupe(self.frontend_resolution.0,
+                                 self.frontend_resolution.1);
+            return;
         }
 
+        // First we draw the visible part of fb_out
         let (fb_x_start, fb_y_start) = self.config.display_top_left;
         let (fb_width, fb_height) = self.config.display_resolution;
 
+        // Don't sample outside of the VRAM if the display area goes
+        // past its edges. In 24bpp mode each displayed pixel takes
+        // 1.5 VRAM pixels.
+        let max_width =
+            if self.config.display_24bpp {
+                ((VRAM_WIDTH_PIXELS - fb_x_start) * 2) / 3
+            } else {
+                VRAM_WIDTH_PIXELS - fb_x_start
+            };
+
+        let fb_width = ::std::cmp::min(fb_width, max_width);
+        let fb_height = ::std::cmp::min(fb_height, VRAM_HEIGHT - fb_y_start);
+
         let fb_x_end = fb_x_start + fb_width;
         let fb_y_end = fb_y_start + fb_height;
 
@@ -526,36 +983,248 @@ impl GlRenderer {
 
         let depth_24bpp = self.config.display_24bpp as GLint;
 
-        self.output_buffer.program()
-            .uniform1i("fb", 1).unwrap();
-        self.output_buffer.program()
-            .uniform1i("depth_24bpp", depth_24bpp).unwrap();
-        self.output_buffer.program()
-            .uniform1ui("internal_upscaling", self.internal_upscaling).unwrap();
+        {
+            let program = self.output_buffer.program();
+            let uniforms = &self.output_uniforms;
 
-        self.output_buffer.draw(gl::TRIANGLE_STRIP).unwrap();
+            program.set1i(uniforms.fb, 1);
+            program.set1i(uniforms.depth_24bpp, depth_24bpp);
+            program.set1ui(uniforms.internal_upscaling,
+                           self.internal_upscaling);
 
-        // Cleanup OpenGL context before returning to the frontend
-        unsafe {
-            gl::Disable(gl::BLEND);
-            gl::BlendColor(0., 0., 0., 0.);
-            gl::BlendEquationSeparate(gl::FUNC_ADD, gl::FUNC_ADD);
-            gl::BlendFuncSeparate(gl::ONE,
-                                  gl::ZERO,
-                                  gl::ONE,
-                                  gl::ZERO);
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-            gl::UseProgram(0);
-            gl::BindVertexArray(0);
-            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
-            gl::LineWidth(1.);
+            let color = &self.color_correction;
+
+            program.set1i(uniforms.pal_crt,
+                          (color.profile == ColorProfile::PalCrt) as GLint);
+            program.set1f(uniforms.gamma, color.gamma);
+            program.set1f(uniforms.brightness, color.brightness);
+            program.set1f(uniforms.saturation, color.saturation);
         }
 
+        {
+            // `fb_out` is sampled from texture unit 1
+            let _pass = RenderPass::new(target)
+                .input(gl::TEXTURE1, &self.fb_out)
+                .begin()
+                .unwrap();
+
+            self.output_buffer.draw(gl::TRIANGLE_STRIP).unwrap();
+        }
+
+        {
+            let _pass = RenderPass::new(target).begin().unwrap();
+
+            self.draw_overlays().unwrap();
+        }
+
+        // Cleanup OpenGL context before returning to the frontend
+        pass::reset_state();
+
+        Program::unbind();
+
+        self.end_frame();
+
         libretro::gl_frame_done(self.frontend_resolution.0,
                                 self.frontend_resolution.1)
     }
 
+    /// Bookkeeping once we're done with a frame, drawn or not
+    fn end_frame(&mut self) {
+        if let Some(ref mut timer) = self.gpu_timer {
+            timer.end();
+        }
+
+        self.last_draw_stats =
+            ::std::mem::replace(&mut self.draw_stats, DrawStats::default());
+    }
+
+    /// Set the buttons displayed by the input echo overlay. None
+    /// disables the overlay.
+    pub fn set_input_echo(&mut self, pressed: Option<PressedButtons>) {
+        self.input_echo = pressed;
+    }
+
+    /// Set the debugger markers outlined on top of the next frame
+    pub fn set_markers(&mut self, markers: Vec<Marker>) {
+        self.markers = markers;
+    }
+
+    /// Cover the next frame with a white flash if `flash` is true
+    pub fn set_flash(&mut self, flash: bool) {
+        self.flash = flash;
+    }
+
+    /// Return the current upscaling factor along with the one
+    /// requested by the user. The former is lower when the dynamic
+    /// resolution kicks in or if the GPU can't handle the requested
+    /// factor.
+    pub fn upscaling(&self) -> (u32, u32) {
+        (self.internal_upscaling, self.upscaling_max)
+    }
+
+    /// Return the draw statistics of the last frame
+    pub fn draw_stats(&self) -> DrawStats {
+        self.last_draw_stats
+    }
+
+    /// Text overlay drawn on top of the next frame
+    pub fn text_overlay(&mut self) -> &mut TextRenderer {
+        &mut self.text
+    }
+
+    /// Hash the display area as rendered in `fb_out`, at native
+    /// resolution
+    pub fn display_hash(&self) -> Result<u64, Error> {
+        let vram = try!(self.rendered_vram());
+
+        Ok(framehash::hash_display(&vram,
+                                   self.config.display_top_left,
+                                   self.config.display_resolution,
+                                   self.config.display_24bpp))
+    }
+
+    /// Dump the VRAM, both our CPU-side copy and what's been rendered
+    /// in `fb_out`
+    pub fn dump_vram(&self) -> Result<(), Error> {
+        let resolution = (self.fb_out.width(), self.fb_out.height());
+
+        let pixels = {
+            let fb = try!(Framebuffer::new(&self.fb_out));
+
+            try!(fb.read_pixels((0, 0), resolution))
+        };
+
+        // Errors are already logged
+        let _ = capture::dump_vram(&self.config.vram,
+                                   Some((&pixels, resolution)));
+
+        Ok(())
+    }
+
+    /// Save the display area at native resolution, decoded from the
+    /// rendered VRAM
+    pub fn native_screenshot(&self) -> Result<(), Error> {
+        let vram = try!(self.rendered_vram());
+
+        // Errors are already logged
+        let _ = capture::native_screenshot(&vram,
+                                           self.config.display_top_left,
+                                           self.config.display_resolution,
+                                           self.config.display_24bpp);
+
+        Ok(())
+    }
+
+    /// Return the display area of the last frame as 8bit RGBA pixels
+    /// along with its dimensions. If `native` is true it's decoded
+    /// from the rendered VRAM at native resolution, otherwise it's
+    /// read from `fb_out` at the internal resolution.
+    pub fn display_pixels(&self, native: bool)
+                          -> Result<(Vec<u8>, (u32, u32)), Error> {
+        if native {
+            let vram = try!(self.rendered_vram());
+
+            return Ok(capture::decode_display(&vram,
+                                              self.config.display_top_left,
+                                              self.config.display_resolution,
+                                              self.config.display_24bpp));
+        }
+
+        let upscale = self.internal_upscaling;
+
+        let (x, y) = self.config.display_top_left;
+        let (w, h) = self.config.display_resolution;
+
+        // Don't attempt to read past the end of the VRAM
+        let w = ::std::cmp::min(w, VRAM_WIDTH_PIXELS - x);
+        let h = ::std::cmp::min(h, VRAM_HEIGHT - y);
+
+        let top_left = (x as u32 * upscale, y as u32 * upscale);
+        let resolution = (w as u32 * upscale, h as u32 * upscale);
+
+        let fb = try!(Framebuffer::new(&self.fb_out));
+
+        let pixels = try!(fb.read_pixels(top_left, resolution));
+
+        Ok((pixels, resolution))
+    }
+
+    /// Dump the next frames to `capture` until it's complete
+    pub fn start_capture(&mut self, capture: FrameCapture) {
+        self.capture = Some(capture);
+    }
+
+    /// Dump the display area of `fb_out` to the capture in progress
+    fn capture_frame(&mut self) -> Result<(), Error> {
+        let mut capture =
+            match self.capture.take() {
+                Some(c) => c,
+                None => return Ok(()),
+            };
+
+        let (pixels, resolution) = try!(self.display_pixels(false));
+
+        let state =
+            format!("display: {:?} {:?} 24bpp: {}\n\
+                     draw area: {:?} {:?} offset: {:?}\n\
+                     internal upscaling: {}x color depth: {}bpp\n",
+                    self.config.display_top_left,
+                    self.config.display_resolution,
+                    self.config.display_24bpp,
+                    self.config.draw_area_top_left,
+                    self.config.draw_area_dimensions,
+                    self.config.draw_offset,
+                    self.internal_upscaling,
+                    self.internal_color_depth);
+
+        if capture.push_frame(pixels, resolution, &state) {
+            self.capture = Some(capture);
+        }
+
+        Ok(())
+    }
+
+    /// Draw the enabled overlays on top of the frontend's
+    /// framebuffer. Must be called within a pass targeting the
+    /// frontend's framebuffer.
+    fn draw_overlays(&mut self) -> Result<(), Error> {
+        if self.flash {
+            unsafe {
+                gl::ClearColor(1., 1., 1., 1.);
+                gl::Clear(gl::COLOR_BUFFER_BIT);
+                gl::ClearColor(0., 0., 0., 0.);
+            }
+        }
+
+        if let Some(ref pressed) = self.input_echo {
+            let vertices =
+                overlay::input_echo_vertices(pressed,
+                                             self.frontend_resolution);
+
+            try!(self.overlay_buffer.clear());
+            try!(self.overlay_buffer.push_slice(&vertices));
+            try!(self.overlay_buffer.draw(gl::TRIANGLES));
+        }
+
+        if !self.markers.is_empty() {
+            let vertices =
+                overlay::marker_vertices(&self.markers,
+                                         self.config.display_resolution);
+
+            // There can be more vertices than fit in the buffer, split
+            // them without cutting through a quad
+            let chunk_len = OVERLAY_BUFFER_LEN - OVERLAY_BUFFER_LEN % 6;
+
+            for chunk in vertices.chunks(chunk_len) {
+                try!(self.overlay_buffer.clear());
+                try!(self.overlay_buffer.push_slice(chunk));
+                try!(self.overlay_buffer.draw(gl::TRIANGLES));
+            }
+        }
+
+        self.text.draw(self.frontend_resolution)
+    }
+
     /// Check if a new primitive's attributes are somehow incompatible
     /// with the ones currently buffered, in which case we must force
     /// a draw to flush the buffers.
@@ -563,9 +1232,16 @@ impl GlRenderer {
                         nvertices: usize,
                         draw_mode: GLenum,
                         attributes: &PrimitiveAttributes) {
+        let buffer_full =
+            self.command_buffer.remaining_capacity() < nvertices;
+
+        if buffer_full && !self.command_buffer.empty() {
+            self.draw_stats.buffer_full += 1;
+        }
+
         let force_draw =
             // Check if we have enough room left in the buffer
-            self.command_buffer.remaining_capacity() < nvertices ||
+            buffer_full ||
             // Check if we're changing the draw mode (line <=> triangle)
             self.command_draw_mode != draw_mode ||
             // Check if we're changing the semi-transparency mode
@@ -599,14 +1275,49 @@ impl Renderer for GlRenderer {
 
         self.config.draw_area_top_left = top_left;
         self.config.draw_area_dimensions = dimensions;
-
-        self.apply_scissor();
     }
 
     fn set_display_mode(&mut self,
                         top_left: (u16, u16),
                         resolution: (u16, u16),
                         depth_24bpp: bool) {
+        // XXX We only keep the last display configuration of the
+        // frame and use it for the whole output pass, so raster
+        // effects changing the display start or mode mid-frame
+        // (split screens, wobble effects) aren't rendered. Handling
+        // them would mean buffering the display parameters per
+        // scanline and drawing the output in horizontal bands but
+        // the renderer interface doesn't tell us at which line the
+        // change happens: rustation's GPU would have to pass the
+        // current scanline along (and process display changes at
+        // scanline granularity with respect to the CPU timers).
+        //
+        // XXX A "force progressive" hack for 480i games (both fields
+        // rendered every frame, no more interlace flicker) isn't
+        // possible from here either: we only see the resolution,
+        // not the interlace bit, the current field or whether the
+        // GPU is skipping the lines of the displayed field while
+        // drawing. Rustation would have to render the skipped lines
+        // anyway when the hack is enabled and give us the field so
+        // that we could keep fb_out at the full 480 lines. Since
+        // some games rely on the field-skipping behavior it would
+        // also need a per-game blocklist, keyed on the disc serial.
+        let mode_changed =
+            resolution != self.config.display_resolution ||
+            depth_24bpp != self.config.display_24bpp;
+
+        if mode_changed {
+            let (width, height) = resolution;
+            let bpp = if depth_24bpp { 24 } else { 15 };
+
+            info!("Display mode changed to {}x{} {}bpp", width, height, bpp);
+
+            if self.notify_display_mode {
+                libretro_message!(100, "Display mode: {}x{} {}bpp",
+                                  width, height, bpp);
+            }
+        }
+
         self.config.display_top_left = top_left;
         self.config.display_resolution = resolution;
         self.config.display_24bpp = depth_24bpp;
@@ -617,6 +1328,8 @@ impl Renderer for GlRenderer {
                  vertices: &[Vertex; 2]) {
 
         self.maybe_force_draw(2, gl::LINES, attributes);
+        self.vram_dirty = true;
+        self.draw_stats.primitives += 1;
 
         let z = self.primitive_ordering;
 
@@ -640,6 +1353,8 @@ impl Renderer for GlRenderer {
                      vertices: &[Vertex; 3]) {
 
         self.maybe_force_draw(3, gl::TRIANGLES, attributes);
+        self.vram_dirty = true;
+        self.draw_stats.primitives += 1;
 
         let z = self.primitive_ordering;
 
@@ -671,17 +1386,37 @@ impl Renderer for GlRenderer {
                  attributes: &PrimitiveAttributes,
                  vertices: &[Vertex; 4]) {
 
-        self.maybe_force_draw(6, gl::TRIANGLES, attributes);
+        let nvertices = if self.split_quads { 12 } else { 6 };
+
+        self.maybe_force_draw(nvertices, gl::TRIANGLES, attributes);
+        self.vram_dirty = true;
+        self.draw_stats.primitives += 1;
 
         let z = self.primitive_ordering;
 
         self.primitive_ordering += 1;
 
-        let v: ArrayVec<[_; 4]> =
+        let mut v: ArrayVec<[_; 4]> =
             vertices.iter().map(|v|
                                 CommandVertex::from_vertex(attributes, v, z))
             .collect();
 
+        if self.sprite_uv_clamp && attributes.blend_mode != BlendMode::None {
+            if let Some(limits) = sprite_texture_limits(&v) {
+                for v in v.iter_mut() {
+                    v.texture_limits = limits;
+                    v.clamp_texture = 1;
+                }
+            }
+        }
+
+        let triangles: ArrayVec<[_; 12]> =
+            if self.split_quads {
+                split_quad(&v).iter().cloned().collect()
+            } else {
+                v[0..3].iter().chain(&v[1..4]).cloned().collect()
+            };
+
         let needs_opaque_draw =
             !attributes.semi_transparent ||
             // Textured semi-transparent polys can contain opaque
@@ -691,13 +1426,11 @@ impl Renderer for GlRenderer {
             attributes.blend_mode != BlendMode::None;
 
         if needs_opaque_draw {
-            self.command_buffer.push_slice(&v[0..3]).unwrap();
-            self.command_buffer.push_slice(&v[1..4]).unwrap();
+            self.command_buffer.push_slice(&triangles).unwrap();
         }
 
         if attributes.semi_transparent {
-            self.semi_transparent_vertices.extend_from_slice(&v[0..3]);
-            self.semi_transparent_vertices.extend_from_slice(&v[1..4]);
+            self.semi_transparent_vertices.extend_from_slice(&triangles);
         }
     }
 
@@ -708,43 +1441,55 @@ impl Renderer for GlRenderer {
         // Draw pending commands
         self.draw().unwrap();
 
-        // Fill rect ignores the draw area. Save the previous scissor
-        // settings and reconfigure the scissor box to the fill
-        // rectangle insteadd.
-        let draw_area_top_left = self.config.draw_area_top_left;
-        let draw_area_dimensions = self.config.draw_area_dimensions;
-
-        self.config.draw_area_top_left = top_left;
-        self.config.draw_area_dimensions = dimensions;
-
-        self.apply_scissor();
+        self.vram_dirty = true;
 
         // ClearColor takes normalized floating point color components
         let clear_color: ArrayVec<[_; 3]> =
             color.iter().map(|&c| (c as f32) / 255.)
             .collect();
 
-        {
-            // Bind the out framebuffer
-            let _fb = Framebuffer::new(&self.fb_out);
-
-            unsafe {
-                gl::ClearColor(clear_color[0],
-                               clear_color[1],
-                               clear_color[2],
-                               // XXX Not entirely sure what happens
-                               // to the mask bit in fill_rect. No$
-                               // seems to say that it's set to 0.
-                               0.);
-                gl::Clear(gl::COLOR_BUFFER_BIT);
+        // The rectangle wraps around the VRAM edges
+        for &(top_left, dimensions) in wrap_vram_rect(top_left,
+                                                      dimensions).iter() {
+            // Fill rect ignores the draw area, the scissor box is
+            // set to the fill rectangle instead
+            let scissor_top_left = self.upscaled(top_left);
+            let scissor_dimensions = self.upscaled(dimensions);
+
+            {
+                let _pass = RenderPass::new(Target::Texture(&self.fb_out,
+                                                            None))
+                    .scissor(scissor_top_left, scissor_dimensions)
+                    .begin()
+                    .unwrap();
+
+                unsafe {
+                    gl::ClearColor(clear_color[0],
+                                   clear_color[1],
+                                   clear_color[2],
+                                   // XXX Not entirely sure what
+                                   // happens to the mask bit in
+                                   // fill_rect. No$ seems to say that
+                                   // it's set to 0.
+                                   0.);
+                    gl::Clear(gl::COLOR_BUFFER_BIT);
+                }
             }
-        }
 
-        // Reconfigure the draw area
-        self.config.draw_area_top_left = draw_area_top_left;
-        self.config.draw_area_dimensions = draw_area_dimensions;
+            // Keep our copy of the VRAM up to date, it's what fast
+            // savestates store
+            let pixel = fill_pixel(color);
+
+            for y in top_left.1..(top_left.1 + dimensions.1) {
+                let line = y as usize * VRAM_WIDTH_PIXELS as usize;
+                let start = line + top_left.0 as usize;
+                let end = start + dimensions.0 as usize;
 
-        self.apply_scissor();
+                for p in &mut self.config.vram[start..end] {
+                    *p = pixel;
+                }
+            }
+        }
     }
 
     fn load_image(&mut self,
@@ -753,20 +1498,26 @@ impl Renderer for GlRenderer {
                   pixel_buffer: &[u16]) {
         self.draw().unwrap();
 
+        self.vram_dirty = true;
+        self.draw_stats.uploaded_pixels +=
+            resolution.0 as u32 * resolution.1 as u32;
+
         let x_start = top_left.0 as usize;
         let y_start = top_left.1 as usize;
 
         let w = resolution.0 as usize;
         let h = resolution.1 as usize;
 
+        let fb_w = VRAM_WIDTH_PIXELS as usize;
+        let fb_h = VRAM_HEIGHT as usize;
+
         // Update the VRAM buffer (this way we won't lose the textures
-        // if the GL context gets destroyed)
+        // if the GL context gets destroyed). The target rectangle
+        // wraps around the VRAM edges.
         for y in 0..h {
             for x in 0..w {
-                let fb_x = x_start + x;
-                let fb_y = y_start + y;
-
-                let fb_w = VRAM_WIDTH_PIXELS as usize;
+                let fb_x = (x_start + x) % fb_w;
+                let fb_y = (y_start + y) % fb_h;
 
                 let fb_index = fb_y * fb_w + fb_x;
                 let buffer_index = y * w + x;
@@ -775,37 +1526,504 @@ impl Renderer for GlRenderer {
             }
         }
 
-        self.upload_textures(top_left, resolution, pixel_buffer).unwrap();
+        let rects = wrap_vram_rect(top_left, resolution);
+
+        if rects.len() == 1 {
+            self.upload_textures(top_left, resolution, pixel_buffer).unwrap();
+        } else {
+            // Upload each piece from the VRAM buffer we just updated
+            for &(top_left, dimensions) in rects.iter() {
+                let pixels = vram_rect(&self.config.vram,
+                                       top_left,
+                                       dimensions);
+
+                self.upload_textures(top_left, dimensions, &pixels).unwrap();
+            }
+        }
+    }
+}
+
+impl Drop for GlRenderer {
+    fn drop(&mut self) {
+        crash::clear();
+    }
+}
+
+/// Split a quad in four triangles sharing a vertex at its center.
+/// When the quad isn't a parallelogram the two triangles of the usual
+/// split interpolate the texture coordinates and colors differently
+/// on each side of the diagonal, which shows as a seam at high
+/// internal resolutions. The center vertex makes the split symmetric.
+///
+/// The vertices don't have subpixel precision so the center is
+/// rounded to the nearest integer coordinates.
+fn split_quad(v: &[CommandVertex]) -> [CommandVertex; 12] {
+    let average = |f: &Fn(&CommandVertex) -> i32| {
+        let sum: i32 = v.iter().map(f).sum();
+
+        (sum as f32 / 4.).round() as i32
+    };
+
+    let mut center = v[0];
+
+    center.position[0] = average(&|v| v.position[0] as i32) as i16;
+    center.position[1] = average(&|v| v.position[1] as i32) as i16;
+
+    for (i, c) in center.texture_coord.iter_mut().enumerate() {
+        *c = average(&|v| v.texture_coord[i] as i32) as u16;
+    }
+
+    for (i, c) in center.color.iter_mut().enumerate() {
+        *c = average(&|v| v.color[i] as i32) as u8;
+    }
+
+    // PlayStation quads are specified in "Z" order: top-left,
+    // top-right, bottom-left, bottom-right
+    [v[0], v[1], center,
+     v[1], v[3], center,
+     v[3], v[2], center,
+     v[2], v[0], center]
+}
+
+/// If the quad `v` is a sprite, that is an axis-aligned rectangle
+/// mapping its texture 1:1 (which is how rectangle draw commands are
+/// pushed), return the inclusive bounds of its texture area within
+/// the page: `[left, top, right, bottom]`.
+///
+/// At high internal resolutions the interpolated texture coordinates
+/// at the edges of these primitives can fall in the neighboring
+/// texels, the command shader clamps them to these bounds.
+fn sprite_texture_limits(v: &[CommandVertex]) -> Option<[u8; 4]> {
+    let pos = |i: usize, c: usize| v[i].position[c] as i32;
+    let tex = |i: usize, c: usize| v[i].texture_coord[c] as i32;
+
+    // Vertices are in "Z" order: top-left, top-right, bottom-left,
+    // bottom-right
+    let aligned = |f: &Fn(usize, usize) -> i32| {
+        f(0, 1) == f(1, 1) && f(2, 1) == f(3, 1) &&
+            f(0, 0) == f(2, 0) && f(1, 0) == f(3, 0)
+    };
+
+    if !aligned(&pos) || !aligned(&tex) {
+        return None;
+    }
+
+    let width = pos(1, 0) - pos(0, 0);
+    let height = pos(2, 1) - pos(0, 1);
+
+    if width <= 0 || height <= 0 ||
+        tex(1, 0) - tex(0, 0) != width ||
+        tex(2, 1) - tex(0, 1) != height {
+        return None;
+    }
+
+    let right = tex(1, 0) - 1;
+    let bottom = tex(2, 1) - 1;
+
+    // Sprites wrapping around the texture page aren't clamped
+    if right > 0xff || bottom > 0xff {
+        return None;
+    }
+
+    Some([tex(0, 0) as u8, tex(0, 1) as u8, right as u8, bottom as u8])
+}
+
+/// Convert a `fill_rect` color to the VRAM's 1555 format. The mask
+/// bit is cleared, see `fill_rect`.
+fn fill_pixel(color: [u8; 3]) -> u16 {
+    let r = (color[0] >> 3) as u16;
+    let g = (color[1] >> 3) as u16;
+    let b = (color[2] >> 3) as u16;
+
+    r | (g << 5) | (b << 10)
+}
+
+/// Copy the contents of `src` to `dst`, rescaling if necessary
+fn blit_texture(src: &Texture, dst: &Texture) -> Result<(), Error> {
+    let fb_src = try!(Framebuffer::new(src));
+    let fb_dst = try!(Framebuffer::new(dst));
+
+    unsafe {
+        // The scissor box applies to blits. The render passes set it
+        // back up as needed.
+        gl::Disable(gl::SCISSOR_TEST);
+    }
+
+    fb_src.blit_to(&fb_dst)
+}
+
+/// Build the dynamic resolution controller if it's enabled in the
+/// core options, starting at `factor`
+fn dynamic_upscaling(settings: &Settings,
+                     upscaling_max: u32,
+                     factor: u32) -> Option<AutoScale> {
+    if settings.dynamic_upscaling {
+        let min = settings.dynamic_upscaling_min;
+
+        Some(AutoScale::new(min, upscaling_max, factor))
+    } else {
+        None
+    }
+}
+
+/// Return the size of the frame sent to the frontend. With
+/// `stable` set it's always the maximum geometry and the display
+/// area is scaled to fit, switching between 240p and 480i (or between
+/// horizontal resolutions) then doesn't resize the frontend's window
+/// or trigger a video mode change. CRT users relying on the reported
+/// resolution to switch modes want it disabled.
+fn output_resolution(resolution: (u16, u16),
+                     upscaling: u32,
+                     stable: bool) -> (u32, u32) {
+    let (w, h) =
+        if stable {
+            // Matches the maximum geometry reported by `get_av_info`
+            (640, 480)
+        } else {
+            (resolution.0 as u32, resolution.1 as u32)
+        };
+
+    (w * upscaling, h * upscaling)
+}
+
+/// Return the aspect ratio of the picture displayed by a real console
+/// on a 4:3 TV for the given display mode. The horizontal modes all
+/// use a different dot clock divider, for most of them the active
+/// area spans the same 2560 GPU cycles (a full 4:3 line) so only
+/// the number of lines displayed changes the ratio. The 368 pixel
+/// mode is slightly wider.
+fn display_aspect_ratio(clock: VideoClock, resolution: (u16, u16)) -> f32 {
+    let (width, height) = resolution;
+
+    let divider =
+        match width {
+            256 => 10,
+            320 => 8,
+            368 => 7,
+            512 => 5,
+            640 => 4,
+            // Not a standard mode, don't try to be clever
+            _ => return 4. / 3.,
+        };
+
+    // Number of lines in a full 4:3 field
+    let full_lines =
+        match clock {
+            VideoClock::Ntsc => 240,
+            VideoClock::Pal => 288,
+        };
+
+    // Interlaced modes display twice the lines in the same area
+    let full_lines =
+        if height as u32 > full_lines {
+            full_lines * 2
+        } else {
+            full_lines
+        };
+
+    if height == 0 {
+        return 4. / 3.;
+    }
+
+    let h_ratio = (width as u32 * divider) as f32 / 2560.;
+    let v_ratio = height as f32 / full_lines as f32;
+
+    4. / 3. * h_ratio / v_ratio
+}
+
+/// Parameters of a frame drawn to the frontend's framebuffer. If
+/// they don't change and the VRAM hasn't been touched the frame would
+/// be identical to the previous one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct OutputParams {
+    display_top_left: (u16, u16),
+    display_resolution: (u16, u16),
+    display_24bpp: bool,
+    frontend_resolution: (u32, u32),
+    internal_upscaling: u32,
+    /// True if overlays were drawn on top of the frame
+    overlays: bool,
+}
+
+/// Return true if idle frames should be sent as dupes: the option is
+/// enabled and the frontend supports it
+fn dupe_idle_frames(settings: &Settings) -> bool {
+    settings.dupe_idle_frames && libretro::can_dupe()
+}
+
+/// Return true if the output must be flipped vertically when drawn
+/// to the frontend's framebuffer
+fn flip_output(settings: &Settings) -> bool {
+    let top_left_origin = !libretro::hw_context::bottom_left_origin();
+
+    // Some drivers get the origin wrong, let the user override it
+    let flip = top_left_origin ^ settings.flip_output;
+
+    if flip {
+        info!("Flipping the video output vertically");
+    }
+
+    flip
+}
+
+/// Allocate `fb_out` and its depth buffer for `upscaling`. If we run
+/// out of video memory (which can happen with high factors and 32bpp
+/// on integrated GPUs) we try again with lower factors. Returns the
+/// factor actually used along with the textures.
+fn alloc_fb_out(upscaling: u32,
+                depth: u8) -> Result<(u32, Texture, Texture), Error> {
+    let texture_storage = try!(fb_out_storage(depth));
+
+    let mut factor = upscaling;
+
+    // Don't even try to allocate textures larger than what the
+    // driver supports, we'd just get a cryptic INVALID_VALUE
+    let max_factor = max_upscaling();
+
+    if factor > max_factor {
+        warn!("Maximum texture size too small for {}x upscaling, \
+               using {}x", factor, max_factor);
+        libretro_message!(300, "Upscaling limited to {}x by the GPU's \
+                                maximum texture size", max_factor);
+
+        factor = max_factor;
+    }
+
+    loop {
+        let w = VRAM_WIDTH_PIXELS as u32 * factor;
+        let h = VRAM_HEIGHT as u32 * factor;
+
+        let textures =
+            Texture::new(w, h, texture_storage)
+            .and_then(|color| {
+                Texture::new(w, h, gl::DEPTH_COMPONENT32F)
+                    .map(|depth| (color, depth))
+            });
+
+        match textures {
+            Ok((color, depth_buffer)) => {
+                // Color plus 32bit depth
+                let bytes = (w * h) as u64 * (depth as u64 / 8 + 4);
+
+                info!("Internal framebuffer: {}x{} ({}MB of video memory)",
+                      w, h, bytes >> 20);
+
+                if factor != upscaling {
+                    warn!("Not enough video memory for {}x upscaling, \
+                           using {}x", upscaling, factor);
+                    libretro_message!(300, "Not enough video memory for {}x \
+                                            upscaling, using {}x",
+                                      upscaling, factor);
+                }
+
+                return Ok((factor, color, depth_buffer));
+            }
+            // Textures larger than GL_MAX_TEXTURE_SIZE are rejected
+            // with INVALID_VALUE
+            Err(Error::OutOfMemory) |
+            Err(Error::InvalidValue) if factor > 1 => {
+                warn!("Couldn't allocate the internal framebuffer for {}x \
+                       upscaling", factor);
+                factor -= 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Return the highest upscaling factor for which `fb_out` fits in
+/// GL_MAX_TEXTURE_SIZE
+fn max_upscaling() -> u32 {
+    let mut max_size: GLint = 0;
+
+    unsafe {
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size);
+    }
+
+    // The VRAM is wider than it's high so the width is the limiting
+    // dimension. OpenGL 3.3 guarantees at least 1024 so we can
+    // always do native resolution.
+    let max_factor = max_size as u32 / VRAM_WIDTH_PIXELS as u32;
+
+    ::std::cmp::max(max_factor, 1)
+}
+
+/// Return the texture format used for `fb_out` at the given internal
+/// color depth
+fn fb_out_storage(depth: u8) -> Result<GLenum, Error> {
+    match depth {
+        16 => Ok(gl::RGB5_A1),
+        32 => Ok(gl::RGBA8),
+        _ => Err(Error::UnsupportedColorDepth(depth)),
+    }
+}
+
+/// Split a VRAM rectangle that wraps around the edges of the VRAM
+/// into up to 4 rectangles that fit within it
+fn wrap_vram_rect(top_left: (u16, u16),
+                  dimensions: (u16, u16))
+                  -> ArrayVec<[((u16, u16), (u16, u16)); 4]> {
+    let mut rects = ArrayVec::new();
+
+    let (x, y) = (top_left.0 % VRAM_WIDTH_PIXELS, top_left.1 % VRAM_HEIGHT);
+    let (w, h) = (::std::cmp::min(dimensions.0, VRAM_WIDTH_PIXELS),
+                  ::std::cmp::min(dimensions.1, VRAM_HEIGHT));
+
+    // Width of each horizontal piece: before and after the wrap
+    let w_first = ::std::cmp::min(w, VRAM_WIDTH_PIXELS - x);
+    let h_first = ::std::cmp::min(h, VRAM_HEIGHT - y);
+
+    let columns = [(x, w_first), (0, w - w_first)];
+    let rows = [(y, h_first), (0, h - h_first)];
+
+    for &(ry, rh) in rows.iter().filter(|&&(_, rh)| rh > 0) {
+        for &(rx, rw) in columns.iter().filter(|&&(_, rw)| rw > 0) {
+            rects.push(((rx, ry), (rw, rh)));
+        }
+    }
+
+    rects
+}
+
+/// Extract a rectangle from a VRAM buffer
+fn vram_rect(vram: &[u16],
+             top_left: (u16, u16),
+             dimensions: (u16, u16)) -> Vec<u16> {
+    let (x, y) = (top_left.0 as usize, top_left.1 as usize);
+    let (w, h) = (dimensions.0 as usize, dimensions.1 as usize);
+
+    let stride = VRAM_WIDTH_PIXELS as usize;
+
+    let mut pixels = Vec::with_capacity(w * h);
+
+    for row in y..(y + h) {
+        let start = row * stride + x;
+
+        pixels.extend_from_slice(&vram[start..start + w]);
+    }
+
+    pixels
+}
+
+/// Uniform locations of the command program, resolved once when the
+/// program is built
+struct CommandUniforms {
+    offset: Uniform,
+    tex_x_mask: Uniform,
+    tex_x_or: Uniform,
+    tex_y_mask: Uniform,
+    tex_y_or: Uniform,
+    fb_texture: Uniform,
+    draw_semi_transparent: Uniform,
+}
+
+impl CommandUniforms {
+    fn new(program: &Program) -> Result<CommandUniforms, Error> {
+        Ok(CommandUniforms {
+            offset: try!(program.find_uniform("offset")),
+            tex_x_mask: try!(program.find_uniform("tex_x_mask")),
+            tex_x_or: try!(program.find_uniform("tex_x_or")),
+            tex_y_mask: try!(program.find_uniform("tex_y_mask")),
+            tex_y_or: try!(program.find_uniform("tex_y_or")),
+            fb_texture: try!(program.find_uniform("fb_texture")),
+            draw_semi_transparent:
+                try!(program.find_uniform("draw_semi_transparent")),
+        })
+    }
+}
+
+/// Uniform locations of the output program
+struct OutputUniforms {
+    fb: Uniform,
+    depth_24bpp: Uniform,
+    internal_upscaling: Uniform,
+    pal_crt: Uniform,
+    gamma: Uniform,
+    brightness: Uniform,
+    saturation: Uniform,
+}
+
+impl OutputUniforms {
+    fn new(program: &Program) -> Result<OutputUniforms, Error> {
+        Ok(OutputUniforms {
+            fb: try!(program.find_uniform("fb")),
+            depth_24bpp: try!(program.find_uniform("depth_24bpp")),
+            internal_upscaling:
+                try!(program.find_uniform("internal_upscaling")),
+            pal_crt: try!(program.find_uniform("pal_crt")),
+            gamma: try!(program.find_uniform("gamma")),
+            brightness: try!(program.find_uniform("brightness")),
+            saturation: try!(program.find_uniform("saturation")),
+        })
+    }
+}
+
+/// Color adjustments set in the core options
+struct ColorCorrection {
+    profile: ColorProfile,
+    gamma: GLfloat,
+    brightness: GLfloat,
+    saturation: GLfloat,
+}
+
+impl ColorCorrection {
+    fn from_options(settings: &Settings) -> ColorCorrection {
+        ColorCorrection {
+            profile: settings.color_profile,
+            gamma: settings.output_gamma,
+            brightness: settings.output_brightness,
+            saturation: settings.output_saturation,
+        }
     }
 }
 
+/// Vertex sent to the command shaders. The layout is explicit and
+/// padded to 32 bytes so that every vertex starts on a 16 byte
+/// boundary in the buffer, which is friendlier to the GPU's vertex
+/// fetch. See `assert_command_vertex_size` when adding fields.
+#[repr(C)]
 #[derive(Default, Debug, Clone, Copy)]
 struct CommandVertex {
     /// Position in PlayStation VRAM coordinates
     position: [i16; 3],
-    /// RGB color, 8bits per component
-    color: [u8; 3],
+    /// Blending mode: 0: no texture, 1: raw-texture, 2: texture-blended
+    texture_blend_mode: u8,
+    /// Right shift from 16bits: 0 for 16bpp textures, 1 for 8bpp, 2
+    /// for 4bpp
+    depth_shift: u8,
     /// Texture coordinates within the page
     texture_coord: [u16; 2],
     /// Texture page (base offset in VRAM used for texture lookup)
     texture_page: [u16; 2],
     /// Color Look-Up Table (palette) coordinates in VRAM
     clut: [u16; 2],
-    /// Blending mode: 0: no texture, 1: raw-texture, 2: texture-blended
-    texture_blend_mode: u8,
-    /// Right shift from 16bits: 0 for 16bpp textures, 1 for 8bpp, 2
-    /// for 4bpp
-    depth_shift: u8,
+    /// RGB color, 8bits per component
+    color: [u8; 3],
     /// True if dithering is enabled for this primitive
     dither: u8,
     /// 0: primitive is opaque, 1: primitive is semi-transparent
     semi_transparent: u8,
+    /// Inclusive bounds of the texture area of sprites, see
+    /// `sprite_texture_limits`
+    texture_limits: [u8; 4],
+    /// 1 if the texture coordinates are clamped to `texture_limits`
+    clamp_texture: u8,
+    /// Unused, pads the vertex to 32 bytes
+    _padding: [u8; 2],
 }
 
 implement_vertex!(CommandVertex,
-                  position, color, texture_page,
-                  texture_coord, clut, texture_blend_mode,
-                  depth_shift, dither, semi_transparent);
+                  position, texture_blend_mode, depth_shift,
+                  texture_coord, texture_page, clut,
+                  color, dither, semi_transparent,
+                  texture_limits, clamp_texture);
+
+/// Never called, fails to build if `CommandVertex` is not exactly 32
+/// bytes long since `transmute` requires types of the same size.
+#[allow(dead_code)]
+fn assert_command_vertex_size(v: CommandVertex) -> [u8; 32] {
+    unsafe { ::std::mem::transmute(v) }
+}
 
 impl CommandVertex {
     fn from_vertex(attributes: &PrimitiveAttributes,
@@ -829,6 +2047,9 @@ impl CommandVertex {
             },
             dither: attributes.dither as u8,
             semi_transparent: attributes.semi_transparent as u8,
+            texture_limits: [0; 4],
+            clamp_texture: 0,
+            _padding: [0; 2],
         }
     }
 }
@@ -850,3 +2071,141 @@ struct ImageLoadVertex {
 
 implement_vertex!(ImageLoadVertex,
                   position);
+
+#[test]
+fn test_display_aspect_ratio() {
+    let ntsc = VideoClock::Ntsc;
+    let pal = VideoClock::Pal;
+
+    let check = |clock, resolution, expected: f32| {
+        let ratio = display_aspect_ratio(clock, resolution);
+
+        assert!((ratio - expected).abs() < 0.0001,
+                "{:?}: {} != {}", resolution, ratio, expected);
+    };
+
+    check(ntsc, (320, 240), 4. / 3.);
+    check(ntsc, (640, 480), 4. / 3.);
+    check(pal, (512, 288), 4. / 3.);
+    check(pal, (320, 576), 4. / 3.);
+
+    // Shorter picture, same width
+    check(ntsc, (320, 224), 4. / 3. * 240. / 224.);
+
+    // 368 pixel mode is a little wider
+    check(ntsc, (368, 240), 4. / 3. * 2576. / 2560.);
+
+    // Unknown modes
+    check(ntsc, (300, 240), 4. / 3.);
+    check(ntsc, (320, 0), 4. / 3.);
+}
+
+#[test]
+fn test_wrap_vram_rect() {
+    let r = wrap_vram_rect((10, 20), (30, 40));
+    assert_eq!(&r[..], &[((10, 20), (30, 40))]);
+
+    let r = wrap_vram_rect((1000, 500), (48, 16));
+    assert_eq!(&r[..], &[((1000, 500), (24, 12)),
+                         ((0, 500), (24, 12)),
+                         ((1000, 0), (24, 4)),
+                         ((0, 0), (24, 4))]);
+}
+
+#[test]
+fn test_fill_pixel() {
+    assert_eq!(fill_pixel([0, 0, 0]), 0);
+    assert_eq!(fill_pixel([255, 255, 255]), 0x7fff);
+    assert_eq!(fill_pixel([0xf8, 0x08, 0x07]), 0x001f | (1 << 5));
+}
+
+#[test]
+fn test_split_quad() {
+    let vertex = |x, y, u, v, r| {
+        let mut c = CommandVertex::default();
+
+        c.position = [x, y, 7];
+        c.texture_coord = [u, v];
+        c.color = [r, 0, 255];
+
+        c
+    };
+
+    let quad = [vertex(0, 0, 0, 0, 0),
+                vertex(10, 0, 16, 0, 100),
+                vertex(0, 10, 0, 16, 100),
+                vertex(13, 11, 16, 16, 200)];
+
+    let t = split_quad(&quad);
+
+    let center = t[2];
+
+    assert_eq!(center.position, [6, 5, 7]);
+    assert_eq!(center.texture_coord, [8, 8]);
+    assert_eq!(center.color, [100, 0, 255]);
+
+    // Every edge of the quad is used exactly once
+    for &(a, b) in &[(0, 1), (1, 3), (3, 2), (2, 0)] {
+        let edges =
+            t.chunks(3)
+            .filter(|tri| tri[0].position == quad[a].position &&
+                    tri[1].position == quad[b].position)
+            .count();
+
+        assert_eq!(edges, 1);
+    }
+}
+
+#[test]
+fn test_sprite_texture_limits() {
+    let vertex = |x, y, u, v| {
+        let mut c = CommandVertex::default();
+
+        c.position = [x, y, 0];
+        c.texture_coord = [u, v];
+
+        c
+    };
+
+    let sprite = [vertex(10, 20, 64, 32),
+                  vertex(26, 20, 80, 32),
+                  vertex(10, 36, 64, 48),
+                  vertex(26, 36, 80, 48)];
+
+    assert_eq!(sprite_texture_limits(&sprite), Some([64, 32, 79, 47]));
+
+    // Scaled texture
+    let scaled = [vertex(10, 20, 64, 32),
+                  vertex(42, 20, 80, 32),
+                  vertex(10, 36, 64, 48),
+                  vertex(42, 36, 80, 48)];
+
+    assert_eq!(sprite_texture_limits(&scaled), None);
+
+    // Rotated quad
+    let rotated = [vertex(10, 20, 64, 32),
+                   vertex(26, 24, 80, 32),
+                   vertex(6, 36, 64, 48),
+                   vertex(22, 40, 80, 48)];
+
+    assert_eq!(sprite_texture_limits(&rotated), None);
+
+    // Wraps around the texture page
+    let wrapping = [vertex(10, 20, 248, 32),
+                    vertex(26, 20, 264, 32),
+                    vertex(10, 36, 248, 48),
+                    vertex(26, 36, 264, 48)];
+
+    assert_eq!(sprite_texture_limits(&wrapping), None);
+}
+
+#[test]
+fn test_output_resolution() {
+    assert_eq!(output_resolution((320, 240), 2, false), (640, 480));
+    assert_eq!(output_resolution((640, 480), 1, false), (640, 480));
+    assert_eq!(output_resolution((256, 240), 1, false), (256, 240));
+
+    assert_eq!(output_resolution((320, 240), 1, true), (640, 480));
+    assert_eq!(output_resolution((640, 480), 1, true), (640, 480));
+    assert_eq!(output_resolution((368, 240), 3, true), (1920, 1440));
+}
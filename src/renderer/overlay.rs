@@ -0,0 +1,162 @@
+//! Overlays drawn on top of the emulated display in the frontend's
+//! framebuffer
+
+use arrayvec::ArrayVec;
+use rustation::padmemcard::gamepad::Button;
+
+use retrogl::types::GlType;
+
+/// Vertex used to draw flat colored overlay quads. Positions are in
+/// OpenGL normalized device coordinates.
+pub struct OverlayVertex {
+    /// Vertex position on the screen
+    pub position: [f32; 2],
+    /// RGB color, 8bits per component
+    pub color: [u8; 3],
+}
+
+implement_vertex!(OverlayVertex,
+                  position, color);
+
+/// Set of PlayStation buttons currently pressed, used to draw the
+/// input echo overlay
+pub type PressedButtons = ArrayVec<[Button; 16]>;
+
+/// Build the vertices for the on-screen pad viewer. The pad is drawn
+/// in the bottom left corner of a `(width, height)` framebuffer.
+pub fn input_echo_vertices(pressed: &PressedButtons,
+                           (width, height): (u32, u32))
+                           -> Vec<OverlayVertex> {
+    let mut vertices = Vec::with_capacity(PAD_LAYOUT.len() * 6);
+
+    // Size of a layout cell in pixels: the whole pad takes roughly a
+    // quarter of the screen width
+    let cell = (width as f32 / 4.) / GRID_WIDTH;
+
+    // Don't let the overlay become taller than a third of the screen
+    let cell = cell.min((height as f32 / 3.) / GRID_HEIGHT);
+
+    let margin = cell;
+
+    for &(button, x, y, w, h) in PAD_LAYOUT.iter() {
+        let color =
+            if pressed.iter().any(|&b| b == button) {
+                PRESSED_COLOR
+            } else {
+                RELEASED_COLOR
+            };
+
+        // Convert from layout cells to framebuffer pixels. The layout
+        // has y going down, OpenGL has it going up.
+        let x0 = margin + x * cell;
+        let x1 = x0 + w * cell;
+        let y1 = margin + (GRID_HEIGHT - y) * cell;
+        let y0 = y1 - h * cell;
+
+        // Convert to normalized device coordinates
+        let x0 = (x0 / width as f32) * 2. - 1.;
+        let x1 = (x1 / width as f32) * 2. - 1.;
+        let y0 = (y0 / height as f32) * 2. - 1.;
+        let y1 = (y1 / height as f32) * 2. - 1.;
+
+        let corners = [[x0, y0], [x1, y0], [x0, y1],
+                       [x1, y0], [x0, y1], [x1, y1]];
+
+        for &position in corners.iter() {
+            vertices.push(OverlayVertex {
+                position: position,
+                color: color,
+            });
+        }
+    }
+
+    vertices
+}
+
+/// Rectangle outlined on top of the display, used by the debugger to
+/// highlight things on screen. Coordinates are in PlayStation pixels
+/// relative to the top left corner of the display area, which may
+/// well be partially or completely off-screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Marker {
+    pub top_left: (i32, i32),
+    pub size: (u32, u32),
+    pub color: [u8; 3],
+}
+
+/// Build the vertices outlining `markers` on a display of
+/// `(width, height)` PlayStation pixels, which covers the whole
+/// framebuffer. The outline is one PlayStation pixel thick whatever
+/// the upscaling factor.
+pub fn marker_vertices(markers: &[Marker],
+                       (width, height): (u16, u16)) -> Vec<OverlayVertex> {
+    let mut vertices = Vec::with_capacity(markers.len() * 4 * 6);
+
+    if width == 0 || height == 0 {
+        return vertices;
+    }
+
+    // Convert from display pixels to normalized device coordinates,
+    // the display has y going down, OpenGL has it going up
+    let ndc = |x: i32, y: i32| {
+        [(x as f32 / width as f32) * 2. - 1.,
+         1. - (y as f32 / height as f32) * 2.]
+    };
+
+    for m in markers {
+        let (x, y) = m.top_left;
+        let w = m.size.0 as i32;
+        let h = m.size.1 as i32;
+
+        // Top, bottom, left and right edges
+        let edges = [(x, y, w, 1),
+                     (x, y + h - 1, w, 1),
+                     (x, y, 1, h),
+                     (x + w - 1, y, 1, h)];
+
+        for &(x, y, w, h) in edges.iter() {
+            let p0 = ndc(x, y);
+            let p1 = ndc(x + w, y + h);
+
+            let corners = [[p0[0], p0[1]], [p1[0], p0[1]], [p0[0], p1[1]],
+                           [p1[0], p0[1]], [p0[0], p1[1]], [p1[0], p1[1]]];
+
+            for &position in corners.iter() {
+                vertices.push(OverlayVertex {
+                    position: position,
+                    color: m.color,
+                });
+            }
+        }
+    }
+
+    vertices
+}
+
+/// Width of the pad layout grid in cells
+const GRID_WIDTH: f32 = 16.;
+/// Height of the pad layout grid in cells
+const GRID_HEIGHT: f32 = 7.;
+
+/// Position of each button in the pad viewer, in grid cells:
+/// `(button, x, y, width, height)`
+const PAD_LAYOUT: [(Button, f32, f32, f32, f32); 16] =
+    [(Button::L2,        1.,  0., 2., 1.),
+     (Button::L1,        1.,  1., 2., 1.),
+     (Button::R2,       13.,  0., 2., 1.),
+     (Button::R1,       13.,  1., 2., 1.),
+     (Button::DUp,       2.,  3., 1., 1.),
+     (Button::DLeft,     1.,  4., 1., 1.),
+     (Button::DRight,    3.,  4., 1., 1.),
+     (Button::DDown,     2.,  5., 1., 1.),
+     (Button::Triangle, 13.,  3., 1., 1.),
+     (Button::Square,   12.,  4., 1., 1.),
+     (Button::Circle,   14.,  4., 1., 1.),
+     (Button::Cross,    13.,  5., 1., 1.),
+     (Button::Select,    6.,  4., 1., 1.),
+     (Button::Start,     9.,  4., 1., 1.),
+     (Button::L3,        5.,  6., 2., 1.),
+     (Button::R3,        9.,  6., 2., 1.)];
+
+const PRESSED_COLOR: [u8; 3] = [0xff, 0xd0, 0x40];
+const RELEASED_COLOR: [u8; 3] = [0x40, 0x40, 0x40];
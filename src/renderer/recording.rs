@@ -0,0 +1,376 @@
+//! Recording of the core's output to plain uncompressed files, for
+//! users who want the exact frames without going through the
+//! frontend's encoder (and its scaling, frame pacing and dropped
+//! frames).
+//!
+//! The display area of every frame is written to a YUV4MPEG2 file,
+//! either at native resolution (decoded from the rendered VRAM) or at
+//! the internal resolution. Y4M has no RGB format so the pixels are
+//! converted to full range 4:4:4 YCbCr, which is as close to lossless
+//! as 8bit YCbCr gets. The video mode can change while recording
+//! (menus and gameplay often use different resolutions), in which
+//! case a new numbered segment is started since a Y4M stream has a
+//! fixed size. The audio goes to a 16bit stereo WAV file next to it.
+//!
+//! The files grow fast (about 14MB per second at native resolution)
+//! and are written from the emulation thread, this isn't meant to be
+//! left running.
+//!
+//! XXX The WAV track only contains silence for now: rustation doesn't
+//! expose its SPU output. It still gets the right number of samples
+//! per frame so that the track stays in sync and the real samples can
+//! be dropped in `end_frame` once they're available.
+
+use std::fs::{self, File};
+use std::io::{self, Write, Seek, SeekFrom, BufWriter};
+use std::path::{Path, PathBuf};
+
+use time;
+
+use libretro;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecordMode {
+    Disabled,
+    /// Display area at native resolution
+    Native,
+    /// Display area at the internal resolution
+    Internal,
+}
+
+impl RecordMode {
+    pub fn parse(opt: &str) -> Result<RecordMode, ()> {
+        let mode =
+            match opt {
+                "disabled" => RecordMode::Disabled,
+                "native resolution" => RecordMode::Native,
+                "internal resolution" => RecordMode::Internal,
+                _ => return Err(()),
+            };
+
+        Ok(mode)
+    }
+}
+
+/// Recording in progress
+pub struct Recording {
+    /// Directory holding the files of this recording
+    directory: PathBuf,
+    mode: RecordMode,
+    /// Frame rate as a fraction, for the Y4M headers
+    framerate: (u32, u32),
+    /// Current video segment
+    video: Option<Y4m>,
+    /// Number of video segments started so far
+    segments: u32,
+    audio: Wav,
+    /// Number of audio samples per frame
+    samples_per_frame: f32,
+    /// Fractional number of samples not written yet
+    samples_owed: f32,
+    /// Number of frames recorded so far
+    frames: u32,
+    /// Set if we encountered a write error, in which case we stop
+    /// recording
+    failed: bool,
+}
+
+impl Recording {
+    /// Start a new recording for `content` in the save directory.
+    /// `fps` is the emulated frame rate.
+    pub fn new(content: &str,
+               mode: RecordMode,
+               fps: f32) -> Result<Recording, ()> {
+        let base =
+            match libretro::get_save_directory() {
+                Some(d) => d,
+                None => {
+                    error!("The frontend didn't give us a save directory, \
+                            can't record");
+                    return Err(());
+                }
+            };
+
+        let directory =
+            base.join("rustation").join("recordings")
+            .join(format!("{}-{}", content, time::get_time().sec));
+
+        if let Err(e) = fs::create_dir_all(&directory) {
+            error!("Couldn't create {:?}: {}", directory, e);
+            return Err(());
+        }
+
+        let audio_path = directory.join("audio.wav");
+
+        let audio =
+            match Wav::new(&audio_path) {
+                Ok(w) => w,
+                Err(e) => {
+                    error!("Couldn't create {:?}: {}", audio_path, e);
+                    return Err(());
+                }
+            };
+
+        info!("Recording to {:?}", directory);
+        libretro_message!(100, "Recording started");
+
+        Ok(Recording {
+            directory: directory,
+            mode: mode,
+            framerate: ((fps * 100.).round() as u32, 100),
+            video: None,
+            segments: 0,
+            audio: audio,
+            samples_per_frame: SAMPLE_RATE as f32 / fps,
+            samples_owed: 0.,
+            frames: 0,
+            failed: false,
+        })
+    }
+
+    pub fn mode(&self) -> RecordMode {
+        self.mode
+    }
+
+    /// Record the display area of the next frame. `rgba` contains
+    /// `resolution` 8bit RGBA pixels, top row first.
+    pub fn push_frame(&mut self, rgba: &[u8], resolution: (u32, u32)) {
+        if self.failed || resolution.0 == 0 || resolution.1 == 0 {
+            return;
+        }
+
+        let res = self.write_frame(rgba, resolution);
+
+        self.check(res);
+    }
+
+    fn write_frame(&mut self,
+                   rgba: &[u8],
+                   resolution: (u32, u32)) -> io::Result<()> {
+        let new_segment =
+            match self.video {
+                Some(ref v) => v.resolution != resolution,
+                None => true,
+            };
+
+        if new_segment {
+            let path =
+                self.directory.join(format!("video-{:02}.y4m",
+                                            self.segments));
+
+            info!("Recording {}x{} video to {:?}",
+                  resolution.0, resolution.1, path);
+
+            self.video = Some(try!(Y4m::new(&path,
+                                            resolution,
+                                            self.framerate)));
+            self.segments += 1;
+        }
+
+        match self.video {
+            Some(ref mut v) => v.push_frame(rgba),
+            None => unreachable!(),
+        }
+    }
+
+    /// Called once per frame after the frame has been pushed
+    pub fn end_frame(&mut self) {
+        self.frames = self.frames.wrapping_add(1);
+
+        if self.failed {
+            return;
+        }
+
+        self.samples_owed += self.samples_per_frame;
+
+        let nsamples = self.samples_owed as usize;
+
+        self.samples_owed -= nsamples as f32;
+
+        let silence = vec![0; nsamples * 2];
+
+        let res = self.audio.push_samples(&silence);
+
+        self.check(res);
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(e) = res {
+            error!("Couldn't write recording, giving up: {}", e);
+            libretro_message!(200, "Recording failed");
+            self.failed = true;
+        }
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let Some(ref mut v) = self.video {
+            if let Err(e) = v.out.flush() {
+                error!("Couldn't flush the video recording: {}", e);
+            }
+        }
+
+        if let Err(e) = self.audio.finish() {
+            error!("Couldn't finalize the audio recording: {}", e);
+        }
+
+        info!("Recorded {} frames to {:?}", self.frames, self.directory);
+        libretro_message!(100, "Recording stopped");
+    }
+}
+
+/// YUV4MPEG2 video stream
+struct Y4m {
+    out: BufWriter<File>,
+    resolution: (u32, u32),
+}
+
+impl Y4m {
+    fn new(path: &Path,
+           resolution: (u32, u32),
+           (fps_num, fps_den): (u32, u32)) -> io::Result<Y4m> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+
+        try!(writeln!(out,
+                      "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444 \
+                       XCOLORRANGE=FULL",
+                      resolution.0, resolution.1, fps_num, fps_den));
+
+        Ok(Y4m {
+            out: out,
+            resolution: resolution,
+        })
+    }
+
+    fn push_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        try!(self.out.write_all(b"FRAME\n"));
+
+        self.out.write_all(&rgba_to_yuv444(rgba))
+    }
+}
+
+/// Convert RGBA pixels to planar full range BT.601 YCbCr
+fn rgba_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let npixels = rgba.len() / 4;
+
+    let mut planes = vec![0; npixels * 3];
+
+    for (i, p) in rgba.chunks(4).enumerate() {
+        let r = p[0] as i32;
+        let g = p[1] as i32;
+        let b = p[2] as i32;
+
+        // 16.16 fixed point coefficients, rounded
+        let y = (19595 * r + 38470 * g + 7471 * b + 0x8000) >> 16;
+        let cb = (-11059 * r - 21709 * g + 32768 * b + 0x80_8000) >> 16;
+        let cr = (32768 * r - 27439 * g - 5329 * b + 0x80_8000) >> 16;
+
+        let clamp = |v: i32| ::std::cmp::max(0, ::std::cmp::min(v, 255));
+
+        planes[i] = clamp(y) as u8;
+        planes[npixels + i] = clamp(cb) as u8;
+        planes[npixels * 2 + i] = clamp(cr) as u8;
+    }
+
+    planes
+}
+
+/// 16bit stereo WAV file. The sizes in the header are only known
+/// once the recording is over, `finish` patches them.
+struct Wav {
+    out: BufWriter<File>,
+    /// Number of stereo frames written so far
+    frames: u32,
+}
+
+impl Wav {
+    fn new(path: &Path) -> io::Result<Wav> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+
+        try!(out.write_all(&wav_header(0)));
+
+        Ok(Wav {
+            out: out,
+            frames: 0,
+        })
+    }
+
+    /// Append interleaved stereo `samples`
+    fn push_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+
+        for &s in samples {
+            bytes.extend_from_slice(&[s as u8, (s >> 8) as u8]);
+        }
+
+        try!(self.out.write_all(&bytes));
+
+        self.frames = self.frames.wrapping_add(samples.len() as u32 / 2);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        try!(self.out.seek(SeekFrom::Start(0)));
+        try!(self.out.write_all(&wav_header(self.frames)));
+
+        self.out.flush()
+    }
+}
+
+/// Build the header of a WAV file containing `frames` stereo frames
+fn wav_header(frames: u32) -> Vec<u8> {
+    let data_len = frames.wrapping_mul(4);
+
+    let mut header = Vec::with_capacity(44);
+
+    let push_u32 = |h: &mut Vec<u8>, v: u32| {
+        h.extend_from_slice(&[v as u8,
+                              (v >> 8) as u8,
+                              (v >> 16) as u8,
+                              (v >> 24) as u8]);
+    };
+
+    header.extend_from_slice(b"RIFF");
+    push_u32(&mut header, data_len.wrapping_add(36));
+    header.extend_from_slice(b"WAVEfmt ");
+    push_u32(&mut header, 16);
+    // PCM, 2 channels
+    header.extend_from_slice(&[1, 0, 2, 0]);
+    push_u32(&mut header, SAMPLE_RATE);
+    // Byte rate
+    push_u32(&mut header, SAMPLE_RATE * 4);
+    // Block alignment, bits per sample
+    header.extend_from_slice(&[4, 0, 16, 0]);
+    header.extend_from_slice(b"data");
+    push_u32(&mut header, data_len);
+
+    header
+}
+
+/// SPU output sample rate
+const SAMPLE_RATE: u32 = 44_100;
+
+#[test]
+fn test_recording_formats() {
+    // White, black, pure red
+    let rgba = [0xff, 0xff, 0xff, 0xff,
+                0, 0, 0, 0xff,
+                0xff, 0, 0, 0xff];
+
+    assert_eq!(rgba_to_yuv444(&rgba), [255, 0, 76,
+                                       128, 128, 85,
+                                       128, 128, 255]);
+
+    let header = wav_header(10);
+
+    assert_eq!(header.len(), 44);
+    assert_eq!(&header[0..4], b"RIFF");
+    assert_eq!(&header[4..8], &[76, 0, 0, 0]);
+    assert_eq!(&header[40..44], &[40, 0, 0, 0]);
+
+    assert_eq!(RecordMode::parse("native resolution"),
+               Ok(RecordMode::Native));
+    assert!(RecordMode::parse("720p").is_err());
+}
@@ -30,6 +30,10 @@ flat in uint frag_depth_shift;
 flat in uint frag_dither;
 // 0: Opaque primitive, 1: semi-transparent primitive
 flat in uint frag_semi_transparent;
+// Sprite texture area: left, top, right, bottom (inclusive)
+flat in uvec4 frag_texture_limits;
+// 1: clamp the texture coordinates to `frag_texture_limits`
+flat in uint frag_clamp_texture;
 
 out vec4 frag_color;
 
@@ -82,9 +86,21 @@ void main() {
     // Number of texel per VRAM 16bit "pixel" for the current depth
     uint pix_per_hw = 1U << frag_depth_shift;
 
+    vec2 coord = frag_texture_coord;
+
+    if (frag_clamp_texture == 1U) {
+      // Sprites map their texture 1:1 but at high internal
+      // resolutions the interpolated coordinates at their edges can
+      // end up in the neighboring texels. Keep them at least half a
+      // texel inside the sprite's texture area.
+      coord = clamp(coord,
+                    vec2(frag_texture_limits.xy) + 0.5,
+                    vec2(frag_texture_limits.zw) + 0.5);
+    }
+
     // Texture pages are limited to 256x256 pixels
-    uint tex_x = uint(frag_texture_coord.x) & 0xffU;
-    uint tex_y = uint(frag_texture_coord.y) & 0xffU;
+    uint tex_x = uint(coord.x) & 0xffU;
+    uint tex_y = uint(coord.y) & 0xffU;
 
     // Texture window adjustments
     tex_x = (tex_x & tex_x_mask) | tex_x_or;
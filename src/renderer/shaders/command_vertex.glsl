@@ -11,6 +11,8 @@ in uint texture_blend_mode;
 in uint depth_shift;
 in uint dither;
 in uint semi_transparent;
+in uvec4 texture_limits;
+in uint clamp_texture;
 
 // Drawing offset
 uniform ivec2 offset;
@@ -23,6 +25,8 @@ flat out uint frag_texture_blend_mode;
 flat out uint frag_depth_shift;
 flat out uint frag_dither;
 flat out uint frag_semi_transparent;
+flat out uvec4 frag_texture_limits;
+flat out uint frag_clamp_texture;
 
 void main() {
   ivec2 pos = position.xy + offset;
@@ -50,4 +54,6 @@ void main() {
   frag_depth_shift = depth_shift;
   frag_dither = dither;
   frag_semi_transparent = semi_transparent;
+  frag_texture_limits = texture_limits;
+  frag_clamp_texture = clamp_texture;
 }
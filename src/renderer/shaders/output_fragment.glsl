@@ -7,6 +7,11 @@ uniform int depth_24bpp;
 // Internal resolution upscaling factor. Necessary for proper 24bpp
 // display since we need to know how the pixels are laid out in RAM.
 uniform uint internal_upscaling;
+// Color adjustments: 1 to approximate the colors of a PAL CRT
+uniform int pal_crt;
+uniform float gamma;
+uniform float brightness;
+uniform float saturation;
 
 in vec2 frag_fb_coord;
 
@@ -23,6 +28,28 @@ int rebuild_color(vec4 color) {
   return (a << 15) | (b << 10) | (g << 5) | r;
 }
 
+// Apply the color adjustments set in the core options
+vec3 adjust_color(vec3 color) {
+  if (pal_crt != 0) {
+    // The TV's gamma is a bit higher than the sRGB one we're
+    // displaying with and its EBU phosphors have slightly different
+    // primaries, convert in linear space.
+    vec3 linear = pow(color, vec3(2.5));
+
+    linear = vec3(dot(linear, vec3(1.0440, -0.0440, 0.0)),
+                  linear.g,
+                  dot(linear, vec3(0.0, 0.0118, 0.9882)));
+
+    color = pow(clamp(linear, 0., 1.), vec3(1. / 2.2));
+  }
+
+  float luma = dot(color, vec3(0.299, 0.587, 0.114));
+
+  color = mix(vec3(luma), color, saturation) * brightness;
+
+  return pow(clamp(color, 0., 1.), vec3(1. / gamma));
+}
+
 void main() {
   vec3 color;
 
@@ -66,5 +93,5 @@ void main() {
     color = vec3(r, g, b);
   }
 
-  frag_color = vec4(color, 1.0);
+  frag_color = vec4(adjust_color(color), 1.0);
 }
@@ -7,9 +7,17 @@ in uvec2 fb_coord;
 
 out vec2 frag_fb_coord;
 
+// Set if the frontend's framebuffer has its origin in the top left
+// corner, in which case we have to flip the image vertically
+uniform bool flip_y;
+
 void main() {
   gl_Position.xyzw = vec4(position, 0.0, 1.0);
 
+  if (flip_y) {
+    gl_Position.y = -gl_Position.y;
+  }
+
   // Convert the PlayStation framebuffer coordinate into an OpenGL
   // texture coordinate
   float fb_x_coord = float(fb_coord.x) / 1024;
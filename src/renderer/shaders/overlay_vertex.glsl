@@ -0,0 +1,23 @@
+#version 330 core
+
+// Vertex shader for the overlays drawn on top of the frontend's
+// framebuffer
+
+in vec2 position;
+in uvec3 color;
+
+out vec3 frag_overlay_color;
+
+// Set if the frontend's framebuffer has its origin in the top left
+// corner, in which case we have to flip the image vertically
+uniform bool flip_y;
+
+void main() {
+  gl_Position.xyzw = vec4(position, 0.0, 1.0);
+
+  if (flip_y) {
+    gl_Position.y = -gl_Position.y;
+  }
+
+  frag_overlay_color = vec3(color) / 255.;
+}
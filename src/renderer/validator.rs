@@ -0,0 +1,284 @@
+//! Optional validation layer sitting between the emulated GPU and
+//! the renderer. It checks the commands for values that make no sense
+//! on the real hardware and logs them before forwarding everything
+//! untouched to the real renderer.
+
+use rustation::gpu::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use rustation::gpu::renderer::{TextureDepth, BlendMode};
+use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+
+/// Persistent validator state, kept across frames
+pub struct Validator {
+    /// Name of the content being run, used to give some context in
+    /// the logs
+    content: String,
+    /// Number of frames validated so far
+    frame: u32,
+    /// Number of violations logged during the current frame
+    frame_violations: u32,
+    /// Total number of violations since the validator was created
+    total_violations: u32,
+    /// Current draw offset, needed to compute the real position of
+    /// the primitives
+    draw_offset: (i16, i16),
+}
+
+impl Validator {
+    pub fn new(content: &str) -> Validator {
+        Validator {
+            content: content.into(),
+            frame: 0,
+            frame_violations: 0,
+            total_violations: 0,
+            draw_offset: (0, 0),
+        }
+    }
+
+    /// Wrap `renderer` for the duration of a frame
+    pub fn wrap<'a>(&'a mut self,
+                    renderer: &'a mut Renderer) -> ValidatingRenderer<'a> {
+        self.frame = self.frame.wrapping_add(1);
+        self.frame_violations = 0;
+
+        ValidatingRenderer {
+            validator: self,
+            renderer: renderer,
+        }
+    }
+
+    fn violation(&mut self, what: &str, details: ::std::fmt::Arguments) {
+        self.frame_violations += 1;
+        self.total_violations += 1;
+
+        if self.frame_violations <= MAX_VIOLATIONS_PER_FRAME {
+            warn!("GPU validation [{} frame {}]: {}: {}",
+                  self.content, self.frame, what, details);
+        }
+
+        if self.frame_violations == MAX_VIOLATIONS_PER_FRAME {
+            warn!("GPU validation [{} frame {}]: too many violations, \
+                   silencing the rest of this frame",
+                  self.content, self.frame);
+        }
+    }
+
+    fn check_vertices(&mut self,
+                      primitive: &str,
+                      attributes: &PrimitiveAttributes,
+                      vertices: &[Vertex]) {
+        let (off_x, off_y) = self.draw_offset;
+
+        for v in vertices {
+            let x = v.position[0] as i32 + off_x as i32;
+            let y = v.position[1] as i32 + off_y as i32;
+
+            if x < VERTEX_MIN || x > VERTEX_MAX ||
+                y < VERTEX_MIN || y > VERTEX_MAX {
+                self.violation(primitive,
+                               format_args!("vertex ({}, {}) out of range",
+                                            x, y));
+            }
+        }
+
+        let min_x = vertices.iter().map(|v| v.position[0]).min().unwrap_or(0);
+        let max_x = vertices.iter().map(|v| v.position[0]).max().unwrap_or(0);
+        let min_y = vertices.iter().map(|v| v.position[1]).min().unwrap_or(0);
+        let max_y = vertices.iter().map(|v| v.position[1]).max().unwrap_or(0);
+
+        let width = max_x as i32 - min_x as i32;
+        let height = max_y as i32 - min_y as i32;
+
+        // The real GPU silently drops primitives that are too large
+        if width > MAX_PRIMITIVE_WIDTH || height > MAX_PRIMITIVE_HEIGHT {
+            self.violation(primitive,
+                           format_args!("primitive too large ({}x{}), \
+                                         the hardware would drop it",
+                                        width, height));
+        }
+
+        if attributes.blend_mode != BlendMode::None {
+            self.check_texture(primitive, attributes);
+        }
+    }
+
+    fn check_texture(&mut self,
+                     primitive: &str,
+                     attributes: &PrimitiveAttributes) {
+        let page_x = attributes.texture_page[0];
+        let page_y = attributes.texture_page[1];
+
+        if page_x >= VRAM_WIDTH_PIXELS || page_y >= VRAM_HEIGHT {
+            self.violation(primitive,
+                           format_args!("texture page ({}, {}) out of VRAM",
+                                        page_x, page_y));
+        }
+
+        let clut_entries =
+            match attributes.texture_depth {
+                TextureDepth::T4Bpp => 16,
+                TextureDepth::T8Bpp => 256,
+                // No CLUT for truecolor textures
+                TextureDepth::T16Bpp => return,
+            };
+
+        let clut_x = attributes.clut[0];
+        let clut_y = attributes.clut[1];
+
+        if clut_x as u32 + clut_entries > VRAM_WIDTH_PIXELS as u32 ||
+            clut_y >= VRAM_HEIGHT {
+            self.violation(primitive,
+                           format_args!("CLUT ({}, {}) with {} entries \
+                                         out of VRAM",
+                                        clut_x, clut_y, clut_entries));
+        }
+    }
+
+    fn check_vram_rect(&mut self,
+                       what: &str,
+                       top_left: (u16, u16),
+                       dimensions: (u16, u16)) {
+        let x_end = top_left.0 as u32 + dimensions.0 as u32;
+        let y_end = top_left.1 as u32 + dimensions.1 as u32;
+
+        if x_end > VRAM_WIDTH_PIXELS as u32 || y_end > VRAM_HEIGHT as u32 {
+            self.violation(what,
+                           format_args!("rectangle {:?} {:?} exceeds VRAM",
+                                        top_left, dimensions));
+        }
+    }
+
+    fn check_display_mode(&mut self,
+                          top_left: (u16, u16),
+                          resolution: (u16, u16),
+                          depth_24bpp: bool) {
+        let (w, h) = resolution;
+
+        if !DISPLAY_WIDTHS.contains(&w) {
+            self.violation("display mode",
+                           format_args!("unusual horizontal resolution {}",
+                                        w));
+        }
+
+        if h == 0 || h > 576 {
+            self.violation("display mode",
+                           format_args!("unusual vertical resolution {}", h));
+        }
+
+        // In 24bpp mode each pixel takes 1.5 VRAM pixels
+        let vram_width =
+            if depth_24bpp {
+                (w as u32 * 3) / 2
+            } else {
+                w as u32
+            };
+
+        self.check_vram_rect("display mode",
+                             top_left,
+                             (vram_width as u16, h));
+    }
+}
+
+impl Drop for Validator {
+    fn drop(&mut self) {
+        if self.total_violations > 0 {
+            info!("GPU validation [{}]: {} violations over {} frames",
+                  self.content, self.total_violations, self.frame);
+        }
+    }
+}
+
+/// Renderer wrapper validating every command before passing it on to
+/// the real renderer
+pub struct ValidatingRenderer<'a> {
+    validator: &'a mut Validator,
+    renderer: &'a mut Renderer,
+}
+
+impl<'a> Renderer for ValidatingRenderer<'a> {
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.validator.draw_offset = (x, y);
+
+        self.renderer.set_draw_offset(x, y)
+    }
+
+    fn set_draw_area(&mut self, top_left: (u16, u16), dimensions: (u16, u16)) {
+        self.validator.check_vram_rect("draw area", top_left, dimensions);
+
+        self.renderer.set_draw_area(top_left, dimensions)
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.validator.check_display_mode(top_left, resolution, depth_24bpp);
+
+        self.renderer.set_display_mode(top_left, resolution, depth_24bpp)
+    }
+
+    fn push_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 2]) {
+        self.validator.check_vertices("line", attributes, vertices);
+
+        self.renderer.push_line(attributes, vertices)
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: &[Vertex; 3]) {
+        self.validator.check_vertices("triangle", attributes, vertices);
+
+        self.renderer.push_triangle(attributes, vertices)
+    }
+
+    fn push_quad(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 4]) {
+        self.validator.check_vertices("quad", attributes, vertices);
+
+        self.renderer.push_quad(attributes, vertices)
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.validator.check_vram_rect("fill rect", top_left, dimensions);
+
+        self.renderer.fill_rect(color, top_left, dimensions)
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  resolution: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        self.validator.check_vram_rect("load image", top_left, resolution);
+
+        let expected = resolution.0 as usize * resolution.1 as usize;
+
+        if pixel_buffer.len() != expected {
+            self.validator.violation(
+                "load image",
+                format_args!("buffer holds {} pixels, expected {}",
+                             pixel_buffer.len(), expected));
+        }
+
+        self.renderer.load_image(top_left, resolution, pixel_buffer)
+    }
+}
+
+/// Maximum number of violations logged in a single frame, in order
+/// not to flood the logs when a game goes haywire
+const MAX_VIOLATIONS_PER_FRAME: u32 = 16;
+
+/// Vertex coordinates are signed 11bit values on the real hardware
+const VERTEX_MIN: i32 = -1024;
+const VERTEX_MAX: i32 = 1023;
+
+/// Primitives larger than this are not drawn by the GPU
+const MAX_PRIMITIVE_WIDTH: i32 = 1023;
+const MAX_PRIMITIVE_HEIGHT: i32 = 511;
+
+/// Horizontal resolutions supported by the PlayStation video output
+const DISPLAY_WIDTHS: [u16; 5] = [256, 320, 368, 512, 640];
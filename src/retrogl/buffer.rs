@@ -144,6 +144,27 @@ impl<T: Vertex> DrawBuffer<T> {
         get_error()
     }
 
+    /// Make all the attributes advance once per instance instead of
+    /// once per vertex, the buffer must then be drawn with
+    /// `draw_instanced`
+    pub fn set_per_instance(&self) -> Result<(), Error> {
+        self.vao.bind();
+
+        for attr in T::attributes() {
+            let index =
+                match self.program.find_attribute(attr.name) {
+                    Ok(i) => i,
+                    // Unused attribute, see `bind_attributes`
+                    Err(Error::InvalidValue) => continue,
+                    Err(e) => return Err(e),
+                };
+
+            unsafe { gl::VertexAttribDivisor(index, 1) };
+        }
+
+        get_error()
+    }
+
     pub fn empty(&self) -> bool {
         self.len == 0
     }
@@ -237,6 +258,34 @@ impl<T> DrawBuffer<T> {
         get_error()
     }
 
+    /// Draw `vertices` vertices for each element in the buffer. The
+    /// vertex shader is expected to generate the geometry from
+    /// `gl_VertexID`.
+    pub fn draw_instanced(&mut self,
+                          mode: GLenum,
+                          vertices: GLsizei) -> Result<(), Error> {
+        // Without glDrawArraysInstancedBaseInstance (GL 4.2) we can't
+        // start at an offset in the instance attributes
+        assert!(!self.lifo, "Can't draw instances from a LIFO buffer");
+
+        self.vao.bind();
+        self.program.bind();
+
+        unsafe {
+            gl::DrawArraysInstanced(mode,
+                                    0,
+                                    vertices,
+                                    self.len as GLsizei);
+        }
+
+        get_error()
+    }
+
+    /// Number of elements waiting to be drawn
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
     pub fn remaining_capacity(&self) -> usize {
         self.capacity - self.len
     }
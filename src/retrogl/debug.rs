@@ -0,0 +1,103 @@
+//! Forward the OpenGL driver's debug messages to the libretro log.
+//! Only useful with a debug context, most drivers are silent
+//! otherwise.
+
+use std::ffi::CStr;
+use std::slice;
+
+use gl;
+use gl::types::{GLenum, GLuint, GLint, GLsizei, GLchar};
+use libc::c_void;
+
+/// Register our debug message callback. Returns false if the context
+/// doesn't support debug output.
+pub fn init() -> bool {
+    if !gl::DebugMessageCallback::is_loaded() {
+        warn!("OpenGL debug output isn't supported by the driver");
+        return false;
+    }
+
+    let mut flags = 0;
+
+    unsafe {
+        gl::GetIntegerv(gl::CONTEXT_FLAGS, &mut flags);
+    }
+
+    if flags & (gl::CONTEXT_FLAG_DEBUG_BIT as GLint) == 0 {
+        warn!("The frontend didn't give us a debug OpenGL context, \
+               driver messages might be missing");
+    }
+
+    unsafe {
+        gl::Enable(gl::DEBUG_OUTPUT);
+        // Report the messages from within the offending call, that
+        // way the log ordering makes sense
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(callback, ::std::ptr::null());
+    }
+
+    info!("OpenGL debug output enabled");
+
+    true
+}
+
+extern "system" fn callback(source: GLenum,
+                            gltype: GLenum,
+                            id: GLuint,
+                            severity: GLenum,
+                            length: GLsizei,
+                            message: *const GLchar,
+                            _: *mut c_void) {
+    let message =
+        if message.is_null() {
+            String::new()
+        } else if length < 0 {
+            // Null-terminated
+            let m = unsafe { CStr::from_ptr(message) };
+
+            m.to_string_lossy().into_owned()
+        } else {
+            let m = unsafe {
+                slice::from_raw_parts(message as *const u8, length as usize)
+            };
+
+            String::from_utf8_lossy(m).into_owned()
+        };
+
+    let source = source_name(source);
+    let gltype = type_name(gltype);
+
+    match severity {
+        gl::DEBUG_SEVERITY_HIGH =>
+            error!("GL {} {} [{}]: {}", source, gltype, id, message),
+        gl::DEBUG_SEVERITY_MEDIUM =>
+            warn!("GL {} {} [{}]: {}", source, gltype, id, message),
+        gl::DEBUG_SEVERITY_LOW =>
+            info!("GL {} {} [{}]: {}", source, gltype, id, message),
+        _ =>
+            debug!("GL {} {} [{}]: {}", source, gltype, id, message),
+    }
+}
+
+fn source_name(source: GLenum) -> &'static str {
+    match source {
+        gl::DEBUG_SOURCE_API => "API",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    }
+}
+
+fn type_name(gltype: GLenum) -> &'static str {
+    match gltype {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        gl::DEBUG_TYPE_MARKER => "marker",
+        _ => "other",
+    }
+}
@@ -1,7 +1,10 @@
+use std::fmt;
+
 use gl;
 use gl::types::GLenum;
 
 use retrogl::shader::ShaderType;
+use libretro::hw_context;
 
 /// OpenGL errors
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -20,6 +23,31 @@ pub enum Error {
     BadProgram,
     /// When using a bad/unknown uniform
     BadUniform,
+    /// When the internal color depth option has an unsupported value
+    UnsupportedColorDepth(u8),
+    /// When the frontend gave us a context older than the one we
+    /// requested: (major, minor)
+    UnsupportedVersion(u32, u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnsupportedColorDepth(d) =>
+                write!(f, "unsupported internal color depth ({}bpp)", d),
+            Error::UnsupportedVersion(major, minor) => {
+                let (req_major, req_minor) = hw_context::VERSION;
+
+                write!(f,
+                       "the driver provides OpenGL {}.{}, we need {}.{}. \
+                        Update your graphics drivers and make sure the \
+                        frontend uses a core profile context (the \
+                        \"glcore\" video driver in RetroArch)",
+                       major, minor, req_major, req_minor)
+            }
+            e => write!(f, "OpenGL error {:?}", e),
+        }
+    }
 }
 
 pub fn get_error() -> Result<(), Error> {
@@ -1,12 +1,12 @@
 use gl;
-use gl::types::{GLuint, GLsizei};
+use gl::types::{GLuint, GLint, GLsizei};
 
 use retrogl::error::{Error, error_or};
 use retrogl::texture::Texture;
 
 pub struct Framebuffer<'a> {
     id: GLuint,
-    _color_texture: &'a Texture,
+    color_texture: &'a Texture,
 }
 
 impl<'a> Framebuffer<'a> {
@@ -21,7 +21,7 @@ impl<'a> Framebuffer<'a> {
 
         let fb = Framebuffer {
             id: id,
-            _color_texture: color_texture,
+            color_texture: color_texture,
         };
 
         fb.bind();
@@ -62,6 +62,58 @@ impl<'a> Framebuffer<'a> {
             gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.id);
         }
     }
+
+    /// Copy the whole color texture to `target`'s, scaling it with
+    /// nearest neighbour filtering if the dimensions don't match
+    pub fn blit_to(&self, target: &Framebuffer) -> Result<(), Error> {
+        let src = &self.color_texture;
+        let dst = &target.color_texture;
+
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            target.bind();
+            gl::BlitFramebuffer(0,
+                                0,
+                                src.width() as GLint,
+                                src.height() as GLint,
+                                0,
+                                0,
+                                dst.width() as GLint,
+                                dst.height() as GLint,
+                                gl::COLOR_BUFFER_BIT,
+                                gl::NEAREST);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+
+        error_or(())
+    }
+
+    /// Read back a rectangle of the color texture as 8bit RGBA
+    /// pixels, starting with the row at `top_left.1`
+    pub fn read_pixels(&self,
+                       top_left: (u32, u32),
+                       resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
+        let (w, h) = resolution;
+
+        let mut pixels = vec![0u8; (w * h * 4) as usize];
+
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(top_left.0 as GLint,
+                           top_left.1 as GLint,
+                           w as GLsizei,
+                           h as GLsizei,
+                           gl::RGBA,
+                           gl::UNSIGNED_BYTE,
+                           pixels.as_mut_ptr() as *mut _);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+
+        error_or(pixels)
+    }
 }
 
 impl<'a> Drop for Framebuffer<'a> {
@@ -1,17 +1,20 @@
 //! PlayStation OpenGL 3.3 renderer playing nice with libretro
 
-use rustc_serialize::{Encodable, Encoder, Decodable, Decoder};
-
 use gl;
 
 use rustation::gpu::VideoClock;
 use rustation::gpu::renderer::Renderer;
 use rustation::gpu::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
-use CoreVariables;
+use {CoreVariables, Settings, RefreshRate};
 
 use libretro;
+use error::Error;
+use savestate::{self, Serialize, Deserialize, Encoder, Decoder};
 
-use renderer::GlRenderer;
+use renderer::{GlRenderer, DrawStats, SavedVram};
+use renderer::overlay::{PressedButtons, Marker};
+use renderer::capture::{self, FrameCapture};
+use renderer::benchmark;
 
 #[macro_use]
 pub mod vertex;
@@ -20,43 +23,70 @@ pub mod types;
 pub mod buffer;
 pub mod texture;
 pub mod framebuffer;
+pub mod pass;
 pub mod shader;
 pub mod program;
+pub mod program_cache;
+pub mod debug;
+pub mod text;
+pub mod version;
 
 pub struct RetroGl {
     state: GlState,
     video_clock: VideoClock,
+    /// Buttons displayed by the input echo overlay, if enabled
+    input_echo: Option<PressedButtons>,
+    /// True if the next frame must flash for the input latency test
+    flash: bool,
+    /// Configuration loaded while the OpenGL context wasn't current
+    /// and whether the VRAM must be uploaded, applied before the
+    /// next frame. See `load_config`.
+    pending_config: Option<(DrawConfig, bool)>,
+    /// Set when a savestate has been loaded: it might have been
+    /// created with different video options or for a different video
+    /// standard, so the renderer must be reconciled with the current
+    /// configuration before the next frame
+    pending_refresh: bool,
+    /// Video clock the frontend's timings were last configured for
+    frontend_clock: VideoClock,
+    /// Refresh rate option the frontend's timings were last
+    /// configured for
+    frontend_refresh_rate: RefreshRate,
+    /// True if the renderer benchmark must be run once the OpenGL
+    /// context is ready
+    run_benchmark: bool,
+    /// Core options last given to `refresh_variables`
+    settings: Settings,
+    /// VRAM contents saved while a test pattern is displayed
+    saved_vram: Option<SavedVram>,
 }
 
 impl RetroGl {
-    pub fn new(video_clock: VideoClock) -> Result<RetroGl, ()> {
+    pub fn new(video_clock: VideoClock,
+               settings: &Settings) -> Result<RetroGl, Error> {
         if !libretro::set_pixel_format(libretro::PixelFormat::Xrgb8888) {
-            error!("Can't set pixel format");
-            return Err(());
+            return Err(Error::GlInit("the frontend doesn't support the \
+                                      XRGB8888 pixel format"));
         }
 
-        if !libretro::hw_context::init() {
-            error!("Failed to init hardware context");
-            return Err(());
+        if !libretro::hw_context::init(settings.gl_debug_context) {
+            return Err(Error::GlInit("the frontend couldn't provide an \
+                                      OpenGL 3.3 core context"));
         }
 
-        // The VRAM's bootup contents are undefined
-        let vram = vec![0xdead; VRAM_PIXELS];
-
-        let config = DrawConfig {
-            display_top_left: (0, 0),
-            display_resolution: (1024, 512),
-            display_24bpp: false,
-            draw_area_top_left: (0, 0),
-            draw_area_dimensions: (0, 0),
-            draw_offset: (0, 0),
-            vram: vram,
-        };
-
         Ok(RetroGl {
             // No context until `context_reset` is called
-            state: GlState::Invalid(config),
+            state: GlState::Invalid(DrawConfig::initial(settings)),
             video_clock: video_clock,
+            input_echo: None,
+            flash: false,
+            pending_config: None,
+            pending_refresh: false,
+            frontend_clock: video_clock,
+            frontend_refresh_rate: settings.refresh_rate,
+            run_benchmark: settings.startup_benchmark,
+            settings: settings.clone(),
+            saved_vram: None,
         })
     }
 
@@ -68,49 +98,438 @@ impl RetroGl {
             libretro::hw_context::get_proc_address(s) as *const _
         });
 
+        if self.settings.gl_debug_context {
+            debug::init();
+        }
+
         let config =
-            match self.state {
-                GlState::Valid(ref r) => r.draw_config().clone(),
-                GlState::Invalid(ref c) => c.clone(),
+            match (self.pending_config.take(), &self.state) {
+                (Some((c, _)), _) => c,
+                (None, &GlState::Valid(ref r)) => r.draw_config().clone(),
+                (None, &GlState::Invalid(ref c)) => c.clone(),
             };
 
-        match GlRenderer::from_config(config) {
-            Ok(r) => self.state = GlState::Valid(r),
-            Err(e) => panic!("Couldn't create RetroGL state: {:?}", e),
+        // Check the version before building anything, otherwise we'd
+        // fail with a shader compilation error
+        if let Err(e) = version::check() {
+            libretro::shutdown_with_error(
+                &format!("Unsupported OpenGL context: {}", e));
+
+            self.state = GlState::Invalid(config);
+            return;
+        }
+
+        match GlRenderer::from_config(config.clone(),
+                                      self.video_clock,
+                                      &self.settings) {
+            Ok(mut r) => {
+                if self.run_benchmark {
+                    // Only once per game, not at every context reset
+                    self.run_benchmark = false;
+                    run_benchmark(&mut r);
+                }
+
+                self.state = GlState::Valid(r);
+            }
+            Err(e) => {
+                libretro::shutdown_with_error(
+                    &format!("Couldn't create the OpenGL renderer: {}", e));
+
+                self.state = GlState::Invalid(config);
+            }
         }
     }
 
     pub fn context_destroy(&mut self) {
         info!("OpenGL context destroy");
 
+        // We can't keep GL objects past this point, only the CPU-side
+        // copy survives
+        if let Some(ref mut saved) = self.saved_vram {
+            saved.release_texture();
+        }
+
         let config =
-        match self.state {
-            GlState::Valid(ref r) => r.draw_config().clone(),
+        match (self.pending_config.take(), &self.state) {
+            (Some((c, _)), &GlState::Valid(_)) => c,
+            // The context is still current, keep whatever has been
+            // rendered on the GPU so that the renderer we rebuild on
+            // reset (or a savestate made in between) doesn't revert
+            // to the images loaded by the CPU
+            (None, &GlState::Valid(ref r)) =>
+                match rendered_config(r) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        warn!("Couldn't read back the VRAM: {:?}", e);
+                        r.draw_config().clone()
+                    }
+                },
             // Looks like we didn't have an OpenGL context anyway...
-            GlState::Invalid(_) => return,
+            (_, &GlState::Invalid(_)) => return,
         };
 
         self.state = GlState::Invalid(config);
     }
 
     pub fn render_frame<F>(&mut self, emulate: F)
+        where F: FnOnce(&mut Renderer) -> Vec<Marker> {
+
+        self.apply_pending_config();
+
+        let renderer =
+            match self.state {
+                GlState::Valid(ref mut r) => r,
+                GlState::Invalid(_) => {
+                    libretro::shutdown_with_error(
+                        "Attempted to render a frame without GL context");
+                    return;
+                }
+            };
+
+        renderer.prepare_render();
+
+        // The debugger markers are drawn on this frame, otherwise
+        // they'd lag one frame behind what they're pointing at
+        let markers = emulate(renderer);
+
+        renderer.set_markers(markers);
+        renderer.set_input_echo(self.input_echo.take());
+        renderer.set_flash(::std::mem::replace(&mut self.flash, false));
+
+        renderer.finalize_frame();
+
+        let fps = ::video_output_framerate(self.video_clock);
+        let frame_period = (1_000_000_000. / fps) as u64;
+
+        if let Err(e) = renderer.update_dynamic_upscaling(frame_period) {
+            libretro::shutdown_with_error(
+                &format!("Couldn't change the internal resolution: {}", e));
+        }
+    }
+
+    /// Display `pressed` in the input echo overlay for the next
+    /// frame. None disables the overlay.
+    pub fn set_input_echo(&mut self, pressed: Option<PressedButtons>) {
+        self.input_echo = pressed;
+    }
+
+    /// Cover the next frame with a white flash, drawn after the
+    /// emulated frame so that it's displayed right away
+    pub fn flash(&mut self) {
+        self.flash = true;
+    }
+
+    /// Display `text` in the debug text overlay of the next frame,
+    /// starting at column `x`, line `y`
+    pub fn print(&mut self, x: u16, y: u16, text: &str) {
+        if let GlState::Valid(ref mut r) = self.state {
+            r.text_overlay().print(x, y, text);
+        }
+    }
+
+    /// Return the current and requested upscaling factors, None if
+    /// we don't have a GL context
+    pub fn upscaling(&self) -> Option<(u32, u32)> {
+        match self.state {
+            GlState::Valid(ref r) => Some(r.upscaling()),
+            GlState::Invalid(_) => None,
+        }
+    }
+
+    /// Return the draw statistics of the last frame, None if we don't
+    /// have a GL context
+    pub fn draw_stats(&self) -> Option<DrawStats> {
+        match self.state {
+            GlState::Valid(ref r) => Some(r.draw_stats()),
+            GlState::Invalid(_) => None,
+        }
+    }
+
+    /// Dump the next `count` frames to disk along with the GPU state
+    pub fn start_capture(&mut self, count: u32) {
+        let renderer =
+            match self.state {
+                GlState::Valid(ref mut r) => r,
+                GlState::Invalid(_) => {
+                    warn!("Can't capture frames without GL context");
+                    return;
+                }
+            };
+
+        if let Ok(capture) = FrameCapture::new(count) {
+            renderer.start_capture(capture);
+        }
+    }
+
+    /// Return the display area of the last rendered frame as RGBA
+    /// pixels along with its dimensions, see
+    /// `GlRenderer::display_pixels`. None if we don't have a GL
+    /// context.
+    pub fn display_pixels(&self,
+                          native: bool) -> Option<(Vec<u8>, (u32, u32))> {
+        match self.state {
+            GlState::Valid(ref r) =>
+                match r.display_pixels(native) {
+                    Ok(p) => Some(p),
+                    Err(e) => {
+                        warn!("Couldn't read back the frame: {:?}", e);
+                        None
+                    }
+                },
+            GlState::Invalid(_) => None,
+        }
+    }
+
+    /// Return the hash of the display area of the last rendered
+    /// frame, None if we don't have a GL context
+    pub fn display_hash(&self) -> Option<u64> {
+        match self.state {
+            GlState::Valid(ref r) =>
+                match r.display_hash() {
+                    Ok(h) => Some(h),
+                    Err(e) => {
+                        warn!("Couldn't hash the frame: {:?}", e);
+                        None
+                    }
+                },
+            GlState::Invalid(_) => None,
+        }
+    }
+
+    /// Dump the VRAM to PNG files. The rendered contents are only
+    /// included if we have a GL context we can read them from.
+    pub fn dump_vram(&self) {
+        let res =
+            match self.state {
+                GlState::Valid(ref r) if libretro::hw_context::is_current() =>
+                    r.dump_vram().map_err(|e| {
+                        warn!("Couldn't read back the VRAM: {:?}", e);
+                    }),
+                GlState::Valid(ref r) =>
+                    capture::dump_vram(&r.draw_config().vram, None),
+                GlState::Invalid(ref c) =>
+                    capture::dump_vram(&c.vram, None),
+            };
+
+        if res.is_err() {
+            libretro_message!(200, "Couldn't dump the VRAM");
+        }
+    }
+
+    /// Save a screenshot of the display area at native resolution.
+    /// Without a GL context we can only use the CPU-side copy of the
+    /// VRAM, which lacks anything drawn by the GPU.
+    pub fn native_screenshot(&self) {
+        let config_screenshot = |c: &DrawConfig| {
+            capture::native_screenshot(&c.vram,
+                                       c.display_top_left,
+                                       c.display_resolution,
+                                       c.display_24bpp)
+        };
+
+        let res =
+            match self.state {
+                GlState::Valid(ref r) if libretro::hw_context::is_current() =>
+                    r.native_screenshot().map_err(|e| {
+                        warn!("Couldn't read back the VRAM: {:?}", e);
+                    }),
+                GlState::Valid(ref r) => config_screenshot(r.draw_config()),
+                GlState::Invalid(ref c) => config_screenshot(c),
+            };
+
+        if res.is_err() {
+            libretro_message!(200, "Couldn't take the screenshot");
+        }
+    }
+
+    /// Render a frame without running the emulator (test patterns
+    /// etc...). The drawing state is restored afterwards so that it
+    /// remains consistent with the emulated GPU, VRAM contents
+    /// however are not.
+    pub fn render_detached_frame<F>(&mut self, draw: F)
         where F: FnOnce(&mut Renderer) {
 
+        self.apply_pending_config();
+
         let renderer =
             match self.state {
                 GlState::Valid(ref mut r) => r,
-                GlState::Invalid(_) =>
-                    panic!("Attempted to render a frame without GL context"),
+                GlState::Invalid(_) => {
+                    libretro::shutdown_with_error(
+                        "Attempted to render a frame without GL context");
+                    return;
+                }
             };
 
+        let saved = renderer.draw_config().state();
+
         renderer.prepare_render();
 
-        emulate(renderer);
+        draw(renderer);
+
+        renderer.set_markers(Vec::new());
+        renderer.set_input_echo(self.input_echo.take());
+        renderer.set_flash(::std::mem::replace(&mut self.flash, false));
 
         renderer.finalize_frame();
+
+        renderer.restore_draw_state(&saved);
+    }
+
+    /// Replace our state with `loaded`, deserialized from a
+    /// savestate. If we have a valid GL context we take the fast path
+    /// and reuse the current renderer, only reloading the VRAM.
+    /// Otherwise the renderer will be rebuilt from scratch when the
+    /// frontend calls `context_reset`.
+    pub fn load_savestate(&mut self, loaded: RetroGl) {
+        self.video_clock = loaded.video_clock;
+
+        let config =
+            match loaded.state {
+                GlState::Valid(r) => r.draw_config().clone(),
+                GlState::Invalid(c) => c,
+            };
+
+        // When runahead rewinds this very instance the GPU-side VRAM
+        // is more accurate than the savestate's (which only contains
+        // the CPU uploads, see `encode`) and the frames about to be
+        // re-emulated will redraw it anyway, so we don't re-upload
+        // it.
+        let upload_vram =
+            libretro::get_savestate_context() !=
+            libretro::SavestateContext::RunaheadSameInstance;
+
+        self.load_config(config, upload_vram);
+
+        // Runahead states come from this very session, no need to
+        // rebuild anything (that would also reset the dynamic
+        // upscaling controller at every frame)
+        self.pending_refresh = upload_vram;
+    }
+
+    /// Put the display and VRAM back in their power-on state, used
+    /// when the console is reset
+    pub fn reset(&mut self) {
+        let config = DrawConfig::initial(&self.settings);
+
+        self.load_config(config, true);
     }
 
-    pub fn refresh_variables(&mut self) {
+    /// Replace the current configuration and VRAM contents, reusing
+    /// the renderer if we have a valid GL context
+    fn load_config(&mut self, config: DrawConfig, upload_vram: bool) {
+        if self.saved_vram.is_some() {
+            // A test pattern is displayed, the new VRAM is the one we
+            // must go back to afterwards
+            self.saved_vram = Some(SavedVram::from_vram(config.vram.clone()));
+        }
+
+        if let GlState::Valid(ref mut r) = self.state {
+            if !libretro::hw_context::is_current() {
+                // We're called from outside `retro_run` (savestate
+                // loaded or console reset from the frontend's main
+                // thread with threaded video...), we can't touch the
+                // GL objects until we get the context back
+                self.pending_config = Some((config, upload_vram));
+                return;
+            }
+
+            self.pending_config = None;
+
+            if let Err(e) = r.load_config(config, upload_vram) {
+                libretro::shutdown_with_error(
+                    &format!("Couldn't load VRAM: {}", e));
+            }
+
+            return;
+        }
+
+        self.pending_config = None;
+        self.state = GlState::Invalid(config);
+    }
+
+    /// Apply the configuration loaded while the GL context wasn't
+    /// current, if any, then reconcile the renderer with the current
+    /// options if a savestate has been loaded
+    fn apply_pending_config(&mut self) {
+        if let Some((config, upload_vram)) = self.pending_config.take() {
+            self.load_config(config, upload_vram);
+        }
+
+        if self.pending_refresh && self.is_valid() {
+            self.pending_refresh = false;
+            self.apply_settings();
+        }
+    }
+
+    /// Save the VRAM contents before they're overwritten by a test
+    /// pattern, see `restore_vram`
+    pub fn save_vram(&mut self) {
+        self.apply_pending_config();
+
+        let is_current = libretro::hw_context::is_current();
+
+        let saved =
+            match self.state {
+                // Without the GL context we can only save what the
+                // CPU loaded
+                GlState::Valid(ref mut r) if is_current =>
+                    match r.save_vram() {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("Couldn't save the VRAM: {:?}", e);
+                            SavedVram::from_vram(r.draw_config().vram.clone())
+                        }
+                    },
+                GlState::Valid(ref r) =>
+                    SavedVram::from_vram(r.draw_config().vram.clone()),
+                GlState::Invalid(ref c) =>
+                    SavedVram::from_vram(c.vram.clone()),
+            };
+
+        self.saved_vram = Some(saved);
+    }
+
+    /// Put back the VRAM contents saved by `save_vram` once the test
+    /// pattern is turned off
+    pub fn restore_vram(&mut self) {
+        let saved =
+            match self.saved_vram.take() {
+                Some(s) => s,
+                None => return,
+            };
+
+        if let GlState::Valid(ref mut r) = self.state {
+            if libretro::hw_context::is_current() {
+                if let Err(e) = r.restore_vram(saved) {
+                    libretro::shutdown_with_error(
+                        &format!("Couldn't restore VRAM: {}", e));
+                }
+
+                return;
+            }
+        }
+
+        let mut config =
+            match (self.pending_config.take(), &self.state) {
+                (Some((c, _)), _) => c,
+                (None, &GlState::Valid(ref r)) => r.draw_config().clone(),
+                (None, &GlState::Invalid(ref c)) => c.clone(),
+            };
+
+        config.vram = saved.vram().to_vec();
+
+        self.load_config(config, true);
+    }
+
+    /// Apply new core options
+    pub fn refresh_variables(&mut self, settings: &Settings) {
+        self.settings = settings.clone();
+
+        self.apply_settings();
+    }
+
+    fn apply_settings(&mut self) {
         let renderer =
             match self.state {
                 GlState::Valid(ref mut r) => r,
@@ -118,16 +537,31 @@ impl RetroGl {
                 GlState::Invalid(_) => return,
             };
 
-        let reconfigure_frontend = renderer.refresh_variables();
+        renderer.set_video_clock(self.video_clock);
+
+        let reconfigure_frontend =
+            match renderer.refresh_variables(&self.settings) {
+                Ok(r) => r,
+                Err(e) => {
+                    libretro::shutdown_with_error(
+                        &format!("Couldn't apply the video options: {}", e));
+                    return;
+                }
+            };
+
+        // A savestate for the other video standard has been loaded
+        // or the refresh rate option has changed, the frontend must
+        // switch to the new framerate
+        let clock_changed = self.video_clock != self.frontend_clock;
 
-        if reconfigure_frontend {
-            // The resolution has changed, we must tell the frontend
-            // to change its format
+        let refresh_rate = self.settings.refresh_rate;
+        let rate_changed = refresh_rate != self.frontend_refresh_rate;
 
-            let upscaling = CoreVariables::internal_upscale_factor();
+        if reconfigure_frontend || clock_changed || rate_changed {
+            // The resolution or framerate has changed, we must tell
+            // the frontend to change its format
 
-            let av_info = ::get_av_info(self.video_clock,
-                                        upscaling);
+            let av_info = ::get_av_info(self.video_clock, &self.settings);
 
             // This call can potentially (but not necessarily) call
             // `context_destroy` and `context_reset` to reinitialize
@@ -136,7 +570,10 @@ impl RetroGl {
                 libretro::set_system_av_info(&av_info)
             };
 
-            if !ok {
+            if ok {
+                self.frontend_clock = self.video_clock;
+                self.frontend_refresh_rate = refresh_rate;
+            } else {
                 // Some frontends might not support changing the video
                 // settings at runtime, if that's the case we continue
                 // with the old settings. The new config will be
@@ -156,43 +593,120 @@ impl RetroGl {
     }
 }
 
-impl Encodable for RetroGl {
-    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-        s.emit_struct("RetroGl", 2, |s| {
-            let draw_config =
-                match self.state {
-                    GlState::Valid(ref r) => r.draw_config(),
-                    GlState::Invalid(ref d) => d,
-                };
+impl Serialize for RetroGl {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), savestate::Error> {
+        // For regular savestates we take the time to read back the
+        // VRAM from the GPU, that way we also save whatever has been
+        // rendered. Fast states (runahead, netplay) only store our
+        // CPU-side copy.
+        // We can only read back the VRAM if the context is current,
+        // with threaded video we might be called from an other
+        // thread.
+        let full =
+            !libretro::get_savestate_context().is_fast() &&
+            libretro::hw_context::is_current();
+
+        let rendered =
+            match (&self.pending_config, &self.state) {
+                // A state loaded from outside `retro_run` hasn't been
+                // applied yet, it's more recent than whatever is in
+                // the GPU
+                (&Some(_), _) => None,
+                (&None, &GlState::Valid(ref r)) if full => {
+                    match rendered_config(r) {
+                        Ok(config) => Some(config),
+                        Err(e) => {
+                            warn!("Couldn't read back the VRAM: {:?}", e);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+
+        let draw_config =
+            match (&rendered, &self.pending_config, &self.state) {
+                (&Some(ref c), _, _) => c,
+                (_, &Some((ref c, _)), _) => c,
+                (_, _, &GlState::Valid(ref r)) => r.draw_config(),
+                (_, _, &GlState::Invalid(ref d)) => d,
+            };
 
-            try!(s.emit_struct_field("draw_config", 0,
-                                     |s| draw_config.encode(s)));
-            try!(s.emit_struct_field("video_clock", 1,
-                                     |s| self.video_clock.encode(s)));
+        try!(e.begin_struct("RetroGl", 1));
+        try!(e.field("draw_config", draw_config));
 
-            Ok(())
-        })
+        e.legacy_field("video_clock", &self.video_clock)
     }
 }
 
-impl Decodable for RetroGl {
-    fn decode<D: Decoder>(d: &mut D) -> Result<RetroGl, D::Error> {
-        d.read_struct("RetroGl", 2, |d| {
-            let draw_config = try!(d.read_struct_field("draw_config", 0,
-                                                       Decodable::decode));
-            let video_clock = try!(d.read_struct_field("video_clock", 1,
-                                                       Decodable::decode));
+impl Deserialize for RetroGl {
+    fn deserialize(d: &mut Decoder) -> Result<RetroGl, savestate::Error> {
+        try!(d.begin_struct("RetroGl", 1));
+
+        let draw_config = try!(d.field("draw_config"));
+        let video_clock = try!(d.legacy_field("video_clock"));
 
-            Ok(RetroGl{
-                state: GlState::Invalid(draw_config),
-                video_clock: video_clock,
-            })
+        // Only the draw config and video clock are used by
+        // `load_savestate`, no need to query the options here
+        Ok(RetroGl{
+            state: GlState::Invalid(draw_config),
+            video_clock: video_clock,
+            input_echo: None,
+            flash: false,
+            pending_config: None,
+            pending_refresh: false,
+            frontend_clock: video_clock,
+            frontend_refresh_rate: RefreshRate::Exact,
+            run_benchmark: false,
+            settings: CoreVariables::defaults(),
         })
     }
 }
 
+/// Return `renderer`'s configuration with the VRAM read back from the
+/// GPU, including the results of the draw commands
+fn rendered_config(renderer: &GlRenderer)
+                   -> Result<DrawConfig, error::Error> {
+    let vram = try!(renderer.rendered_vram());
+
+    let mut config = renderer.draw_config().clone();
+    config.vram = vram;
+
+    Ok(config)
+}
+
+/// Run the renderer benchmark and report the results
+fn run_benchmark(renderer: &mut GlRenderer) {
+    info!("Running renderer benchmark");
+
+    match benchmark::run(renderer) {
+        Ok(r) => {
+            let (upscaling, _) = renderer.upscaling();
+
+            info!("Renderer benchmark at {}x: {:.0} triangles/s, \
+                   {:.0}MB/s VRAM uploads",
+                  upscaling, r.triangles, r.upload);
+            libretro_message!(300, "{}x: {:.1}M triangles/s, \
+                                    {:.0}MB/s uploads",
+                              upscaling, r.triangles / 1_000_000.,
+                              r.upload);
+        }
+        Err(e) => warn!("Renderer benchmark failed: {}", e),
+    }
+}
+
 /// State machine dealing with OpenGL context
 /// destruction/reconstruction
+///
+/// XXX Switching to an other backend at runtime would go through the
+/// same path as a context loss: read the VRAM back with
+/// `rendered_config`, drop the renderer to get `Invalid(config)` and
+/// build the new backend from that config. It's not worth adding the
+/// option and the backend abstraction until there's a second backend
+/// to switch to, we only have the OpenGL renderer for now (and the
+/// frontend's hardware context is requested once, when the content
+/// is loaded, so a software backend would also need
+/// `retro_video_refresh` with a CPU-side framebuffer).
 enum GlState {
     /// OpenGL context is ready
     Valid(GlRenderer),
@@ -200,7 +714,7 @@ enum GlState {
     Invalid(DrawConfig),
 }
 
-#[derive(RustcEncodable, RustcDecodable, Clone)]
+#[derive(Clone)]
 pub struct DrawConfig {
     pub display_top_left: (u16, u16),
     pub display_resolution: (u16, u16),
@@ -215,4 +729,136 @@ pub struct DrawConfig {
     pub vram: Vec<u16>,
 }
 
+impl Serialize for DrawConfig {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), savestate::Error> {
+        try!(e.begin_struct("DrawConfig", 1));
+        try!(e.field("display_top_left", &self.display_top_left));
+        try!(e.field("display_resolution", &self.display_resolution));
+        try!(e.field("display_24bpp", &self.display_24bpp));
+        try!(e.field("draw_offset", &self.draw_offset));
+        try!(e.field("draw_area_top_left", &self.draw_area_top_left));
+        try!(e.field("draw_area_dimensions", &self.draw_area_dimensions));
+
+        e.field_u16_array("vram", &self.vram)
+    }
+}
+
+impl Deserialize for DrawConfig {
+    fn deserialize(d: &mut Decoder)
+                   -> Result<DrawConfig, savestate::Error> {
+        try!(d.begin_struct("DrawConfig", 1));
+
+        Ok(DrawConfig {
+            display_top_left: try!(d.field("display_top_left")),
+            display_resolution: try!(d.field("display_resolution")),
+            display_24bpp: try!(d.field("display_24bpp")),
+            draw_offset: try!(d.field("draw_offset")),
+            draw_area_top_left: try!(d.field("draw_area_top_left")),
+            draw_area_dimensions: try!(d.field("draw_area_dimensions")),
+            vram: try!(d.field_u16_array("vram")),
+        })
+    }
+}
+
+impl DrawConfig {
+    /// Configuration at power-on
+    fn initial(settings: &Settings) -> DrawConfig {
+        DrawConfig {
+            display_top_left: (0, 0),
+            display_resolution: (1024, 512),
+            display_24bpp: false,
+            draw_area_top_left: (0, 0),
+            draw_area_dimensions: (0, 0),
+            draw_offset: (0, 0),
+            // The VRAM's bootup contents are undefined
+            vram: initial_vram(settings.vram_init_pattern,
+                               settings.vram_random_seed),
+        }
+    }
+
+    /// Return a copy of the display and drawing state, without the
+    /// VRAM contents
+    pub fn state(&self) -> DrawState {
+        DrawState {
+            display_top_left: self.display_top_left,
+            display_resolution: self.display_resolution,
+            display_24bpp: self.display_24bpp,
+            draw_offset: self.draw_offset,
+            draw_area_top_left: self.draw_area_top_left,
+            draw_area_dimensions: self.draw_area_dimensions,
+        }
+    }
+}
+
+/// The part of `DrawConfig` describing the display and drawing
+/// configuration
+#[derive(Clone, Copy)]
+pub struct DrawState {
+    pub display_top_left: (u16, u16),
+    pub display_resolution: (u16, u16),
+    pub display_24bpp: bool,
+    pub draw_offset: (i16, i16),
+    pub draw_area_top_left: (u16, u16),
+    pub draw_area_dimensions: (u16, u16),
+}
+
+/// Pattern used to fill the VRAM at startup
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VramInit {
+    Zeros,
+    Dead,
+    PseudoRandom,
+}
+
+impl VramInit {
+    pub fn parse(opt: &str) -> Result<VramInit, ()> {
+        match opt {
+            "0xdead" => Ok(VramInit::Dead),
+            "zeros" => Ok(VramInit::Zeros),
+            "pseudo-random" => Ok(VramInit::PseudoRandom),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parse the seed of the pseudo-random VRAM pattern
+pub fn parse_random_seed(opt: &str) -> Result<u32, ()> {
+    match opt {
+        "default" => Ok(VRAM_RANDOM_SEED),
+        // Xorshift gets stuck on 0
+        n => match n.parse() {
+            Ok(0) | Err(_) => Err(()),
+            Ok(seed) => Ok(seed),
+        },
+    }
+}
+
+/// Build the initial VRAM contents for `pattern`. The pseudo-random
+/// pattern is fully determined by `seed` so that two instances
+/// starting the same content with the same options end up with the
+/// same VRAM (netplay, TAS...).
+fn initial_vram(pattern: VramInit, seed: u32) -> Vec<u16> {
+    match pattern {
+        VramInit::Zeros => vec![0; VRAM_PIXELS],
+        VramInit::Dead => vec![0xdead; VRAM_PIXELS],
+        VramInit::PseudoRandom => {
+            info!("VRAM pseudo-random seed: 0x{:08x}", seed);
+
+            // Xorshift32, good enough for garbage
+            let mut state = seed;
+
+            (0..VRAM_PIXELS).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+
+                state as u16
+            }).collect()
+        }
+    }
+}
+
+/// Default seed for the pseudo-random VRAM init pattern
+const VRAM_RANDOM_SEED: u32 = 0x5053_5821;
+
 const VRAM_PIXELS: usize = VRAM_WIDTH_PIXELS as usize * VRAM_HEIGHT as usize;
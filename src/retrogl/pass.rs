@@ -0,0 +1,195 @@
+//! Render passes. Each pass declares the framebuffer it renders to,
+//! the textures it samples and the fixed function state it needs
+//! (scissor box, depth test, blending...). `begin` sets up all of it
+//! from scratch so a pass never depends on whatever state the
+//! previous one (or the frontend) left behind.
+
+use gl;
+use gl::types::{GLenum, GLuint, GLint, GLsizei, GLfloat};
+
+use retrogl::error::{Error, error_or};
+use retrogl::texture::Texture;
+use retrogl::framebuffer::Framebuffer;
+
+/// Where a pass renders
+#[derive(Clone, Copy)]
+pub enum Target<'a> {
+    /// One of our textures, with an optional depth buffer
+    Texture(&'a Texture, Option<&'a Texture>),
+    /// The frontend's framebuffer object and its dimensions
+    Frontend(GLuint, (u32, u32)),
+}
+
+/// Blending configuration. The alpha channel (which holds the mask
+/// bit) is never blended.
+#[derive(Clone, Copy)]
+pub struct Blend {
+    pub equation: GLenum,
+    pub src: GLenum,
+    pub dst: GLenum,
+    /// Constant color used by the `CONSTANT_*` factors
+    pub color: [GLfloat; 4],
+}
+
+impl Blend {
+    fn apply(blend: Option<Blend>) {
+        unsafe {
+            match blend {
+                Some(b) => {
+                    gl::BlendColor(b.color[0], b.color[1],
+                                   b.color[2], b.color[3]);
+                    gl::BlendEquationSeparate(b.equation, gl::FUNC_ADD);
+                    gl::BlendFuncSeparate(b.src, b.dst, gl::ONE, gl::ZERO);
+                    gl::Enable(gl::BLEND);
+                }
+                None => gl::Disable(gl::BLEND),
+            }
+        }
+    }
+}
+
+pub struct RenderPass<'a> {
+    target: Target<'a>,
+    /// Textures sampled by the pass and the texture unit they're
+    /// bound to
+    inputs: Vec<(GLenum, &'a Texture)>,
+    /// Scissor box in target pixels: (x, y, width, height)
+    scissor: Option<(u32, u32, u32, u32)>,
+    depth_test: bool,
+    blend: Option<Blend>,
+    polygon_mode: GLenum,
+    line_width: GLfloat,
+}
+
+impl<'a> RenderPass<'a> {
+    /// Pass rendering to `target` with everything disabled
+    pub fn new(target: Target<'a>) -> RenderPass<'a> {
+        RenderPass {
+            target: target,
+            inputs: Vec::new(),
+            scissor: None,
+            depth_test: false,
+            blend: None,
+            polygon_mode: gl::FILL,
+            line_width: 1.,
+        }
+    }
+
+    pub fn input(mut self,
+                 texture_unit: GLenum,
+                 texture: &'a Texture) -> RenderPass<'a> {
+        self.inputs.push((texture_unit, texture));
+        self
+    }
+
+    pub fn scissor(mut self,
+                   top_left: (u32, u32),
+                   dimensions: (u32, u32)) -> RenderPass<'a> {
+        self.scissor =
+            Some((top_left.0, top_left.1, dimensions.0, dimensions.1));
+        self
+    }
+
+    /// Enable the depth test, passing if the fragment is closer or
+    /// at the same depth
+    pub fn depth_test(mut self) -> RenderPass<'a> {
+        self.depth_test = true;
+        self
+    }
+
+    pub fn polygon_mode(mut self, mode: GLenum) -> RenderPass<'a> {
+        self.polygon_mode = mode;
+        self
+    }
+
+    pub fn line_width(mut self, width: GLfloat) -> RenderPass<'a> {
+        self.line_width = width;
+        self
+    }
+
+    /// Bind the target and inputs and configure the OpenGL state for
+    /// the pass. The target remains bound as long as the returned
+    /// value is alive.
+    pub fn begin(&self) -> Result<ActivePass<'a>, Error> {
+        let framebuffer =
+            match self.target {
+                Target::Texture(color, Some(depth)) =>
+                    Some(try!(Framebuffer::new_with_depth(color, depth))),
+                Target::Texture(color, None) =>
+                    Some(try!(Framebuffer::new(color))),
+                Target::Frontend(fbo, (w, h)) => {
+                    unsafe {
+                        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo);
+                        gl::Viewport(0, 0, w as GLsizei, h as GLsizei);
+                    }
+                    None
+                }
+            };
+
+        for &(unit, texture) in &self.inputs {
+            texture.bind(unit);
+        }
+
+        unsafe {
+            match self.scissor {
+                Some((x, y, w, h)) => {
+                    gl::Scissor(x as GLint,
+                                y as GLint,
+                                w as GLsizei,
+                                h as GLsizei);
+                    gl::Enable(gl::SCISSOR_TEST);
+                }
+                None => gl::Disable(gl::SCISSOR_TEST),
+            }
+
+            if self.depth_test {
+                gl::DepthFunc(gl::LEQUAL);
+                gl::Enable(gl::DEPTH_TEST);
+            } else {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+
+            gl::PolygonMode(gl::FRONT_AND_BACK, self.polygon_mode);
+            gl::LineWidth(self.line_width);
+        }
+
+        Blend::apply(self.blend);
+
+        error_or(ActivePass {
+            _framebuffer: framebuffer,
+        })
+    }
+}
+
+/// Pass in progress
+pub struct ActivePass<'a> {
+    /// Our framebuffer object if we're rendering to one of our
+    /// textures
+    _framebuffer: Option<Framebuffer<'a>>,
+}
+
+impl<'a> ActivePass<'a> {
+    /// Change the blending configuration within the pass
+    pub fn set_blend(&self, blend: Option<Blend>) {
+        Blend::apply(blend);
+    }
+}
+
+/// Put the OpenGL state back to the defaults before returning control
+/// to the frontend
+pub fn reset_state() {
+    unsafe {
+        gl::Disable(gl::SCISSOR_TEST);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::BLEND);
+        gl::BlendColor(0., 0., 0., 0.);
+        gl::BlendEquationSeparate(gl::FUNC_ADD, gl::FUNC_ADD);
+        gl::BlendFuncSeparate(gl::ONE, gl::ZERO, gl::ONE, gl::ZERO);
+        gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+        gl::LineWidth(1.);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::BindVertexArray(0);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+    }
+}
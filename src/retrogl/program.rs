@@ -1,11 +1,22 @@
 use std::ffi::CString;
 
 use gl;
-use gl::types::{GLint, GLuint, GLsizei};
+use gl::types::{GLint, GLuint, GLsizei, GLfloat};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
-use retrogl::shader::Shader;
+use retrogl::shader::{Shader, ShaderType};
 use retrogl::error::{Error, error_or, get_error};
+use retrogl::program_cache::{self, ProgramBinary};
+
+/// Program currently bound with glUseProgram, 0 if unknown. Used to
+/// skip redundant binds.
+static BOUND_PROGRAM: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Location of a uniform in a `Program`, resolved once after linking
+/// to avoid looking it up by name every time it's set
+#[derive(Clone, Copy, Debug)]
+pub struct Uniform(GLint);
 
 pub struct Program {
     id: GLuint,
@@ -16,8 +27,58 @@ pub struct Program {
 impl Program {
     pub fn new(vertex_shader: Shader,
                fragment_shader: Shader) -> Result<Program, Error> {
+        Program::link(vertex_shader, fragment_shader, false)
+    }
+
+    /// Build a program from the shader sources. If the driver
+    /// supports it the linked program is cached on disk and reloaded
+    /// from there the next time instead of being recompiled.
+    pub fn from_sources(vertex_shader: &str,
+                        fragment_shader: &str) -> Result<Program, Error> {
+        if !program_cache::is_supported() {
+            let vs = try!(Shader::new(vertex_shader, ShaderType::Vertex));
+            let fs = try!(Shader::new(fragment_shader, ShaderType::Fragment));
+
+            return Program::new(vs, fs);
+        }
+
+        let key = program_cache::key(vertex_shader, fragment_shader);
+
+        if let Some(binary) = program_cache::load(key) {
+            match Program::from_binary(&binary) {
+                Some(p) => return Ok(p),
+                None => info!("Cached program binary rejected, recompiling"),
+            }
+        }
+
+        let vs = try!(Shader::new(vertex_shader, ShaderType::Vertex));
+        let fs = try!(Shader::new(fragment_shader, ShaderType::Fragment));
+
+        let program = try!(Program::link(vs, fs, true));
+
+        if let Some(binary) = program.binary() {
+            program_cache::store(key, &binary);
+        }
+
+        Ok(program)
+    }
+
+    /// Link `vertex_shader` and `fragment_shader` into a new
+    /// program. If `retrievable` is true we tell the driver that
+    /// we're going to retrieve the program binary.
+    fn link(vertex_shader: Shader,
+            fragment_shader: Shader,
+            retrievable: bool) -> Result<Program, Error> {
         let id = unsafe { gl::CreateProgram() };
 
+        if retrievable {
+            unsafe {
+                gl::ProgramParameteri(id,
+                                      gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+                                      gl::TRUE as GLint);
+            }
+        }
+
         vertex_shader.attach_to(id);
         fragment_shader.attach_to(id);
 
@@ -27,18 +88,8 @@ impl Program {
         fragment_shader.detach_from(id);
 
         // Check if the program linking was successful
-        let mut status = gl::FALSE as GLint;
-        unsafe { gl::GetProgramiv(id, gl::LINK_STATUS, &mut status) };
-
-        if status == gl::TRUE as GLint {
-            let uniforms = try!(load_program_uniforms(id));
-
-            // There shouldn't be anything in glGetError but let's
-            // check to make sure.
-            error_or(Program {
-                id: id,
-                uniforms: uniforms
-            })
+        if is_linked(id) {
+            Program::from_linked(id)
         } else {
             error!("OpenGL program linking failed");
 
@@ -51,6 +102,83 @@ impl Program {
         }
     }
 
+    /// Load a program from a binary previously retrieved with
+    /// `binary`. Returns None if the driver rejects it, which is
+    /// allowed to happen at any time (after a driver update for
+    /// instance).
+    fn from_binary(binary: &ProgramBinary) -> Option<Program> {
+        let id = unsafe { gl::CreateProgram() };
+
+        unsafe {
+            gl::ProgramBinary(id,
+                              binary.format,
+                              binary.data.as_ptr() as *const _,
+                              binary.data.len() as GLsizei);
+        }
+
+        if is_linked(id) {
+            if let Ok(p) = Program::from_linked(id) {
+                return Some(p);
+            }
+        }
+
+        unsafe { gl::DeleteProgram(id) };
+
+        // glProgramBinary raises GL_INVALID_ENUM if the format is
+        // unknown, don't let it be picked up by the next error check
+        let _ = get_error();
+
+        None
+    }
+
+    fn from_linked(id: GLuint) -> Result<Program, Error> {
+        let uniforms = try!(load_program_uniforms(id));
+
+        // There shouldn't be anything in glGetError but let's
+        // check to make sure.
+        error_or(Program {
+            id: id,
+            uniforms: uniforms
+        })
+    }
+
+    /// Retrieve the binary of this program, None if the driver
+    /// doesn't give us one
+    fn binary(&self) -> Option<ProgramBinary> {
+        let mut len = 0;
+
+        unsafe {
+            gl::GetProgramiv(self.id, gl::PROGRAM_BINARY_LENGTH, &mut len);
+        }
+
+        if len <= 0 {
+            return None;
+        }
+
+        let mut data = vec![0u8; len as usize];
+        let mut written = 0;
+        let mut format = 0;
+
+        unsafe {
+            gl::GetProgramBinary(self.id,
+                                 len,
+                                 &mut written,
+                                 &mut format,
+                                 data.as_mut_ptr() as *mut _);
+        }
+
+        if get_error().is_err() || written <= 0 {
+            return None;
+        }
+
+        data.truncate(written as usize);
+
+        Some(ProgramBinary {
+            format: format,
+            data: data,
+        })
+    }
+
     pub fn find_attribute(&self, attr: &str) -> Result<GLuint, Error> {
         let cstr = CString::new(attr).unwrap();
 
@@ -67,12 +195,31 @@ impl Program {
     }
 
     pub fn bind(&self) {
-        unsafe { gl::UseProgram(self.id) };
+        let id = self.id as usize;
+
+        if BOUND_PROGRAM.swap(id, Ordering::Relaxed) != id {
+            unsafe { gl::UseProgram(self.id) };
+        }
+    }
+
+    /// Unbind any program
+    pub fn unbind() {
+        unsafe { gl::UseProgram(0) };
+
+        BOUND_PROGRAM.store(0, Ordering::Relaxed);
     }
 
-    fn uniform(&self, name: &str) -> Result<GLint, Error> {
+    /// Forget which program is bound. Must be called when we get
+    /// control back from the frontend since it might have changed
+    /// the binding behind our back.
+    pub fn invalidate_binding() {
+        BOUND_PROGRAM.store(0, Ordering::Relaxed);
+    }
+
+    /// Look up the location of uniform `name`
+    pub fn find_uniform(&self, name: &str) -> Result<Uniform, Error> {
         let e = self.uniforms.get(name)
-            .map(|&u| u)
+            .map(|&u| Uniform(u))
             .ok_or(Error::BadUniform);
 
         if e.is_err() {
@@ -83,36 +230,68 @@ impl Program {
     }
 
     pub fn uniform1i(&self, name: &str, i: GLint) -> Result<(), Error> {
-        self.bind();
-
-        self.uniform(name)
-            .map(|u| unsafe { gl::Uniform1i(u, i) })
+        self.find_uniform(name)
+            .map(|u| self.set1i(u, i))
     }
 
     pub fn uniform1ui(&self, name: &str, i: GLuint) -> Result<(), Error> {
-        self.bind();
-
-        self.uniform(name)
-            .map(|u| unsafe { gl::Uniform1ui(u, i) })
+        self.find_uniform(name)
+            .map(|u| self.set1ui(u, i))
     }
 
     pub fn uniform2i(&self,
                      name: &str,
                      a: GLint,
                      b: GLint) -> Result<(), Error> {
+        self.find_uniform(name)
+            .map(|u| self.set2i(u, a, b))
+    }
+
+    /// Set a uniform whose location has been resolved with
+    /// `find_uniform` on this program
+    pub fn set1i(&self, u: Uniform, i: GLint) {
+        self.bind();
+
+        unsafe { gl::Uniform1i(u.0, i) }
+    }
+
+    pub fn set1ui(&self, u: Uniform, i: GLuint) {
+        self.bind();
+
+        unsafe { gl::Uniform1ui(u.0, i) }
+    }
+
+    pub fn set1f(&self, u: Uniform, f: GLfloat) {
+        self.bind();
+
+        unsafe { gl::Uniform1f(u.0, f) }
+    }
+
+    pub fn set2i(&self, u: Uniform, a: GLint, b: GLint) {
         self.bind();
 
-        self.uniform(name)
-            .map(|u| unsafe { gl::Uniform2i(u, a, b) })
+        unsafe { gl::Uniform2i(u.0, a, b) }
     }
 }
 
 impl Drop for Program {
     fn drop(&mut self) {
+        // The ID might be reused by a new program
+        let id = self.id as usize;
+        BOUND_PROGRAM.compare_and_swap(id, 0, Ordering::Relaxed);
+
         unsafe { gl::DeleteProgram(self.id) };
     }
 }
 
+fn is_linked(id: GLuint) -> bool {
+    let mut status = gl::FALSE as GLint;
+
+    unsafe { gl::GetProgramiv(id, gl::LINK_STATUS, &mut status) };
+
+    status == gl::TRUE as GLint
+}
+
 fn get_program_info_log(id: GLuint) -> Option<String> {
     let mut log_len = 0 as GLint;
 
@@ -0,0 +1,156 @@
+//! On-disk cache of linked program binaries (GL_ARB_get_program_binary)
+//! in order not to recompile all our shaders every time the OpenGL
+//! context is (re)created. Binaries are only valid for the driver
+//! that produced them so the driver strings are part of the cache
+//! key, along with the shader sources.
+
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use gl;
+use gl::types::{GLenum, GLint};
+
+use libretro;
+
+/// Binary program as returned by glGetProgramBinary
+pub struct ProgramBinary {
+    pub format: GLenum,
+    pub data: Vec<u8>,
+}
+
+/// Return true if the driver lets us retrieve and load program
+/// binaries
+pub fn is_supported() -> bool {
+    if !gl::ProgramBinary::is_loaded() ||
+        !gl::GetProgramBinary::is_loaded() ||
+        !gl::ProgramParameteri::is_loaded() {
+        return false;
+    }
+
+    let mut formats: GLint = 0;
+
+    unsafe {
+        gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+    }
+
+    formats > 0
+}
+
+/// Compute the cache key of the program built from `vertex_shader`
+/// and `fragment_shader` with the current driver
+pub fn key(vertex_shader: &str, fragment_shader: &str) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+
+    for &s in &[gl::VENDOR, gl::RENDERER, gl::VERSION] {
+        hash = fnv1a(hash, &gl_string(s));
+        // Separator so that moving bytes from one string to the next
+        // changes the key
+        hash = fnv1a(hash, &[0]);
+    }
+
+    hash = fnv1a(hash, vertex_shader.as_bytes());
+    hash = fnv1a(hash, &[0]);
+    fnv1a(hash, fragment_shader.as_bytes())
+}
+
+/// Look for the binary for `key` in the cache
+pub fn load(key: u64) -> Option<ProgramBinary> {
+    let path =
+        match cache_path(key) {
+            Some(p) => p,
+            None => return None,
+        };
+
+    let mut contents = Vec::new();
+
+    match File::open(&path).and_then(|mut f| f.read_to_end(&mut contents)) {
+        Ok(_) => (),
+        // Not cached yet
+        Err(_) => return None,
+    }
+
+    if contents.len() < 4 {
+        warn!("Ignoring truncated program binary {:?}", path);
+        return None;
+    }
+
+    let format =
+        contents[0] as GLenum |
+        (contents[1] as GLenum) << 8 |
+        (contents[2] as GLenum) << 16 |
+        (contents[3] as GLenum) << 24;
+
+    Some(ProgramBinary {
+        format: format,
+        data: contents[4..].to_vec(),
+    })
+}
+
+/// Store `binary` in the cache. Failures are logged and otherwise
+/// ignored, we'll just have to recompile the program next time.
+pub fn store(key: u64, binary: &ProgramBinary) {
+    let path =
+        match cache_path(key) {
+            Some(p) => p,
+            None => return,
+        };
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            warn!("Couldn't create {:?}: {}", dir, e);
+            return;
+        }
+    }
+
+    let f = binary.format;
+    let header = [f as u8, (f >> 8) as u8, (f >> 16) as u8, (f >> 24) as u8];
+
+    let res =
+        File::create(&path).and_then(|mut file| {
+            try!(file.write_all(&header));
+            file.write_all(&binary.data)
+        });
+
+    match res {
+        Ok(_) => debug!("Cached program binary in {:?}", path),
+        Err(e) => {
+            warn!("Couldn't write {:?}: {}", path, e);
+            // Don't leave a truncated binary behind
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+fn cache_path(key: u64) -> Option<PathBuf> {
+    libretro::get_save_directory().map(|d| {
+        d.join("rustation")
+            .join("shader-cache")
+            .join(format!("{:016x}.bin", key))
+    })
+}
+
+/// Return the value of the OpenGL string `name`, empty if the driver
+/// returns NULL
+pub fn gl_string(name: GLenum) -> Vec<u8> {
+    let s = unsafe { gl::GetString(name) };
+
+    if s.is_null() {
+        return Vec::new();
+    }
+
+    unsafe { CStr::from_ptr(s as *const _).to_bytes().to_vec() }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+/// Update a 64bit FNV-1a hash with `data`
+fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for &b in data {
+        hash = (hash ^ b as u64).wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
@@ -0,0 +1,40 @@
+#version 330 core
+
+uniform sampler2D font;
+
+in vec2 frag_cell_coord;
+flat in uint frag_glyph;
+flat in vec3 frag_text_color;
+
+out vec4 frag_color;
+
+// Size of a character cell in font pixels
+const ivec2 cell_size = ivec2(6, 8);
+// Number of glyphs per row in the font texture
+const uint font_columns = 16u;
+
+// Return true if the font pixel at `pos` in the current glyph's cell
+// is set
+bool font_pixel(ivec2 pos) {
+  if (pos.x < 0 || pos.y < 0) {
+    return false;
+  }
+
+  ivec2 origin = ivec2(int(frag_glyph % font_columns),
+                       int(frag_glyph / font_columns)) * cell_size;
+
+  return texelFetch(font, origin + pos, 0).r > 0.5;
+}
+
+void main() {
+  ivec2 pos = ivec2(frag_cell_coord);
+
+  if (font_pixel(pos)) {
+    frag_color = vec4(frag_text_color, 1.0);
+  } else if (font_pixel(pos - ivec2(1, 1))) {
+    // Drop shadow to keep the text readable on bright backgrounds
+    frag_color = vec4(0.0, 0.0, 0.0, 1.0);
+  } else {
+    discard;
+  }
+}
@@ -0,0 +1,49 @@
+#version 330 core
+
+// Vertex shader for the debug text overlay. Each instance is a
+// character cell, the corners of its quad are generated from the
+// vertex index.
+
+// Position of the cell, in characters from the top left corner
+in uvec2 position;
+// Index of the glyph in the font texture
+in uint glyph;
+in uvec3 color;
+
+out vec2 frag_cell_coord;
+flat out uint frag_glyph;
+flat out vec3 frag_text_color;
+
+// Resolution of the frontend's framebuffer
+uniform ivec2 resolution;
+// Size of a font pixel in framebuffer pixels
+uniform uint scale;
+// Set if the frontend's framebuffer has its origin in the top left
+// corner, in which case we have to flip the image vertically
+uniform bool flip_y;
+
+// Size of a character cell in font pixels
+const vec2 cell_size = vec2(6.0, 8.0);
+
+void main() {
+  // Triangle strip: (0, 0), (1, 0), (0, 1), (1, 1)
+  vec2 corner = vec2(gl_VertexID & 1, gl_VertexID >> 1);
+
+  vec2 cell_coord = corner * cell_size;
+
+  // Position in framebuffer pixels with y going down
+  vec2 pos = (vec2(position) * cell_size + cell_coord) * float(scale);
+
+  vec2 ndc = pos / vec2(resolution) * 2.0 - 1.0;
+
+  // y goes up in normalized device coordinates
+  gl_Position.xyzw = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+
+  if (flip_y) {
+    gl_Position.y = -gl_Position.y;
+  }
+
+  frag_cell_coord = cell_coord;
+  frag_glyph = glyph;
+  frag_text_color = vec3(color) / 255.;
+}
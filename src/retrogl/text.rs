@@ -0,0 +1,294 @@
+//! Text rendering for the debug overlays, using a built-in 5x7 bitmap
+//! font. Each character is an instance of a single quad so the whole
+//! overlay is drawn with one draw call.
+
+use gl;
+use gl::types::GLint;
+
+use retrogl::buffer::DrawBuffer;
+use retrogl::error::Error;
+use retrogl::program::Program;
+use retrogl::texture::Texture;
+use retrogl::types::GlType;
+
+/// Character cell in the overlay
+struct Glyph {
+    /// Position of the cell, in characters from the top left corner
+    position: [u16; 2],
+    /// Index of the glyph in `FONT`
+    glyph: u8,
+    /// RGB color, 8bits per component
+    color: [u8; 3],
+}
+
+implement_vertex!(Glyph,
+                  position, glyph, color);
+
+pub struct TextRenderer {
+    /// Buffer holding one instance per character
+    buffer: DrawBuffer<Glyph>,
+    /// Font texture, one byte per pixel
+    font: Texture,
+    /// Characters queued for the next draw
+    glyphs: Vec<Glyph>,
+}
+
+impl TextRenderer {
+    pub fn new() -> Result<TextRenderer, Error> {
+        let program =
+            try!(Program::from_sources(
+                include_str!("shaders/text_vertex.glsl"),
+                include_str!("shaders/text_fragment.glsl")));
+
+        let buffer = try!(DrawBuffer::new(MAX_GLYPHS, program, false));
+
+        try!(buffer.set_per_instance());
+        try!(buffer.program().uniform1i("font", 0));
+
+        let font = try!(Texture::new(FONT_TEXTURE_WIDTH as u32,
+                                     FONT_TEXTURE_HEIGHT as u32,
+                                     gl::R8));
+
+        try!(font.set_sub_image((0, 0),
+                                (FONT_TEXTURE_WIDTH, FONT_TEXTURE_HEIGHT),
+                                gl::RED,
+                                gl::UNSIGNED_BYTE,
+                                &font_texture()));
+
+        Ok(TextRenderer {
+            buffer: buffer,
+            font: font,
+            glyphs: Vec::new(),
+        })
+    }
+
+    /// Set if the frontend's framebuffer has its origin in the top
+    /// left corner
+    pub fn set_flip_y(&self, flip: bool) -> Result<(), Error> {
+        self.buffer.program().uniform1i("flip_y", flip as GLint)
+    }
+
+    /// Queue `text` to be drawn in white with its first character at
+    /// column `x`, line `y`
+    pub fn print(&mut self, x: u16, y: u16, text: &str) {
+        self.print_color(x, y, [0xff, 0xff, 0xff], text);
+    }
+
+    /// Queue `text` to be drawn in `color`. A newline moves to the
+    /// next line, starting back at column `x`.
+    pub fn print_color(&mut self,
+                       x: u16,
+                       y: u16,
+                       color: [u8; 3],
+                       text: &str) {
+        let mut pos = [x, y];
+
+        for c in text.chars() {
+            if c == '\n' {
+                pos = [x, pos[1] + 1];
+                continue;
+            }
+
+            if c != ' ' {
+                self.glyphs.push(Glyph {
+                    position: pos,
+                    glyph: glyph_index(c),
+                    color: color,
+                });
+            }
+
+            pos[0] += 1;
+        }
+    }
+
+    /// Return true if there's no text queued
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    /// Draw the queued text on top of the currently bound
+    /// framebuffer of size `resolution` and clear the queue. Binds
+    /// the font to texture unit 0.
+    pub fn draw(&mut self, resolution: (u32, u32)) -> Result<(), Error> {
+        if self.glyphs.is_empty() {
+            return Ok(());
+        }
+
+        if self.glyphs.len() > MAX_GLYPHS {
+            warn!("Too many characters in the text overlay ({}), \
+                   truncating", self.glyphs.len());
+            self.glyphs.truncate(MAX_GLYPHS);
+        }
+
+        let (width, height) = resolution;
+
+        // Scale the font with the output so that we get roughly 30
+        // lines of text
+        let scale = ::std::cmp::max(1, height / 240);
+
+        {
+            let program = self.buffer.program();
+
+            try!(program.uniform2i("resolution",
+                                   width as GLint,
+                                   height as GLint));
+            try!(program.uniform1ui("scale", scale));
+        }
+
+        self.font.bind(gl::TEXTURE0);
+
+        try!(self.buffer.clear());
+        try!(self.buffer.push_slice(&self.glyphs));
+
+        self.glyphs.clear();
+
+        self.buffer.draw_instanced(gl::TRIANGLE_STRIP, 4)
+    }
+}
+
+/// Return the index in `FONT` of the glyph for `c`. Lowercase letters
+/// are drawn in uppercase, characters not in the font are drawn as
+/// '?'.
+fn glyph_index(c: char) -> u8 {
+    let c =
+        match c {
+            'a'...'z' => (c as u8 - b'a' + b'A') as char,
+            ' '...'_' => c,
+            _ => '?',
+        };
+
+    (c as u32 - FONT_FIRST as u32) as u8
+}
+
+/// Expand `FONT` into a 16 glyphs wide texture, one byte per pixel
+fn font_texture() -> Vec<u8> {
+    let width = FONT_TEXTURE_WIDTH as usize;
+    let height = FONT_TEXTURE_HEIGHT as usize;
+
+    let mut pixels = vec![0; width * height];
+
+    for (i, glyph) in FONT.iter().enumerate() {
+        let x0 = (i % FONT_COLUMNS) * CELL_WIDTH;
+        let y0 = (i / FONT_COLUMNS) * CELL_HEIGHT;
+
+        for (y, &row) in glyph.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (0x10 >> x) != 0 {
+                    pixels[(y0 + y) * width + x0 + x] = 0xff;
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Maximum number of characters drawn at once
+const MAX_GLYPHS: usize = 4096;
+
+/// Width of a glyph in pixels
+const GLYPH_WIDTH: usize = 5;
+/// Size of a character cell, including the spacing. Must match the
+/// shaders.
+const CELL_WIDTH: usize = 6;
+const CELL_HEIGHT: usize = 8;
+/// Number of glyphs per row in the font texture. Must match the
+/// fragment shader.
+const FONT_COLUMNS: usize = 16;
+
+const FONT_TEXTURE_WIDTH: u16 = (FONT_COLUMNS * CELL_WIDTH) as u16;
+const FONT_TEXTURE_HEIGHT: u16 =
+    ((FONT_GLYPHS + FONT_COLUMNS - 1) / FONT_COLUMNS * CELL_HEIGHT) as u16;
+
+/// First character in `FONT`
+const FONT_FIRST: char = ' ';
+/// Number of glyphs in `FONT`
+const FONT_GLYPHS: usize = 64;
+
+/// 5x7 font covering ASCII 0x20 to 0x5f (space to underscore), which
+/// is enough for uppercase text, digits and the usual punctuation.
+/// One byte per row from the top, the MSB of the 5 bits is the
+/// leftmost pixel.
+const FONT: [[u8; 7]; FONT_GLYPHS] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // #
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // &
+    [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00], // quote
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // 0
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // 1
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // 2
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // 3
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // 4
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // 5
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // 6
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // 8
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // 9
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // :
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // @
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // A
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // B
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // C
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // D
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // E
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // F
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // G
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // H
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // L
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // O
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // P
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // Q
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // R
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // S
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // W
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // Y
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // Z
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // backslash
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ]
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // _
+];
+
+#[test]
+fn test_glyph_index() {
+    assert_eq!(glyph_index(' '), 0);
+    assert_eq!(glyph_index('A'), 33);
+    assert_eq!(glyph_index('a'), 33);
+    assert_eq!(glyph_index('_'), 63);
+    assert_eq!(glyph_index('~'), glyph_index('?'));
+    assert_eq!(glyph_index('\u{e9}'), glyph_index('?'));
+
+    let pixels = font_texture();
+
+    assert_eq!(pixels.len(), 96 * 32);
+    // Top of the '!' glyph
+    assert_eq!(pixels[6 + 2], 0xff);
+    assert_eq!(pixels[6 + 1], 0);
+}
@@ -50,7 +50,13 @@ impl Texture {
             panic!("Invalid texture sub_image size");
         }
 
+        // `data` is passed in the host's native byte order. That's
+        // what OpenGL expects for packed pixel types such as
+        // UNSIGNED_SHORT_1_5_5_5_REV so no byteswapping is needed on
+        // big endian hosts, as long as the frontend didn't enable
+        // UNPACK_SWAP_BYTES.
         unsafe {
+            gl::PixelStorei(gl::UNPACK_SWAP_BYTES, gl::FALSE as GLint);
             gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::BindTexture(gl::TEXTURE_2D, self.id);
             gl::TexSubImage2D(gl::TEXTURE_2D,
@@ -66,6 +66,16 @@ impl GlType for [u8; 3] {
     }
 }
 
+impl GlType for [u8; 4] {
+    fn attribute_type() -> GLenum {
+        gl::UNSIGNED_BYTE
+    }
+
+    fn components() -> GlComponents {
+        GlComponents::Quad
+    }
+}
+
 impl GlType for [i16; 2] {
     fn attribute_type() -> GLenum {
         gl::SHORT
@@ -0,0 +1,61 @@
+//! OpenGL version check. Frontends don't always give us the context
+//! version we asked for (macOS and older Mesa drivers in particular),
+//! without this check we'd fail much later with confusing shader
+//! compilation errors.
+
+use gl;
+
+use libretro::hw_context;
+use retrogl::error::Error;
+use retrogl::program_cache::gl_string;
+
+/// Make sure the current context is at least the version we requested
+pub fn check() -> Result<(), Error> {
+    let version = String::from_utf8_lossy(&gl_string(gl::VERSION))
+        .into_owned();
+
+    info!("OpenGL version: {}", version);
+
+    match parse(&version) {
+        Some(v) if v < hw_context::VERSION =>
+            Err(Error::UnsupportedVersion(v.0, v.1)),
+        Some(_) => Ok(()),
+        None => {
+            // Better try and see than refuse to run
+            warn!("Couldn't parse the OpenGL version string");
+            Ok(())
+        }
+    }
+}
+
+/// Parse a GL_VERSION string: "<major>.<minor>[.<release>] [vendor
+/// info]", OpenGL ES prefixes it with "OpenGL ES "
+fn parse(version: &str) -> Option<(u32, u32)> {
+    let number =
+        match version.split_whitespace()
+            .find(|w| w.starts_with(|c: char| c.is_digit(10))) {
+            Some(n) => n,
+            None => return None,
+        };
+
+    let mut parts = number.split('.');
+
+    let major = parts.next().and_then(|p| p.parse().ok());
+    let minor = parts.next().and_then(|p| p.parse().ok());
+
+    match (major, minor) {
+        (Some(major), Some(minor)) => Some((major, minor)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse_version() {
+    assert_eq!(parse("3.3.0 NVIDIA 390.77"), Some((3, 3)));
+    assert_eq!(parse("4.5 (Core Profile) Mesa 18.1.6"), Some((4, 5)));
+    assert_eq!(parse("3.0 Mesa 10.1.3"), Some((3, 0)));
+    assert_eq!(parse("2.1 INTEL-10.2.37"), Some((2, 1)));
+    assert_eq!(parse("OpenGL ES 3.2 V@269.0"), Some((3, 2)));
+    assert_eq!(parse(""), None);
+    assert_eq!(parse("garbage"), None);
+}
@@ -56,16 +56,25 @@ impl Attribute {
     }
 }
 
+/// Return a raw pointer to `$field` of the uninitialized `$st`
+/// instance `$instance` (a `MaybeUninit<$st>`) along with the field's
+/// offset in the struct. `addr_of!` doesn't create an intermediate
+/// reference so nothing reads or references uninitialized memory.
+macro_rules! field_pointer {
+    ($instance: expr, $st: ident, $field: ident) => ({
+        let base: *const $st = $instance.as_ptr();
+        let field = unsafe { ::std::ptr::addr_of!((*base).$field) };
+
+        (field, field as usize - base as usize)
+    })
+}
+
 /// Retrieve the offset of `$field` in struct `$st`
 macro_rules! offset_of {
     ($st: ident, $field: ident) => ({
-        let null_instance: &$st = unsafe { ::std::mem::transmute(0usize) };
-        let offset: usize =
-            unsafe {
-                ::std::mem::transmute(&null_instance.$field)
-            };
+        let instance = ::std::mem::MaybeUninit::<$st>::uninit();
 
-        offset
+        field_pointer!(instance, $st, $field).1
     })
 }
 
@@ -73,9 +82,9 @@ macro_rules! offset_of {
 macro_rules! build_attribute {
     ($st: ident, $field: ident) => ({
         /// Helper function used to build an Attribute from a struct
-        /// field. The first parameter is *not* a valid pointer, it's just
-        /// here in order to get the proper generic type T
-        fn build<T: GlType>(_invalid: *const T,
+        /// field. The first parameter points to uninitialized memory,
+        /// it's only here in order to get the proper generic type T
+        fn build<T: GlType>(_uninit: *const T,
                             name: &'static str,
                             offset: usize)
                             -> $crate::retrogl::vertex::Attribute {
@@ -88,8 +97,10 @@ macro_rules! build_attribute {
             }
         }
 
-        let null_instance: &$st = unsafe { ::std::mem::transmute(0usize) };
-        build(&null_instance.$field, stringify!($field), offset_of!($st, $field))
+        let instance = ::std::mem::MaybeUninit::<$st>::uninit();
+        let (field, offset) = field_pointer!(instance, $st, $field);
+
+        build(field, stringify!($field), offset)
     })
 }
 
@@ -104,3 +115,17 @@ macro_rules! implement_vertex {
         }
     )
 }
+
+#[test]
+fn test_offset_of() {
+    #[repr(C)]
+    struct TestVertex {
+        a: u8,
+        b: u32,
+        c: [u16; 3],
+    }
+
+    assert_eq!(offset_of!(TestVertex, a), 0);
+    assert_eq!(offset_of!(TestVertex, b), 4);
+    assert_eq!(offset_of!(TestVertex, c), 8);
+}
@@ -4,16 +4,282 @@ use log;
 use libretro;
 
 use std::io::{Write, stderr};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+/// Subsystems whose log level can be configured independently
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Subsystem {
+    Cpu = 0,
+    Gpu = 1,
+    Cdrom = 2,
+    Pad = 3,
+    Debugger = 4,
+}
+
+impl Subsystem {
+    /// Find the subsystem a log record belongs to based on its
+    /// target (the module path by default)
+    fn from_target(target: &str) -> Option<Subsystem> {
+        for &(prefix, subsystem) in SUBSYSTEM_TARGETS.iter() {
+            if target.starts_with(prefix) {
+                return Some(subsystem);
+            }
+        }
+
+        None
+    }
+}
+
+/// Mapping between module paths and subsystems
+const SUBSYSTEM_TARGETS: [(&'static str, Subsystem); 9] =
+    [("rustation::cpu", Subsystem::Cpu),
+     ("rustation::gpu", Subsystem::Gpu),
+     ("rustation_retro::renderer", Subsystem::Gpu),
+     ("rustation_retro::retrogl", Subsystem::Gpu),
+     ("rustation::cdrom", Subsystem::Cdrom),
+     ("cdimage", Subsystem::Cdrom),
+     ("rustation::padmemcard", Subsystem::Pad),
+     ("rustation::debugger", Subsystem::Debugger),
+     ("rustation_retro::debugger", Subsystem::Debugger)];
+
+/// Global log level, stored as a `LogLevelFilter` converted to usize
+static GLOBAL_LEVEL: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Per-subsystem log level overrides. 0 means "use the global level",
+/// otherwise the value is the `LogLevelFilter` plus one.
+static SUBSYSTEM_LEVELS: [AtomicUsize; 5] = [ATOMIC_USIZE_INIT,
+                                             ATOMIC_USIZE_INIT,
+                                             ATOMIC_USIZE_INIT,
+                                             ATOMIC_USIZE_INIT,
+                                             ATOMIC_USIZE_INIT];
+
+/// Log file level, stored as a `LogLevelFilter` converted to
+/// usize. `Off` (0) means that the log file is disabled.
+static FILE_LEVEL: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Log file shared by the loggers, allocated once in `init` and
+/// never freed
+static mut LOG_FILE: *const Mutex<Option<LogFile>> = ::std::ptr::null();
+
+/// The log file is rotated when it grows past this size
+const LOG_FILE_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Log file in the save directory. When it gets too big it's renamed
+/// with a `.1` suffix (replacing the previous one) and a new file is
+/// started, that way we keep at most twice `LOG_FILE_MAX_SIZE` of
+/// logs around.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    /// Number of bytes written to `file`
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> ::std::io::Result<LogFile> {
+        let file = try!(fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path));
+
+        let size = try!(file.metadata()).len();
+
+        Ok(LogFile {
+            path: path,
+            file: file,
+            size: size,
+        })
+    }
+
+    fn write(&mut self, record: &log::LogRecord) {
+        if self.size >= LOG_FILE_MAX_SIZE {
+            let old = self.path.with_extension("log.1");
+
+            // If any of this fails we'll just keep writing to the
+            // current file
+            let rotated =
+                fs::rename(&self.path, &old)
+                .and_then(|_| File::create(&self.path));
+
+            if let Ok(f) = rotated {
+                self.file = f;
+                self.size = 0;
+            }
+        }
+
+        let line = format!("{} {} - {}\n",
+                           record.level(),
+                           record.target(),
+                           record.args());
+
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+}
+
+fn log_file() -> Option<&'static Mutex<Option<LogFile>>> {
+    unsafe {
+        if LOG_FILE.is_null() {
+            None
+        } else {
+            Some(&*LOG_FILE)
+        }
+    }
+}
+
+/// Set the level of the messages written to `rustation/rustation.log`
+/// in the save directory, `Off` closes the file
+pub fn set_file_level(level: log::LogLevelFilter) {
+    let mutex =
+        match log_file() {
+            Some(m) => m,
+            None => return,
+        };
+
+    let enable = level != log::LogLevelFilter::Off;
+
+    let open = mutex.lock().unwrap().is_some();
+
+    if enable && !open {
+        let dir =
+            match libretro::get_save_directory() {
+                Some(d) => d.join("rustation"),
+                None => {
+                    error!("The frontend didn't give us a save directory, \
+                            can't write the log file");
+                    return;
+                }
+            };
+
+        let path = dir.join("rustation.log");
+
+        let res =
+            fs::create_dir_all(&dir)
+            .and_then(|_| LogFile::open(path.clone()));
+
+        match res {
+            Ok(f) => {
+                *mutex.lock().unwrap() = Some(f);
+                info!("Logging to {:?}", path);
+            }
+            Err(e) => {
+                error!("Couldn't open log file {:?}: {}", path, e);
+                return;
+            }
+        }
+    } else if !enable && open {
+        *mutex.lock().unwrap() = None;
+    }
+
+    FILE_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+fn file_enabled(metadata: &log::LogMetadata) -> bool {
+    metadata.level() <= level_from_usize(FILE_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Write `record` to the log file if it's enabled for this level
+fn log_to_file(record: &log::LogRecord) {
+    if !file_enabled(record.metadata()) {
+        return;
+    }
+
+    if let Some(mutex) = log_file() {
+        // Don't make things worse if a thread panicked while logging
+        if let Ok(mut file) = mutex.lock() {
+            if let Some(ref mut f) = *file {
+                f.write(record);
+            }
+        }
+    }
+}
+
+/// Set the log level used for all messages not covered by a
+/// subsystem override
+pub fn set_level(level: log::LogLevelFilter) {
+    GLOBAL_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Override the log level for `subsystem`. `None` means that the
+/// global level is used.
+pub fn set_subsystem_level(subsystem: Subsystem,
+                           level: Option<log::LogLevelFilter>) {
+    let v =
+        match level {
+            Some(l) => l as usize + 1,
+            None => 0,
+        };
+
+    SUBSYSTEM_LEVELS[subsystem as usize].store(v, Ordering::Relaxed);
+}
+
+/// Parse a log level core option value
+pub fn parse_level(opt: &str) -> Result<log::LogLevelFilter, ()> {
+    match opt {
+        "off" => Ok(log::LogLevelFilter::Off),
+        "error" => Ok(log::LogLevelFilter::Error),
+        "warn" => Ok(log::LogLevelFilter::Warn),
+        "info" => Ok(log::LogLevelFilter::Info),
+        "debug" => Ok(log::LogLevelFilter::Debug),
+        "trace" => Ok(log::LogLevelFilter::Trace),
+        _ => Err(()),
+    }
+}
+
+/// Parse a subsystem log level core option value, "default" means
+/// that we use the global level
+pub fn parse_subsystem_level(opt: &str)
+                             -> Result<Option<log::LogLevelFilter>, ()> {
+    match opt {
+        "default" => Ok(None),
+        _ => parse_level(opt).map(Some),
+    }
+}
+
+fn level_from_usize(v: usize) -> log::LogLevelFilter {
+    match v {
+        0 => log::LogLevelFilter::Off,
+        1 => log::LogLevelFilter::Error,
+        2 => log::LogLevelFilter::Warn,
+        3 => log::LogLevelFilter::Info,
+        4 => log::LogLevelFilter::Debug,
+        _ => log::LogLevelFilter::Trace,
+    }
+}
+
+/// Check whether a message should be logged given the current global
+/// and subsystem levels
+fn level_enabled(metadata: &log::LogMetadata) -> bool {
+    let subsystem_level =
+        Subsystem::from_target(metadata.target())
+        .map(|s| SUBSYSTEM_LEVELS[s as usize].load(Ordering::Relaxed))
+        .unwrap_or(0);
+
+    let filter =
+        if subsystem_level != 0 {
+            level_from_usize(subsystem_level - 1)
+        } else {
+            level_from_usize(GLOBAL_LEVEL.load(Ordering::Relaxed))
+        };
+
+    metadata.level() <= filter
+}
 
 struct RetroLogger;
 
 impl log::Log for RetroLogger {
-    fn enabled(&self, _: &log::LogMetadata) -> bool {
-        true
+    fn enabled(&self, metadata: &log::LogMetadata) -> bool {
+        level_enabled(metadata) || file_enabled(metadata)
     }
 
     fn log(&self, record: &log::LogRecord) {
-        if self.enabled(record.metadata()) {
+        log_to_file(record);
+
+        if level_enabled(record.metadata()) {
             let s = ::std::fmt::format(*record.args());
 
             let lvl =
@@ -34,12 +300,14 @@ impl log::Log for RetroLogger {
 struct StdErrLogger;
 
 impl log::Log for StdErrLogger {
-    fn enabled(&self, _: &log::LogMetadata) -> bool {
-        true
+    fn enabled(&self, metadata: &log::LogMetadata) -> bool {
+        level_enabled(metadata) || file_enabled(metadata)
     }
 
     fn log(&self, record: &log::LogRecord) {
-        if self.enabled(record.metadata()) {
+        log_to_file(record);
+
+        if level_enabled(record.metadata()) {
             let _ =
                 writeln!(&mut stderr(),
                          "{} - {}",
@@ -52,8 +320,16 @@ impl log::Log for StdErrLogger {
 pub fn init() {
     let retrolog_ok = libretro::log::init();
 
+    // Log everything until the core options are loaded
+    set_level(log::LogLevelFilter::max());
+
+    unsafe {
+        LOG_FILE = Box::into_raw(Box::new(Mutex::new(None)));
+    }
+
     log::set_logger(|max_log_level| {
-        // XXX Should we make this configurable?
+        // The actual filtering is done in `level_enabled` since the
+        // level can be changed at runtime
         max_log_level.set(log::LogLevelFilter::max());
 
         if retrolog_ok {
@@ -1,5 +1,23 @@
 use std::io;
 
+use rustc_serialize::{Encodable, Decodable};
+
+/// Types that can be stored in a savestate. The savestate format
+/// used to be driven by rustc_serialize's `Encodable`, these traits
+/// give us control over the layout: bulk arrays, versioned
+/// structs...
+pub trait Serialize {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error>;
+}
+
+/// Counterpart of `Serialize`
+pub trait Deserialize: Sized {
+    fn deserialize(d: &mut Decoder) -> Result<Self, Error>;
+}
+
+/// Savestate encoder. All integers are serialized byte by byte in
+/// little endian order regardless of the host's endianness so that
+/// savestates can be shared between hosts.
 pub struct Encoder<'a> {
     writer: &'a mut io::Write,
 }
@@ -27,25 +45,8 @@ impl<'a> Encoder<'a> {
             Err(e) => Err(Error::IoError(e)),
         }
     }
-}
-
-impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
-
-    type Error = Error;
-
-    fn emit_nil(&mut self) -> Result<(), Error> {
-        self.emit_str("nil")
-    }
-
-    fn emit_usize(&mut self, v: usize) -> Result<(), Error> {
-        if v as u32 as usize != v {
-            Err(Error::USizeOverflow(v))
-        } else {
-            self.emit_u32(v as u32)
-        }
-    }
 
-    fn emit_u64(&mut self, v: u64) -> Result<(), Error> {
+    pub fn write_u64(&mut self, v: u64) -> Result<(), Error> {
         let b = [
             v as u8,
             (v >> 8) as u8,
@@ -60,7 +61,7 @@ impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
         self.write_bytes(&b)
     }
 
-    fn emit_u32(&mut self, v: u32) -> Result<(), Error> {
+    pub fn write_u32(&mut self, v: u32) -> Result<(), Error> {
         let b = [
             v as u8,
             (v >> 8) as u8,
@@ -71,7 +72,7 @@ impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
         self.write_bytes(&b)
     }
 
-    fn emit_u16(&mut self, v: u16) -> Result<(), Error> {
+    pub fn write_u16(&mut self, v: u16) -> Result<(), Error> {
         let b = [
             v as u8,
             (v >> 8) as u8,
@@ -80,10 +81,133 @@ impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
         self.write_bytes(&b)
     }
 
-    fn emit_u8(&mut self, v: u8) -> Result<(), Error> {
+    pub fn write_u8(&mut self, v: u8) -> Result<(), Error> {
         self.write_bytes(&[v])
     }
 
+    /// usizes are stored like u32s
+    pub fn write_usize(&mut self, v: usize) -> Result<(), Error> {
+        if v as u32 as usize != v {
+            Err(Error::USizeOverflow(v))
+        } else {
+            self.write_u32(v as u32)
+        }
+    }
+
+    pub fn write_str(&mut self, v: &str) -> Result<(), Error> {
+        // Convert into bytes
+        let s = v.as_bytes();
+
+        let len = s.len();
+
+        if len > STRING_MAX_LEN {
+            return Err(Error::StringTooLong(len));
+        }
+
+        try!(self.write_usize(len));
+        try!(self.write_bytes(s));
+
+        Ok(())
+    }
+
+    /// Store a length-prefixed u16 array. The layout is the same as
+    /// a `Vec<u16>` but we convert the data in blocks instead of
+    /// going through the writer once per element, that makes a big
+    /// difference for the VRAM.
+    pub fn write_u16_array(&mut self, v: &[u16]) -> Result<(), Error> {
+        if v.len() > ARRAY_MAX_LEN {
+            return Err(Error::ArrayTooLong(v.len()));
+        }
+
+        try!(self.write_usize(v.len()));
+
+        let mut buf = [0; BULK_BLOCK_LEN * 2];
+
+        for block in v.chunks(BULK_BLOCK_LEN) {
+            for (&w, b) in block.iter().zip(buf.chunks_mut(2)) {
+                b[0] = w as u8;
+                b[1] = (w >> 8) as u8;
+            }
+
+            try!(self.write_bytes(&buf[..block.len() * 2]));
+        }
+
+        Ok(())
+    }
+
+    /// Start a struct. The version is stored alongside the name so
+    /// that `Deserialize` implementations can keep loading older
+    /// layouts when they change.
+    pub fn begin_struct(&mut self,
+                        name: &str,
+                        version: u32) -> Result<(), Error> {
+        try!(self.write_str(name));
+
+        self.write_u32(version)
+    }
+
+    /// Store a struct field. Field names are stored to catch layout
+    /// mismatches instead of loading garbage.
+    pub fn field<T>(&mut self, name: &str, v: &T) -> Result<(), Error>
+        where T: Serialize + ?Sized {
+        try!(self.write_str(name));
+
+        v.serialize(self)
+    }
+
+    /// Store a u16 array field with `write_u16_array`
+    pub fn field_u16_array(&mut self,
+                           name: &str,
+                           v: &[u16]) -> Result<(), Error> {
+        try!(self.write_str(name));
+
+        self.write_u16_array(v)
+    }
+
+    /// Store a struct field implementing rustc_serialize's
+    /// `Encodable`.
+    ///
+    /// XXX rustation's own types (CPU, GPU clock, shared state...)
+    /// still use rustc_serialize so we keep the `Encoder` impl below
+    /// to store them until they're ported.
+    pub fn legacy_field<T>(&mut self,
+                           name: &str,
+                           v: &T) -> Result<(), Error>
+        where T: Encodable {
+        try!(self.write_str(name));
+
+        v.encode(self)
+    }
+}
+
+impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
+
+    type Error = Error;
+
+    fn emit_nil(&mut self) -> Result<(), Error> {
+        self.emit_str("nil")
+    }
+
+    fn emit_usize(&mut self, v: usize) -> Result<(), Error> {
+        self.write_usize(v)
+    }
+
+    fn emit_u64(&mut self, v: u64) -> Result<(), Error> {
+        self.write_u64(v)
+    }
+
+    fn emit_u32(&mut self, v: u32) -> Result<(), Error> {
+        self.write_u32(v)
+    }
+
+    fn emit_u16(&mut self, v: u16) -> Result<(), Error> {
+        self.write_u16(v)
+    }
+
+    fn emit_u8(&mut self, v: u8) -> Result<(), Error> {
+        self.write_u8(v)
+    }
+
     fn emit_isize(&mut self, v: isize) -> Result<(), Error> {
         if v as i32 as isize != v {
             Err(Error::ISizeOverflow(v))
@@ -93,51 +217,39 @@ impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
     }
 
     fn emit_i64(&mut self, v: i64) -> Result<(), Error> {
-        self.emit_u64(v as u64)
+        self.write_u64(v as u64)
     }
 
     fn emit_i32(&mut self, v: i32) -> Result<(), Error> {
-        self.emit_u32(v as u32)
+        self.write_u32(v as u32)
     }
 
     fn emit_i16(&mut self, v: i16) -> Result<(), Error> {
-        self.emit_u16(v as u16)
+        self.write_u16(v as u16)
     }
 
     fn emit_i8(&mut self, v: i8) -> Result<(), Error> {
-        self.emit_u8(v as u8)
+        self.write_u8(v as u8)
     }
 
     fn emit_bool(&mut self, v: bool) -> Result<(), Error> {
-        self.emit_u8(v as u8)
+        self.write_u8(v as u8)
     }
 
-    fn emit_f64(&mut self, _: f64) -> Result<(), Error> {
-        panic!("f64 serialization")
+    fn emit_f64(&mut self, v: f64) -> Result<(), Error> {
+        v.serialize(self)
     }
 
-    fn emit_f32(&mut self, _: f32) -> Result<(), Error> {
-        panic!("f32 serialization")
+    fn emit_f32(&mut self, v: f32) -> Result<(), Error> {
+        v.serialize(self)
     }
 
     fn emit_char(&mut self, v: char) -> Result<(), Error> {
-        self.emit_u32(v as u32)
+        self.write_u32(v as u32)
     }
 
     fn emit_str(&mut self, v: &str) -> Result<(), Error> {
-        // Convert into bytes
-        let s = v.as_bytes();
-
-        let len = s.len();
-
-        if len > STRING_MAX_LEN {
-            return Err(Error::StringTooLong(len));
-        }
-
-        try!(self.emit_usize(len));
-        try!(self.write_bytes(s));
-
-        Ok(())
+        self.write_str(v)
     }
 
     fn emit_enum<F>(&mut self, name: &str, f: F) -> Result<(), Error>
@@ -283,6 +395,7 @@ impl<'a> ::rustc_serialize::Encoder for Encoder<'a> {
 }
 
 /// Rustation savestate format deserializer
+/// Savestate decoder, the counterpart of `Encoder`
 pub struct Decoder<'a> {
     reader: &'a mut io::Read,
 }
@@ -315,8 +428,6 @@ impl<'a> Decoder<'a> {
 
     /// Validate that an expected symbol matches the file value
     fn validate_symbol(&mut self, expected: &str) -> Result<(), Error> {
-        use rustc_serialize::Decoder;
-
         let s = try!(self.read_str());
 
         if s != expected {
@@ -325,21 +436,8 @@ impl<'a> Decoder<'a> {
             Ok(())
         }
     }
-}
 
-impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
-    type Error = Error;
-
-    fn read_nil(&mut self) -> Result<(), Error> {
-        self.validate_symbol("nil")
-    }
-
-    fn read_usize(&mut self) -> Result<usize, Error> {
-        // usize are stored like u32s
-        self.read_u32().map(|v| v as usize)
-    }
-
-    fn read_u64(&mut self) -> Result<u64, Error> {
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
         let mut b = [0; 8];
 
         try!(self.read_bytes(&mut b));
@@ -354,7 +452,7 @@ impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
         Ok(v)
     }
 
-    fn read_u32(&mut self) -> Result<u32, Error> {
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
         let mut b = [0; 4];
 
         try!(self.read_bytes(&mut b));
@@ -369,7 +467,7 @@ impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
         Ok(v)
     }
 
-    fn read_u16(&mut self) -> Result<u16, Error> {
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
         let mut b = [0; 2];
 
         try!(self.read_bytes(&mut b));
@@ -384,7 +482,7 @@ impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
         Ok(v)
     }
 
-    fn read_u8(&mut self) -> Result<u8, Error> {
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
         let mut b = [0];
 
         try!(self.read_bytes(&mut b));
@@ -392,6 +490,132 @@ impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
         Ok(b[0])
     }
 
+    pub fn read_usize(&mut self) -> Result<usize, Error> {
+        // usize are stored like u32s
+        self.read_u32().map(|v| v as usize)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Error> {
+        match try!(self.read_u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            n => Err(Error::BadBool(n)),
+        }
+    }
+
+    pub fn read_str(&mut self) -> Result<String, Error> {
+        // First read the string length
+        let len = try!(self.read_usize());
+
+        if len > STRING_MAX_LEN {
+            return Err(Error::StringTooLong(len));
+        }
+
+        let mut buf = vec![0; len];
+
+        // Now we can read the string itself
+        try!(self.read_bytes(&mut buf));
+
+        // Finally we can convert the bytes to a String
+        String::from_utf8(buf).map_err(|e| Error::BadString(e))
+    }
+
+    /// Read an array stored by `Encoder::write_u16_array`
+    pub fn read_u16_array(&mut self) -> Result<Vec<u16>, Error> {
+        let len = try!(self.read_usize());
+
+        if len > ARRAY_MAX_LEN {
+            return Err(Error::ArrayTooLong(len));
+        }
+
+        let mut v = Vec::with_capacity(len);
+        let mut buf = [0; BULK_BLOCK_LEN * 2];
+
+        while v.len() < len {
+            let n = ::std::cmp::min(len - v.len(), BULK_BLOCK_LEN);
+
+            let block = &mut buf[..n * 2];
+
+            try!(self.read_bytes(block));
+
+            v.extend(block.chunks(2).map(|b| b[0] as u16 |
+                                         (b[1] as u16) << 8));
+        }
+
+        Ok(v)
+    }
+
+    /// Check the name of the struct being read and return its
+    /// version. Fails if the struct was saved by a newer version of
+    /// the code.
+    pub fn begin_struct(&mut self,
+                        name: &str,
+                        version: u32) -> Result<u32, Error> {
+        try!(self.validate_symbol(name));
+
+        let v = try!(self.read_u32());
+
+        if v > version {
+            Err(Error::BadVersion(name.into(), v))
+        } else {
+            Ok(v)
+        }
+    }
+
+    /// Read a field stored by `Encoder::field`
+    pub fn field<T>(&mut self, name: &str) -> Result<T, Error>
+        where T: Deserialize {
+        try!(self.validate_symbol(name));
+
+        T::deserialize(self)
+    }
+
+    /// Read a field stored by `Encoder::field_u16_array`
+    pub fn field_u16_array(&mut self, name: &str) -> Result<Vec<u16>, Error> {
+        try!(self.validate_symbol(name));
+
+        self.read_u16_array()
+    }
+
+    /// Read a field stored by `Encoder::legacy_field`
+    pub fn legacy_field<T>(&mut self, name: &str) -> Result<T, Error>
+        where T: Decodable {
+        try!(self.validate_symbol(name));
+
+        T::decode(self)
+    }
+}
+
+impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
+    type Error = Error;
+
+    fn read_nil(&mut self) -> Result<(), Error> {
+        self.validate_symbol("nil")
+    }
+
+    // The methods below share their names with our own, the calls
+    // resolve to the inherent methods
+
+    fn read_usize(&mut self) -> Result<usize, Error> {
+        self.read_usize()
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        self.read_u64()
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        self.read_u32()
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        self.read_u16()
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        self.read_u8()
+    }
+
     fn read_isize(&mut self) -> Result<isize, Error> {
         self.read_usize().map(|v| v as isize)
     }
@@ -413,19 +637,15 @@ impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
     }
 
     fn read_bool(&mut self) -> Result<bool, Error> {
-        match try!(self.read_u8()) {
-            0 => Ok(false),
-            1 => Ok(true),
-            n => Err(Error::BadBool(n)),
-        }
+        self.read_bool()
     }
 
     fn read_f64(&mut self) -> Result<f64, Error> {
-        panic!()
+        f64::deserialize(self)
     }
 
     fn read_f32(&mut self) -> Result<f32, Error> {
-        panic!()
+        f32::deserialize(self)
     }
 
     fn read_char(&mut self) -> Result<char, Error> {
@@ -435,20 +655,7 @@ impl<'a> ::rustc_serialize::Decoder for Decoder<'a> {
     }
 
     fn read_str(&mut self) -> Result<String, Error> {
-        // First read the string length
-        let len = try!(self.read_usize());
-
-        if len > STRING_MAX_LEN {
-            return Err(Error::StringTooLong(len));
-        }
-
-        let mut buf = vec![0; len];
-
-        // Now we can read the string itself
-        try!(self.read_bytes(&mut buf));
-
-        // Finally we can convert the bytes to a String
-        String::from_utf8(buf).map_err(|e| Error::BadString(e))
+        self.read_str()
     }
 
     fn read_enum<T, F>(&mut self, name: &str, f: F) -> Result<T, Error>
@@ -628,6 +835,10 @@ pub enum Error {
     BadTupleLength(usize, usize),
     /// Encountered an invalid bool while decoding
     BadBool(u8),
+    /// Attempted to encode or decode an unreasonably large array
+    ArrayTooLong(usize),
+    /// Struct saved by a newer version of the code: `(name, version)`
+    BadVersion(String, u32),
 }
 
 /// "Magic" string stored in the header to indentify the file format
@@ -636,6 +847,177 @@ pub const MAGIC: &'static [u8] = b"RSXB";
 /// useful while decoding a bogus savestate, we don't want to allocate
 /// a huge string only to discover that there's a missmatch later.
 pub const STRING_MAX_LEN: usize = 1024 * 1024;
+/// Maximum number of elements of arrays stored with
+/// `write_u16_array`, enough for the whole VRAM
+pub const ARRAY_MAX_LEN: usize = 1024 * 1024;
+/// Number of elements converted at once by the bulk array methods
+const BULK_BLOCK_LEN: usize = 1024;
+
+macro_rules! serialize_integer {
+    ($t:ty, $write:ident, $read:ident) => {
+        impl Serialize for $t {
+            fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+                e.$write(*self)
+            }
+        }
+
+        impl Deserialize for $t {
+            fn deserialize(d: &mut Decoder) -> Result<$t, Error> {
+                d.$read()
+            }
+        }
+    };
+    // Signed integers are stored like their unsigned counterpart
+    ($t:ty, $write:ident, $read:ident, $unsigned:ty) => {
+        impl Serialize for $t {
+            fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+                e.$write(*self as $unsigned)
+            }
+        }
+
+        impl Deserialize for $t {
+            fn deserialize(d: &mut Decoder) -> Result<$t, Error> {
+                d.$read().map(|v| v as $t)
+            }
+        }
+    };
+}
+
+serialize_integer!(u8, write_u8, read_u8);
+serialize_integer!(u16, write_u16, read_u16);
+serialize_integer!(u32, write_u32, read_u32);
+serialize_integer!(u64, write_u64, read_u64);
+serialize_integer!(usize, write_usize, read_usize);
+serialize_integer!(i8, write_u8, read_u8, u8);
+serialize_integer!(i16, write_u16, read_u16, u16);
+serialize_integer!(i32, write_u32, read_u32, u32);
+serialize_integer!(i64, write_u64, read_u64, u64);
+
+impl Serialize for bool {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        e.write_u8(*self as u8)
+    }
+}
+
+impl Deserialize for bool {
+    fn deserialize(d: &mut Decoder) -> Result<bool, Error> {
+        d.read_bool()
+    }
+}
+
+/// Floats are stored as their IEEE 754 representation
+impl Serialize for f32 {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        e.write_u32(self.to_bits())
+    }
+}
+
+impl Deserialize for f32 {
+    fn deserialize(d: &mut Decoder) -> Result<f32, Error> {
+        d.read_u32().map(f32::from_bits)
+    }
+}
+
+impl Serialize for f64 {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        e.write_u64(self.to_bits())
+    }
+}
+
+impl Deserialize for f64 {
+    fn deserialize(d: &mut Decoder) -> Result<f64, Error> {
+        d.read_u64().map(f64::from_bits)
+    }
+}
+
+impl Serialize for str {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        e.write_str(self)
+    }
+}
+
+impl Serialize for String {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        e.write_str(self)
+    }
+}
+
+impl Deserialize for String {
+    fn deserialize(d: &mut Decoder) -> Result<String, Error> {
+        d.read_str()
+    }
+}
+
+/// Options are stored with a bool tag
+impl<T: Serialize> Serialize for Option<T> {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        match *self {
+            Some(ref v) => {
+                try!(e.write_u8(1));
+                v.serialize(e)
+            }
+            None => e.write_u8(0),
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Option<T> {
+    fn deserialize(d: &mut Decoder) -> Result<Option<T>, Error> {
+        if try!(d.read_bool()) {
+            T::deserialize(d).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Vecs are stored with their length followed by the elements. Use
+/// `write_u16_array` for big arrays of u16.
+impl<T: Serialize> Serialize for Vec<T> {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        if self.len() > ARRAY_MAX_LEN {
+            return Err(Error::ArrayTooLong(self.len()));
+        }
+
+        try!(e.write_usize(self.len()));
+
+        for v in self {
+            try!(v.serialize(e));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+    fn deserialize(d: &mut Decoder) -> Result<Vec<T>, Error> {
+        let len = try!(d.read_usize());
+
+        if len > ARRAY_MAX_LEN {
+            return Err(Error::ArrayTooLong(len));
+        }
+
+        (0..len).map(|_| T::deserialize(d)).collect()
+    }
+}
+
+/// Tuples have a fixed length, only the elements are stored
+impl<A: Serialize, B: Serialize> Serialize for (A, B) {
+    fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+        try!(self.0.serialize(e));
+
+        self.1.serialize(e)
+    }
+}
+
+impl<A: Deserialize, B: Deserialize> Deserialize for (A, B) {
+    fn deserialize(d: &mut Decoder) -> Result<(A, B), Error> {
+        let a = try!(A::deserialize(d));
+        let b = try!(B::deserialize(d));
+
+        Ok((a, b))
+    }
+}
 
 
 #[test]
@@ -704,3 +1086,103 @@ fn test_serialize_deserialize() {
 
     assert_eq!(decoded, object);
 }
+
+#[test]
+fn test_serialize_traits() {
+    #[derive(Debug, PartialEq)]
+    struct Test {
+        flag: bool,
+        pos: (u16, i16),
+        ratio: f32,
+        name: Option<String>,
+        points: Vec<u32>,
+        vram: Vec<u16>,
+    }
+
+    impl Serialize for Test {
+        fn serialize(&self, e: &mut Encoder) -> Result<(), Error> {
+            try!(e.begin_struct("Test", 2));
+            try!(e.field("flag", &self.flag));
+            try!(e.field("pos", &self.pos));
+            try!(e.field("ratio", &self.ratio));
+            try!(e.field("name", &self.name));
+            try!(e.field("points", &self.points));
+            e.field_u16_array("vram", &self.vram)
+        }
+    }
+
+    impl Deserialize for Test {
+        fn deserialize(d: &mut Decoder) -> Result<Test, Error> {
+            try!(d.begin_struct("Test", 2));
+
+            Ok(Test {
+                flag: try!(d.field("flag")),
+                pos: try!(d.field("pos")),
+                ratio: try!(d.field("ratio")),
+                name: try!(d.field("name")),
+                points: try!(d.field("points")),
+                vram: try!(d.field_u16_array("vram")),
+            })
+        }
+    }
+
+    let object = Test {
+        flag: true,
+        pos: (640, -12),
+        ratio: 4. / 3.,
+        name: Some("madoka".to_string()),
+        points: vec![0xbfc0_0000, 0x8001_0000],
+        vram: (0..3000).map(|v| v * 7).collect(),
+    };
+
+    let mut serialized = Vec::new();
+
+    {
+        let mut encoder = Encoder::new(&mut serialized).unwrap();
+
+        object.serialize(&mut encoder).unwrap();
+    }
+
+    {
+        let mut reader: &[u8] = &serialized;
+        let mut decoder = Decoder::new(&mut reader).unwrap();
+
+        assert_eq!(Test::deserialize(&mut decoder).unwrap(), object);
+    }
+
+    // States made by a newer version of the struct are rejected
+    let mut reader: &[u8] = &serialized;
+    let mut decoder = Decoder::new(&mut reader).unwrap();
+
+    match decoder.begin_struct("Test", 1) {
+        Err(Error::BadVersion(_, 2)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_byte_order() {
+    let mut serialized = Vec::new();
+
+    {
+        let mut encoder = Encoder::new(&mut serialized).unwrap();
+
+        encoder.write_u16(0x1234).unwrap();
+        encoder.write_u32(0x89ab_cdef).unwrap();
+        encoder.write_u64(0x0102_0304_0506_0708).unwrap();
+    }
+
+    // The layout must not depend on the host's endianness
+    assert_eq!(&serialized[MAGIC.len()..],
+               &[0x34, 0x12,
+                 0xef, 0xcd, 0xab, 0x89,
+                 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01][..]);
+
+    let mut reader: &[u8] = &serialized;
+
+    let mut decoder = Decoder::new(&mut reader).unwrap();
+
+    assert_eq!(decoder.read_u16().unwrap(), 0x1234);
+    assert_eq!(decoder.read_u32().unwrap(), 0x89ab_cdef);
+    assert_eq!(decoder.read_u64().unwrap(), 0x0102_0304_0506_0708);
+}
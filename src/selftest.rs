@@ -0,0 +1,177 @@
+//! Build self-test. With the `selftest` feature enabled, loading a
+//! content file named `rustation-selftest.exe` (its contents are
+//! ignored, an empty file will do) runs a tiny test program embedded
+//! in the core instead. After a few hundred frames we check the values
+//! it left in RAM, report the result and shut down. That gives
+//! packagers a quick way to validate a build on a new platform
+//! without any game.
+//!
+//! The test program is our own, hand-assembled below, so it can be
+//! distributed freely.
+//!
+//! XXX A BIOS is still required: rustation's EXE loader works by
+//! patching the real BIOS boot sequence. The test will only be fully
+//! self-contained once we have an HLE BIOS to boot it with.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use rustation::cpu::Cpu;
+use rustation::memory::Byte;
+
+use libretro;
+use psexe;
+use error::Error;
+
+/// Name of the placeholder content file requesting the self-test
+const PLACEHOLDER: &'static str = "rustation-selftest.exe";
+
+/// Number of frames to run before checking the results
+const FRAMES: u32 = 300;
+
+/// Load address and entry point of the test program
+const TEXT_ADDR: u32 = 0x8001_0000;
+
+/// Test program: store a magic value, count to 1000 in a loop, store
+/// the result and spin forever
+const PROGRAM: [u32; 12] = [
+    0x3c08_800f, // lui   $t0, 0x800f
+    0x3c09_600d, // lui   $t1, 0x600d
+    0x3529_f00d, // ori   $t1, $t1, 0xf00d
+    0xad09_0000, // sw    $t1, 0($t0)
+    0x240a_0000, // li    $t2, 0
+    0x240b_03e8, // li    $t3, 1000
+    0x254a_0001, // loop: addiu $t2, $t2, 1
+    0x154b_fffe, // bne   $t2, $t3, loop
+    0x0000_0000, // nop
+    0xad0a_0004, // sw    $t2, 4($t0)
+    0x1000_ffff, // spin: b spin
+    0x0000_0000, // nop
+];
+
+/// Values expected in RAM once the program has run: (address, value)
+const EXPECTED: [(u32, u32); 2] = [
+    (0x800f_0000, 0x600d_f00d),
+    (0x800f_0004, 1000),
+];
+
+/// If self-tests are enabled and `path` is the placeholder content,
+/// write the test program to the executable cache and return its
+/// path
+pub fn prepare(path: &Path) -> Result<Option<PathBuf>, Error> {
+    if !cfg!(feature = "selftest") {
+        return Ok(None);
+    }
+
+    match path.file_name() {
+        Some(name) if name == PLACEHOLDER => (),
+        _ => return Ok(None),
+    }
+
+    info!("Running the build self-test");
+
+    let exe_path = try!(psexe::cache_directory()).join(PLACEHOLDER);
+
+    let res = File::create(&exe_path)
+        .and_then(|mut f| f.write_all(&exe()));
+
+    match res {
+        Ok(_) => Ok(Some(exe_path)),
+        Err(e) => Err(Error::Io(exe_path, e)),
+    }
+}
+
+/// Build the PS-X EXE containing the test program
+fn exe() -> Vec<u8> {
+    let mut text = Vec::with_capacity(PROGRAM.len() * 4);
+
+    for &w in PROGRAM.iter() {
+        text.extend_from_slice(&[w as u8,
+                                 (w >> 8) as u8,
+                                 (w >> 16) as u8,
+                                 (w >> 24) as u8]);
+    }
+
+    psexe::build_exe(TEXT_ADDR, TEXT_ADDR, &text)
+}
+
+/// Self-test in progress
+pub struct SelfTest {
+    /// Frames left to run before checking the results
+    remaining: u32,
+}
+
+impl SelfTest {
+    pub fn new() -> SelfTest {
+        SelfTest {
+            remaining: FRAMES,
+        }
+    }
+
+    /// Called after every emulated frame. Once enough frames have
+    /// been run the results are checked and reported and the core
+    /// shut down.
+    pub fn frame(&mut self, cpu: &mut Cpu) {
+        if self.remaining == 0 {
+            // Already done, waiting for the frontend to shut down
+            return;
+        }
+
+        self.remaining -= 1;
+
+        if self.remaining > 0 {
+            return;
+        }
+
+        match check(|addr| read_word(cpu, addr)) {
+            Ok(()) => {
+                info!("Self-test passed");
+                libretro::set_message(300, "Self-test passed");
+                libretro::shutdown();
+            }
+            Err(e) => {
+                libretro::shutdown_with_error(
+                    &format!("Self-test failed: {}", e));
+            }
+        }
+    }
+}
+
+fn read_word(cpu: &mut Cpu, addr: u32) -> u32 {
+    (0..4).fold(0, |w, i| {
+        let b = cpu.examine::<Byte>(addr + i) as u8;
+
+        w | ((b as u32) << (i * 8))
+    })
+}
+
+/// Compare the values read with `read` with the expected results
+fn check<F>(mut read: F) -> Result<(), String>
+    where F: FnMut(u32) -> u32 {
+
+    for &(addr, expected) in EXPECTED.iter() {
+        let v = read(addr);
+
+        if v != expected {
+            return Err(format!("expected 0x{:08x} at 0x{:08x}, got 0x{:08x}",
+                               expected, addr, v));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_selftest_exe() {
+    let exe = exe();
+
+    assert_eq!(&exe[0..8], b"PS-X EXE");
+    assert_eq!(exe.len(), 2048 * 2);
+    assert_eq!(&exe[2048..2052], &[0x0f, 0x80, 0x08, 0x3c]);
+
+    assert!(check(|addr| {
+        EXPECTED.iter().find(|e| e.0 == addr).unwrap().1
+    }).is_ok());
+    assert!(check(|_| 0).is_err());
+}
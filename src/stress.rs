@@ -0,0 +1,116 @@
+//! "Spanish inquisition" mode: nobody expects the video options to
+//! change every few seconds. This developer stress test overrides a
+//! random subset of the video options every N frames in order to
+//! exercise the renderer rebuild paths (framebuffer reallocation,
+//! frontend reconfiguration...) and shake out the leaks and crashes
+//! users hit when fiddling with the settings mid-game.
+
+use time;
+
+use libretro;
+
+/// Options we shuffle, with the values we pick from
+const OPTIONS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("internal_upscale_factor", &["1x (native)", "2x", "3x", "4x"]),
+    ("internal_color_depth", &["dithered 16bpp (native)", "32bpp"]),
+    ("scale_dither", &["enabled", "disabled"]),
+    ("wireframe", &["disabled", "enabled"]),
+    ("dynamic_upscaling", &["disabled", "enabled"]),
+];
+
+pub struct Stress {
+    /// Number of frames between two changes
+    period: u32,
+    /// Frames left until the next change
+    countdown: u32,
+    /// xorshift32 state
+    rng: u32,
+    /// Number of changes so far
+    changes: u32,
+}
+
+impl Stress {
+    pub fn new(period: u32) -> Stress {
+        // We want a different sequence every run but we log the seed
+        // in order to be able to reproduce a crash
+        let seed = (time::precise_time_ns() as u32) | 1;
+
+        warn!("Option stress test enabled: changing the video options \
+               every {} frames (seed 0x{:08x})", period, seed);
+
+        Stress {
+            period: period,
+            countdown: period,
+            rng: seed,
+            changes: 0,
+        }
+    }
+
+    pub fn period(&self) -> u32 {
+        self.period
+    }
+
+    /// Called once per frame, returns true if the options have been
+    /// changed and must be reloaded
+    pub fn tick(&mut self) -> bool {
+        self.countdown -= 1;
+
+        if self.countdown > 0 {
+            return false;
+        }
+
+        self.countdown = self.period;
+        self.changes += 1;
+
+        let mut changed = Vec::new();
+
+        for &(var, values) in OPTIONS {
+            // Change each option with a 50% probability
+            if self.next() & 1 == 0 {
+                continue;
+            }
+
+            let value = values[self.next() as usize % values.len()];
+
+            libretro::override_variable(var, value);
+
+            changed.push(format!("{}={}", var, value));
+        }
+
+        info!("Stress test change #{}: {}", self.changes, changed.join(" "));
+
+        !changed.is_empty()
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.rng;
+
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+
+        self.rng = x;
+
+        x
+    }
+}
+
+impl Drop for Stress {
+    fn drop(&mut self) {
+        info!("Option stress test stopped after {} changes", self.changes);
+
+        libretro::clear_variable_overrides();
+    }
+}
+
+/// Parse the stress test period core option
+pub fn parse_period(opt: &str) -> Result<Option<u32>, ()> {
+    if opt == "disabled" {
+        return Ok(None);
+    }
+
+    match opt.parse() {
+        Ok(0) | Err(_) => Err(()),
+        Ok(n) => Ok(Some(n)),
+    }
+}
@@ -0,0 +1,177 @@
+//! Calibration test patterns rendered through the regular GPU
+//! renderer interface. This way they go through the whole pipeline
+//! (command shaders, upscaling, dithering, output pass) like any game
+//! would, which makes them useful to validate the frontend's
+//! filtering/shader configuration and the core's color output.
+
+use rustation::gpu::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use rustation::gpu::renderer::{TextureDepth, BlendMode, SemiTransparencyMode};
+
+/// Test patterns available through the `test_pattern` core option
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TestPattern {
+    /// Test patterns disabled, run the emulator normally
+    Disabled,
+    /// 75% color bars with black and white references
+    ColorBars,
+    /// Shaded gradients for each color component, with dithering
+    DitherGradient,
+    /// 1 pixel wide lines and checkerboards to check scaling
+    /// sharpness
+    Sharpness,
+}
+
+impl TestPattern {
+    pub fn parse(opt: &str) -> Result<TestPattern, ()> {
+        match opt {
+            "disabled" => Ok(TestPattern::Disabled),
+            "color bars" => Ok(TestPattern::ColorBars),
+            "dither gradient" => Ok(TestPattern::DitherGradient),
+            "sharpness" => Ok(TestPattern::Sharpness),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Draw a full frame of `pattern` using `renderer`
+pub fn draw(pattern: TestPattern, renderer: &mut Renderer) {
+    renderer.set_display_mode((0, 0), (WIDTH, HEIGHT), false);
+    renderer.set_draw_offset(0, 0);
+    renderer.set_draw_area((0, 0), (WIDTH, HEIGHT));
+
+    // Start from a black screen
+    renderer.fill_rect([0, 0, 0], (0, 0), (WIDTH, HEIGHT));
+
+    match pattern {
+        TestPattern::Disabled => (),
+        TestPattern::ColorBars => draw_color_bars(renderer),
+        TestPattern::DitherGradient => draw_gradients(renderer),
+        TestPattern::Sharpness => draw_sharpness(renderer),
+    }
+}
+
+fn draw_color_bars(renderer: &mut Renderer) {
+    // 75% intensity bars: white, yellow, cyan, green, magenta, red,
+    // blue
+    let bars = [[191, 191, 191],
+                [191, 191, 0],
+                [0, 191, 191],
+                [0, 191, 0],
+                [191, 0, 191],
+                [191, 0, 0],
+                [0, 0, 191]];
+
+    let bar_width = WIDTH / bars.len() as u16;
+    let bar_height = (HEIGHT * 3) / 4;
+
+    for (i, &color) in bars.iter().enumerate() {
+        let x = i as u16 * bar_width;
+
+        renderer.fill_rect(color, (x, 0), (bar_width, bar_height));
+    }
+
+    // Bottom strip: full white and full black references plus a
+    // gray scale
+    let strip_y = bar_height;
+    let strip_height = HEIGHT - bar_height;
+    let step = WIDTH / 8;
+
+    for i in 0..8 {
+        let v = ((i as u32 * 255) / 7) as u8;
+
+        renderer.fill_rect([v, v, v],
+                           (i * step, strip_y),
+                           (step, strip_height));
+    }
+}
+
+fn draw_gradients(renderer: &mut Renderer) {
+    let colors = [[255, 0, 0],
+                  [0, 255, 0],
+                  [0, 0, 255],
+                  [255, 255, 255]];
+
+    let band_height = HEIGHT / colors.len() as u16;
+
+    let attributes = flat_attributes(true);
+
+    for (i, &color) in colors.iter().enumerate() {
+        let y0 = (i as u16 * band_height) as i16;
+        let y1 = y0 + band_height as i16;
+        let x1 = WIDTH as i16;
+
+        let vertices = [vertex(0, y0, [0, 0, 0]),
+                        vertex(x1, y0, color),
+                        vertex(0, y1, [0, 0, 0]),
+                        vertex(x1, y1, color)];
+
+        renderer.push_quad(&attributes, &vertices);
+    }
+}
+
+fn draw_sharpness(renderer: &mut Renderer) {
+    let white = [255, 255, 255];
+    let half_w = WIDTH / 2;
+    let half_h = HEIGHT / 2;
+
+    // Top left: 1 pixel wide vertical lines
+    for x in (0..half_w).filter(|x| x % 2 == 0) {
+        renderer.fill_rect(white, (x, 0), (1, half_h));
+    }
+
+    // Top right: 1 pixel high horizontal lines
+    for y in (0..half_h).filter(|y| y % 2 == 0) {
+        renderer.fill_rect(white, (half_w, y), (half_w, 1));
+    }
+
+    // Bottom left: 1 pixel checkerboard, drawn using 1 pixel long
+    // lines
+    let attributes = flat_attributes(false);
+
+    for y in half_h..HEIGHT {
+        for x in (0..half_w).filter(|x| (x + y) % 2 == 0) {
+            let x = x as i16;
+            let y = y as i16;
+
+            let v = [vertex(x, y, white),
+                     vertex(x + 1, y, white)];
+
+            renderer.push_line(&attributes, &v);
+        }
+    }
+
+    // Bottom right: 4 pixel checkerboard
+    for cell_y in 0..(half_h / 4) {
+        for cell_x in (0..(half_w / 4)).filter(|x| (x + cell_y) % 2 == 0) {
+            let x = half_w + cell_x * 4;
+            let y = half_h + cell_y * 4;
+
+            renderer.fill_rect(white, (x, y), (4, 4));
+        }
+    }
+}
+
+/// Attributes for untextured opaque primitives
+fn flat_attributes(dither: bool) -> PrimitiveAttributes {
+    PrimitiveAttributes {
+        semi_transparent: false,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::None,
+        texture_page: [0, 0],
+        clut: [0, 0],
+        texture_depth: TextureDepth::T16Bpp,
+        dither: dither,
+    }
+}
+
+fn vertex(x: i16, y: i16, color: [u8; 3]) -> Vertex {
+    Vertex {
+        position: [x, y],
+        color: color,
+        texture_coord: [0, 0],
+    }
+}
+
+/// Resolution of the test patterns
+const WIDTH: u16 = 320;
+const HEIGHT: u16 = 240;
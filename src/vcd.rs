@@ -1,14 +1,56 @@
-use std::io::Write;
+//! Value Change Dump output for the traces collected by rustation
+//! when the "trace" feature is enabled, to be viewed in GTKWave.
+//!
+//! Every module returned by `tracer::remove_trace` gets its own
+//! scope, there's nothing subsystem-specific here. Tracing a new
+//! subsystem (such as the SPU's register writes and voice
+//! key-on/key-off events) only requires rustation to log the
+//! corresponding variables through its tracer; they'll show up
+//! here in their own scope.
+//!
+//! XXX Renderer events (batch flushes, texture uploads, end of
+//! frame) can't be added in a scope of their own yet: the
+//! `Renderer` callbacks don't receive the emulated date and the core
+//! has no access to rustation's timekeeper, so we'd have nothing to
+//! put in the `#date` lines that matches the other scopes. Once the
+//! renderer calls carry the cycle count the `GlRenderer` could log
+//! its events in a `Module` merged into the map given to
+//! `dump_trace`.
+//!
+//! Traces can be huge so they're split in numbered files of bounded
+//! size (`rustation-trace-001.vcd`, `rustation-trace-002.vcd`...),
+//! only the most recent ones are kept.
+
+use std::io::{self, Write, BufWriter};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 
 use std::collections::HashMap;
 use rustation::tracer::Module;
 
-pub fn dump_trace(w: &mut Write,
+/// Limits on the files written by `dump_trace`
+pub struct Rotation {
+    /// Maximum size of a trace file in bytes (None for unlimited).
+    /// The limit can be exceeded if all the changes at a single date
+    /// don't fit.
+    pub max_size: Option<u64>,
+    /// Number of trace files kept, the oldest ones are deleted
+    pub max_files: usize,
+}
+
+/// Dump `trace` in files named after `base` (`<base>-NNN.vcd`),
+/// numbered after the ones already present
+pub fn dump_trace(base: &Path,
+                  rotation: &Rotation,
                   content: &str,
                   bios: &str,
-                  trace: HashMap<&'static str, Module>) {
+                  trace: HashMap<&'static str, Module>) -> io::Result<()> {
+
+    // The header and variable declarations are repeated at the top
+    // of every file so that each one can be viewed on its own
+    let mut preamble = Vec::new();
 
-    write_header(w, content, bios);
+    write_header(&mut preamble, content, bios);
 
     let mut cur_id: u32 = 0;
 
@@ -22,7 +64,7 @@ pub fn dump_trace(w: &mut Write,
         }
 
         let scope = format!("$scope module {} $end\n", name);
-        write_str(w, &scope);
+        write_str(&mut preamble, &scope);
 
         for (v_name, v) in v.iter() {
 
@@ -31,7 +73,7 @@ pub fn dump_trace(w: &mut Write,
 
             let var = format!("$var wire {} {} {} $end\n",
                               v.size(), id, v_name);
-            write_str(w, &var);
+            write_str(&mut preamble, &var);
 
             // Scalars (1bit values) don't have space between the
             // value and identifier in the VCD dump format
@@ -42,34 +84,196 @@ pub fn dump_trace(w: &mut Write,
             }
         }
 
-        write_str(w, "$upscope $end\n");
+        write_str(&mut preamble, "$upscope $end\n");
     }
 
     // Sort log by date
     log.sort_by_key(|v| v.0);
 
-    let mut cur_date = 0;
+    let mut output = try!(Output::new(base, rotation, &preamble));
 
-    write_str(w, "#0\n");
+    // Changes happening at the same date are never split across
+    // files
+    let mut block = String::new();
 
-    for &(date, id, is_scalar, value) in log.iter() {
-        if date != cur_date {
-            let d = format!("#{}\n", date);
-            write_str(w, &d);
-            cur_date = date;
+    for (i, &(date, id, is_scalar, value)) in log.iter().enumerate() {
+        if i == 0 || log[i - 1].0 != date {
+            try!(output.write_block(&block));
+
+            block = format!("#{}\n", date);
+        }
+
+        if is_scalar {
+            block.push_str(&format!("{}{}\n", value, id));
+        } else {
+            // Apparently only binary is supported...
+            block.push_str(&format!("b{:b} {}\n", value, id));
         }
+    }
+
+    try!(output.write_block(&block));
+
+    output.finish()
+}
+
+/// Trace file being written, moves on to the next file once the
+/// current one is full
+struct Output<'a> {
+    base: &'a Path,
+    rotation: &'a Rotation,
+    preamble: &'a [u8],
+    file: BufWriter<File>,
+    /// Number of the current file
+    index: u32,
+    /// Number of bytes written to the current file
+    size: u64,
+}
+
+impl<'a> Output<'a> {
+    fn new(base: &'a Path,
+           rotation: &'a Rotation,
+           preamble: &'a [u8]) -> io::Result<Output<'a>> {
+        let index =
+            try!(trace_files(base)).last().map(|&(i, _)| i + 1)
+            .unwrap_or(1);
+
+        let file = try!(Output::create(base, rotation, preamble, index));
+
+        Ok(Output {
+            base: base,
+            rotation: rotation,
+            preamble: preamble,
+            file: file,
+            index: index,
+            size: preamble.len() as u64,
+        })
+    }
+
+    fn create(base: &Path,
+              rotation: &Rotation,
+              preamble: &[u8],
+              index: u32) -> io::Result<BufWriter<File>> {
+        let path = trace_path(base, index);
+
+        info!("Dumping VCD trace file to {}", path.display());
+
+        let mut file = BufWriter::new(try!(File::create(&path)));
+
+        try!(file.write_all(preamble));
+
+        try!(prune(base, rotation.max_files));
+
+        Ok(file)
+    }
 
-        let v =
-            if is_scalar {
-                format!("{}{}\n", value, id)
-            } else {
-                // Apparently only binary is supported...
-                format!("b{:b} {}\n", value, id)
+    fn write_block(&mut self, block: &str) -> io::Result<()> {
+        let len = block.len() as u64;
+
+        let full =
+            match self.rotation.max_size {
+                Some(max) => self.size + len > max,
+                None => false,
             };
-        write_str(w, &v);
+
+        // Don't leave a file without any change in it
+        if full && self.size > self.preamble.len() as u64 {
+            try!(self.file.flush());
+
+            self.index += 1;
+            self.file = try!(Output::create(self.base,
+                                            self.rotation,
+                                            self.preamble,
+                                            self.index));
+            self.size = self.preamble.len() as u64;
+        }
+
+        try!(self.file.write_all(block.as_bytes()));
+        self.size += len;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
     }
 }
 
+/// Path of the trace file number `index`
+fn trace_path(base: &Path, index: u32) -> PathBuf {
+    let name =
+        match base.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => String::new(),
+        };
+
+    base.with_file_name(format!("{}-{:03}.vcd", name, index))
+}
+
+/// Return the number of the trace file `file_name` if it belongs to
+/// the traces named after `base_name`
+fn trace_index(base_name: &str, file_name: &str) -> Option<u32> {
+    if file_name.len() < base_name.len() + 4 ||
+        !file_name.starts_with(base_name) ||
+        !file_name.ends_with(".vcd") {
+        return None;
+    }
+
+    let num = &file_name[base_name.len()..file_name.len() - 4];
+
+    if !num.starts_with('-') ||
+        !num[1..].chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+
+    num[1..].parse().ok()
+}
+
+/// Return the existing trace files named after `base`, sorted by
+/// number
+fn trace_files(base: &Path) -> io::Result<Vec<(u32, PathBuf)>> {
+    let dir =
+        match base.parent() {
+            Some(d) if d != Path::new("") => d,
+            _ => Path::new("."),
+        };
+
+    let base_name =
+        match base.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => return Ok(Vec::new()),
+        };
+
+    let mut files = Vec::new();
+
+    for entry in try!(fs::read_dir(dir)) {
+        let entry = try!(entry);
+        let name = entry.file_name();
+
+        if let Some(i) = trace_index(&base_name, &name.to_string_lossy()) {
+            files.push((i, entry.path()));
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Delete the oldest trace files to keep at most `max_files`
+fn prune(base: &Path, max_files: usize) -> io::Result<()> {
+    let files = try!(trace_files(base));
+
+    let excess = files.len().saturating_sub(max_files);
+
+    for &(_, ref path) in &files[..excess] {
+        info!("Removing old trace file {}", path.display());
+
+        try!(fs::remove_file(path));
+    }
+
+    Ok(())
+}
+
 fn write_header(w: &mut Write,
                 content: &str,
                 bios: &str) {
@@ -89,6 +293,15 @@ fn write_header(w: &mut Write,
 
     write_str(w, &comment);
 
+    // Monotonic timestamp from the frontend's clock (if available)
+    // to correlate the trace with the frontend's own profiling. The
+    // calendar date below still comes from the system clock since
+    // libretro doesn't provide one.
+    if let Some(us) = ::libretro::perf::time_usec() {
+        let comment = format!("$comment\n  Frontend time: {}us\n$end\n", us);
+        write_str(w, &comment);
+    }
+
     let months = [ "January",    "February",
                     "March",     "April",
                     "May",       "June",
@@ -123,3 +336,14 @@ fn write_header(w: &mut Write,
 fn write_str(w: &mut Write, s: &str) {
     w.write_all(s.as_bytes()).unwrap();
 }
+
+#[test]
+fn test_trace_index() {
+    assert_eq!(trace_index("trace", "trace-001.vcd"), Some(1));
+    assert_eq!(trace_index("trace", "trace-1234.vcd"), Some(1234));
+    assert_eq!(trace_index("trace", "trace.vcd"), None);
+    assert_eq!(trace_index("trace", "trace-001.vcd.gz"), None);
+    assert_eq!(trace_index("trace", "trace-old-001.vcd"), None);
+    assert_eq!(trace_index("trace", "trace-.vcd"), None);
+    assert_eq!(trace_index("tr", "trace-001.vcd"), None);
+}
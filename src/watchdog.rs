@@ -0,0 +1,123 @@
+//! Hang detection. When a game (or an emulation bug) leaves the CPU
+//! spinning in a tight loop with interrupts disabled nothing can
+//! ever get it out of there and the user is left with a frozen
+//! screen and no idea what happened. The watchdog samples the CPU at
+//! the end of every frame and reports the hang once it's been stuck
+//! for a few seconds.
+//!
+//! Spinning with interrupts enabled is normal (that's how games wait
+//! for the VBLANK), so is a static screen, so neither is considered
+//! a hang.
+
+/// Largest distance in bytes between the instructions sampled
+/// during a hang. Stuck loops are generally a handful of
+/// instructions long.
+const LOOP_SPAN: u32 = 64;
+
+/// COP0 status register "current interrupt enable" bit
+const SR_IEC: u32 = 1;
+
+pub struct Watchdog {
+    /// Number of frames the CPU must be stuck before we report it
+    timeout: u32,
+    /// Lowest and highest PC sampled since the CPU got stuck
+    window: Option<(u32, u32)>,
+    /// Number of consecutive frames spent in `window`
+    frames: u32,
+    /// Set once the hang has been reported, we don't report it again
+    /// until the CPU makes progress
+    reported: bool,
+}
+
+impl Watchdog {
+    /// Create a watchdog reporting hangs lasting at least `timeout`
+    /// frames
+    pub fn new(timeout: u32) -> Watchdog {
+        Watchdog {
+            timeout: timeout,
+            window: None,
+            frames: 0,
+            reported: false,
+        }
+    }
+
+    /// Forget the current hang, if any. Called when the state of the
+    /// CPU changes behind our back (reset, savestate load)
+    pub fn reset(&mut self) {
+        self.window = None;
+        self.frames = 0;
+        self.reported = false;
+    }
+
+    /// Sample the CPU's `pc` and status register `sr` at the end of a
+    /// frame. Returns true when the CPU has just been stuck for
+    /// `timeout` frames.
+    pub fn frame(&mut self, pc: u32, sr: u32) -> bool {
+        if sr & SR_IEC != 0 {
+            self.reset();
+            return false;
+        }
+
+        let window =
+            match self.window {
+                Some((low, high)) => {
+                    let low = ::std::cmp::min(low, pc);
+                    let high = ::std::cmp::max(high, pc);
+
+                    if high - low > LOOP_SPAN {
+                        // Still running code outside of the loop,
+                        // start again from here
+                        self.reset();
+                        (pc, pc)
+                    } else {
+                        (low, high)
+                    }
+                }
+                None => (pc, pc),
+            };
+
+        self.window = Some(window);
+        self.frames += 1;
+
+        if self.frames >= self.timeout && !self.reported {
+            self.reported = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Number of frames the CPU has been stuck for
+    pub fn stuck_frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+#[test]
+fn test_watchdog() {
+    let mut w = Watchdog::new(3);
+
+    // Interrupts enabled: waiting for the VBLANK is fine
+    for _ in 0..10 {
+        assert!(!w.frame(0x8001_0000, 0x401));
+    }
+
+    // Progress with interrupts disabled isn't a hang either
+    for i in 0..10 {
+        assert!(!w.frame(0x8001_0000 + i * 0x100, 0x400));
+    }
+
+    assert!(!w.frame(0x8002_0000, 0x400));
+    assert!(!w.frame(0x8002_0008, 0x400));
+    assert!(w.frame(0x8002_0004, 0x400));
+    assert_eq!(w.stuck_frames(), 3);
+
+    // Only reported once
+    assert!(!w.frame(0x8002_0000, 0x400));
+
+    // The CPU got out of the loop, we can report again
+    assert!(!w.frame(0x8002_0000, 0x401));
+    assert!(!w.frame(0x8002_0000, 0x400));
+    assert!(!w.frame(0x8002_0000, 0x400));
+    assert!(w.frame(0x8002_0000, 0x400));
+}
@@ -0,0 +1,243 @@
+//! Background worker threads for the slow I/O that doesn't need to
+//! happen on the emulation thread, such as frame capture PNG
+//! encoding.
+//!
+//! The pool is started when a game is loaded and shut down when it's
+//! unloaded, the shutdown waits for the pending jobs so nothing is
+//! lost. Jobs submitted while the pool isn't running are executed
+//! immediately on the caller's thread.
+//!
+//! Jobs can't talk to the frontend (libretro callbacks are only
+//! valid on the thread calling us), they can only log.
+//!
+//! XXX Savestates aren't compressed so there's nothing to offload
+//! there: the frontend owns the serialized buffer and the quick-save
+//! file has to be complete before `quick_load` can read it back.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+/// Number of worker threads
+const WORKERS: usize = 2;
+
+/// Maximum number of pending jobs. Once the queue is full the
+/// submitting thread waits, otherwise a slow disk could make us
+/// buffer frame captures until we run out of memory.
+const QUEUE_LEN: usize = 8;
+
+/// `Box<FnOnce>` can't be called directly, this trait works around
+/// it
+trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F> Job for F
+    where F: FnOnce() + Send {
+    fn run(self: Box<F>) {
+        (*self)()
+    }
+}
+
+enum Message {
+    Run(Box<Job>),
+    /// Sent once per thread when the pool shuts down, after all the
+    /// pending jobs
+    Shutdown,
+}
+
+struct Pool {
+    queue: SyncSender<Message>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    fn new() -> Pool {
+        let (queue, jobs) = mpsc::sync_channel(QUEUE_LEN);
+
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        let threads = (0..WORKERS).filter_map(|n| {
+            let jobs = jobs.clone();
+
+            let res = thread::Builder::new()
+                .name(format!("rustation-worker-{}", n))
+                .spawn(move || work(&jobs));
+
+            match res {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    error!("Couldn't start worker thread: {}", e);
+                    None
+                }
+            }
+        }).collect();
+
+        Pool {
+            queue: queue,
+            threads: threads,
+        }
+    }
+
+    /// Queue `job`, returns it if the workers are gone
+    fn submit(&self, job: Box<Job>) -> Result<(), Box<Job>> {
+        if self.threads.is_empty() {
+            return Err(job);
+        }
+
+        let job =
+            match self.queue.try_send(Message::Run(job)) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(m)) => {
+                    debug!("Worker queue full, waiting");
+                    m
+                }
+                Err(TrySendError::Disconnected(m)) => m,
+            };
+
+        self.queue.send(job).map_err(|e| {
+            match e.0 {
+                Message::Run(job) => job,
+                Message::Shutdown => unreachable!(),
+            }
+        })
+    }
+
+    /// Finish the pending jobs and stop the threads
+    fn shutdown(self) {
+        for _ in 0..self.threads.len() {
+            // If the send fails all the threads are already gone
+            let _ = self.queue.send(Message::Shutdown);
+        }
+
+        for t in self.threads {
+            let _ = t.join();
+        }
+    }
+}
+
+fn work(jobs: &Mutex<Receiver<Message>>) {
+    loop {
+        let message = {
+            let jobs =
+                match jobs.lock() {
+                    Ok(j) => j,
+                    // An other worker panicked while holding the
+                    // lock, which `recv` can't do
+                    Err(_) => return,
+                };
+
+            jobs.recv()
+        };
+
+        match message {
+            Ok(Message::Run(job)) => run(job),
+            Ok(Message::Shutdown) | Err(_) => return,
+        }
+    }
+}
+
+/// Run `job`, a panic only loses this job instead of taking the
+/// thread (and eventually the whole pool) down
+fn run(job: Box<Job>) {
+    if panic::catch_unwind(AssertUnwindSafe(|| job.run())).is_err() {
+        error!("Worker job panicked");
+    }
+}
+
+/// Running pool, allocated once in `init` and never freed
+static mut POOL: *const Mutex<Option<Pool>> = ::std::ptr::null();
+
+fn pool() -> Option<&'static Mutex<Option<Pool>>> {
+    unsafe {
+        if POOL.is_null() {
+            None
+        } else {
+            Some(&*POOL)
+        }
+    }
+}
+
+/// Must be called once before any other function in this module
+pub fn init() {
+    unsafe {
+        POOL = Box::into_raw(Box::new(Mutex::new(None)));
+    }
+}
+
+/// Start the worker threads if they're not already running
+pub fn start() {
+    if let Some(pool) = pool() {
+        if let Ok(mut pool) = pool.lock() {
+            if pool.is_none() {
+                *pool = Some(Pool::new());
+            }
+        }
+    }
+}
+
+/// Wait for the pending jobs and stop the worker threads
+pub fn shutdown() {
+    let running =
+        match pool().and_then(|p| p.lock().ok()) {
+            Some(mut p) => p.take(),
+            None => None,
+        };
+
+    // Don't hold the lock while we wait for the jobs
+    if let Some(pool) = running {
+        pool.shutdown();
+    }
+}
+
+/// Run `f` on a worker thread, or right away if the pool isn't
+/// running
+pub fn spawn<F>(f: F)
+    where F: FnOnce() + Send + 'static {
+    let job: Box<Job> = Box::new(f);
+
+    let job =
+        match pool().and_then(|p| p.lock().ok()) {
+            Some(p) =>
+                match *p {
+                    Some(ref pool) =>
+                        match pool.submit(job) {
+                            Ok(()) => return,
+                            Err(job) => job,
+                        },
+                    None => job,
+                },
+            None => job,
+        };
+
+    run(job);
+}
+
+#[test]
+fn test_pool() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let pool = Pool::new();
+    let count = Arc::new(AtomicUsize::new(0));
+
+    for i in 0..32 {
+        let count = count.clone();
+
+        let job: Box<Job> = Box::new(move || {
+            if i == 3 {
+                panic!("job failure");
+            }
+
+            count.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert!(pool.submit(job).is_ok());
+    }
+
+    // All the jobs are done once the pool is shut down, the panic
+    // didn't take anything else down
+    pool.shutdown();
+
+    assert_eq!(count.load(Ordering::SeqCst), 31);
+}
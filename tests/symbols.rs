@@ -0,0 +1,126 @@
+//! Check that every libretro entry point is exported with the
+//! signature libretro.h expects. Each symbol is coerced to a function
+//! pointer of the right type so any mismatch (wrong argument types,
+//! missing `extern "C"`...) fails to build instead of crashing the
+//! frontend at runtime. The built library is then opened the way a
+//! frontend would and every symbol is looked up in it, which catches
+//! entry points that aren't exported at all (missing `#[no_mangle]`,
+//! wrong crate type...).
+//!
+//! The pointer types use `unsafe` so that entry points which happen
+//! to be declared `unsafe` on our side are accepted too.
+
+extern crate libc;
+extern crate rustation_retro;
+
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use libc::{c_char, c_uint, c_void, size_t};
+
+use rustation_retro::libretro::*;
+
+/// Return the path of the library built by cargo. Test executables
+/// live in `target/<profile>/deps`, the library is copied to
+/// `target/<profile>`.
+#[cfg(unix)]
+fn library_path() -> PathBuf {
+    let name = format!("{}rustation_retro{}", DLL_PREFIX, DLL_SUFFIX);
+
+    let exe = env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+
+    let mut path = deps.parent().unwrap().join(&name);
+
+    if !path.exists() {
+        path = deps.join(&name);
+    }
+
+    path
+}
+
+/// Return the last `dlerror` message
+#[cfg(unix)]
+fn dl_error() -> String {
+    unsafe {
+        let e = libc::dlerror();
+
+        if e.is_null() {
+            "unknown error".into()
+        } else {
+            CStr::from_ptr(e).to_string_lossy().into_owned()
+        }
+    }
+}
+
+macro_rules! check_symbols {
+    ($($name:ident: $ty:ty,)+) => {
+        #[test]
+        fn test_libretro_symbol_types() {
+            $(
+                let _: $ty = $name;
+            )+
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn test_libretro_symbols_exported() {
+            let path = library_path();
+            let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+            let lib = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW) };
+
+            assert!(!lib.is_null(),
+                    "couldn't open {}: {}", path.display(), dl_error());
+
+            $(
+                let name = CString::new(stringify!($name)).unwrap();
+
+                let sym = unsafe { libc::dlsym(lib, name.as_ptr()) };
+
+                assert!(!sym.is_null(),
+                        "{} isn't exported by {}",
+                        stringify!($name), path.display());
+            )+
+
+            unsafe {
+                libc::dlclose(lib);
+            }
+        }
+    }
+}
+
+check_symbols!(
+    retro_api_version: unsafe extern "C" fn() -> c_uint,
+    retro_set_environment: unsafe extern "C" fn(EnvironmentFn),
+    retro_set_video_refresh: unsafe extern "C" fn(VideoRefreshFn),
+    retro_set_audio_sample: unsafe extern "C" fn(AudioSampleFn),
+    retro_set_audio_sample_batch: unsafe extern "C" fn(AudioSampleBatchFn),
+    retro_set_input_poll: unsafe extern "C" fn(InputPollFn),
+    retro_set_input_state: unsafe extern "C" fn(InputStateFn),
+    retro_init: unsafe extern "C" fn(),
+    retro_deinit: unsafe extern "C" fn(),
+    retro_get_system_info: unsafe extern "C" fn(*mut SystemInfo),
+    retro_get_system_av_info: unsafe extern "C" fn(*mut SystemAvInfo),
+    retro_set_controller_port_device: unsafe extern "C" fn(c_uint, c_uint),
+    retro_reset: unsafe extern "C" fn(),
+    retro_run: unsafe extern "C" fn(),
+    retro_serialize_size: unsafe extern "C" fn() -> size_t,
+    retro_serialize: unsafe extern "C" fn(*mut c_void, size_t) -> bool,
+    retro_unserialize: unsafe extern "C" fn(*const c_void, size_t) -> bool,
+    retro_cheat_reset: unsafe extern "C" fn(),
+    retro_cheat_set: unsafe extern "C" fn(c_uint, bool, *const c_char),
+    retro_load_game: unsafe extern "C" fn(*const GameInfo) -> bool,
+    retro_load_game_special:
+        unsafe extern "C" fn(c_uint, *const GameInfo, size_t) -> bool,
+    retro_unload_game: unsafe extern "C" fn(),
+    retro_get_region: unsafe extern "C" fn() -> c_uint,
+    retro_get_memory_data: unsafe extern "C" fn(c_uint) -> *mut c_void,
+    retro_get_memory_size: unsafe extern "C" fn(c_uint) -> size_t,
+);